@@ -0,0 +1,43 @@
+//! Benchmarks for the report encode path
+//!
+//! Run with `cargo bench`. Criterion keeps the last run under
+//! `target/criterion` and reports changes against it; to guard a branch,
+//! save a baseline with `cargo bench -- --save-baseline main` and compare
+//! with `cargo bench -- --baseline main`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use octo_virtual_sensors::{checksum::Crc16Usb, VirtualSensorReport};
+use std::hint::black_box;
+
+/// Encoding sensor values into a report, the per-update cost
+fn update(c: &mut Criterion) {
+    let values = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+    let mut report = VirtualSensorReport::default();
+    c.bench_function("update_all_sensors", |b| {
+        b.iter(|| report.update(black_box(&values)))
+    });
+    c.bench_function("update_no_sensors", |b| {
+        b.iter(|| report.update(black_box(&[])))
+    });
+}
+
+/// The checksum over a full report
+fn checksum(c: &mut Criterion) {
+    let bytes = VirtualSensorReport::default().as_bytes().to_vec();
+    c.bench_function("crc16_usb", |b| {
+        b.iter(|| Crc16Usb::compute(black_box(&bytes)))
+    });
+}
+
+/// Parsing a report read back from the device
+fn parse(c: &mut Criterion) {
+    let mut report = VirtualSensorReport::default();
+    report.update(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+    let bytes = report.as_bytes().to_vec();
+    c.bench_function("from_bytes", |b| {
+        b.iter(|| VirtualSensorReport::from_bytes(black_box(&bytes)).unwrap())
+    });
+}
+
+criterion_group!(benches, update, checksum, parse);
+criterion_main!(benches);
@@ -0,0 +1,52 @@
+//! Read-modify-write the control report the way the fan setters do
+//!
+//! Malformed input must be rejected with an error, never a panic. Writing
+//! back a fan's settings unchanged must leave the report as it was, and
+//! new settings must read back and keep the report parseable.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use octo_virtual_sensors::{
+    control::{ControlMode, ControlReport},
+    layout::DEVICES,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&edit, data)) = data.split_first() else {
+        return;
+    };
+    for device in DEVICES {
+        let layout = device.control;
+        let _ = ControlReport::parse(layout, data);
+
+        let mut bytes = data.to_vec();
+        bytes.resize(layout.len, 0);
+        bytes[0] = layout.report_id;
+        layout.checksum.apply(&mut bytes);
+        let mut report = ControlReport::parse(layout, &bytes).unwrap();
+        for channel in 0..report.fan_count() {
+            let Ok(fan) = report.fan(channel) else {
+                continue;
+            };
+            report.set_fan(channel, &fan).unwrap();
+            assert_eq!(report.as_bytes(), &bytes[..]);
+        }
+        assert!(report.fan(report.fan_count()).is_err());
+
+        let channel = usize::from(edit) % report.fan_count().max(1);
+        let Ok(mut fan) = report.fan(channel) else {
+            continue;
+        };
+        fan.mode = ControlMode::from_raw(edit & 0x03);
+        fan.duty = u16::from(edit) * 40;
+        fan.curve.truncate(usize::from(edit % 4) + 1);
+        report.set_fan(channel, &fan).unwrap();
+        let reread = ControlReport::parse(layout, report.as_bytes()).unwrap();
+        let written = reread.fan(channel).unwrap();
+        assert_eq!(
+            (written.mode, written.duty, written.source),
+            (fan.mode, fan.duty, fan.source)
+        );
+        assert_eq!(written.curve[..fan.curve.len()], fan.curve[..]);
+    }
+});
@@ -0,0 +1,14 @@
+//! Parse arbitrary bytes as a virtual sensor report
+//!
+//! Malformed or truncated input must be rejected with an error, never a
+//! panic, and anything that parses must round trip unchanged.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use octo_virtual_sensors::VirtualSensorReport;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(report) = VirtualSensorReport::from_bytes(data) {
+        assert_eq!(report.as_bytes(), data);
+    }
+});
@@ -0,0 +1,46 @@
+//! Read a report, update the sensors and write it back
+//!
+//! Only the sensor bytes and checksum may change, and the result must
+//! always parse again. Adopting the device report's trailer into the
+//! built-in template must give the same bytes.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use octo_virtual_sensors::{
+    checksum::{Checksum, Crc16Usb},
+    VirtualSensorReport,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < VirtualSensorReport::LEN {
+        return;
+    }
+    let (template, values) = data.split_at(VirtualSensorReport::LEN);
+    let mut template = template.to_vec();
+    template[0] = VirtualSensorReport::ID;
+
+    // Give the template a valid checksum so it survives parsing
+    Crc16Usb.apply(&mut template);
+    let Ok(mut report) = VirtualSensorReport::from_bytes(&template) else {
+        return;
+    };
+
+    // Any whole degrees, ones too large for the encoding saturate
+    let values: Vec<i16> = values
+        .chunks_exact(2)
+        .map(|c| i16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    report.update(&values);
+
+    let written = report.as_bytes();
+    assert_eq!(written.len(), VirtualSensorReport::LEN);
+    assert_eq!(written[0], template[0]);
+    assert_eq!(written[33..49], template[33..49]);
+    VirtualSensorReport::from_bytes(written).unwrap();
+
+    let device = VirtualSensorReport::from_bytes(&template).unwrap();
+    let mut adopted = VirtualSensorReport::default();
+    adopted.adopt_trailer(&device).unwrap();
+    adopted.update(&values);
+    assert_eq!(adopted.as_bytes(), written);
+});
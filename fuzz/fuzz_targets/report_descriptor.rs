@@ -0,0 +1,25 @@
+//! Parse arbitrary bytes as a HID report descriptor
+//!
+//! Descriptors come straight from the device, so malformed ones and ones
+//! declaring absurd sizes must be rejected with an error, never a panic,
+//! and no declared length may size a report past the HID maximum.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use octo_virtual_sensors::{hid::ReportDescriptor, layout::DEVICES};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(descriptor) = ReportDescriptor::parse(data) else {
+        return;
+    };
+    assert_eq!(descriptor.as_bytes(), data);
+    for report in descriptor.reports() {
+        let len = report.wire_len();
+        for device in DEVICES {
+            if let Some(layout) = device.virtual_sensors.resized(len) {
+                assert_eq!(layout.len, len);
+                layout.check().unwrap();
+            }
+        }
+    }
+});
@@ -0,0 +1,25 @@
+//! Parse arbitrary bytes as a status report of every model
+//!
+//! Malformed input must be rejected with an error, never a panic. Any
+//! bytes with the right length, report ID and checksum must parse, with
+//! one reading per sensor and fan the layout declares.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use octo_virtual_sensors::{layout::DEVICES, status::Status};
+
+fuzz_target!(|data: &[u8]| {
+    for device in DEVICES {
+        let layout = &device.status;
+        let _ = Status::parse(layout, data);
+
+        let mut report = data.to_vec();
+        report.resize(layout.len, 0);
+        report[0] = layout.report_id;
+        layout.checksum.apply(&mut report);
+        let status = Status::parse(layout, &report).unwrap();
+        assert_eq!(status.sensors.len(), layout.sensor_count);
+        assert_eq!(status.virtual_sensors.len(), layout.virtual_sensor_count);
+        assert_eq!(status.fans.len(), layout.fans.len());
+    }
+});
@@ -0,0 +1,169 @@
+/* C interface to octo_virtual_sensors, see src/ffi.rs */
+
+#ifndef OCTO_VIRTUAL_SENSORS_H
+#define OCTO_VIRTUAL_SENSORS_H
+
+/* Generated with cbindgen, regenerate rather than editing by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of a C call
+ */
+typedef enum OctoStatus {
+  /**
+   * Success
+   */
+  OCTO_STATUS_OK = 0,
+  /**
+   * A null pointer, bad string or sensor index was passed
+   */
+  OCTO_STATUS_INVALID_ARGUMENT = -1,
+  /**
+   * No matching device is connected
+   */
+  OCTO_STATUS_DEVICE_NOT_FOUND = -2,
+  /**
+   * Not allowed to open the device
+   */
+  OCTO_STATUS_PERMISSION_DENIED = -3,
+  /**
+   * A kernel driver or another program holds the device
+   */
+  OCTO_STATUS_BUSY = -4,
+  /**
+   * The device went away; reopen it with [`octo_reopen`]
+   */
+  OCTO_STATUS_DISCONNECTED = -5,
+  /**
+   * The device didn't answer in time
+   */
+  OCTO_STATUS_TIMEOUT = -6,
+  /**
+   * A report failed its checksum
+   */
+  OCTO_STATUS_CHECKSUM_MISMATCH = -7,
+  /**
+   * A value doesn't fit what the device accepts
+   */
+  OCTO_STATUS_OUT_OF_RANGE = -8,
+  /**
+   * Any other failure, see [`octo_last_error`]
+   */
+  OCTO_STATUS_OTHER = -9,
+  /**
+   * The library panicked, a bug
+   */
+  OCTO_STATUS_PANIC = -10,
+  /**
+   * A report from the device wasn't what was expected
+   */
+  OCTO_STATUS_INVALID_REPORT = -11,
+  /**
+   * A report doesn't match the device's layout
+   */
+  OCTO_STATUS_INVALID_LAYOUT = -12,
+  /**
+   * The device, its firmware or the backend can't do this
+   */
+  OCTO_STATUS_UNSUPPORTED = -13,
+} OctoStatus;
+
+/**
+ * Simple interface to update the 'Virtual sensors on the Aquacomputer Octo
+ */
+typedef struct Octo Octo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Open the first OCTO found and store its handle in `*out`
+ *
+ * # Safety
+ * `out` must be valid for a pointer write. Free the handle with
+ * [`octo_free`].
+ */
+enum OctoStatus octo_new(struct Octo **out);
+
+/**
+ * Open the OCTO with serial number `serial` and store its handle in
+ * `*out`
+ *
+ * # Safety
+ * `serial` must be a NUL-terminated string and `out` valid for a pointer
+ * write. Free the handle with [`octo_free`].
+ */
+enum OctoStatus octo_open_by_serial(const char *serial, struct Octo **out);
+
+/**
+ * Close the device and free `octo`
+ *
+ * # Safety
+ * `octo` must be null or a handle from this library not used afterwards.
+ */
+void octo_free(struct Octo *octo);
+
+/**
+ * Set virtual sensor `index` to `degrees` Celsius
+ *
+ * # Safety
+ * `octo` must be a live handle.
+ */
+enum OctoStatus octo_set_sensor(struct Octo *octo, size_t index, float degrees);
+
+/**
+ * Disconnect virtual sensor `index`
+ *
+ * # Safety
+ * `octo` must be a live handle.
+ */
+enum OctoStatus octo_clear_sensor(struct Octo *octo, size_t index);
+
+/**
+ * Set virtual sensors in order from `len` values in degrees Celsius
+ *
+ * NaN and slots past `len` are disconnected.
+ *
+ * # Safety
+ * `octo` must be a live handle and `values` point to `len` floats.
+ */
+enum OctoStatus octo_update_sensors(struct Octo *octo, const float *values, size_t len);
+
+/**
+ * Read up to `len` physical temperature sensors into `out`, in degrees
+ * Celsius with NaN for unplugged ones
+ *
+ * The number of sensors written is stored in `*count` unless it is null.
+ *
+ * # Safety
+ * `octo` must be a live handle, `out` valid for `len` floats and `count`
+ * null or valid for a write.
+ */
+enum OctoStatus octo_read_sensors(struct Octo *octo, float *out, size_t len, size_t *count);
+
+/**
+ * Reopen the device after it was unplugged
+ *
+ * # Safety
+ * `octo` must be a live handle.
+ */
+enum OctoStatus octo_reopen(struct Octo *octo);
+
+/**
+ * Message of the last failed call on this thread, or null
+ *
+ * The string stays valid until the next failing call on the thread.
+ */
+const char *octo_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OCTO_VIRTUAL_SENSORS_H */
@@ -0,0 +1,40 @@
+//! Privileged helper owning the Octo
+//!
+//! Usage: `octo-vs-helper [--failsafe SECONDS] [SOCKET]`
+//!
+//! Run it as a user with USB access and a group shared with the clients,
+//! e.g. systemd's `User=` and `Group=`. The socket is group-writable.
+//!
+//! With `--failsafe`, virtual sensors are disconnected when no client has
+//! written for that many seconds, so a daemon that dies doesn't leave a
+//! stale temperature behind.
+use anyhow::Context;
+use octo_virtual_sensors::{
+    helper::{self, Helper},
+    Failsafe, Octo,
+};
+use std::time::Duration;
+
+fn main() -> anyhow::Result<()> {
+    octo_virtual_sensors::logging::log_to_stderr()?;
+    let mut args = std::env::args().skip(1).peekable();
+    let mut failsafe = None;
+    if args.next_if(|arg| arg == "--failsafe").is_some() {
+        let seconds: f64 = args
+            .next()
+            .context("--failsafe needs a number of seconds")?
+            .parse()
+            .context("Parsing --failsafe")?;
+        failsafe = Some(Duration::try_from_secs_f64(seconds).context("Bad --failsafe")?);
+    }
+    let path = args
+        .next()
+        .unwrap_or_else(|| helper::DEFAULT_SOCKET.to_owned());
+    let octo = Octo::new()?;
+    let listener = helper::bind(&path)?;
+    let mut helper = Helper::new(octo);
+    if let Some(timeout) = failsafe {
+        helper = helper.with_failsafe(timeout, Failsafe::Disconnect);
+    }
+    Ok(helper.serve(listener)?)
+}
@@ -0,0 +1,618 @@
+//! One-shot commands
+//!
+//! Each run opens the device, does one thing and exits, for scripts and
+//! systemd units. Values set this way are held until the device's virtual
+//! sensor timeout unless something keeps sending them.
+use anyhow::{Context, Result};
+#[cfg(feature = "import")]
+use octo_virtual_sensors::{config::SourceConfig, import};
+use octo_virtual_sensors::{
+    control::TemperatureSource, curve::FanCurve, kernel, stream, units::Unit, Octo,
+};
+use std::{fs, io, path::Path, process::Command, time::Duration};
+
+/// Zero-based slot from a one-based argument
+pub fn parse_slot(slot: &str) -> Result<usize> {
+    let slot: usize = slot.parse().with_context(|| format!("Bad slot {slot:?}"))?;
+    slot.checked_sub(1).context("Slots are numbered from 1")
+}
+
+/// Centidegrees Celsius from a temperature in `unit`
+pub fn parse_temperature(temperature: &str, unit: Unit) -> Result<i16> {
+    let degrees: f64 = temperature
+        .parse()
+        .with_context(|| format!("Bad temperature {temperature:?}"))?;
+    Ok(unit.centidegrees(degrees)?)
+}
+
+/// Interval such as `2s`, `500ms` or plain seconds
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let (number, scale) = if let Some(ms) = interval.strip_suffix("ms") {
+        (ms, 0.001)
+    } else {
+        (interval.strip_suffix('s').unwrap_or(interval), 1.0)
+    };
+    let seconds = number
+        .parse::<f64>()
+        .ok()
+        .map(|number| number * scale)
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.01)
+        .with_context(|| format!("Bad interval {interval:?}"))?;
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Zero-based slot and centidegrees from `SLOT=TEMP`
+fn parse_assignment(assignment: &str, unit: Unit) -> Result<(usize, i16)> {
+    let (slot, temperature) = assignment
+        .split_once('=')
+        .with_context(|| format!("Expected SLOT=TEMP, got {assignment:?}"))?;
+    Ok((parse_slot(slot)?, parse_temperature(temperature, unit)?))
+}
+
+/// Values the device currently holds, so one-shot changes keep the rest
+fn current_values(octo: &mut Octo) -> Result<Vec<Option<i16>>> {
+    let mut values = octo.read_status()?.virtual_sensors;
+    values.resize(octo.report_layout().sensor_count, None);
+    Ok(values)
+}
+
+/// Set slots from `SLOT=TEMP` arguments, keeping the others
+pub fn set(octo: &mut Octo, assignments: &[String], unit: Unit) -> Result<()> {
+    if assignments.is_empty() {
+        anyhow::bail!("set needs at least one SLOT=TEMP");
+    }
+    let assignments = assignments
+        .iter()
+        .map(|assignment| parse_assignment(assignment, unit))
+        .collect::<Result<Vec<_>>>()?;
+    let mut values = current_values(octo)?;
+    for (slot, centidegrees) in assignments {
+        let count = values.len();
+        *values
+            .get_mut(slot)
+            .with_context(|| format!("The device has slots 1 to {count}"))? = Some(centidegrees);
+    }
+    octo.update_centidegrees(&values)?;
+    Ok(())
+}
+
+/// Disconnect the given slots, or every slot
+pub fn clear(octo: &mut Octo, slots: &[String]) -> Result<()> {
+    let slots = slots
+        .iter()
+        .map(|slot| parse_slot(slot))
+        .collect::<Result<Vec<_>>>()?;
+    let mut values = if slots.is_empty() {
+        Vec::new()
+    } else {
+        current_values(octo)?
+    };
+    for slot in slots {
+        let count = values.len();
+        *values
+            .get_mut(slot)
+            .with_context(|| format!("The device has slots 1 to {count}"))? = None;
+    }
+    octo.update_centidegrees(&values)?;
+    Ok(())
+}
+
+/// Publish hwmon channels from `[OPTION...] SLOT=SOURCE...` until killed
+///
+/// Sources are `*_input` paths or `CHIP/CHANNEL`, e.g. `1=k10temp/temp1`.
+/// Options come in any order, see [`SyncArgs`].
+#[cfg(feature = "service")]
+pub fn sync(octo: Octo, args: &[String]) -> Result<()> {
+    let args = SyncArgs::parse(args)?;
+    let engine = sync_engine(octo, &args)?;
+    run_engine(engine, args.on_stop)
+}
+
+/// Engine publishing what `args` map, as `sync` and `tui` run it
+#[cfg(feature = "service")]
+pub fn sync_engine(
+    octo: Octo,
+    args: &SyncArgs,
+) -> Result<octo_virtual_sensors::daemon::SyncEngine> {
+    use octo_virtual_sensors::{
+        config::Config,
+        daemon::SyncEngine,
+        recorder::{Format, Recorder},
+        source::HwmonSource,
+    };
+    #[cfg(feature = "prometheus")]
+    let metrics = args.metrics.as_deref().map(serve_metrics).transpose()?;
+    let recorder = match &args.record {
+        Some(path) => {
+            let format = Format::for_path(Path::new(path));
+            Some(Recorder::create(path, format)?.with_rotation(RECORD_MAX_BYTES, RECORD_KEEP))
+        }
+        None => None,
+    };
+    let mut engine = match &args.config {
+        Some(path) => Config::load(path)?.engine(octo)?,
+        None => SyncEngine::new(octo),
+    };
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics) = metrics {
+        engine = engine.with_metrics(metrics);
+    }
+    if let Some(recorder) = recorder {
+        engine = engine.with_recorder(recorder);
+    }
+    if let Some(interval) = args.interval {
+        engine = engine.with_interval(interval);
+    }
+    for (slot, spec) in &args.sources {
+        engine = engine.with_source(*slot, HwmonSource::from_spec(spec)?);
+    }
+    Ok(engine)
+}
+
+/// Arguments of `sync`
+///
+/// `--on-stop clear|freeze` picks what `SIGTERM` leaves on the sensors,
+/// `--record PATH` keeps a rotated log of what was sent and read,
+/// `--metrics ADDRESS` serves Prometheus metrics, `--interval INTERVAL`
+/// sets how often to publish and `--config PATH` takes the mapping from a
+/// file instead of `SLOT=SOURCE` arguments.
+#[cfg(feature = "service")]
+#[derive(Debug, PartialEq)]
+pub struct SyncArgs {
+    /// What stopping leaves on the sensors
+    pub on_stop: octo_virtual_sensors::Failsafe,
+    metrics: Option<String>,
+    record: Option<String>,
+    interval: Option<Duration>,
+    config: Option<String>,
+    /// `(slot, source)` pairs, slots from 0
+    sources: Vec<(usize, String)>,
+}
+
+#[cfg(feature = "service")]
+impl SyncArgs {
+    /// Parse `args`, options in any order
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = Self {
+            on_stop: octo_virtual_sensors::Failsafe::Disconnect,
+            metrics: None,
+            record: None,
+            interval: None,
+            config: None,
+            sources: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{arg} needs a value"))
+                    .cloned()
+            };
+            match arg.as_str() {
+                "--on-stop" => parsed.on_stop = parse_on_stop(&value()?)?,
+                "--metrics" if cfg!(feature = "prometheus") => parsed.metrics = Some(value()?),
+                "--metrics" => anyhow::bail!("--metrics needs the prometheus feature"),
+                "--record" => parsed.record = Some(value()?),
+                "--interval" => parsed.interval = Some(parse_interval(&value()?)?),
+                "--config" => parsed.config = Some(value()?),
+                option if option.starts_with("--") => {
+                    anyhow::bail!("Unknown option {option:?}")
+                }
+                mapping => {
+                    let (slot, spec) = mapping
+                        .split_once('=')
+                        .with_context(|| format!("Expected SLOT=SOURCE, got {mapping:?}"))?;
+                    parsed.sources.push((parse_slot(slot)?, spec.to_owned()));
+                }
+            }
+        }
+        match (&parsed.config, parsed.sources.is_empty()) {
+            (Some(_), false) => anyhow::bail!("--config can't be combined with SLOT=SOURCE"),
+            (None, true) => anyhow::bail!("Expected at least one SLOT=SOURCE, or --config"),
+            _ => Ok(parsed),
+        }
+    }
+}
+
+/// Size at which `sync --record` starts a new file
+#[cfg(feature = "service")]
+static RECORD_MAX_BYTES: u64 = 10 << 20;
+
+/// Old files `sync --record` keeps
+#[cfg(feature = "service")]
+static RECORD_KEEP: usize = 5;
+
+/// What `clear` and `freeze` leave on the sensors when the sync stops
+#[cfg(feature = "service")]
+fn parse_on_stop(on_stop: &str) -> Result<octo_virtual_sensors::Failsafe> {
+    use octo_virtual_sensors::Failsafe;
+    match on_stop {
+        "clear" => Ok(Failsafe::Disconnect),
+        "freeze" => Ok(Failsafe::DeviceTimeout),
+        _ => anyhow::bail!("--on-stop is clear or freeze, not {on_stop:?}"),
+    }
+}
+
+/// Sync until terminated, reporting to systemd when started by it
+#[cfg(feature = "service")]
+fn run_engine(
+    mut engine: octo_virtual_sensors::daemon::SyncEngine,
+    on_stop: octo_virtual_sensors::Failsafe,
+) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use octo_virtual_sensors::systemd;
+        systemd::handle_termination()?;
+        if let Some(notifier) = systemd::Notifier::from_env()? {
+            engine = engine.with_notifier(notifier);
+        }
+        engine.run_until(systemd::terminated);
+    }
+    #[cfg(not(target_os = "linux"))]
+    engine.run_until(|| false);
+    Ok(engine.shutdown(on_stop)?)
+}
+
+/// Serve metrics on `address` from a thread of their own
+#[cfg(all(feature = "service", feature = "prometheus"))]
+fn serve_metrics(address: &str) -> Result<octo_virtual_sensors::prometheus::Metrics> {
+    let listener = std::net::TcpListener::bind(address)
+        .with_context(|| format!("Binding metrics on {address}"))?;
+    let metrics = octo_virtual_sensors::prometheus::Metrics::new();
+    let server = metrics.clone();
+    std::thread::spawn(move || {
+        if let Err(error) = server.serve(listener) {
+            eprintln!("Metrics server stopped: {error:#}");
+        }
+    });
+    Ok(metrics)
+}
+
+/// Print every connected device
+pub fn list_devices() -> Result<()> {
+    let devices = Octo::list()?;
+    if devices.is_empty() {
+        anyhow::bail!("No Octo found");
+    }
+    for device in devices {
+        let serial = device.serial.as_deref().unwrap_or("unknown serial");
+        let firmware = device
+            .firmware
+            .map_or("unknown firmware".to_owned(), |firmware| {
+                format!("firmware {firmware}")
+            });
+        println!(
+            "bus {:03} address {:03}  {serial}  {firmware}",
+            device.bus, device.address
+        );
+    }
+    Ok(())
+}
+
+/// Publish `SLOT=TEMP` lines from stdin, or from `[PATH]`, until the end
+/// of input
+pub fn watch(mut octo: Octo, args: &[String], unit: Unit) -> Result<()> {
+    match args {
+        [] => Ok(stream::watch(
+            &mut octo,
+            io::BufReader::new(io::stdin()),
+            unit,
+        )?),
+        [path] => Ok(stream::watch_path(&mut octo, Path::new(path), unit)?),
+        _ => anyhow::bail!("Usage: watch [PATH]"),
+    }
+}
+
+/// Install the udev rule from [`Octo::udev_rule`] and reload udev, or
+/// print it with `--print`
+pub fn install_udev_rule(args: &[String]) -> Result<()> {
+    let rule = Octo::udev_rule();
+    match args {
+        [] => {}
+        [print] if print == "--print" => {
+            print!("{rule}");
+            return Ok(());
+        }
+        _ => anyhow::bail!("Usage: install-udev-rule [--print]"),
+    }
+    let path = kernel::UDEV_RULE_PATH;
+    match fs::write(path, rule) {
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(error).context(format!(
+                "Writing {path} needs root: run `sudo octo-vs install-udev-rule`, or \
+                 install the output of `octo-vs install-udev-rule --print`"
+            ))
+        }
+        result => result.with_context(|| format!("Writing {path}"))?,
+    }
+    println!("Installed {path}");
+    for args in [
+        &["control", "--reload-rules"][..],
+        &["trigger", "--subsystem-match=usb"],
+    ] {
+        let reloaded = Command::new("udevadm")
+            .args(args)
+            .status()
+            .is_ok_and(|status| status.success());
+        if !reloaded {
+            eprintln!("`udevadm {}` failed; replug the device", args.join(" "));
+            return Ok(());
+        }
+    }
+    println!("Reloaded udev; replug the device if it still can't be opened");
+    Ok(())
+}
+
+/// Work on configuration files
+///
+/// `migrate PATH [--print]` brings one up to the current version, keeping
+/// the old file next to it, or only prints the result.
+/// `lmsensors PATH [--output FILE]` prints or writes the lm-sensors
+/// snippet labelling the slots the file gives a `label`.
+/// `import-aquasuite PATH [PATTERN=SOURCE...]` and `import-fancontrol`
+/// print one made from an Aquasuite export or a FanControl
+/// `userConfig.json`, with the import feature. See [`import_config`].
+#[cfg(feature = "service")]
+pub fn config(args: &[String]) -> Result<()> {
+    match args {
+        [command, rest @ ..] if command == "migrate" => migrate_config(rest),
+        [command, rest @ ..] if command == "lmsensors" => lmsensors_config(rest),
+        #[cfg(feature = "import")]
+        [command, path, mappings @ ..] if command == "import-aquasuite" => {
+            import_config(octo_virtual_sensors::import::aquasuite, path, mappings)
+        }
+        #[cfg(feature = "import")]
+        [command, path, mappings @ ..] if command == "import-fancontrol" => {
+            import_config(octo_virtual_sensors::import::fancontrol, path, mappings)
+        }
+        _ => anyhow::bail!(
+            "Usage: config migrate PATH [--print]\n       \
+             config lmsensors PATH [--output FILE]\n       \
+             config import-aquasuite|import-fancontrol PATH [PATTERN=SOURCE...]"
+        ),
+    }
+}
+
+/// Print the configuration `importer` makes of the file at `path`
+///
+/// Data sources whose names contain `PATTERN` are read from the hwmon
+/// channel `SOURCE`.
+#[cfg(feature = "import")]
+fn import_config(
+    importer: fn(&str, &import::SourceMap) -> octo_virtual_sensors::Result<import::Import>,
+    path: &str,
+    mappings: &[String],
+) -> Result<()> {
+    let mut sources = import::SourceMap::default();
+    for mapping in mappings {
+        let (pattern, spec) = mapping
+            .split_once('=')
+            .with_context(|| format!("Expected PATTERN=SOURCE, got {mapping:?}"))?;
+        sources = sources.with(pattern, SourceConfig::Hwmon(spec.to_owned()));
+    }
+    let text = fs::read_to_string(path).with_context(|| format!("Reading {path}"))?;
+    let import = importer(&text, &sources).with_context(|| format!("Importing {path}"))?;
+    print!("{}", import.to_toml());
+    Ok(())
+}
+
+/// `config migrate PATH [--print]`
+#[cfg(feature = "service")]
+fn migrate_config(args: &[String]) -> Result<()> {
+    use octo_virtual_sensors::config::{self, VERSION};
+    let (path, print) = match args {
+        [path] => (path, false),
+        [path, print] if print == "--print" => (path, true),
+        _ => anyhow::bail!("Usage: config migrate PATH [--print]"),
+    };
+    let text = fs::read_to_string(path).with_context(|| format!("Reading {path}"))?;
+    let migrated = config::migrate(&text).with_context(|| format!("Migrating {path}"))?;
+    if print {
+        print!("{}", migrated.text);
+        return Ok(());
+    }
+    if migrated.from == VERSION {
+        println!("{path} is already version {VERSION}");
+        return Ok(());
+    }
+    let backup = format!("{path}.v{}", migrated.from);
+    fs::copy(path, &backup).with_context(|| format!("Copying {path} to {backup}"))?;
+    fs::write(path, migrated.text).with_context(|| format!("Writing {path}"))?;
+    println!(
+        "Migrated {path} from version {} to {VERSION}, the old file is {backup}",
+        migrated.from
+    );
+    Ok(())
+}
+
+/// `config lmsensors PATH [--output FILE]`
+#[cfg(feature = "service")]
+fn lmsensors_config(args: &[String]) -> Result<()> {
+    use octo_virtual_sensors::{config::Config, layout::OCTO, lmsensors};
+    let (path, output) = match args {
+        [path] => (path, None),
+        [path, flag, output] if flag == "--output" => (path, Some(output)),
+        _ => anyhow::bail!("Usage: config lmsensors PATH [--output FILE]"),
+    };
+    let config = Config::load(path)?;
+    let Some(output) = output else {
+        print!("{}", config.lmsensors()?);
+        return Ok(());
+    };
+    lmsensors::write_config(output, &OCTO, config.labels())?;
+    println!("Wrote {output}, `sensors` shows the labels from now on");
+    Ok(())
+}
+
+/// Show the flow calibration, or set it from `[PULSES]`
+pub fn flow_calibration(octo: &mut Octo, args: &[String]) -> Result<()> {
+    match args {
+        [] => println!("{} impulses per litre", octo.flow_calibration()?),
+        [pulses] => {
+            let pulses = pulses
+                .parse()
+                .with_context(|| format!("Bad impulses per litre {pulses:?}"))?;
+            octo.set_flow_calibration(pulses)?;
+        }
+        _ => anyhow::bail!("flow-calibration takes at most one value"),
+    }
+    Ok(())
+}
+
+/// Run every fan at full power, until the settings are changed or the
+/// device loses power
+pub fn panic(octo: &mut Octo, args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        anyhow::bail!("panic takes no arguments");
+    }
+    octo.emergency_max_fans()?;
+    println!("Fans held at 100%; power cycle the device or set fan curves again to undo");
+    Ok(())
+}
+
+/// Upload a curve from `FAN SOURCE TEMP=POWER...`
+///
+/// Fans and sources are numbered from 1, sources given as `sensorN` or
+/// `virtualN`, and powers in percent.
+pub fn fan_curve(octo: &mut Octo, args: &[String], unit: Unit) -> Result<()> {
+    let [fan, source, points @ ..] = args else {
+        anyhow::bail!("fan-curve needs FAN SOURCE TEMP=POWER...");
+    };
+    let fan = fan
+        .parse::<usize>()
+        .ok()
+        .and_then(|fan| fan.checked_sub(1))
+        .with_context(|| format!("Bad fan {fan:?}, fans are numbered from 1"))?;
+    let source = parse_source(source)?;
+    let points = points
+        .iter()
+        .map(|point| {
+            let (temperature, power) = point
+                .split_once('=')
+                .with_context(|| format!("Expected TEMP=POWER, got {point:?}"))?;
+            let power: f64 = power
+                .trim_end_matches('%')
+                .parse()
+                .with_context(|| format!("Bad power {power:?}"))?;
+            if !(0.0..=100.0).contains(&power) {
+                anyhow::bail!("Fan power {power}% is not between 0 and 100%");
+            }
+            Ok((
+                parse_temperature(temperature, unit)?,
+                (power * 100.0).round() as u16,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(octo.set_fan_curve(fan, &FanCurve::new(&points)?, source)?)
+}
+
+/// Temperature source from `sensorN` or `virtualN`, numbered from 1
+fn parse_source(source: &str) -> Result<TemperatureSource> {
+    let number = |number: &str| {
+        number
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| number.checked_sub(1))
+    };
+    let parsed = if let Some(sensor) = source.strip_prefix("sensor") {
+        number(sensor)
+            .and_then(|sensor| u16::try_from(sensor).ok())
+            .map(TemperatureSource::Sensor)
+    } else if let Some(slot) = source.strip_prefix("virtual") {
+        number(slot).map(TemperatureSource::VirtualSensor)
+    } else {
+        None
+    };
+    parsed.with_context(|| format!("Bad source {source:?}, expected sensorN or virtualN"))
+}
+
+/// Print the device's status report
+pub fn print_status(octo: &mut Octo, unit: Unit) -> Result<()> {
+    let info = octo.info()?;
+    println!(
+        "{} {}  firmware {}  {} power cycles",
+        info.name, info.serial, info.firmware, info.power_cycles
+    );
+    let status = octo.read_status()?;
+    for (index, value) in status.sensors.iter().enumerate() {
+        let value = value.map_or("-".to_owned(), |value| unit.format(value));
+        println!("sensor {}   {value}", index + 1);
+    }
+    for (slot, value) in status.virtual_sensors.iter().enumerate() {
+        let value = value.map_or("-".to_owned(), |value| unit.format(value));
+        println!("virtual {:>2}  {value}", slot + 1);
+    }
+    if let Some(flow) = status.flow_litres_per_hour() {
+        println!("flow       {flow:.1} L/h");
+    }
+    for (channel, fan) in status.fans.iter().enumerate() {
+        println!(
+            "fan {}      {} rpm  {:.2}%  {:.2} V  {} mA  {:.2} W",
+            channel + 1,
+            fan.rpm,
+            f64::from(fan.duty) / 100.0,
+            f64::from(fan.voltage) / 100.0,
+            fan.current,
+            f64::from(fan.power) / 100.0
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_assignment;
+    #[cfg(feature = "service")]
+    use super::SyncArgs;
+    use octo_virtual_sensors::units::Unit;
+    #[cfg(feature = "service")]
+    use octo_virtual_sensors::Failsafe;
+    #[cfg(feature = "service")]
+    use std::time::Duration;
+
+    /// Assignments are one-based slots and temperatures in the unit
+    #[test]
+    fn assignments() {
+        assert_eq!(
+            parse_assignment("1=42.5", Unit::Celsius).unwrap(),
+            (0, 4250)
+        );
+        assert_eq!(
+            parse_assignment("16=-4", Unit::Fahrenheit).unwrap(),
+            (15, -2000)
+        );
+        for bad in ["1", "0=40", "1=hot", "=40", "1=40=2"] {
+            assert!(parse_assignment(bad, Unit::Celsius).is_err(), "{bad}");
+        }
+    }
+
+    /// Sync options parse in any order, around the mappings
+    #[cfg(feature = "service")]
+    #[test]
+    fn sync_args() {
+        let args = |args: &str| {
+            let args: Vec<String> = args.split_whitespace().map(str::to_owned).collect();
+            SyncArgs::parse(&args)
+        };
+        let parsed = args("--record /tmp/x.csv --on-stop freeze 1=k10temp/temp1").unwrap();
+        assert_eq!(parsed.record.as_deref(), Some("/tmp/x.csv"));
+        assert_eq!(parsed.on_stop, Failsafe::DeviceTimeout);
+        assert_eq!(parsed.sources, [(0, "k10temp/temp1".to_owned())]);
+        let reversed = args("--on-stop freeze --record /tmp/x.csv 1=k10temp/temp1").unwrap();
+        assert_eq!(reversed, parsed);
+        let parsed = args("2=a/temp1 --interval 500ms --config x.toml");
+        assert!(parsed.is_err());
+        let parsed = args("--interval 500ms --config x.toml").unwrap();
+        assert_eq!(parsed.interval, Some(Duration::from_millis(500)));
+        assert_eq!(parsed.config.as_deref(), Some("x.toml"));
+        for bad in [
+            "",
+            "--record",
+            "--on-stop never 1=a/temp1",
+            "--loud 1=a/temp1",
+            "x",
+        ] {
+            assert!(args(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Command line tool for the Octo's virtual sensors
+//!
+//! Usage: `octo-vs [--units UNIT] [--serial SERIAL] [--dry-run] <COMMAND>`
+mod commands;
+mod repl;
+#[cfg(feature = "tui")]
+mod tui;
+
+use anyhow::Context;
+use octo_virtual_sensors::{units::Unit, Backend, Octo};
+use std::time::Duration;
+
+static USAGE: &str = "Usage: octo-vs [--units UNIT] [--serial SERIAL] [--dry-run] <COMMAND>
+
+Commands:
+  set SLOT=TEMP...  Publish temperatures, e.g. set 1=42.5 2=38
+  clear [SLOT...]   Disconnect the given slots, or every slot
+  status            Show what the device reports
+  list-devices      Show every connected Octo and its serial number
+  flow-calibration [PULSES]
+                    Show or set the flow sensor's impulses per litre
+  fan-curve FAN SOURCE TEMP=POWER...
+                    Have the device drive a fan from a sensor, e.g.
+                    fan-curve 1 virtual1 30=20 40=60 50=100
+  panic             Run every fan at 100% whatever its curve follows,
+                    until the device loses power
+  sync [--interval INTERVAL] SLOT=SOURCE...
+                    Keep publishing hwmon channels, given as *_input paths
+                    or CHIP/CHANNEL, e.g. sync 1=k10temp/temp1
+  sync --config PATH
+                    Keep publishing what a configuration file maps
+  config migrate PATH [--print]
+                    Update a configuration file from an older release,
+                    keeping the old one as PATH.vN, or only print it
+  config lmsensors PATH [--output FILE]
+                    Print the lm-sensors snippet naming the slots after
+                    the file's labels, or write it to FILE, e.g.
+                    /etc/sensors.d/octo-vs.conf
+  config import-aquasuite|import-fancontrol PATH [PATTERN=SOURCE...]
+                    Print a configuration file made from an Aquasuite
+                    export or a FanControl userConfig.json, reading data
+                    sources named like PATTERN from hwmon channel SOURCE,
+                    with the import feature
+  sync --metrics ADDRESS ...
+                    Also serve Prometheus metrics on ADDRESS, e.g.
+                    127.0.0.1:9528, with the prometheus feature
+  sync --on-stop clear|freeze ...
+                    On SIGTERM clear the sensors (default) or leave the
+                    last values until the device's timeout
+  sync --record PATH ...
+                    Also record sent values and readings to PATH, as CSV
+                    or as JSON lines for .jsonl, rotated at 10 MiB
+  repl              Interactive session keeping the device open
+  tui [--interval INTERVAL] SLOT=SOURCE...|--config PATH
+                    Sync as sync does, with a dashboard of the slots,
+                    their sources, the device's readings and warnings,
+                    with the tui feature
+  watch [PATH]      Publish SLOT=TEMP lines as they arrive on stdin, or
+                    from PATH, a file or named pipe
+  install-udev-rule [--print]
+                    Let the logged-in user open the device without root,
+                    or only print the rule
+
+Values set with set are held until the device's virtual sensor timeout,
+unless something keeps sending them.
+
+Options:
+  --units UNIT      Temperatures in celsius, fahrenheit or kelvin,
+                    default from OCTO_VS_UNITS or celsius
+  --serial SERIAL   Open the Octo with this serial number
+  --dry-run         Print the reports that would be sent instead of
+                    opening the device";
+
+/// First and longest wait between attempts to reopen an unplugged device
+static RECONNECT_BACKOFF: (Duration, Duration) = (Duration::from_secs(1), Duration::from_secs(60));
+
+/// Environment variable holding the default unit
+static UNITS_ENV: &str = "OCTO_VS_UNITS";
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut unit = match std::env::var(UNITS_ENV) {
+        Ok(unit) => unit
+            .parse()
+            .with_context(|| format!("Parsing {UNITS_ENV}"))?,
+        Err(_) => Unit::default(),
+    };
+    let mut serial = None;
+    let mut backend = Backend::default();
+    while let Some(option) = args.next_if(|arg| arg.starts_with("--") && arg != "--help") {
+        match option.as_str() {
+            "--units" => unit = args.next().context("--units needs a unit")?.parse()?,
+            "--serial" => serial = Some(args.next().context("--serial needs a serial number")?),
+            "--dry-run" => backend = Backend::DryRun,
+            _ => anyhow::bail!("Unknown option {option:?}\n\n{USAGE}"),
+        }
+    }
+    let builder = || match &serial {
+        Some(serial) => Octo::builder().serial(serial).backend(backend),
+        None => Octo::builder().backend(backend),
+    };
+    // hwmon can't read the device, so commands that do take USB unless
+    // asked otherwise
+    let open = || match backend {
+        Backend::Auto => builder().backend(Backend::Usb).open(),
+        _ => builder().open(),
+    };
+    // Commands that keep running follow the device through a replug
+    let open_long_running = || {
+        let (initial, max) = RECONNECT_BACKOFF;
+        builder().reconnect(initial, max).open()
+    };
+    let command = args.next();
+    let rest: Vec<String> = args.collect();
+    // The dashboard owns the terminal, so it shows warnings itself
+    #[cfg(feature = "tui")]
+    if command.as_deref() == Some("tui") {
+        tui::log_to_dashboard()?;
+    } else {
+        octo_virtual_sensors::logging::log_to_stderr_at(log_level(backend))?;
+    }
+    #[cfg(not(feature = "tui"))]
+    octo_virtual_sensors::logging::log_to_stderr_at(log_level(backend))?;
+    match command.as_deref() {
+        Some("set") => commands::set(&mut open()?, &rest, unit),
+        Some("clear") => commands::clear(&mut open()?, &rest),
+        Some("status") => commands::print_status(&mut open()?, unit),
+        Some("list-devices") => commands::list_devices(),
+        Some("install-udev-rule") => commands::install_udev_rule(&rest),
+        Some("flow-calibration") => commands::flow_calibration(&mut open()?, &rest),
+        Some("fan-curve") => commands::fan_curve(&mut open()?, &rest, unit),
+        Some("panic") => commands::panic(&mut open()?, &rest),
+        #[cfg(feature = "service")]
+        Some("sync") => commands::sync(open_long_running()?, &rest),
+        #[cfg(feature = "service")]
+        Some("config") => commands::config(&rest),
+        Some("repl") => repl::run(open_long_running()?, unit),
+        #[cfg(feature = "tui")]
+        Some("tui") => tui::run(open_long_running()?, &rest, unit),
+        Some("watch") => commands::watch(open_long_running()?, &rest, unit),
+        Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        Some(command) => anyhow::bail!("Unknown command {command:?}\n\n{USAGE}"),
+        None => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Level of the crate's own records printed on stderr: warnings, each
+/// report with `--dry-run`, and the `trace` feature's events when asked
+/// for with `OCTO_VS_TRACE`
+fn log_level(backend: Backend) -> log::LevelFilter {
+    #[cfg(feature = "trace")]
+    if let Some(level) = octo_virtual_sensors::trace::env_level() {
+        return level;
+    }
+    match backend {
+        Backend::DryRun => log::LevelFilter::Info,
+        _ => log::LevelFilter::Warn,
+    }
+}
@@ -0,0 +1,315 @@
+//! Interactive session
+//!
+//! Keeps the device open and the set values alive between commands, which
+//! makes poking at the device by hand much quicker than one process per
+//! change. Slots are numbered from 1, like the hwmon labels.
+//! Temperatures are in the session's unit, Celsius unless changed.
+use crate::commands::{self, parse_interval, parse_slot, parse_temperature};
+use anyhow::{Context, Result};
+use octo_virtual_sensors::{control::TemperatureSource, units::Unit, Octo};
+use std::{
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often set values are re-sent so the firmware doesn't time them out
+static KEEPALIVE: Duration = Duration::from_secs(1);
+
+static HELP: &str = "Commands:
+  set SLOT TEMP     Publish TEMP on SLOT, e.g. set 3 41.5
+  clear [SLOT]      Disconnect SLOT, or every slot
+  status            Show what the device reports
+  preview FAN TEMP  Show the duty FAN would run at with its source at TEMP
+  units [UNIT]      Show or change the unit, celsius, fahrenheit
+                    or kelvin
+  watch INTERVAL    Show the status every INTERVAL (1s, 500ms) until Enter
+  help              Show this help
+  quit              Leave, values time out on the device shortly after";
+
+/// One line of input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Set { slot: usize, centidegrees: i16 },
+    Clear(Option<usize>),
+    Status,
+    Preview { channel: usize, centidegrees: i16 },
+    Units(Option<Unit>),
+    Watch(Duration),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parse a line, reading temperatures in `unit`
+    fn parse(line: &str, unit: Unit) -> Result<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["set", slot, temperature] => Ok(Self::Set {
+                slot: parse_slot(slot)?,
+                centidegrees: parse_temperature(temperature, unit)?,
+            }),
+            ["clear"] => Ok(Self::Clear(None)),
+            ["clear", slot] => Ok(Self::Clear(Some(parse_slot(slot)?))),
+            ["status"] => Ok(Self::Status),
+            ["preview", channel, temperature] => Ok(Self::Preview {
+                channel: parse_slot(channel)?,
+                centidegrees: parse_temperature(temperature, unit)?,
+            }),
+            ["units"] => Ok(Self::Units(None)),
+            ["units", unit] => Ok(Self::Units(Some(unit.parse()?))),
+            ["watch", interval] => Ok(Self::Watch(parse_interval(interval)?)),
+            ["help" | "?"] => Ok(Self::Help),
+            ["quit" | "exit"] => Ok(Self::Quit),
+            _ => anyhow::bail!("Unknown command {line:?}, try help"),
+        }
+    }
+}
+
+/// Device and the values set so far
+struct Session {
+    octo: Octo,
+    values: Vec<Option<i16>>,
+    sent: Instant,
+    unit: Unit,
+}
+
+impl Session {
+    /// Run one command, returning false to quit
+    fn execute(&mut self, command: Command, lines: &Receiver<String>) -> Result<bool> {
+        match command {
+            Command::Set { slot, centidegrees } => {
+                *self.slot(slot)? = Some(centidegrees);
+                self.send()?;
+            }
+            Command::Clear(Some(slot)) => {
+                *self.slot(slot)? = None;
+                self.send()?;
+            }
+            Command::Clear(None) => {
+                self.values.fill(None);
+                self.send()?;
+            }
+            Command::Status => self.print_status()?,
+            Command::Preview {
+                channel,
+                centidegrees,
+            } => self.preview(channel, centidegrees)?,
+            Command::Units(Some(unit)) => self.unit = unit,
+            Command::Units(None) => println!("{}", self.unit),
+            Command::Watch(interval) => self.watch(interval, lines)?,
+            Command::Help => println!("{HELP}"),
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Value of a zero-based slot
+    fn slot(&mut self, slot: usize) -> Result<&mut Option<i16>> {
+        let count = self.values.len();
+        self.values
+            .get_mut(slot)
+            .with_context(|| format!("The device has slots 1 to {count}"))
+    }
+
+    /// Send the current values
+    fn send(&mut self) -> Result<()> {
+        self.sent = Instant::now();
+        self.octo.update_centidegrees(&self.values)?;
+        Ok(())
+    }
+
+    /// Re-send the values if any are set and they're due
+    fn keep_alive(&mut self) -> Result<()> {
+        if self.values.iter().any(Option::is_some) && self.sent.elapsed() >= KEEPALIVE {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    /// Print the device's status report
+    fn print_status(&mut self) -> Result<()> {
+        commands::print_status(&mut self.octo, self.unit)
+    }
+
+    /// Print the duty cycle a fan would run at, without sending anything
+    fn preview(&mut self, channel: usize, centidegrees: i16) -> Result<()> {
+        let fan = self.octo.read_control()?.fan(channel)?;
+        let source = match fan.source {
+            TemperatureSource::Sensor(index) => format!("sensor {}", index + 1),
+            TemperatureSource::VirtualSensor(slot) => format!("virtual {}", slot + 1),
+            TemperatureSource::Other(index) => format!("source {index}"),
+        };
+        let temperature = self.unit.format(centidegrees);
+        match fan.duty_at(centidegrees) {
+            Some(duty) => println!(
+                "fan {} follows {source}, at {temperature} it would run at {:.2}%",
+                channel + 1,
+                f64::from(duty) / 100.0
+            ),
+            None => println!(
+                "fan {} is in {:?} mode, its duty doesn't follow one temperature",
+                channel + 1,
+                fan.mode
+            ),
+        }
+        Ok(())
+    }
+
+    /// Print the status every `interval` until a line is entered
+    fn watch(&mut self, interval: Duration, lines: &Receiver<String>) -> Result<()> {
+        loop {
+            self.print_status()?;
+            println!();
+            let next = Instant::now() + interval;
+            while let Some(remaining) = next.checked_duration_since(Instant::now()) {
+                match lines.recv_timeout(remaining.min(KEEPALIVE)) {
+                    Ok(_) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                    Err(RecvTimeoutError::Timeout) => self.keep_alive()?,
+                }
+            }
+        }
+    }
+}
+
+/// Lines from stdin, read on their own thread so values can be kept alive
+fn stdin_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Print the prompt
+fn prompt() {
+    print!("octo> ");
+    let _ = io::stdout().flush();
+}
+
+/// Run the session until `quit` or the end of input
+pub fn run(octo: Octo, unit: Unit) -> Result<()> {
+    let slots = octo.report_layout().sensor_count;
+    let mut session = Session {
+        octo,
+        values: vec![None; slots],
+        sent: Instant::now(),
+        unit,
+    };
+    let lines = stdin_lines();
+    println!("{HELP}");
+    prompt();
+    loop {
+        let line = match lines.recv_timeout(KEEPALIVE) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Err(error) = session.keep_alive() {
+                    eprintln!("\nerror: {error:#}");
+                    prompt();
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        if !line.trim().is_empty() {
+            match Command::parse(&line, session.unit)
+                .and_then(|command| session.execute(command, &lines))
+            {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(error) => eprintln!("error: {error:#}"),
+            }
+        }
+        prompt();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Command;
+    use octo_virtual_sensors::units::Unit;
+    use std::time::Duration;
+
+    /// Parse a Celsius command, panicking on bad test input
+    fn parse(line: &str) -> Command {
+        Command::parse(line, Unit::Celsius).unwrap()
+    }
+
+    /// Commands and their arguments parse, slots from 1
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            parse("set 3 41.5"),
+            Command::Set {
+                slot: 2,
+                centidegrees: 4150
+            }
+        );
+        assert_eq!(
+            parse("  set 1   -0.255 "),
+            Command::Set {
+                slot: 0,
+                centidegrees: -26
+            }
+        );
+        assert_eq!(parse("clear"), Command::Clear(None));
+        assert_eq!(parse("clear 16"), Command::Clear(Some(15)));
+        assert_eq!(parse("status"), Command::Status);
+        assert_eq!(
+            parse("preview 2 45"),
+            Command::Preview {
+                channel: 1,
+                centidegrees: 4500
+            }
+        );
+        assert_eq!(parse("watch 1s"), Command::Watch(Duration::from_secs(1)));
+        assert_eq!(
+            parse("watch 250ms"),
+            Command::Watch(Duration::from_millis(250))
+        );
+        assert_eq!(parse("watch 2"), Command::Watch(Duration::from_secs(2)));
+        assert_eq!(parse("units"), Command::Units(None));
+        assert_eq!(parse("units f"), Command::Units(Some(Unit::Fahrenheit)));
+        assert_eq!(parse("exit"), Command::Quit);
+    }
+
+    /// Temperatures are read in the session's unit
+    #[test]
+    fn parse_fahrenheit() {
+        let command = Command::parse("set 1 104", Unit::Fahrenheit).unwrap();
+        assert_eq!(
+            command,
+            Command::Set {
+                slot: 0,
+                centidegrees: 4000
+            }
+        );
+        assert!(Command::parse("set 1 400", Unit::Fahrenheit).is_ok());
+    }
+
+    /// Bad input is an error, not a panic
+    #[test]
+    fn reject_bad_input() {
+        for line in [
+            "set 0 40",
+            "set 3",
+            "set 3 hot",
+            "set 3 400",
+            "set 3 NaN",
+            "watch 0s",
+            "watch soon",
+            "clear x",
+            "preview 0 40",
+            "units rankine",
+            "frobnicate",
+        ] {
+            assert!(Command::parse(line, Unit::Celsius).is_err(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,574 @@
+//! Terminal dashboard
+//!
+//! `octo-vs tui` runs the same sync as `octo-vs sync` and shows it live:
+//! what each slot publishes, how its source is doing, graphs of what the
+//! device reports and the latest warnings. Keys switch slots off or set
+//! them by hand on top of their sources; quitting leaves the sensors as
+//! `--on-stop` says, like stopping a sync.
+//!
+//! Slots are numbered from 1, like the hwmon labels, and temperatures are
+//! in the `--units` unit.
+use crate::commands::{self, SyncArgs};
+use anyhow::Result;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use octo_virtual_sensors::{
+    codec, daemon::SyncEngine, source::SourceHealth, status::Status, units::Unit, Octo,
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, List, Paragraph, Row, Sparkline, Table, TableState},
+    DefaultTerminal, Frame,
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Points kept for each telemetry graph
+static HISTORY: usize = 120;
+
+/// Warnings kept for the log pane
+static WARNINGS: usize = 100;
+
+/// Change of a hand-set value per `+` or `-`, in the dashboard's unit
+static STEP: f64 = 0.5;
+
+static KEYS: &str = "↑↓ slot  space on/off  +/- set by hand  r back to source  q quit";
+
+/// Warnings logged since the dashboard started, oldest first
+static LOGGED: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Logger keeping warnings for the dashboard instead of printing them
+struct DashboardLogger;
+
+impl Log for DashboardLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            let mut logged = LOGGED.lock().unwrap_or_else(|e| e.into_inner());
+            if logged.len() == WARNINGS {
+                logged.pop_front();
+            }
+            logged.push_back(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Keep the crate's warnings for the dashboard, which owns the terminal
+///
+/// Takes the place of
+/// [`log_to_stderr`](octo_virtual_sensors::logging::log_to_stderr).
+pub fn log_to_dashboard() -> Result<(), SetLoggerError> {
+    static LOGGER: DashboardLogger = DashboardLogger;
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(LevelFilter::Warn);
+    Ok(())
+}
+
+/// A slot set by hand, in place of its source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Override {
+    /// Disconnected
+    Off,
+    /// Fixed at centidegrees
+    Value(i16),
+}
+
+/// Slots set by hand, shared with the engine's last transform
+type Overrides = Arc<Mutex<Vec<Option<Override>>>>;
+
+/// `values` with the overridden slots replaced
+fn apply(overrides: &[Option<Override>], values: &[Option<i16>]) -> Vec<Option<i16>> {
+    let mut values = values.to_vec();
+    for (slot, value) in overrides.iter().enumerate() {
+        let Some(value) = value else {
+            continue;
+        };
+        if values.len() <= slot {
+            values.resize(slot + 1, None);
+        }
+        values[slot] = match value {
+            Override::Off => None,
+            Override::Value(centidegrees) => Some(*centidegrees),
+        };
+    }
+    values
+}
+
+/// One telemetry graph
+#[derive(Debug, Clone, PartialEq)]
+struct Series {
+    label: String,
+    /// Symbol after the current value
+    unit: &'static str,
+    /// Readings in the displayed unit, oldest first
+    points: VecDeque<Option<f64>>,
+}
+
+impl Series {
+    /// Bars for a sparkline, the lowest point in view at the bottom
+    fn bars(&self) -> Vec<Option<u64>> {
+        let lowest = self
+            .points
+            .iter()
+            .flatten()
+            .copied()
+            .fold(f64::MAX, f64::min);
+        self.points
+            .iter()
+            .map(|point| point.map(|point| ((point - lowest) * 100.0).round() as u64 + 1))
+            .collect()
+    }
+
+    /// Title with the latest reading
+    fn title(&self) -> String {
+        match self.points.back().copied().flatten() {
+            Some(point) => format!("{} {point:.1} {}", self.label, self.unit),
+            None => format!("{} –", self.label),
+        }
+    }
+}
+
+/// History of what the device reports
+#[derive(Debug, Default)]
+struct Telemetry {
+    series: Vec<Series>,
+    /// Why the last status read failed
+    error: Option<String>,
+}
+
+impl Telemetry {
+    /// Add a point to every graph from `status`
+    ///
+    /// Graphs start with the first reading they have, so physical sensors
+    /// that aren't plugged in and fans that never turned stay out of view.
+    fn record(&mut self, status: &Status, unit: Unit) {
+        self.error = None;
+        let mut readings = Vec::new();
+        for (index, sensor) in status.sensors.iter().enumerate() {
+            let reading = sensor.map(|value| unit.degrees(value));
+            readings.push((format!("Sensor {}", index + 1), unit.symbol(), reading));
+        }
+        let flow = status.flow_litres_per_hour().map(f64::from);
+        readings.push(("Flow".to_owned(), "l/h", flow));
+        for (index, fan) in status.fans.iter().enumerate() {
+            let rpm = Some(f64::from(fan.rpm)).filter(|&rpm| rpm > 0.0);
+            readings.push((format!("Fan {}", index + 1), "rpm", rpm));
+        }
+        for (label, unit, reading) in readings {
+            self.push(label, unit, reading);
+        }
+    }
+
+    /// Add `reading` to the graph called `label`
+    fn push(&mut self, label: String, unit: &'static str, reading: Option<f64>) {
+        let series = match self
+            .series
+            .iter_mut()
+            .position(|series| series.label == label)
+        {
+            Some(index) => &mut self.series[index],
+            None if reading.is_some() => {
+                self.series.push(Series {
+                    label,
+                    unit,
+                    points: VecDeque::new(),
+                });
+                self.series.last_mut().expect("just pushed")
+            }
+            None => return,
+        };
+        if series.points.len() == HISTORY {
+            series.points.pop_front();
+        }
+        series.points.push_back(reading);
+    }
+}
+
+/// Everything on screen
+struct Dashboard {
+    /// Model, serial number and firmware
+    device: String,
+    unit: Unit,
+    interval: Duration,
+    slots: TableState,
+    slot_count: usize,
+    overrides: Overrides,
+    sources: Vec<SourceHealth>,
+    /// Values last published
+    sent: Vec<Option<i16>>,
+    /// Whether the last tick was skipped while the device is away
+    backing_off: bool,
+    telemetry: Telemetry,
+}
+
+impl Dashboard {
+    /// Dashboard for the device `octo`, sources not read yet
+    fn new(octo: &Octo, unit: Unit, interval: Duration, overrides: Overrides) -> Self {
+        let mut device = octo.device().name.to_owned();
+        if let Some(serial) = octo.serial() {
+            device.push_str(&format!(" {serial}"));
+        }
+        if let Some(firmware) = octo.firmware() {
+            device.push_str(&format!(", firmware {firmware}"));
+        }
+        Self {
+            device,
+            unit,
+            interval,
+            slots: TableState::default().with_selected(0),
+            slot_count: octo.report_layout().sensor_count,
+            overrides,
+            sources: Vec::new(),
+            sent: Vec::new(),
+            backing_off: false,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    fn overrides(&self) -> MutexGuard<'_, Vec<Option<Override>>> {
+        self.overrides.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sync once, then read what the device reports
+    fn tick(&mut self, engine: &mut SyncEngine) {
+        match engine.tick() {
+            Ok(Some(values)) => {
+                self.sent = values;
+                self.backing_off = false;
+            }
+            Ok(None) => self.backing_off = true,
+            Err(error) => log::warn!("{error:#}"),
+        }
+        self.sources = engine.sources();
+        match engine.octo().read_status() {
+            Ok(status) => self.telemetry.record(&status, self.unit),
+            Err(error) => self.telemetry.error = Some(format!("{error:#}")),
+        }
+    }
+
+    /// Act on `key`, returning false to quit
+    fn key(&mut self, key: KeyEvent) -> bool {
+        let selected = self.slots.selected().unwrap_or_default();
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.slots.select(Some(selected.saturating_sub(1)));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                let last = self.slot_count.saturating_sub(1);
+                self.slots.select(Some((selected + 1).min(last)));
+            }
+            KeyCode::Char(' ') => {
+                let off = self.overridden(selected) == Some(Override::Off);
+                self.set_override(selected, (!off).then_some(Override::Off));
+            }
+            KeyCode::Char('+' | '=') => self.nudge(selected, STEP),
+            KeyCode::Char('-') => self.nudge(selected, -STEP),
+            KeyCode::Char('r') => self.set_override(selected, None),
+            _ => {}
+        }
+        true
+    }
+
+    /// What `slot` is set to by hand, if anything
+    fn overridden(&self, slot: usize) -> Option<Override> {
+        self.overrides().get(slot).copied().flatten()
+    }
+
+    fn set_override(&mut self, slot: usize, value: Option<Override>) {
+        let mut overrides = self.overrides();
+        if overrides.len() <= slot {
+            overrides.resize(slot + 1, None);
+        }
+        overrides[slot] = value;
+    }
+
+    /// Set `slot` by hand to `degrees` more than it publishes now
+    fn nudge(&mut self, slot: usize, degrees: f64) {
+        let current = match self.overridden(slot) {
+            Some(Override::Value(value)) => Some(value),
+            _ => self.sent.get(slot).copied().flatten(),
+        };
+        let Ok(step) = self.unit.delta_centidegrees(degrees) else {
+            return;
+        };
+        let value = current
+            .unwrap_or_default()
+            .saturating_add(step)
+            .min(codec::MAX_CENTIDEGREES);
+        self.set_override(slot, Some(Override::Value(value)));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, middle, warnings, keys] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [slots, telemetry] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(middle);
+        let mut status = format!("{}, every {:?}", self.device, self.interval);
+        if self.backing_off {
+            status.push_str(", device away, backing off");
+        }
+        frame.render_widget(Paragraph::new(status).style(Style::new().bold()), header);
+        self.draw_slots(frame, slots);
+        self.draw_telemetry(frame, telemetry);
+        let logged = LOGGED.lock().unwrap_or_else(|e| e.into_inner());
+        let shown = usize::from(warnings.height.saturating_sub(2));
+        let lines = logged.iter().skip(logged.len().saturating_sub(shown));
+        let list = List::new(lines.map(String::as_str)).block(Block::bordered().title("Warnings"));
+        frame.render_widget(list, warnings);
+        frame.render_widget(Paragraph::new(KEYS).style(Style::new().dim()), keys);
+    }
+
+    fn draw_slots(&mut self, frame: &mut Frame, area: Rect) {
+        let overrides = self.overrides().clone();
+        let degrees = |value: Option<i16>| match value {
+            Some(value) => format!("{:.2}", self.unit.degrees(value)),
+            None => "–".to_owned(),
+        };
+        let rows: Vec<Row> = (0..self.slot_count)
+            .map(|slot| {
+                let source = self.sources.iter().rev().find(|source| source.slot == slot);
+                let state = match (overrides.get(slot).copied().flatten(), source) {
+                    (Some(Override::Off), _) => "off".to_owned(),
+                    (Some(Override::Value(_)), _) => "by hand".to_owned(),
+                    (
+                        None,
+                        Some(SourceHealth {
+                            error: Some(_),
+                            failures,
+                            ..
+                        }),
+                    ) => format!("failing ×{failures}"),
+                    (None, Some(_)) => "ok".to_owned(),
+                    (None, None) => String::new(),
+                };
+                let style = match source {
+                    Some(source) if source.error.is_some() => Style::new().fg(Color::Red),
+                    _ => Style::new(),
+                };
+                Row::new([
+                    (slot + 1).to_string(),
+                    source.map_or_else(String::new, |source| source.name.clone()),
+                    source.map_or_else(String::new, |source| degrees(source.value)),
+                    degrees(self.sent.get(slot).copied().flatten()),
+                    state,
+                ])
+                .style(style)
+            })
+            .collect();
+        let unit = self.unit.symbol();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(4),
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(11),
+            ],
+        )
+        .header(
+            Row::new([
+                "Slot".to_owned(),
+                "Source".to_owned(),
+                format!("Read {unit}"),
+                format!("Sent {unit}"),
+                "State".to_owned(),
+            ])
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+        )
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered().title("Virtual sensors"));
+        frame.render_stateful_widget(table, area, &mut self.slots);
+    }
+
+    fn draw_telemetry(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title("Device");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        if let Some(error) = &self.telemetry.error {
+            let message = format!("Status unavailable: {error}");
+            frame.render_widget(Paragraph::new(message), inner);
+            return;
+        }
+        let room = usize::from(inner.height / 2);
+        let shown: Vec<&Series> = self.telemetry.series.iter().take(room).collect();
+        let rows = Layout::vertical(vec![Constraint::Length(2); shown.len()]).split(inner);
+        for (series, row) in shown.into_iter().zip(rows.iter()) {
+            let [title, graph] =
+                Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(*row);
+            frame.render_widget(Line::from(series.title()), title);
+            // Newest on the right, as many as fit
+            let bars = series.bars();
+            let skip = bars.len().saturating_sub(usize::from(graph.width));
+            let sparkline = Sparkline::default()
+                .data(bars.into_iter().skip(skip))
+                .style(Style::new().fg(Color::Cyan));
+            frame.render_widget(sparkline, graph);
+        }
+    }
+
+    /// Draw and handle keys, syncing every interval, until quit
+    fn run(&mut self, terminal: &mut DefaultTerminal, engine: &mut SyncEngine) -> Result<()> {
+        let mut next = Instant::now();
+        loop {
+            #[cfg(target_os = "linux")]
+            if octo_virtual_sensors::systemd::terminated() {
+                return Ok(());
+            }
+            if Instant::now() >= next {
+                next = Instant::now() + self.interval;
+                self.tick(engine);
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+            if !event::poll(next.saturating_duration_since(Instant::now()))? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Sync what `args` map, as `sync` does, with the dashboard on screen
+pub fn run(octo: Octo, args: &[String], unit: Unit) -> Result<()> {
+    let args = SyncArgs::parse(args)?;
+    let overrides = Overrides::default();
+    let shared = overrides.clone();
+    let mut engine = commands::sync_engine(octo, &args)?.with_transform(move |values| {
+        apply(&shared.lock().unwrap_or_else(|e| e.into_inner()), values)
+    });
+    #[cfg(target_os = "linux")]
+    octo_virtual_sensors::systemd::handle_termination()?;
+    let interval = engine.interval();
+    let mut dashboard = Dashboard::new(engine.octo(), unit, interval, overrides);
+    ratatui::run(|terminal| dashboard.run(terminal, &mut engine))?;
+    Ok(engine.shutdown(args.on_stop)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, Dashboard, Override, Overrides, Series, Telemetry, HISTORY};
+    use octo_virtual_sensors::{
+        daemon::SyncEngine,
+        dryrun::DryRun,
+        layout::OCTO,
+        status::{FanStatus, Status},
+        units::Unit,
+        Octo,
+    };
+    use ratatui::{
+        backend::TestBackend,
+        crossterm::event::{KeyCode, KeyEvent},
+        Terminal,
+    };
+    use std::time::Duration;
+
+    /// Slots set by hand replace what their sources publish
+    #[test]
+    fn overrides() {
+        let values = [Some(3000), Some(4000)];
+        let overrides = [None, Some(Override::Off), None, Some(Override::Value(2500))];
+        assert_eq!(
+            apply(&overrides, &values),
+            [Some(3000), None, None, Some(2500)]
+        );
+        assert_eq!(apply(&[], &values), values);
+    }
+
+    /// Graphs start with their first reading and keep a bounded history
+    #[test]
+    fn telemetry() {
+        let mut fans = vec![FanStatus::default(); 8];
+        fans[7].rpm = 2100;
+        let status = Status {
+            power_cycles: 1,
+            sensors: vec![Some(2500), None, None, None],
+            virtual_sensors: vec![None; 16],
+            flow: None,
+            fans,
+        };
+        let mut telemetry = Telemetry::default();
+        for _ in 0..HISTORY + 5 {
+            telemetry.record(&status, Unit::Celsius);
+        }
+        let labels: Vec<&str> = telemetry
+            .series
+            .iter()
+            .map(|series| series.label.as_str())
+            .collect();
+        assert_eq!(labels, ["Sensor 1", "Fan 8"]);
+        assert_eq!(telemetry.series[0].points.len(), HISTORY);
+        assert_eq!(telemetry.series[1].title(), "Fan 8 2100.0 rpm");
+        let series = Series {
+            label: "Flow".to_owned(),
+            unit: "l/h",
+            points: [Some(120.0), None, Some(121.5)].into(),
+        };
+        assert_eq!(series.bars(), [Some(1), None, Some(151)]);
+    }
+
+    /// Keys pick slots and set them by hand, which the next tick sends
+    #[test]
+    fn dashboard() {
+        let dry_run = DryRun::new(OCTO).with_log(false);
+        let octo = Octo::with_transport(dry_run.clone()).unwrap();
+        let overrides = Overrides::default();
+        let shared = overrides.clone();
+        let mut engine = SyncEngine::new(octo)
+            .with_transform(move |values| apply(&shared.lock().unwrap(), values));
+        let interval = Duration::from_secs(1);
+        let mut dashboard = Dashboard::new(engine.octo(), Unit::Celsius, interval, overrides);
+        let press = |code| KeyEvent::from(code);
+        assert!(dashboard.key(press(KeyCode::Down)));
+        for _ in 0..3 {
+            dashboard.key(press(KeyCode::Char('+')));
+        }
+        dashboard.key(press(KeyCode::Char('-')));
+        dashboard.key(press(KeyCode::Down));
+        dashboard.key(press(KeyCode::Char(' ')));
+        dashboard.tick(&mut engine);
+        assert_eq!(dashboard.sent[..3], [None, Some(100), None]);
+        assert_eq!(dry_run.last().unwrap().sensors[1], Some(100));
+        assert_eq!(dashboard.telemetry.error, None);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Virtual sensors"), "{screen}");
+        assert!(screen.contains("by hand"), "{screen}");
+        assert!(screen.contains("off"), "{screen}");
+
+        dashboard.key(press(KeyCode::Up));
+        dashboard.key(press(KeyCode::Char('r')));
+        dashboard.tick(&mut engine);
+        assert_eq!(dashboard.sent.get(1).copied().flatten(), None);
+        assert!(!dashboard.key(press(KeyCode::Char('q'))));
+    }
+}
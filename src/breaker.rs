@@ -0,0 +1,180 @@
+//! Backing off from a device that keeps failing
+//!
+//! While the controller is unplugged for maintenance every send fails.
+//! Retrying at the normal update rate floods the log and keeps the USB
+//! stack busy for nothing. A [`CircuitBreaker`] stops calling through
+//! after a run of failures and only probes now and then until the device
+//! answers again.
+use crate::error::Result;
+use std::time::{Duration, Instant};
+
+/// Health changes reported by a [`CircuitBreaker`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakerEvent {
+    /// Too many failures in a row, calls are now only probes
+    Opened { failures: u32, error: String },
+    /// A call succeeded, calls go through again
+    Closed { failures: u32 },
+}
+
+/// Stops calling through after consecutive failures
+///
+/// ```no_run
+/// use octo_virtual_sensors::{breaker::CircuitBreaker, Octo};
+/// let mut octo = Octo::new().unwrap();
+/// let mut breaker = CircuitBreaker::new(5);
+/// loop {
+///     if let Err(error) = breaker.call(|| octo.update_virtual_sensors(&[42])) {
+///         eprintln!("{error:#}");
+///     }
+///     for event in breaker.take_events() {
+///         eprintln!("{event:?}");
+///     }
+///     std::thread::sleep(std::time::Duration::from_secs(1));
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    probe_interval: Duration,
+    failures: u32,
+    last_probe: Option<Instant>,
+    events: Vec<BreakerEvent>,
+}
+
+impl CircuitBreaker {
+    /// Open after `threshold` failures in a row, probing every 30 seconds
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            probe_interval: Duration::from_secs(30),
+            failures: 0,
+            last_probe: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Probe the device every `interval` while open
+    pub fn with_probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Whether calls are going through
+    pub fn is_healthy(&self) -> bool {
+        self.failures < self.threshold
+    }
+
+    /// Failures in a row so far
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Run `call` unless the breaker is open and no probe is due
+    ///
+    /// Returns `Ok(None)` for skipped calls. Errors are returned until the
+    /// breaker opens; after that the [`BreakerEvent::Opened`] event carries
+    /// the error and failed probes stay quiet.
+    pub fn call<T>(&mut self, call: impl FnOnce() -> Result<T>) -> Result<Option<T>> {
+        self.call_at(Instant::now(), call)
+    }
+
+    /// [`CircuitBreaker::call`] at `now`
+    pub fn call_at<T>(
+        &mut self,
+        now: Instant,
+        call: impl FnOnce() -> Result<T>,
+    ) -> Result<Option<T>> {
+        let healthy = self.is_healthy();
+        if !healthy {
+            let waiting = self
+                .last_probe
+                .is_some_and(|last| now.saturating_duration_since(last) < self.probe_interval);
+            if waiting {
+                return Ok(None);
+            }
+            self.last_probe = Some(now);
+        }
+        match call() {
+            Ok(value) => {
+                if !healthy {
+                    let failures = self.failures;
+                    self.events.push(BreakerEvent::Closed { failures });
+                }
+                self.failures = 0;
+                Ok(Some(value))
+            }
+            Err(error) => {
+                self.failures = self.failures.saturating_add(1);
+                if healthy && !self.is_healthy() {
+                    self.last_probe = Some(now);
+                    self.events.push(BreakerEvent::Opened {
+                        failures: self.failures,
+                        error: format!("{error:#}"),
+                    });
+                }
+                if healthy {
+                    Err(error)
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Events since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<BreakerEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BreakerEvent, CircuitBreaker};
+    use std::time::{Duration, Instant};
+
+    /// A failing call for tests
+    fn fail() -> crate::Result<()> {
+        bail!("unplugged")
+    }
+
+    /// Failures open the breaker, which then only probes now and then
+    #[test]
+    fn open_and_probe() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut breaker = CircuitBreaker::new(2).with_probe_interval(Duration::from_secs(10));
+        assert!(breaker.call_at(at(0), fail).is_err());
+        assert!(breaker.take_events().is_empty());
+        assert!(breaker.call_at(at(1), fail).is_err());
+        assert!(!breaker.is_healthy());
+        let opened = BreakerEvent::Opened {
+            failures: 2,
+            error: "unplugged".to_owned(),
+        };
+        assert_eq!(breaker.take_events(), [opened]);
+        let mut calls = 0;
+        for second in 2..30 {
+            let result = breaker.call_at(at(second), || {
+                calls += 1;
+                fail()
+            });
+            assert!(result.unwrap().is_none());
+        }
+        assert_eq!(calls, 2);
+        assert_eq!(breaker.failures(), 4);
+    }
+
+    /// A successful probe closes the breaker again
+    #[test]
+    fn recover() {
+        let now = Instant::now();
+        let mut breaker = CircuitBreaker::new(1).with_probe_interval(Duration::ZERO);
+        assert!(breaker.call_at(now, fail).is_err());
+        assert_eq!(breaker.call_at(now, || Ok(7)).unwrap(), Some(7));
+        assert!(breaker.is_healthy());
+        let events = breaker.take_events();
+        assert_eq!(events[1], BreakerEvent::Closed { failures: 1 });
+        assert_eq!(breaker.failures(), 0);
+    }
+}
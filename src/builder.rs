@@ -0,0 +1,657 @@
+//! Configuring how an [`Octo`] is opened
+use crate::error::{Context, Result};
+use crate::{
+    calibration::Calibration, layout, Failsafe, Octo, OctoError, OctoInfo, StallPolicy, Transport,
+    UsbTransport, WriteStrategy,
+};
+use rusb::{Device, DeviceList, GlobalContext};
+use std::time::Duration;
+
+/// What to do when the firmware is older than the oldest version known to
+/// support virtual sensors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FirmwareCheck {
+    /// Fail to open the device
+    #[default]
+    Refuse,
+    /// Print a warning and carry on
+    Warn,
+    /// Don't check
+    Ignore,
+}
+
+/// What to do with temperatures the device can't carry
+///
+/// The encoding covers -327.68 to 327.66 °C, see
+/// [`codec::CENTIDEGREES`](crate::codec::CENTIDEGREES).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RangeCheck {
+    /// Clamp them to the nearest temperature that fits
+    #[default]
+    Saturate,
+    /// Fail the update with [`OctoError::OutOfRange`], sending nothing
+    Reject,
+}
+
+/// How [`OctoBuilder::open`] reaches the device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The hwmon driver if its virtual sensor channels are writable and no
+    /// serial number was asked for, USB otherwise
+    #[default]
+    Auto,
+    /// USB through libusb
+    Usb,
+    /// Attributes of the hwmon driver, Linux only, see
+    /// [`hwmon`](crate::hwmon)
+    Hwmon,
+    /// Nothing: reports are decoded and logged instead, see
+    /// [`dryrun`](crate::dryrun)
+    DryRun,
+}
+
+/// Environment variable listing extra USB IDs to open as an Octo
+///
+/// Comma separated `VENDOR:PRODUCT` pairs in hex, e.g. `0c70:f0ff`.
+pub const USB_IDS_ENV: &str = "OCTO_VS_USB_IDS";
+
+/// Builder for [`Octo`]
+///
+/// ```no_run
+/// use octo_virtual_sensors::{FirmwareCheck, Octo};
+/// let octo = Octo::builder()
+///     .firmware_check(FirmwareCheck::Warn)
+///     .open()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct OctoBuilder {
+    pub(crate) firmware_check: FirmwareCheck,
+    stall_policy: StallPolicy,
+    timeout: Option<Duration>,
+    keep_kernel_driver: bool,
+    write_strategy: WriteStrategy,
+    backend: Backend,
+    pub(crate) retry: Option<(u32, Duration)>,
+    usb_ids: Vec<(u16, u16)>,
+    pub(crate) reset_after_timeouts: Option<u32>,
+    pub(crate) checksum_attempts: Option<usize>,
+    pub(crate) virtual_sensor_timeout: Option<Duration>,
+    pub(crate) serial: Option<String>,
+    pub(crate) reconnect: Option<(Duration, Duration)>,
+    pub(crate) builtin_template: bool,
+    pub(crate) failsafe: Failsafe,
+    pub(crate) deadband: Option<(u16, Duration)>,
+    pub(crate) range_check: RangeCheck,
+    pub(crate) calibrations: Vec<(usize, Calibration)>,
+    device: Option<layout::DeviceLayout>,
+    #[cfg(feature = "hidapi")]
+    usage_page: Option<u16>,
+    #[cfg(feature = "hidapi")]
+    hid_path: Option<String>,
+    #[cfg(all(feature = "hidapi", windows))]
+    container_id: Option<String>,
+}
+
+impl OctoBuilder {
+    /// Builder with default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how outdated firmware is handled
+    pub fn firmware_check(mut self, firmware_check: FirmwareCheck) -> Self {
+        self.firmware_check = firmware_check;
+        self
+    }
+
+    /// Set how stalled USB endpoints are handled
+    pub fn stall_policy(mut self, stall_policy: StallPolicy) -> Self {
+        self.stall_policy = stall_policy;
+        self
+    }
+
+    /// Set how the device is reached, by default through the hwmon driver
+    /// where it takes virtual sensor writes and over USB otherwise
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Set how output reports are written over USB
+    ///
+    /// By default the interrupt OUT endpoint is tried first, and hosts that
+    /// reject it get a HID SET_REPORT control transfer instead. Doesn't
+    /// apply to [`OctoBuilder::open_hid`].
+    pub fn write_strategy(mut self, write_strategy: WriteStrategy) -> Self {
+        self.write_strategy = write_strategy;
+        self
+    }
+
+    /// Give up on a USB transfer after `timeout`, one second by default
+    ///
+    /// A transfer that runs out fails with [`OctoError::Timeout`], one
+    /// that finds the device gone with [`OctoError::Disconnected`]. Doesn't
+    /// apply to [`OctoBuilder::open_hid`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set whether a kernel driver bound to the device is detached while
+    /// it's open, on by default
+    ///
+    /// On Linux the `aquacomputer_d5next` hwmon driver usually claims the
+    /// HID interface. It's detached when the device is opened and
+    /// reattached when the [`Octo`] is dropped, so its readings pause in
+    /// between. Doesn't apply to [`OctoBuilder::open_hid`].
+    pub fn detach_kernel_driver(mut self, detach: bool) -> Self {
+        self.keep_kernel_driver = !detach;
+        self
+    }
+
+    /// Try transfers that time out or fail in USB again, up to `retries`
+    /// more times
+    ///
+    /// The first retry waits `backoff`, doubling for each one after. The
+    /// error of the last attempt is returned, so its kind still tells a
+    /// timeout from other failures. A device that went away isn't retried
+    /// here; that's for [`OctoBuilder::reconnect`]. No retries by default.
+    pub fn retry(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retry = Some((retries, backoff));
+        self
+    }
+
+    /// Read a status, control or virtual sensor report up to `attempts`
+    /// times while it fails its checksum, 3 by default
+    ///
+    /// After that the read fails with [`OctoError::ChecksumMismatch`]
+    /// rather than hand back corrupted data. 1 fails on the first bad
+    /// report.
+    pub fn checksum_attempts(mut self, attempts: usize) -> Self {
+        self.checksum_attempts = Some(attempts.max(1));
+        self
+    }
+
+    /// Reset the device after `count` transfers in a row time out
+    ///
+    /// Recovers controllers that stop answering until replugged, see
+    /// [`Octo::reset_device`]. Off by default.
+    pub fn reset_after_timeouts(mut self, count: u32) -> Self {
+        self.reset_after_timeouts = Some(count.max(1));
+        self
+    }
+
+    /// Reopen the device when it goes away, such as after a replug
+    ///
+    /// A transfer that finds the device gone tries to reopen it and, if
+    /// it's back, goes ahead. Otherwise the error is returned and the next
+    /// attempt waits `initial`, doubling up to `max`, so a long-running
+    /// service survives the device resetting without a restart. Off by
+    /// default.
+    pub fn reconnect(mut self, initial: Duration, max: Duration) -> Self {
+        self.reconnect = Some((initial, max.max(initial)));
+        self
+    }
+
+    /// What to do when the `Octo` is dropped, see [`Failsafe`]
+    ///
+    /// Covers the program exiting or panicking, though not being killed
+    /// outright; a watchdog in another process covers that. Nothing by
+    /// default.
+    pub fn failsafe(mut self, failsafe: Failsafe) -> Self {
+        self.failsafe = failsafe;
+        self
+    }
+
+    /// Skip updates that change no value by more than `threshold`
+    ///
+    /// `threshold` is in centidegrees. Updates are still sent at least
+    /// every `interval`, so keep it below the device's virtual sensor
+    /// timeout. A skipped update returns `Ok(0)`, as nothing was written;
+    /// connecting or disconnecting a slot is always sent, and so are
+    /// [`Octo::republish`] and [`Octo::keep_alive`]. For callers pushing
+    /// values many times a second. Off by default.
+    pub fn deadband(mut self, threshold: u16, interval: Duration) -> Self {
+        self.deadband = Some((threshold, interval));
+        self
+    }
+
+    /// Open this model instead of an Octo, such as [`layout::QUADRO`]
+    ///
+    /// Its layout is used to find, probe and talk to the device.
+    pub fn device(mut self, device: layout::DeviceLayout) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Layout of the model being opened
+    pub(crate) fn layout(&self) -> layout::DeviceLayout {
+        self.device.unwrap_or(layout::OCTO)
+    }
+
+    /// Set how temperatures out of the device's range are handled
+    ///
+    /// Sensors too hot or cold for the encoding saturate by default, which
+    /// is the safer reading for fan control; rejecting them surfaces
+    /// unit mixups and broken probes instead.
+    pub fn range_check(mut self, range_check: RangeCheck) -> Self {
+        self.range_check = range_check;
+        self
+    }
+
+    /// Correct every value published on `slot` with `calibration`
+    ///
+    /// Applied before encoding, after the range check, by every update
+    /// method. Setting a slot again replaces its calibration.
+    pub fn calibration(mut self, slot: usize, calibration: Calibration) -> Self {
+        self.calibrations
+            .retain(|(calibrated, _)| *calibrated != slot);
+        self.calibrations.push((slot, calibration));
+        self
+    }
+
+    /// Always start from the built-in virtual sensor report
+    ///
+    /// By default the report the device holds is read back when opening
+    /// and only its sensors are changed, see
+    /// [`Octo::refresh_report_template`]. This skips that read and sends
+    /// the built-in template's other bytes instead.
+    pub fn builtin_template(mut self) -> Self {
+        self.builtin_template = true;
+        self
+    }
+
+    /// Virtual sensor timeout configured on the device
+    ///
+    /// The firmware disconnects a virtual sensor that hasn't been updated
+    /// for this long. The setting lives in a part of the control report
+    /// that isn't documented, so it can't be read back and has to match
+    /// what was set in aquasuite. Knowing it lets [`Octo`] warn about
+    /// updates that come too slowly and pace
+    /// [`Octo::keep_alive`].
+    pub fn virtual_sensor_timeout(mut self, timeout: Duration) -> Self {
+        self.virtual_sensor_timeout = Some(timeout);
+        self
+    }
+
+    /// Also accept a device with this vendor and product ID
+    ///
+    /// For rebadged controllers, engineering samples and new product IDs
+    /// the crate doesn't know yet. The device is driven as an Octo.
+    pub fn with_usb_id(mut self, vendor_id: u16, product_id: u16) -> Self {
+        self.usb_ids.push((vendor_id, product_id));
+        self
+    }
+
+    /// Also accept every ID in a comma separated `VENDOR:PRODUCT` list
+    ///
+    /// The list format is the one read from [`USB_IDS_ENV`].
+    pub fn with_usb_ids(mut self, ids: &str) -> Result<Self> {
+        self.usb_ids.extend(parse_usb_ids(ids)?);
+        Ok(self)
+    }
+
+    /// Only open the device with this serial number
+    ///
+    /// Serial numbers look like `12345-06789`, as listed by
+    /// [`OctoBuilder::list`]. For machines with more than one Octo.
+    pub fn serial(mut self, serial: &str) -> Self {
+        self.serial = Some(serial.to_owned());
+        self
+    }
+
+    /// Only open HID interfaces on this usage page with [`OctoBuilder::open_hid`]
+    ///
+    /// For platforms that list one HID device per top-level collection.
+    #[cfg(feature = "hidapi")]
+    pub fn usage_page(mut self, usage_page: u16) -> Self {
+        self.usage_page = Some(usage_page);
+        self
+    }
+
+    /// Only open the HID interface at this path with [`OctoBuilder::open_hid`]
+    ///
+    /// The path as [`hidapi::devices`](crate::hidapi::devices) lists it,
+    /// such as `/dev/hidraw3`, or on Windows the device instance path
+    /// Device Manager shows, see [`HidDeviceInfo::is_at`]. Picks one of
+    /// several devices where serial numbers can't be read.
+    ///
+    /// [`HidDeviceInfo::is_at`]: crate::hidapi::HidDeviceInfo::is_at
+    #[cfg(feature = "hidapi")]
+    pub fn hid_path(mut self, path: &str) -> Self {
+        self.hid_path = Some(path.to_owned());
+        self
+    }
+
+    /// Only open the device with this container ID with
+    /// [`OctoBuilder::open_hid`]
+    ///
+    /// Container IDs look like `{4F2E5A1C-8D3B-11EF-9C6A-0A1B2C3D4E5F}`, as
+    /// on the Details tab in Device Manager, and don't change when the
+    /// device moves to another port. Braces and case don't matter.
+    #[cfg(all(feature = "hidapi", windows))]
+    pub fn container_id(mut self, container_id: &str) -> Self {
+        self.container_id = Some(container_id.to_owned());
+        self
+    }
+
+    /// Find the connected Octo, or the model set with
+    /// [`OctoBuilder::device`], and open it
+    ///
+    /// Fails if unable to find it based on vendor_id and product_id, or
+    /// one of the extra IDs from the builder or [`USB_IDS_ENV`]. With a
+    /// serial number set, devices with other serial numbers are skipped.
+    /// The hwmon driver is used instead of USB as set with
+    /// [`OctoBuilder::backend`], or nothing at all with
+    /// [`Backend::DryRun`].
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(name = "open", level = "debug", skip_all)
+    )]
+    pub fn open(self) -> Result<Octo> {
+        if self.backend == Backend::DryRun {
+            let transport = crate::dryrun::DryRun::new(self.layout());
+            return Octo::open_transport(Box::new(transport), &self);
+        }
+        if let Some(octo) = self.open_hwmon()? {
+            return Ok(octo);
+        }
+        let mut skipped = Skipped::default();
+        for device in self.devices()? {
+            trace!(
+                Debug,
+                "Trying the device at bus {} address {}",
+                device.bus_number(),
+                device.address()
+            );
+            let transport = self.usb_transport(device);
+            match (
+                Octo::open_transport(Box::new(transport), &self),
+                &self.serial,
+            ) {
+                (Err(error), Some(_)) => skipped.add(error),
+                (octo, _) => return octo,
+            }
+        }
+        skipped.or(self.not_found())
+    }
+
+    /// Find the connected Octo and open it through the HID stack
+    ///
+    /// Like [`OctoBuilder::open`], but through [`HidTransport`], for
+    /// Windows, macOS and anywhere else the HID driver owns the device.
+    /// The stall policy, timeout and kernel driver detaching don't apply.
+    /// Interfaces at other paths than [`OctoBuilder::hid_path`], or on
+    /// Windows of devices with other container IDs, are skipped.
+    ///
+    /// [`HidTransport`]: crate::hidapi::HidTransport
+    #[cfg(feature = "hidapi")]
+    pub fn open_hid(self) -> Result<Octo> {
+        use crate::hidapi::{self, HidTransport};
+        let mut skipped = Skipped::default();
+        #[cfg(windows)]
+        let selective = self.serial.is_some() || self.container_id.is_some();
+        #[cfg(not(windows))]
+        let selective = self.serial.is_some();
+        for (vendor_id, product_id) in self.usb_ids()? {
+            for info in hidapi::devices(vendor_id, product_id)? {
+                if self.usage_page.is_some_and(|page| page != info.usage_page) {
+                    continue;
+                }
+                if self.hid_path.as_ref().is_some_and(|path| !info.is_at(path)) {
+                    continue;
+                }
+                let transport = HidTransport::open(&info);
+                #[cfg(windows)]
+                let transport = transport.and_then(|transport| self.check_container_id(transport));
+                let octo = transport
+                    .and_then(|transport| Octo::open_transport(Box::new(transport), &self));
+                match octo {
+                    Err(error) if selective => skipped.add(error),
+                    octo => return octo,
+                }
+            }
+        }
+        match &self.hid_path {
+            Some(path) => skipped.or(Err(OctoError::DeviceNotFound)
+                .with_context(|| format!("No {} HID interface at {path}", self.layout().name))),
+            None => skipped.or(self.not_found()),
+        }
+    }
+
+    /// `transport` if its device has the container ID asked for
+    #[cfg(all(feature = "hidapi", windows))]
+    fn check_container_id(
+        &self,
+        transport: crate::hidapi::HidTransport,
+    ) -> Result<crate::hidapi::HidTransport> {
+        let Some(wanted) = &self.container_id else {
+            return Ok(transport);
+        };
+        let normalized = |id: &str| id.trim_matches(['{', '}']).to_ascii_uppercase();
+        let container_id = transport.container_id()?;
+        if normalized(&container_id) != normalized(wanted) {
+            return Err(OctoError::DeviceNotFound)
+                .with_context(|| format!("Container ID is {container_id}, not {wanted}"));
+        }
+        Ok(transport)
+    }
+
+    /// The device through the hwmon driver, if the backend calls for it
+    ///
+    /// Fails for [`Backend::Hwmon`] if no device takes writes that way.
+    fn open_hwmon(&self) -> Result<Option<Octo>> {
+        let wanted = match self.backend {
+            Backend::Usb | Backend::DryRun => return Ok(None),
+            Backend::Auto => self.serial.is_none(),
+            Backend::Hwmon => true,
+        };
+        if !wanted {
+            return Ok(None);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(transport) = crate::hwmon::HwmonTransport::find(&self.layout())? {
+            return Octo::open_transport(Box::new(transport), self).map(Some);
+        }
+        if self.backend == Backend::Hwmon {
+            return Err(OctoError::DeviceNotFound).with_context(|| {
+                format!(
+                    "No {} takes virtual sensor writes through hwmon",
+                    self.layout().name
+                )
+            });
+        }
+        Ok(None)
+    }
+
+    /// Transport for `device` with the builder's USB settings
+    fn usb_transport(&self, device: Device<GlobalContext>) -> UsbTransport {
+        let transport = UsbTransport::new(device)
+            .with_stall_policy(self.stall_policy)
+            .with_kernel_driver_detach(!self.keep_kernel_driver)
+            .with_write_strategy(self.write_strategy);
+        match self.timeout {
+            Some(timeout) => transport.with_timeout(timeout),
+            None => transport,
+        }
+    }
+
+    /// Error for when no device matched
+    fn not_found(&self) -> Result<Octo> {
+        let error = Err(OctoError::DeviceNotFound);
+        let name = self.layout().name;
+        match &self.serial {
+            Some(serial) => error.with_context(|| {
+                format!("Could not find Aquacomputer {name} with serial {serial}")
+            }),
+            None => error.with_context(|| format!("Could not find Aquacomputer {name}")),
+        }
+    }
+
+    /// Every connected device that would be opened
+    ///
+    /// Serial number and firmware come from a status report, and are
+    /// `None` for devices that can't be read, such as ones claimed by
+    /// another program.
+    pub fn list(&self) -> Result<Vec<OctoInfo>> {
+        let layout = self.layout();
+        Ok(self
+            .devices()?
+            .into_iter()
+            .map(|device| {
+                let (bus, address) = (device.bus_number(), device.address());
+                let mut transport = self.usb_transport(device);
+                info(&layout, bus, address, &mut transport)
+            })
+            .collect())
+    }
+
+    /// The model's vendor and product ID, then the extra ones
+    fn usb_ids(&self) -> Result<Vec<(u16, u16)>> {
+        let layout = self.layout();
+        let mut usb_ids = vec![(layout.vendor_id, layout.product_id)];
+        usb_ids.extend(&self.usb_ids);
+        if let Ok(ids) = std::env::var(USB_IDS_ENV) {
+            usb_ids.extend(parse_usb_ids(&ids).with_context(|| format!("Parsing {USB_IDS_ENV}"))?);
+        }
+        Ok(usb_ids)
+    }
+
+    /// USB devices matching the model's or one of the extra IDs
+    fn devices(&self) -> Result<Vec<Device<GlobalContext>>> {
+        let usb_ids = self.usb_ids()?;
+        let mut devices = Vec::new();
+        for device in DeviceList::new().context("Getting USB Device list")?.iter() {
+            let dd = &device.device_descriptor().context("Getting device ID")?;
+
+            if usb_ids.contains(&(dd.vendor_id(), dd.product_id())) {
+                devices.push(device);
+            }
+        }
+        trace!(
+            Debug,
+            "{} devices match USB IDs {usb_ids:04x?}",
+            devices.len()
+        );
+        Ok(devices)
+    }
+
+    /// Open an Octo that talks through the given transport
+    pub fn with_transport(self, transport: impl Transport + Send + 'static) -> Result<Octo> {
+        Octo::open_transport(Box::new(transport), &self)
+    }
+}
+
+/// Devices passed over while looking for a serial number
+///
+/// Ones with another serial are what the search expects. Any other failure,
+/// such as a device that couldn't be opened, is kept so the search fails
+/// with its reason rather than as not found.
+#[derive(Default)]
+struct Skipped {
+    error: Option<crate::Error>,
+}
+
+impl Skipped {
+    /// Pass over a device that failed to open with `error`
+    fn add(&mut self, error: crate::Error) {
+        let other_serial = error.kind() == Some(OctoError::DeviceNotFound);
+        if !other_serial && self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// The first real failure, or `not_found` if every device had another
+    /// serial
+    fn or(self, not_found: Result<Octo>) -> Result<Octo> {
+        match self.error {
+            Some(error) => Err(error),
+            None => not_found,
+        }
+    }
+}
+
+/// What the status report of the device behind `transport` says about it
+pub(crate) fn info(
+    layout: &layout::DeviceLayout,
+    bus: u8,
+    address: u8,
+    transport: &mut dyn Transport,
+) -> OctoInfo {
+    let status = crate::read_status(transport, layout).ok();
+    let status = status.as_deref();
+    OctoInfo {
+        bus,
+        address,
+        serial: status.and_then(|status| crate::serial(status, layout)),
+        firmware: status.and_then(|status| crate::firmware(status, layout)),
+    }
+}
+
+/// Parse a comma separated list of hex `VENDOR:PRODUCT` pairs
+fn parse_usb_ids(ids: &str) -> Result<Vec<(u16, u16)>> {
+    ids.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            let (vendor, product) = id
+                .split_once(':')
+                .with_context(|| format!("Expected VENDOR:PRODUCT, got {id:?}"))?;
+            let hex = |part: &str| {
+                let part = part.trim_start_matches("0x");
+                u16::from_str_radix(part, 16)
+                    .with_context(|| format!("Bad USB ID {part:?} in {id:?}"))
+            };
+            Ok((hex(vendor)?, hex(product)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_usb_ids, OctoBuilder, Skipped};
+    use crate::{emulator::Emulator, mock::MockTransport, OctoError};
+
+    /// Lists of hex pairs parse, with or without 0x and spaces
+    #[test]
+    fn usb_ids() {
+        let ids = parse_usb_ids("0c70:f0ff, 0x1234:0xABCD,").unwrap();
+        assert_eq!(ids, [(0x0c70, 0xf0ff), (0x1234, 0xabcd)]);
+        assert_eq!(parse_usb_ids("").unwrap(), []);
+        assert!(parse_usb_ids("0c70").is_err());
+        assert!(parse_usb_ids("0c70:fffff").is_err());
+        let builder = OctoBuilder::new()
+            .with_usb_id(1, 2)
+            .with_usb_ids("3:4")
+            .unwrap();
+        assert_eq!(builder.usb_ids, [(1, 2), (3, 4)]);
+    }
+
+    /// Devices with another serial are passed over, but the first real
+    /// failure is what a fruitless search reports
+    #[test]
+    fn serial_search() {
+        let builder = || OctoBuilder::new().serial("00000-00001");
+        let other = builder().with_transport(Emulator::new()).err().unwrap();
+        assert_eq!(other.kind(), Some(OctoError::DeviceNotFound));
+        let silent = builder()
+            .with_transport(MockTransport::new())
+            .err()
+            .unwrap();
+        assert_eq!(silent.kind(), Some(OctoError::Timeout));
+
+        let mut skipped = Skipped::default();
+        skipped.add(other);
+        let error = skipped.or(builder().not_found()).err().unwrap();
+        assert_eq!(
+            format!("{error}"),
+            "Could not find Aquacomputer Octo with serial 00000-00001"
+        );
+        let mut skipped = Skipped::default();
+        skipped.add(builder().with_transport(Emulator::new()).err().unwrap());
+        skipped.add(silent);
+        let error = skipped.or(builder().not_found()).err().unwrap();
+        assert_eq!(error.kind(), Some(OctoError::Timeout));
+    }
+}
@@ -0,0 +1,108 @@
+//! Per-slot corrections applied to every published value
+//!
+//! A probe that reads 1.5 °C high is best corrected once, where values go
+//! out, rather than in every program that publishes it.
+//! [`OctoBuilder::calibration`](crate::OctoBuilder::calibration) gives a
+//! slot a [`Calibration`], which every update of that slot then passes
+//! through before it is encoded:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{calibration::Calibration, Octo};
+//! let mut octo = Octo::builder()
+//!     .calibration(0, Calibration::new().with_offset(-150))
+//!     .open()
+//!     .unwrap();
+//! octo.set_virtual_sensor(0, 41.5).unwrap(); // 40.0 °C reaches the device
+//! ```
+//!
+//! Values are scaled, then offset, then clamped. Disconnected slots stay
+//! disconnected, and values re-sent by [`Octo::republish`](crate::Octo::republish)
+//! or a keep-alive aren't corrected twice.
+use crate::codec::{MAX_CENTIDEGREES, MIN_CENTIDEGREES};
+
+/// Linear correction and limits for one slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    scale: f64,
+    offset: i16,
+    min: i16,
+    max: i16,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            offset: 0,
+            min: MIN_CENTIDEGREES,
+            max: MAX_CENTIDEGREES,
+        }
+    }
+}
+
+impl Calibration {
+    /// Calibration that leaves values as they are
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `centidegrees` to every value, after scaling
+    pub fn with_offset(mut self, centidegrees: i16) -> Self {
+        self.offset = centidegrees;
+        self
+    }
+
+    /// Multiply every value by `scale`, for probes whose error grows with
+    /// the temperature
+    ///
+    /// Non-finite scales are ignored.
+    pub fn with_scale(mut self, scale: f64) -> Self {
+        if scale.is_finite() {
+            self.scale = scale;
+        }
+        self
+    }
+
+    /// Keep corrected values between `min` and `max` centidegrees
+    ///
+    /// The bounds are swapped if given the wrong way round.
+    pub fn with_clamp(mut self, min: i16, max: i16) -> Self {
+        self.min = min.min(max);
+        self.max = max.max(min);
+        self
+    }
+
+    /// The corrected value of `centidegrees`
+    pub fn apply(&self, centidegrees: i16) -> i16 {
+        let corrected = (f64::from(centidegrees) * self.scale + f64::from(self.offset)).round();
+        // The float to int cast saturates, and the clamp keeps the value
+        // clear of the disconnected marker
+        (corrected as i16)
+            .clamp(self.min, self.max)
+            .min(MAX_CENTIDEGREES)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Calibration;
+    use crate::codec::MAX_CENTIDEGREES;
+
+    /// Scale, then offset, then clamp
+    #[test]
+    fn apply() {
+        assert_eq!(Calibration::new().apply(4150), 4150);
+        let offset = Calibration::new().with_offset(-150);
+        assert_eq!(offset.apply(4150), 4000);
+        let scaled = Calibration::new().with_scale(0.5).with_offset(100);
+        assert_eq!(scaled.apply(4001), 2101);
+        let clamped = Calibration::new().with_offset(1000).with_clamp(6000, 2000);
+        assert_eq!(clamped.apply(4500), 5500);
+        assert_eq!(clamped.apply(5500), 6000);
+        assert_eq!(clamped.apply(-3000), 2000);
+        let huge = Calibration::new().with_scale(1000.0);
+        assert_eq!(huge.apply(4000), MAX_CENTIDEGREES);
+        assert_eq!(huge.apply(-4000), i16::MIN);
+        assert_eq!(Calibration::new().with_scale(f64::NAN).apply(100), 100);
+    }
+}
@@ -0,0 +1,124 @@
+//! Report checksums
+//!
+//! Most Aquacomputer devices end their reports with a CRC-16/USB over
+//! everything after the report ID. Devices that differ plug in their own
+//! [`Checksum`] through the layout tables.
+#![deny(clippy::indexing_slicing)]
+use crate::protocol;
+use std::fmt::Debug;
+
+/// Computes, places and verifies a report's checksum
+pub trait Checksum: Debug + Sync {
+    /// Name used to tell checksum schemes apart
+    fn name(&self) -> &'static str;
+
+    /// Number of bytes the checksum occupies at the end of the report
+    fn size(&self) -> usize;
+
+    /// Write the checksum into `report`
+    fn apply(&self, report: &mut [u8]);
+
+    /// Whether `report` carries a valid checksum
+    fn verify(&self, report: &[u8]) -> bool;
+}
+
+/// CRC-16/USB over everything between the report ID and the checksum,
+/// stored big-endian in the last two bytes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc16Usb;
+
+impl Crc16Usb {
+    /// Checksum of a whole report, ignoring its current checksum bytes
+    ///
+    /// Reports too short to carry a checksum yield `None`.
+    pub fn compute(report: &[u8]) -> Option<u16> {
+        protocol::crc(report)
+    }
+}
+
+impl Checksum for Crc16Usb {
+    fn name(&self) -> &'static str {
+        "CRC-16/USB"
+    }
+
+    fn size(&self) -> usize {
+        protocol::CRC_SIZE
+    }
+
+    fn apply(&self, report: &mut [u8]) {
+        protocol::apply_crc(report);
+    }
+
+    fn verify(&self, report: &[u8]) -> bool {
+        protocol::verify_crc(report)
+    }
+}
+
+/// Reports without a checksum
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoChecksum;
+
+impl Checksum for NoChecksum {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn apply(&self, _report: &mut [u8]) {}
+
+    fn verify(&self, _report: &[u8]) -> bool {
+        true
+    }
+}
+
+impl PartialEq for dyn Checksum {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for dyn Checksum {}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod test {
+    use super::{Checksum, Crc16Usb, NoChecksum};
+
+    /// The checksum of the all-disconnected Octo report
+    #[test]
+    fn crc16_usb_known_report() {
+        let mut report = vec![4];
+        for _ in 0..16 {
+            report.extend([0x7f, 0xff]);
+        }
+        report.extend([0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Crc16Usb::compute(&report), Some(0xbc58));
+        assert!(!Crc16Usb.verify(&report));
+        Crc16Usb.apply(&mut report);
+        assert_eq!(report[49..], [0xbc, 0x58]);
+        assert!(Crc16Usb.verify(&report));
+    }
+
+    /// Reports too short for a checksum never verify
+    #[test]
+    fn crc16_usb_short_report() {
+        assert_eq!(Crc16Usb::compute(&[4]), None);
+        assert!(!Crc16Usb.verify(&[4, 0]));
+        let mut report = [4];
+        Crc16Usb.apply(&mut report);
+        assert_eq!(report, [4]);
+    }
+
+    /// No checksum leaves the report alone
+    #[test]
+    fn no_checksum() {
+        let mut report = [4, 1, 2];
+        NoChecksum.apply(&mut report);
+        assert_eq!(report, [4, 1, 2]);
+        assert!(NoChecksum.verify(&report));
+        assert_eq!(NoChecksum.size(), 0);
+    }
+}
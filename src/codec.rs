@@ -0,0 +1,301 @@
+//! Encoding of values inside reports
+//!
+//! Multi-byte fields are big-endian. Temperatures are signed centidegrees
+//! in [`CENTIDEGREES`], with [`DISCONNECTED`] marking an absent sensor; fan
+//! power is unsigned centipercent.
+//!
+//! Every access is bounds checked, so a short or malformed report is an
+//! error rather than a panic.
+#![deny(clippy::indexing_slicing)]
+use crate::error::{Context, Result};
+use crate::OctoError;
+use std::ops::RangeInclusive;
+
+pub use crate::protocol::{decode_temperature, encode_temperature, DISCONNECTED};
+
+/// Lowest temperature the encoding carries, in centidegrees
+pub const MIN_CENTIDEGREES: i16 = i16::MIN;
+
+/// Highest temperature the encoding carries, in centidegrees, as the next
+/// value up is [`DISCONNECTED`]
+pub const MAX_CENTIDEGREES: i16 = DISCONNECTED - 1;
+
+/// Temperatures the encoding carries, in centidegrees
+pub const CENTIDEGREES: RangeInclusive<i16> =
+    RangeInclusive::new(MIN_CENTIDEGREES, MAX_CENTIDEGREES);
+
+/// Whole degrees the encoding carries, -327 to 327
+pub const DEGREES: RangeInclusive<i16> =
+    RangeInclusive::new(MIN_CENTIDEGREES / 100, MAX_CENTIDEGREES / 100);
+
+/// Full scale of a centipercent value
+pub const FULL_POWER: u16 = 100 * 100;
+
+/// `len` bytes at `offset`, failing if they run past the end
+fn field(report: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| report.get(offset..end))
+        .ok_or(OctoError::InvalidLayout)
+        .with_context(|| out_of_bounds(report.len(), offset, len))
+}
+
+/// Mutable `len` bytes at `offset`, failing if they run past the end
+fn field_mut(report: &mut [u8], offset: usize, len: usize) -> Result<&mut [u8]> {
+    let report_len = report.len();
+    offset
+        .checked_add(len)
+        .and_then(|end| report.get_mut(offset..end))
+        .ok_or(OctoError::InvalidLayout)
+        .with_context(|| out_of_bounds(report_len, offset, len))
+}
+
+/// Error message for a field that doesn't fit the report
+fn out_of_bounds(report_len: usize, offset: usize, len: usize) -> String {
+    format!("{len} byte field at {offset:#x} is past the end of a {report_len} byte report")
+}
+
+/// Read a big-endian u16 at `offset`
+pub fn get_u16(report: &[u8], offset: usize) -> Result<u16> {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(field(report, offset, 2)?);
+    Ok(u16::from_be_bytes(bytes))
+}
+
+/// Write a big-endian u16 at `offset`
+pub fn put_u16(report: &mut [u8], offset: usize, value: u16) -> Result<()> {
+    field_mut(report, offset, 2)?.copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+/// Read a big-endian i16 at `offset`
+pub fn get_i16(report: &[u8], offset: usize) -> Result<i16> {
+    Ok(get_u16(report, offset)? as i16)
+}
+
+/// Write a big-endian i16 at `offset`
+pub fn put_i16(report: &mut [u8], offset: usize, value: i16) -> Result<()> {
+    put_u16(report, offset, value as u16)
+}
+
+/// Read a big-endian u32 at `offset`
+pub fn get_u32(report: &[u8], offset: usize) -> Result<u32> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(field(report, offset, 4)?);
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// Write a big-endian u32 at `offset`
+pub fn put_u32(report: &mut [u8], offset: usize, value: u32) -> Result<()> {
+    field_mut(report, offset, 4)?.copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+/// Read a temperature in centidegrees, `None` if disconnected
+pub fn get_temperature(report: &[u8], offset: usize) -> Result<Option<i16>> {
+    Ok(decode_temperature(get_i16(report, offset)?))
+}
+
+/// Write a temperature in centidegrees, `None` for disconnected
+pub fn put_temperature(report: &mut [u8], offset: usize, centidegrees: Option<i16>) -> Result<()> {
+    put_i16(report, offset, encode_temperature(centidegrees))
+}
+
+/// Centidegrees for whole degrees, `None` if it doesn't fit the encoding
+pub fn centidegrees(degrees: i16) -> Option<i16> {
+    degrees
+        .checked_mul(100)
+        .filter(|value| CENTIDEGREES.contains(value))
+}
+
+/// Centidegrees for whole degrees, failing with [`OctoError::OutOfRange`]
+/// outside [`DEGREES`]
+pub fn checked_centidegrees(degrees: i16) -> Result<i16> {
+    centidegrees(degrees).ok_or_else(|| out_of_range(degrees))
+}
+
+/// Centidegrees for fractional degrees, rounded to nearest, failing with
+/// [`OctoError::OutOfRange`] for what the encoding can't carry
+///
+/// NaN has no temperature and yields `None`.
+pub fn checked_centidegrees_f32(degrees: f32) -> Result<Option<i16>> {
+    if degrees.is_nan() {
+        return Ok(None);
+    }
+    let centidegrees = (f64::from(degrees) * 100.0).round();
+    if !(f64::from(MIN_CENTIDEGREES)..=f64::from(MAX_CENTIDEGREES)).contains(&centidegrees) {
+        return Err(out_of_range(degrees));
+    }
+    Ok(Some(centidegrees as i16))
+}
+
+/// Centidegrees as they are, failing with [`OctoError::OutOfRange`] for
+/// [`DISCONNECTED`], which would otherwise disconnect the sensor
+pub fn checked_temperature(centidegrees: Option<i16>) -> Result<Option<i16>> {
+    match centidegrees {
+        Some(value) if !CENTIDEGREES.contains(&value) => {
+            Err(out_of_range(f64::from(value) / 100.0))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Error for `degrees` Celsius not fitting the encoding
+fn out_of_range(degrees: impl std::fmt::Display) -> crate::Error {
+    crate::Error::from(OctoError::OutOfRange).context(format!(
+        "{degrees} °C is outside {:.2} to {:.2} °C",
+        f64::from(MIN_CENTIDEGREES) / 100.0,
+        f64::from(MAX_CENTIDEGREES) / 100.0
+    ))
+}
+
+/// Centidegrees for whole degrees, saturating at the range the encoding
+/// can carry
+pub fn saturating_centidegrees(degrees: i16) -> i16 {
+    degrees.saturating_mul(100).min(MAX_CENTIDEGREES)
+}
+
+/// Centidegrees for fractional degrees, rounded to nearest and saturating
+/// at the range the encoding can carry
+///
+/// NaN has no temperature and yields `None`.
+pub fn saturating_centidegrees_f32(degrees: f32) -> Option<i16> {
+    // Float to int casts saturate, and NaN is handled before
+    (!degrees.is_nan()).then(|| ((degrees * 100.0).round() as i16).min(MAX_CENTIDEGREES))
+}
+
+/// Centipercent for an 8 bit PWM duty cycle, rounded to nearest
+pub fn pwm_to_centipercent(pwm: u8) -> u16 {
+    let scaled = u32::from(pwm) * u32::from(FULL_POWER);
+    ((scaled + 127) / 255) as u16
+}
+
+/// 8 bit PWM duty cycle for centipercent, rounded to nearest and clamped
+/// to full power
+pub fn centipercent_to_pwm(centipercent: u16) -> u8 {
+    let centipercent = u32::from(centipercent.min(FULL_POWER));
+    let full = u32::from(FULL_POWER);
+    ((centipercent * 255 + full / 2) / full) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Every temperature except the sentinel survives a round trip
+    #[test]
+    fn temperature_round_trip() {
+        let mut report = [0; 2];
+        for raw in i16::MIN..=i16::MAX {
+            put_temperature(&mut report, 0, decode_temperature(raw)).unwrap();
+            assert_eq!(get_i16(&report, 0).unwrap(), raw);
+        }
+        put_temperature(&mut report, 0, None).unwrap();
+        assert_eq!(report, [0x7f, 0xff]);
+        assert_eq!(get_temperature(&report, 0).unwrap(), None);
+    }
+
+    /// Negative temperatures are two's complement
+    #[test]
+    fn negative_temperature() {
+        let mut report = [0; 2];
+        put_temperature(&mut report, 0, Some(-500)).unwrap();
+        assert_eq!(report, [0xfe, 0x0c]);
+        assert_eq!(get_temperature(&report, 0).unwrap(), Some(-500));
+    }
+
+    /// Fields past the end are errors, even with offsets near usize::MAX
+    #[test]
+    fn out_of_bounds() {
+        let mut report = [0; 3];
+        assert!(get_u16(&report, 2).is_err());
+        assert!(get_u32(&report, 0).is_err());
+        assert!(get_u16(&report, usize::MAX).is_err());
+        assert!(get_temperature(&[], 0).is_err());
+        assert!(put_u16(&mut report, 2, 1).is_err());
+        assert!(put_u32(&mut report, usize::MAX - 1, 1).is_err());
+        assert_eq!(report, [0; 3]);
+        let error = get_u16(&report, 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "2 byte field at 0x2 is past the end of a 3 byte report"
+        );
+    }
+
+    /// Whole degrees convert until the encoding overflows
+    #[test]
+    fn whole_degrees() {
+        assert_eq!(centidegrees(42), Some(4200));
+        assert_eq!(centidegrees(-327), Some(-32700));
+        assert_eq!(centidegrees(327), Some(32700));
+        assert_eq!(centidegrees(328), None);
+        assert_eq!(centidegrees(-328), None);
+        assert_eq!(saturating_centidegrees(42), 4200);
+        assert_eq!(saturating_centidegrees(328), DISCONNECTED - 1);
+        assert_eq!(saturating_centidegrees(i16::MIN), i16::MIN);
+    }
+
+    /// Fractional degrees round to the nearest centidegree and saturate
+    #[test]
+    fn fractional_degrees() {
+        assert_eq!(saturating_centidegrees_f32(42.37), Some(4237));
+        assert_eq!(saturating_centidegrees_f32(-0.005), Some(-1));
+        assert_eq!(saturating_centidegrees_f32(-12.5), Some(-1250));
+        assert_eq!(saturating_centidegrees_f32(327.67), Some(DISCONNECTED - 1));
+        assert_eq!(
+            saturating_centidegrees_f32(f32::INFINITY),
+            Some(DISCONNECTED - 1)
+        );
+        assert_eq!(
+            saturating_centidegrees_f32(f32::NEG_INFINITY),
+            Some(i16::MIN)
+        );
+        assert_eq!(saturating_centidegrees_f32(f32::NAN), None);
+    }
+
+    /// Checked conversions fail where the saturating ones clamp
+    #[test]
+    fn checked() {
+        assert_eq!(checked_centidegrees(327).unwrap(), 32700);
+        let error = checked_centidegrees(656).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::OutOfRange));
+        assert_eq!(checked_centidegrees_f32(327.66).unwrap(), Some(32766));
+        assert_eq!(checked_centidegrees_f32(-327.68).unwrap(), Some(i16::MIN));
+        assert_eq!(checked_centidegrees_f32(f32::NAN).unwrap(), None);
+        assert!(checked_centidegrees_f32(327.67).is_err());
+        assert!(checked_centidegrees_f32(f32::NEG_INFINITY).is_err());
+        assert_eq!(checked_temperature(None).unwrap(), None);
+        assert!(checked_temperature(Some(DISCONNECTED)).is_err());
+        assert_eq!(*DEGREES.end(), 327);
+    }
+
+    /// Every PWM value survives a round trip through centipercent
+    #[test]
+    fn pwm_round_trip() {
+        for pwm in 0..=u8::MAX {
+            assert_eq!(centipercent_to_pwm(pwm_to_centipercent(pwm)), pwm);
+        }
+        assert_eq!(pwm_to_centipercent(0), 0);
+        assert_eq!(pwm_to_centipercent(255), FULL_POWER);
+        assert_eq!(pwm_to_centipercent(128), 5020);
+    }
+
+    /// Centipercent above full power clamps
+    #[test]
+    fn centipercent_clamps() {
+        assert_eq!(centipercent_to_pwm(FULL_POWER), 255);
+        assert_eq!(centipercent_to_pwm(u16::MAX), 255);
+        assert_eq!(centipercent_to_pwm(5000), 128);
+    }
+
+    /// Multi-byte fields are big-endian
+    #[test]
+    fn big_endian() {
+        let mut report = [0; 6];
+        put_u16(&mut report, 0, 0x1234).unwrap();
+        put_u32(&mut report, 2, 0x89abcdef).unwrap();
+        assert_eq!(report, [0x12, 0x34, 0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(get_u16(&report, 0).unwrap(), 0x1234);
+        assert_eq!(get_u32(&report, 2).unwrap(), 0x89abcdef);
+    }
+}
@@ -0,0 +1,1186 @@
+//! Configuration files mapping sources to virtual sensors
+//!
+//! A small TOML file says what a [`SyncEngine`] publishes where:
+//!
+//! ```toml
+//! version = 2
+//! interval = 2        # seconds between updates
+//! units = "celsius"   # unit of every temperature in the file
+//! ramp = 30           # seconds to glide to new values, optional
+//! broker = "localhost:1883"  # MQTT broker for mqtt sources and telemetry, optional
+//! state = "/var/lib/octo-vs/state"  # last values, republished after a restart, optional
+//!
+//! [[sensor]]
+//! slot = 1            # virtual sensor, numbered from 1
+//! label = "CPU"       # name for lm-sensors, optional
+//! hwmon = "k10temp/temp1"
+//! offset = -5
+//! max = 90
+//! fallback = 60
+//! filter = "median:5"  # or average:N, exponential:FACTOR, optional
+//!
+//! [[sensor]]
+//! slot = 2
+//! command = "nvidia-smi --query-gpu=temperature.gpu --format=csv,noheader"
+//!
+//! [[sensor]]
+//! slot = 3
+//! fixed = 25
+//!
+//! [[sensor]]
+//! slot = 4
+//! mqtt = "home/livingroom/temperature"
+//!
+//! [[profile]]
+//! name = "quiet"
+//! schedule = "23:00-07:00"  # or process = "superposition"
+//! offset = -5         # every slot without a rule of its own, optional
+//! max = 45
+//!
+//! [[profile.sensor]]
+//! slot = 2
+//! from = 1            # publish slot 1's value, optional
+//! offset = 5
+//! ```
+//!
+//! A [`Profile`] rewrites the published values while it's active, after
+//! each sensor's own filter and rule and before the ramp. The first
+//! `[[profile]]` whose process is running or whose schedule covers the
+//! local time wins.
+//!
+//! `label`s name the slots in an lm-sensors snippet, see
+//! [`Config::lmsensors`].
+//!
+//! `state` is a [`StateFile`]: what was last published is saved there and
+//! stands in for sources that haven't been read yet after a restart.
+//!
+//! Setting `broker` also publishes the device's telemetry with Home
+//! Assistant discovery, see [`crate::mqtt`]. That needs the `mqtt` feature.
+//!
+//! Files from older releases are brought up to [`VERSION`] as they're
+//! parsed, and [`migrate`] rewrites them, keeping comments and layout.
+//! Files without a `version` are taken as version 1. Version 2 renamed the
+//! top-level `mqtt` to `broker`, which `[[sensor]]` tables' `mqtt` topics
+//! were easy to confuse with.
+//!
+//! Files are read with the `toml` crate. Unknown keys and tables are
+//! errors, so a typo doesn't silently publish the wrong thing.
+use crate::error::{Context, Result};
+use crate::{
+    daemon::SyncEngine,
+    layout, lmsensors,
+    profile::{self, Conditions, Processes, Profile, SlotRule, Trigger},
+    schedule::{Schedule, TimeOfDay},
+    source::{CommandSource, FixedSource, HwmonSource},
+    state::StateFile,
+    transform::{FilterConfig, Filters, Interpolation},
+    units::Unit,
+    Octo, OctoError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml::Spanned;
+use toml_edit::{DocumentMut, Item, Key};
+
+/// Version of the file format written by this release
+pub const VERSION: u32 = 2;
+
+/// Changes from each version to the next, the first from version 1
+static MIGRATIONS: [fn(&mut DocumentMut) -> Result<()>; VERSION as usize - 1] = [rename_broker];
+
+/// A parsed configuration file
+///
+/// Serializes with durations in seconds, slots numbered from 0 and
+/// temperatures in centidegrees, as held here rather than as in the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// File format version
+    pub version: u32,
+    /// Time between updates
+    #[serde(with = "seconds")]
+    pub interval: Duration,
+    /// Unit of temperatures in the file and of command output
+    pub units: Unit,
+    /// Time to glide to new values, see [`Interpolation`]
+    #[serde(with = "optional_seconds")]
+    pub ramp: Option<Duration>,
+    /// MQTT broker, `host` or `host:port`
+    pub mqtt: Option<String>,
+    /// Where the last published values are kept, see [`StateFile`]
+    pub state: Option<PathBuf>,
+    /// What each slot publishes
+    pub sensors: Vec<SensorConfig>,
+    /// Profiles in order, the first active one applies
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+}
+
+/// One `[[sensor]]` table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorConfig {
+    /// Virtual sensor slot, numbered from 0
+    pub slot: usize,
+    /// What the slot carries, for lm-sensors
+    pub label: Option<String>,
+    /// Where the value comes from
+    pub source: SourceConfig,
+    /// Smoothing applied to the value before the rule
+    pub filter: Option<FilterConfig>,
+    /// Offset, cap and fallback applied to the value
+    pub rule: SlotRule,
+}
+
+/// Where a slot's value comes from
+///
+/// Serializes as an object with one key naming its kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceConfig {
+    /// hwmon channel, see [`HwmonSource::from_spec`]
+    Hwmon(String),
+    /// Shell command, see [`CommandSource`]
+    Command(String),
+    /// Fixed value in centidegrees
+    Fixed(i16),
+    /// MQTT topic on the configured broker
+    Mqtt(String),
+}
+
+impl Config {
+    /// Parse a configuration file's contents
+    ///
+    /// Files from older versions are migrated first. Line numbers in errors
+    /// can then be off where a migration renamed keys. Failures are an
+    /// [`OctoError::InvalidConfig`], or [`OctoError::OutOfRange`] for
+    /// temperatures the device can't take.
+    pub fn parse(text: &str) -> Result<Self> {
+        Self::parse_file(text).map_err(invalid)
+    }
+
+    /// [`Config::parse`] without the failure's kind
+    fn parse_file(text: &str) -> Result<Self> {
+        let migrated = match text.parse::<DocumentMut>() {
+            Ok(mut document) if file_version(&document).is_ok_and(|version| version < VERSION) => {
+                let from = upgrade(&mut document)?;
+                let upgraded = document.to_string();
+                if upgraded != text || document.contains_key("version") {
+                    warn!(
+                        "Configuration is version {from}, migrate it to version {VERSION} \
+                         with `octo-vs config migrate`"
+                    );
+                }
+                Some(upgraded)
+            }
+            _ => None,
+        };
+        let text = migrated.as_deref().unwrap_or(text);
+        let lines = Lines(text);
+        let file: File = toml::from_str(text).map_err(|error| {
+            let line = error.span().map_or(1, |span| lines.line(&span));
+            crate::Error::msg(format!("Line {line}: {}", error.message()))
+        })?;
+        let version = lines
+            .get("version", file.version, |version| {
+                if !(1..=i64::from(VERSION)).contains(&version) {
+                    bail!("Version {version} is not supported, expected 1 to {VERSION}");
+                }
+                Ok(VERSION)
+            })?
+            .unwrap_or(VERSION);
+        let units = lines
+            .get("units", file.units, |units| units.parse())?
+            .unwrap_or_default();
+        let interval = lines
+            .get("interval", file.interval, seconds)?
+            .unwrap_or(Duration::from_secs(1));
+        if interval.is_zero() {
+            bail!("The interval must be more than 0 seconds");
+        }
+        let ramp = lines.get("ramp", file.ramp, seconds)?;
+        let mqtt = file.broker;
+        let state = file.state.map(PathBuf::from);
+        let mut sensors: Vec<SensorConfig> = Vec::new();
+        for table in file.sensor {
+            let line = lines.line(&table.span());
+            let sensor = SensorConfig::parse(table.into_inner(), line, &lines, units)?;
+            if sensors.iter().any(|other| other.slot == sensor.slot) {
+                bail!("Line {line}: slot {} is mapped twice", sensor.slot + 1);
+            }
+            if matches!(sensor.source, SourceConfig::Mqtt(_)) && mqtt.is_none() {
+                bail!(
+                    "Line {line}: slot {} reads MQTT but no broker is set",
+                    sensor.slot + 1
+                );
+            }
+            sensors.push(sensor);
+        }
+        let mut profiles: Vec<Profile> = Vec::new();
+        for table in file.profile {
+            let line = lines.line(&table.span());
+            let profile = parse_profile(table.into_inner(), line, &lines, units)?;
+            if profiles.iter().any(|other| other.name() == profile.name()) {
+                bail!("Line {line}: profile {:?} is defined twice", profile.name());
+            }
+            profiles.push(profile);
+        }
+        Ok(Self {
+            version,
+            interval,
+            units,
+            ramp,
+            mqtt,
+            state,
+            sensors,
+            profiles,
+        })
+    }
+
+    /// The configuration as a file [`Config::parse`] reads back
+    ///
+    /// Temperatures are written in [`Config::units`]. Sensor rules taking
+    /// their value from another slot aren't part of the file format and
+    /// their source is left out.
+    pub fn to_toml(&self) -> String {
+        let mut text = format!("version = {VERSION}\n");
+        let number = |value: f64| (value * 1000.0).round() / 1000.0;
+        let string = |value: &str| toml::Value::from(value).to_string();
+        let mut line = |key: &str, value: String| text.push_str(&format!("{key} = {value}\n"));
+        line("interval", number(self.interval.as_secs_f64()).to_string());
+        line("units", string(&self.units.to_string()));
+        if let Some(ramp) = self.ramp {
+            line("ramp", number(ramp.as_secs_f64()).to_string());
+        }
+        if let Some(broker) = &self.mqtt {
+            line("broker", string(broker));
+        }
+        if let Some(state) = &self.state {
+            line("state", string(&state.to_string_lossy()));
+        }
+        let degrees = |value| number(self.units.degrees(value)).to_string();
+        // Offset, cap and fallback, where a rule's source is written apart
+        let rule = |text: &mut String, rule: SlotRule| {
+            let mut line = |key: &str, value: String| text.push_str(&format!("{key} = {value}\n"));
+            if rule.offset() != 0 {
+                let offset = number(self.units.delta_degrees(rule.offset()));
+                line("offset", offset.to_string());
+            }
+            if let Some(cap) = rule.cap() {
+                line("max", degrees(cap));
+            }
+            if let Some(fallback) = rule.fallback() {
+                line("fallback", degrees(fallback));
+            }
+        };
+        for sensor in &self.sensors {
+            text.push_str("\n[[sensor]]\n");
+            let mut line = |key: &str, value: String| text.push_str(&format!("{key} = {value}\n"));
+            line("slot", (sensor.slot + 1).to_string());
+            if let Some(label) = &sensor.label {
+                line("label", string(label));
+            }
+            match &sensor.source {
+                SourceConfig::Hwmon(spec) => line("hwmon", string(spec)),
+                SourceConfig::Command(command) => line("command", string(command)),
+                SourceConfig::Fixed(value) => line("fixed", degrees(*value)),
+                SourceConfig::Mqtt(topic) => line("mqtt", string(topic)),
+            }
+            rule(&mut text, sensor.rule);
+            if let Some(filter) = sensor.filter {
+                text.push_str(&format!("filter = {}\n", string(&filter.to_string())));
+            }
+        }
+        for profile in &self.profiles {
+            text.push_str("\n[[profile]]\n");
+            let mut line = |key: &str, value: String| text.push_str(&format!("{key} = {value}\n"));
+            line("name", string(profile.name()));
+            match profile.trigger() {
+                Trigger::Process(name) => line("process", string(name)),
+                Trigger::Schedule(schedule) => line("schedule", string(&schedule.to_string())),
+            }
+            if let Some(others) = profile.other_slots() {
+                rule(&mut text, others);
+            }
+            for &(slot, slot_rule) in profile.slots() {
+                text.push_str(&format!("\n[[profile.sensor]]\nslot = {}\n", slot + 1));
+                if let Some(source) = slot_rule.source() {
+                    text.push_str(&format!("from = {}\n", source + 1));
+                }
+                rule(&mut text, slot_rule);
+            }
+        }
+        text
+    }
+
+    /// Labels of the slots that have one
+    pub fn labels(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.sensors
+            .iter()
+            .filter_map(|sensor| Some((sensor.slot, sensor.label.as_deref()?)))
+    }
+
+    /// `sensors.d` snippet giving the Octo's virtual sensors their labels
+    ///
+    /// See [`lmsensors::write_config`] for writing it in place.
+    pub fn lmsensors(&self) -> Result<String> {
+        lmsensors::config(&layout::OCTO, self.labels())
+    }
+
+    /// Read and parse the file at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    /// Engine publishing this configuration on `octo`
+    ///
+    /// Fails if an hwmon source can't be found or the MQTT broker can't be
+    /// reached.
+    pub fn engine(&self, octo: Octo) -> Result<SyncEngine> {
+        #[cfg(feature = "mqtt")]
+        let mut octo = octo;
+        #[cfg(feature = "mqtt")]
+        let mut mqtt = match &self.mqtt {
+            Some(broker) => {
+                let node_id = octo.info().map_or("octo".to_owned(), |info| info.serial);
+                let mqtt = crate::mqtt::MqttOptions::new(broker, node_id).connect();
+                Some(mqtt.with_context(|| format!("Connecting to MQTT broker {broker}"))?)
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "mqtt"))]
+        if self.mqtt.is_some() {
+            return Err(OctoError::Unsupported)
+                .context("mqtt needs octo_virtual_sensors built with the mqtt feature");
+        }
+        let mut engine = SyncEngine::new(octo).with_interval(self.interval);
+        if let Some(state) = &self.state {
+            engine = engine.with_state(StateFile::new(state));
+        }
+        for sensor in &self.sensors {
+            engine = match &sensor.source {
+                SourceConfig::Hwmon(spec) => {
+                    engine.with_source(sensor.slot, HwmonSource::from_spec(spec)?)
+                }
+                SourceConfig::Command(command) => {
+                    engine.with_source(sensor.slot, CommandSource::new(command, self.units))
+                }
+                SourceConfig::Fixed(value) => engine.with_source(sensor.slot, FixedSource(*value)),
+                #[cfg(feature = "mqtt")]
+                SourceConfig::Mqtt(topic) => {
+                    let mqtt = mqtt.as_mut().context("No mqtt broker set")?;
+                    engine.with_source(sensor.slot, mqtt.source(topic, self.units)?)
+                }
+                #[cfg(not(feature = "mqtt"))]
+                SourceConfig::Mqtt(_) => {
+                    return Err(OctoError::Unsupported)
+                        .context("mqtt needs octo_virtual_sensors built with the mqtt feature")
+                }
+            };
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = mqtt {
+            engine = engine.with_mqtt(mqtt);
+        }
+        let mut filters = Filters::new();
+        for sensor in &self.sensors {
+            if let Some(filter) = sensor.filter {
+                filters = filters.with_filter(sensor.slot, filter)?;
+            }
+        }
+        if !filters.is_empty() {
+            engine = engine.with_transform(move |values| filters.apply(values));
+        }
+        let rules: Vec<(usize, SlotRule)> = self
+            .sensors
+            .iter()
+            .map(|sensor| (sensor.slot, sensor.rule))
+            .collect();
+        engine = engine.with_transform(move |values| {
+            let mut published = values.to_vec();
+            for (slot, rule) in &rules {
+                if let Some(value) = published.get_mut(*slot) {
+                    *value = rule.apply(*slot, values);
+                }
+            }
+            published
+        });
+        if !self.profiles.is_empty() {
+            let profiles = self.profiles.clone();
+            engine = engine.with_transform(move |values| apply_profiles(&profiles, values));
+        }
+        if let Some(ramp) = self.ramp {
+            let mut interpolation = Interpolation::new(ramp);
+            engine = engine.with_transform(move |values| interpolation.apply(values));
+        }
+        Ok(engine)
+    }
+}
+
+impl SensorConfig {
+    /// Sensor from its table starting on `line`, temperatures in `units`
+    fn parse(table: SensorTable, line: usize, lines: &Lines, units: Unit) -> Result<Self> {
+        let slot = lines
+            .get("slot", table.slot, slot)?
+            .with_context(|| format!("Line {line}: [[sensor]] needs a slot"))?;
+        let mut sources = Vec::new();
+        if let Some(spec) = table.hwmon {
+            sources.push(SourceConfig::Hwmon(spec));
+        }
+        if let Some(command) = table.command {
+            sources.push(SourceConfig::Command(command));
+        }
+        if let Some(value) = lines.get("fixed", table.fixed, |value| units.centidegrees(value))? {
+            sources.push(SourceConfig::Fixed(value));
+        }
+        if let Some(topic) = table.mqtt {
+            sources.push(SourceConfig::Mqtt(topic));
+        }
+        if sources.len() > 1 {
+            bail!("Line {line}: slot {} has more than one source", slot + 1);
+        }
+        let source = sources.pop().with_context(|| {
+            format!(
+                "Line {line}: slot {} needs hwmon, command, fixed or mqtt",
+                slot + 1
+            )
+        })?;
+        let rule = RuleKeys {
+            offset: table.offset,
+            max: table.max,
+            fallback: table.fallback,
+        }
+        .parse(lines, units)?;
+        let filter = lines.get("filter", table.filter, |filter| filter.parse())?;
+        Ok(Self {
+            slot,
+            label: table.label,
+            source,
+            filter,
+            rule,
+        })
+    }
+}
+
+/// Profile from its `[[profile]]` table starting on `line`
+fn parse_profile(table: ProfileTable, line: usize, lines: &Lines, units: Unit) -> Result<Profile> {
+    let name = table
+        .name
+        .with_context(|| format!("Line {line}: [[profile]] needs a name"))?;
+    let mut triggers = Vec::new();
+    if let Some(process) = table.process {
+        triggers.push(Trigger::Process(process));
+    }
+    if let Some(schedule) = lines.get("schedule", table.schedule, |schedule| {
+        schedule.parse::<Schedule>()
+    })? {
+        triggers.push(Trigger::Schedule(schedule));
+    }
+    if triggers.len() > 1 {
+        bail!("Line {line}: profile {name:?} has both a process and a schedule");
+    }
+    let trigger = triggers
+        .pop()
+        .with_context(|| format!("Line {line}: profile {name:?} needs a process or schedule"))?;
+    let mut profile = Profile::new(name, trigger);
+    let others = RuleKeys {
+        offset: table.offset,
+        max: table.max,
+        fallback: table.fallback,
+    }
+    .parse(lines, units)?;
+    if others != SlotRule::new() {
+        profile = profile.with_other_slots(others);
+    }
+    for sensor in table.sensor {
+        let line = lines.line(&sensor.span());
+        let sensor = sensor.into_inner();
+        let slot = lines
+            .get("slot", sensor.slot, slot)?
+            .with_context(|| format!("Line {line}: [[profile.sensor]] needs a slot"))?;
+        if profile.slots().iter().any(|&(other, _)| other == slot) {
+            bail!("Line {line}: slot {} has two rules", slot + 1);
+        }
+        let mut rule = RuleKeys {
+            offset: sensor.offset,
+            max: sensor.max,
+            fallback: sensor.fallback,
+        }
+        .parse(lines, units)?;
+        if let Some(from) = lines.get("from", sensor.from, self::slot)? {
+            rule = rule.with_source(from);
+        }
+        profile = profile.with_slot(slot, rule);
+    }
+    Ok(profile)
+}
+
+/// Values rewritten by the first active profile
+///
+/// When the running processes or the time can't be read the values are
+/// published unchanged.
+fn apply_profiles(profiles: &[Profile], values: &[Option<i16>]) -> Vec<Option<i16>> {
+    let scan = profiles
+        .iter()
+        .any(|profile| matches!(profile.trigger(), Trigger::Process(_)));
+    let processes = if scan {
+        Processes::scan().map_err(crate::Error::from)
+    } else {
+        Ok(Processes::default())
+    };
+    let conditions =
+        processes.and_then(|processes| Ok(Conditions::new(processes, TimeOfDay::now()?)));
+    match conditions {
+        Ok(conditions) => match profile::active(profiles, &conditions) {
+            Some(profile) => profile.apply(values),
+            None => values.to_vec(),
+        },
+        Err(error) => {
+            warn!("Checking profiles: {error:#}");
+            values.to_vec()
+        }
+    }
+}
+
+/// Slot index from a slot numbered from 1
+fn slot(slot: i64) -> Result<usize> {
+    let count = layout::OCTO.virtual_sensors.sensor_count;
+    if !(1..=count as i64).contains(&slot) {
+        bail!("Slots are numbered from 1 to {count}");
+    }
+    Ok(slot as usize - 1)
+}
+
+/// The top-level table as written, before anything is checked
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    version: Option<Spanned<i64>>,
+    interval: Option<Spanned<f64>>,
+    units: Option<Spanned<String>>,
+    ramp: Option<Spanned<f64>>,
+    broker: Option<String>,
+    state: Option<String>,
+    #[serde(default)]
+    sensor: Vec<Spanned<SensorTable>>,
+    #[serde(default)]
+    profile: Vec<Spanned<ProfileTable>>,
+}
+
+/// A `[[sensor]]` table as written, temperatures in the file's units
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SensorTable {
+    slot: Option<Spanned<i64>>,
+    label: Option<String>,
+    hwmon: Option<String>,
+    command: Option<String>,
+    fixed: Option<Spanned<f64>>,
+    mqtt: Option<String>,
+    offset: Option<Spanned<f64>>,
+    max: Option<Spanned<f64>>,
+    fallback: Option<Spanned<f64>>,
+    filter: Option<Spanned<String>>,
+}
+
+/// A `[[profile]]` table as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileTable {
+    name: Option<String>,
+    process: Option<String>,
+    schedule: Option<Spanned<String>>,
+    offset: Option<Spanned<f64>>,
+    max: Option<Spanned<f64>>,
+    fallback: Option<Spanned<f64>>,
+    #[serde(default)]
+    sensor: Vec<Spanned<ProfileSensorTable>>,
+}
+
+/// A `[[profile.sensor]]` table as written
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileSensorTable {
+    slot: Option<Spanned<i64>>,
+    from: Option<Spanned<i64>>,
+    offset: Option<Spanned<f64>>,
+    max: Option<Spanned<f64>>,
+    fallback: Option<Spanned<f64>>,
+}
+
+/// Offset, cap and fallback keys of a table, temperatures in the file's
+/// units
+struct RuleKeys {
+    offset: Option<Spanned<f64>>,
+    max: Option<Spanned<f64>>,
+    fallback: Option<Spanned<f64>>,
+}
+
+impl RuleKeys {
+    /// The rule, temperatures converted from `units`
+    fn parse(self, lines: &Lines, units: Unit) -> Result<SlotRule> {
+        let mut rule = SlotRule::new();
+        if let Some(offset) = lines.get("offset", self.offset, |offset| {
+            units.delta_centidegrees(offset)
+        })? {
+            rule = rule.with_offset(offset);
+        }
+        if let Some(max) = lines.get("max", self.max, |max| units.centidegrees(max))? {
+            rule = rule.with_cap(max);
+        }
+        if let Some(fallback) =
+            lines.get("fallback", self.fallback, |value| units.centidegrees(value))?
+        {
+            rule = rule.with_fallback(fallback);
+        }
+        Ok(rule)
+    }
+}
+
+/// A file brought up to date by [`migrate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migrated {
+    /// Version the file was at
+    pub from: u32,
+    /// The file at [`VERSION`], unchanged if it already was
+    pub text: String,
+}
+
+/// Bring a configuration file up to [`VERSION`]
+///
+/// Comments and layout are kept, apart from renamed keys moving to the end
+/// of their table. Fails with [`OctoError::InvalidConfig`] for files newer
+/// than this release.
+pub fn migrate(text: &str) -> Result<Migrated> {
+    migrate_file(text).map_err(invalid)
+}
+
+/// [`migrate`] without the failure's kind
+fn migrate_file(text: &str) -> Result<Migrated> {
+    let mut document: DocumentMut = text.parse().context("Not a TOML file")?;
+    let from = upgrade(&mut document)?;
+    if from == VERSION {
+        return Ok(Migrated {
+            from,
+            text: text.to_owned(),
+        });
+    }
+    match document.get_mut("version").and_then(Item::as_value_mut) {
+        Some(version) => {
+            let decor = version.decor().clone();
+            *version = i64::from(VERSION).into();
+            *version.decor_mut() = decor;
+        }
+        None => {
+            // Re-insert everything after the version, which goes first
+            let root = document.as_table_mut();
+            let keys: Vec<String> = root.iter().map(|(key, _)| key.to_owned()).collect();
+            let mut entries: Vec<(Key, Item)> = keys
+                .iter()
+                .filter_map(|key| root.remove_entry(key))
+                .collect();
+            let mut version = Key::new("version");
+            if let Some((first, _)) = entries.first_mut().filter(|(_, item)| item.is_value()) {
+                // A comment heading the file stays at the top
+                if let Some(prefix) = first.leaf_decor().prefix().cloned() {
+                    version.leaf_decor_mut().set_prefix(prefix);
+                    first.leaf_decor_mut().set_prefix("");
+                }
+            }
+            root.insert_formatted(&version, toml_edit::value(i64::from(VERSION)));
+            for (key, item) in entries {
+                root.insert_formatted(&key, item);
+            }
+        }
+    }
+    Ok(Migrated {
+        from,
+        text: document.to_string(),
+    })
+}
+
+/// Apply the migrations from the file's version on, returning that version
+///
+/// Leaves `version` itself alone.
+fn upgrade(document: &mut DocumentMut) -> Result<u32> {
+    let from = file_version(document)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(from as usize - 1) {
+        let to = index + 2;
+        migration(document).with_context(|| format!("Migrating to version {to}"))?;
+    }
+    Ok(from)
+}
+
+/// Version a file says it's at, 1 if it doesn't say
+fn file_version(document: &DocumentMut) -> Result<u32> {
+    let Some(version) = document.get("version") else {
+        return Ok(1);
+    };
+    let version = version
+        .as_integer()
+        .context("version: expected a whole number")?;
+    match u32::try_from(version) {
+        Ok(version @ 1..=VERSION) => Ok(version),
+        _ => bail!("Version {version} is not supported, expected 1 to {VERSION}"),
+    }
+}
+
+/// Version 2: the top-level `mqtt` broker became `broker`
+fn rename_broker(document: &mut DocumentMut) -> Result<()> {
+    let root = document.as_table_mut();
+    let Some((key, item)) = root.remove_entry("mqtt") else {
+        return Ok(());
+    };
+    if root.contains_key("broker") {
+        bail!("Both mqtt and broker are set");
+    }
+    let mut broker = Key::new("broker");
+    *broker.leaf_decor_mut() = key.leaf_decor().clone();
+    root.insert_formatted(&broker, item);
+    Ok(())
+}
+
+/// `error` as an [`OctoError::InvalidConfig`], unless it has a kind already
+///
+/// The kind goes underneath, so the messages are joined into one.
+pub(crate) fn invalid(error: crate::Error) -> crate::Error {
+    if error.kind().is_some() {
+        return error;
+    }
+    crate::Error::from(OctoError::InvalidConfig).context(format!("{error:#}"))
+}
+
+/// The file's text, for turning spans into line numbers
+struct Lines<'a>(&'a str);
+
+impl Lines<'_> {
+    /// Line, numbered from 1, that `span` starts on
+    fn line(&self, span: &Range<usize>) -> usize {
+        let before = self.0.get(..span.start).unwrap_or(self.0);
+        before.matches('\n').count() + 1
+    }
+
+    /// Convert `value` if it's set, naming `key` and its line on failure
+    fn get<T, U>(
+        &self,
+        key: &str,
+        value: Option<Spanned<T>>,
+        convert: impl FnOnce(T) -> Result<U>,
+    ) -> Result<Option<U>> {
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        let line = self.line(&value.span());
+        convert(value.into_inner())
+            .map(Some)
+            .with_context(|| format!("Line {line}: {key}"))
+    }
+}
+
+/// Duration from a number of seconds
+fn seconds(seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds).with_context(|| format!("Bad duration {seconds}"))
+}
+
+/// Serde for durations as a number of seconds
+mod seconds {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    /// `duration` in seconds
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    /// Duration from a number of seconds
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let seconds = f64::deserialize(deserializer)?;
+        super::seconds(seconds).map_err(D::Error::custom)
+    }
+}
+
+/// Serde for optional durations as a number of seconds or `null`
+mod optional_seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    /// `duration` in seconds, `null` for `None`
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::seconds::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Duration from a number of seconds, `None` for `null`
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        /// A duration that isn't optional
+        #[derive(Deserialize)]
+        struct Seconds(#[serde(with = "super::seconds")] Duration);
+        let seconds = Option::<Seconds>::deserialize(deserializer)?;
+        Ok(seconds.map(|Seconds(duration)| duration))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{migrate, Config, SourceConfig, VERSION};
+    use crate::{
+        emulator::Emulator,
+        profile::{Profile, SlotRule, Trigger},
+        transform::FilterConfig,
+        units::Unit,
+        Octo, OctoError,
+    };
+    use std::time::Duration;
+
+    /// Everything the format supports
+    static FULL: &str = r#"
+version = 2
+interval = 0.5   # twice a second
+units = "fahrenheit"
+
+[[sensor]]
+slot = 1
+command = 'echo "212 # boiling"'
+offset = -9
+max = 194.0
+filter = "exponential:0.5"
+
+[[sensor]]
+slot = 16
+label = "Ambient \"room\""
+fixed = 77
+fallback = 32
+
+[[profile]]
+name = "benchmark"
+process = "superposition"
+offset = 9
+
+[[profile.sensor]]
+slot = 2
+from = 1
+max = 194
+"#;
+
+    /// Every key ends up in the parsed configuration
+    #[test]
+    fn parse() {
+        let config = Config::parse(FULL).unwrap();
+        assert_eq!(config.version, VERSION);
+        assert_eq!(config.interval, Duration::from_millis(500));
+        assert_eq!(config.units, Unit::Fahrenheit);
+        assert_eq!(config.ramp, None);
+        assert_eq!(config.sensors.len(), 2);
+        assert_eq!(
+            config.sensors[0].source,
+            SourceConfig::Command(r#"echo "212 # boiling""#.to_owned())
+        );
+        assert_eq!(
+            config.sensors[0].rule,
+            SlotRule::new().with_offset(-500).with_cap(9000)
+        );
+        assert_eq!(
+            config.sensors[0].filter,
+            Some(FilterConfig::Exponential(0.5))
+        );
+        assert_eq!(config.sensors[0].label, None);
+        assert_eq!(config.sensors[1].slot, 15);
+        assert_eq!(
+            config.sensors[1].label.as_deref(),
+            Some(r#"Ambient "room""#)
+        );
+        assert_eq!(config.sensors[1].filter, None);
+        assert_eq!(config.sensors[1].source, SourceConfig::Fixed(2500));
+        assert_eq!(config.sensors[1].rule, SlotRule::new().with_fallback(0));
+        let benchmark = Profile::new("benchmark", Trigger::Process("superposition".into()))
+            .with_other_slots(SlotRule::new().with_offset(500))
+            .with_slot(1, SlotRule::new().with_source(0).with_cap(9000));
+        assert_eq!(config.profiles, [benchmark]);
+    }
+
+    /// Defaults for everything but the sensors
+    #[test]
+    fn defaults() {
+        let config = Config::parse("[[sensor]]\nslot = 2\nhwmon = \"k10temp/temp1\"").unwrap();
+        assert_eq!(config.interval, Duration::from_secs(1));
+        assert_eq!(config.units, Unit::Celsius);
+        assert_eq!(
+            config.sensors[0].source,
+            SourceConfig::Hwmon("k10temp/temp1".to_owned())
+        );
+        assert_eq!(Config::parse("").unwrap().sensors, []);
+    }
+
+    /// MQTT sources name a topic on the file's broker
+    #[test]
+    fn mqtt() {
+        let config =
+            Config::parse("broker = \"broker:1883\"\n[[sensor]]\nslot = 4\nmqtt = \"room\"")
+                .unwrap();
+        assert_eq!(config.mqtt.as_deref(), Some("broker:1883"));
+        assert_eq!(config.sensors[0].slot, 3);
+        assert_eq!(
+            config.sensors[0].source,
+            SourceConfig::Mqtt("room".to_owned())
+        );
+    }
+
+    /// Labels become an lm-sensors snippet for the virtual sensor channels
+    #[test]
+    fn lmsensors() {
+        let config = Config::parse(FULL).unwrap();
+        assert_eq!(
+            config.labels().collect::<Vec<_>>(),
+            [(15, r#"Ambient "room""#)]
+        );
+        let snippet = config.lmsensors().unwrap();
+        assert!(snippet.contains("chip \"octo-*\"\n"), "{snippet}");
+        assert!(
+            snippet.ends_with("    label temp20 \"Ambient \\\"room\\\"\"\n"),
+            "{snippet}"
+        );
+    }
+
+    /// Configurations survive a trip through JSON
+    #[test]
+    fn json() {
+        let mut config = Config::parse(FULL).unwrap();
+        config.ramp = Some(Duration::from_secs(30));
+        config.sensors[1].rule = config.sensors[1].rule.with_source(3);
+        let text = serde_json::to_string(&config).unwrap();
+        assert!(text.contains(r#""source":{"fixed":2500}"#), "{text}");
+        assert!(text.contains(r#""filter":"exponential:0.5""#), "{text}");
+        assert_eq!(serde_json::from_str::<Config>(&text).unwrap(), config);
+    }
+
+    /// Written files parse back into the same configuration
+    #[test]
+    fn to_toml() {
+        let mut config = Config::parse(FULL).unwrap();
+        assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+        config.units = Unit::Celsius;
+        config.ramp = Some(Duration::from_secs(30));
+        config.mqtt = Some("nas:1883".to_owned());
+        config.state = Some("/var/lib/octo-vs/state".into());
+        config.sensors[1].source = SourceConfig::Mqtt("room".to_owned());
+        let text = config.to_toml();
+        assert!(text.starts_with("version = 2\ninterval = 0.5\nunits = \"celsius\"\n"));
+        assert!(text.contains("broker = \"nas:1883\"\nstate = \"/var/lib/octo-vs/state\"\n"));
+        assert!(text.contains("\n[[sensor]]\nslot = 1\ncommand = 'echo \"212 # boiling\"'\n"));
+        assert!(text.contains("offset = -5\nmax = 90\nfilter = \"exponential:0.5\"\n"));
+        assert!(text.ends_with(
+            "\n[[profile]]\nname = \"benchmark\"\nprocess = \"superposition\"\noffset = 5\n\n\
+             [[profile.sensor]]\nslot = 2\nfrom = 1\nmax = 90\n"
+        ));
+        assert_eq!(Config::parse(&text).unwrap(), config);
+    }
+
+    /// Old files are brought up to date, keeping their comments
+    #[test]
+    fn migrations() {
+        let old = "# Living room\nversion = 1\n# The NAS\nmqtt = \"nas:1883\"  # no TLS\n\n\
+                   [[sensor]]\nslot = 1\nmqtt = \"room\"\n";
+        let migrated = migrate(old).unwrap();
+        assert_eq!(migrated.from, 1);
+        assert_eq!(
+            migrated.text,
+            "# Living room\nversion = 2\n# The NAS\nbroker = \"nas:1883\"  # no TLS\n\n\
+             [[sensor]]\nslot = 1\nmqtt = \"room\"\n"
+        );
+        assert_eq!(migrate(&migrated.text).unwrap().from, VERSION);
+        assert_eq!(migrate(&migrated.text).unwrap().text, migrated.text);
+        let config = Config::parse(old).unwrap();
+        assert_eq!(config, Config::parse(&migrated.text).unwrap());
+        assert_eq!(config.version, VERSION);
+        assert_eq!(config.mqtt.as_deref(), Some("nas:1883"));
+
+        let unversioned = migrate("# Desk\ninterval = 2\nmqtt = \"nas\"\n").unwrap();
+        assert_eq!(
+            unversioned.text,
+            "# Desk\nversion = 2\ninterval = 2\nbroker = \"nas\"\n"
+        );
+        let error = migrate("version = 1\nmqtt = \"a\"\nbroker = \"b\"").unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Migrating to version 2: Both mqtt and broker are set: Invalid configuration"
+        );
+        assert_eq!(error.kind(), Some(OctoError::InvalidConfig));
+        assert!(migrate("version = 3").is_err());
+        assert!(migrate("version = \"1\"").is_err());
+    }
+
+    /// Mistakes are reported with their line
+    #[test]
+    fn errors() {
+        let cases = [
+            ("version = 3", "Line 1: version"),
+            ("interval = 0", "The interval must be more than 0 seconds"),
+            ("interval = \"1s\"", "Line 1: invalid type: string"),
+            ("units = \"rankine\"", "Line 1: units"),
+            ("colour = 1", "Line 1: unknown field `colour`"),
+            ("[sensors]", "Line 1: unknown field `sensors`"),
+            ("[sensor]", "Line 1: invalid type: map"),
+            ("slot", "Line 1: key with no value"),
+            ("ramp = \"unterminated", "Line 1: invalid basic string"),
+            ("ramp = 1\nramp = 2", "Line 2: duplicate key"),
+            ("[[sensor]]\nfixed = 20", "Line 1: [[sensor]] needs a slot"),
+            ("[[sensor]]\nslot = 17\nfixed = 20", "Line 2: slot"),
+            ("[[sensor]]\nslot = 1.5\nfixed = 20", "Line 2: invalid type"),
+            (
+                "[[sensor]]\nslot = 1\ncolour = 3",
+                "Line 3: unknown field `colour`",
+            ),
+            ("[[sensor]]\nslot = 1", "Line 1: slot 1 needs hwmon"),
+            (
+                "[[sensor]]\nslot = 1\nfixed = 1\ncommand = 'true'",
+                "Line 1: slot 1 has more than one source",
+            ),
+            (
+                "[[sensor]]\nslot = 1\nfixed = 1\n[[sensor]]\nslot = 1\nfixed = 2",
+                "Line 4: slot 1 is mapped twice",
+            ),
+            ("[[sensor]]\nslot = 1\nfixed = 400", "Line 3: fixed"),
+            (
+                "[[sensor]]\nslot = 1\nfixed = 1\nfilter = \"median:0\"",
+                "Line 4: filter",
+            ),
+            (
+                "[[sensor]]\nslot = 2\nmqtt = 'room'",
+                "Line 1: slot 2 reads MQTT but no broker is set",
+            ),
+            (
+                "[[profile]]\nprocess = 'x'",
+                "Line 1: [[profile]] needs a name",
+            ),
+            (
+                "[[profile]]\nname = 'x'",
+                "Line 1: profile \"x\" needs a process or schedule",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nprocess = 'y'\nschedule = '23:00-07:00'",
+                "Line 1: profile \"x\" has both a process and a schedule",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nschedule = '23:00'",
+                "Line 3: schedule",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nprocess = 'y'\n[[profile]]\nname = 'x'\nprocess = 'z'",
+                "Line 4: profile \"x\" is defined twice",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nprocess = 'y'\n[[profile.sensor]]\nfrom = 1",
+                "Line 4: [[profile.sensor]] needs a slot",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nprocess = 'y'\n[[profile.sensor]]\nslot = 1\nfrom = 17",
+                "Line 6: from",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nprocess = 'y'\n\
+                 [[profile.sensor]]\nslot = 1\n[[profile.sensor]]\nslot = 1",
+                "Line 6: slot 1 has two rules",
+            ),
+            (
+                "[[profile]]\nname = 'x'\nprocess = 'y'\nfilter = 'median:3'",
+                "Line 4: unknown field `filter`",
+            ),
+        ];
+        for (text, expected) in cases {
+            let error = Config::parse(text).unwrap_err();
+            let kind = if expected == "Line 3: fixed" {
+                OctoError::OutOfRange
+            } else {
+                OctoError::InvalidConfig
+            };
+            assert_eq!(error.kind(), Some(kind), "{text:?}");
+            let error = format!("{error:#}");
+            assert!(error.starts_with(expected), "{text:?} gave {error:?}");
+        }
+    }
+
+    /// The engine publishes each source through its rule
+    #[test]
+    fn engine() {
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let config = Config::parse(FULL).unwrap();
+        let values = config.engine(octo).unwrap().tick().unwrap().unwrap();
+        assert_eq!(values[0], Some(9000));
+        assert_eq!(values[15], Some(2500));
+        assert_eq!(emulator.virtual_sensors()[15], Some(2500));
+    }
+
+    /// The first active profile rewrites the published values
+    #[test]
+    fn profiles() {
+        let text = r#"
+[[sensor]]
+slot = 1
+fixed = 40
+
+[[profile]]
+name = "never"
+process = "no-such-process"
+offset = 50
+
+[[profile]]
+name = "always"
+schedule = "12:00-12:00"
+max = 35
+
+[[profile.sensor]]
+slot = 2
+from = 1
+"#;
+        let config = Config::parse(text).unwrap();
+        assert_eq!(config.profiles.len(), 2);
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let values = config.engine(octo).unwrap().tick().unwrap().unwrap();
+        assert_eq!(values, [Some(3500), Some(4000)]);
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(3500), Some(4000)]);
+    }
+
+    /// A state file is restored on the first tick and saved on each one
+    #[test]
+    fn state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state");
+        std::fs::write(&path, format!("saved {}\n4 3300\n", now_secs())).unwrap();
+        let text = format!(
+            "state = {}\n[[sensor]]\nslot = 1\nfixed = 20\n",
+            toml::Value::from(path.to_str().unwrap())
+        );
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(config.state.as_deref(), Some(path.as_path()));
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let values = config.engine(octo).unwrap().tick().unwrap().unwrap();
+        assert_eq!(values, [Some(2000), None, None, None, Some(3300)]);
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.ends_with("\n0 2000\n4 3300\n"), "{saved}");
+    }
+
+    /// Seconds since the epoch, as state files record saving
+    fn now_secs() -> u64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
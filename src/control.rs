@@ -0,0 +1,398 @@
+//! Decoding the device's control report
+//!
+//! The control report is a feature report holding the device's settings,
+//! including how each fan channel is driven. Modes and fields are decoded
+//! the way the aquacomputer_d5next hwmon driver does.
+#![deny(clippy::indexing_slicing)]
+use crate::error::{Context, Result};
+use crate::{
+    codec,
+    curve::{FanCurve, FULL_DUTY},
+    layout::{fan_control, ControlLayout, SENSOR_SIZE},
+    OctoError,
+};
+
+/// How the firmware drives a fan channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMode {
+    /// Fixed duty cycle
+    Manual,
+    /// PID controller holding a target temperature
+    Pid,
+    /// Duty cycle from a temperature curve
+    Curve,
+    /// Same duty cycle as another channel
+    Follow,
+    /// A mode this crate doesn't know
+    Unknown(u8),
+}
+
+impl ControlMode {
+    /// Mode for a raw mode byte
+    pub fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => Self::Manual,
+            1 => Self::Pid,
+            2 => Self::Curve,
+            3 => Self::Follow,
+            raw => Self::Unknown(raw),
+        }
+    }
+
+    /// Raw mode byte for the mode
+    pub fn raw(self) -> u8 {
+        match self {
+            Self::Manual => 0,
+            Self::Pid => 1,
+            Self::Curve => 2,
+            Self::Follow => 3,
+            Self::Unknown(raw) => raw,
+        }
+    }
+}
+
+/// Temperature a fan channel follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureSource {
+    /// Physical sensor, numbered from 0
+    Sensor(u16),
+    /// Virtual sensor slot, numbered from 0
+    VirtualSensor(usize),
+    /// Any other source, such as a flow or software sensor
+    Other(u16),
+}
+
+impl TemperatureSource {
+    /// Source for a raw source index
+    pub fn from_raw(layout: &ControlLayout, raw: u16) -> Self {
+        match raw.checked_sub(layout.virtual_sensor_source) {
+            None => Self::Sensor(raw),
+            Some(slot) if usize::from(slot) < layout.virtual_sensor_count => {
+                Self::VirtualSensor(usize::from(slot))
+            }
+            Some(_) => Self::Other(raw),
+        }
+    }
+
+    /// Raw source index for the source
+    pub fn raw(self, layout: &ControlLayout) -> u16 {
+        match self {
+            Self::Sensor(raw) | Self::Other(raw) => raw,
+            Self::VirtualSensor(slot) => layout.virtual_sensor_source.saturating_add(slot as u16),
+        }
+    }
+}
+
+/// Settings of one fan channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FanControl {
+    /// How the channel is driven
+    pub mode: ControlMode,
+    /// Duty cycle used in manual mode, in centipercent
+    pub duty: u16,
+    /// Temperature the curve follows
+    pub source: TemperatureSource,
+    /// `(centidegrees, duty)` curve points as stored on the device
+    pub curve: Vec<(i16, u16)>,
+}
+
+impl FanControl {
+    /// Duty cycle the firmware would apply with the source at `temperature`
+    ///
+    /// Manual mode ignores the temperature. `None` for modes that can't be
+    /// worked out from one reading, such as PID control.
+    pub fn duty_at(&self, temperature: i16) -> Option<u16> {
+        match self.mode {
+            ControlMode::Manual => Some(self.duty.min(FULL_DUTY)),
+            ControlMode::Curve => Some(self.fan_curve()?.interpolate(temperature)),
+            _ => None,
+        }
+    }
+
+    /// The stored curve as a [`FanCurve`]
+    ///
+    /// Unused trailing points repeat a temperature, so points that don't
+    /// raise the temperature are dropped. `None` if no point is left.
+    pub fn fan_curve(&self) -> Option<FanCurve> {
+        let mut points: Vec<(i16, u16)> = Vec::with_capacity(self.curve.len());
+        for &(temperature, duty) in &self.curve {
+            if points.last().is_none_or(|&(last, _)| temperature > last) {
+                points.push((temperature, duty.min(FULL_DUTY)));
+            }
+        }
+        FanCurve::new(&points).ok()
+    }
+}
+
+/// Control report as read from the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlReport {
+    layout: ControlLayout,
+    buffer: Vec<u8>,
+}
+
+impl ControlReport {
+    /// Create a report with every setting zeroed
+    ///
+    /// Every fan is in manual mode at zero duty. Only useful as a starting
+    /// point for tests and emulation; real settings should always be read
+    /// from the device first.
+    pub fn new(layout: ControlLayout) -> Self {
+        let mut buffer = vec![0; layout.len];
+        if let Some(id) = buffer.first_mut() {
+            *id = layout.report_id;
+        }
+        layout.checksum.apply(&mut buffer);
+        Self { layout, buffer }
+    }
+
+    /// Parse a raw control report
+    ///
+    /// Fails if the length, report ID or checksum don't match the layout.
+    pub fn parse(layout: ControlLayout, report: &[u8]) -> Result<Self> {
+        if report.len() != layout.len {
+            return Err(OctoError::InvalidReport).with_context(|| {
+                format!(
+                    "Expected {} byte control report, got {}",
+                    layout.len,
+                    report.len()
+                )
+            });
+        }
+        let id = report.first().copied().unwrap_or_default();
+        if id != layout.report_id {
+            return Err(OctoError::InvalidReport)
+                .with_context(|| format!("Expected report ID {}, got {id}", layout.report_id));
+        }
+        if !layout.checksum.verify(report) {
+            return Err(OctoError::ChecksumMismatch)
+                .with_context(|| format!("{} checksum mismatch", layout.checksum.name()));
+        }
+        Ok(Self {
+            layout,
+            buffer: report.to_vec(),
+        })
+    }
+
+    /// Layout the report was parsed with
+    pub fn layout(&self) -> &ControlLayout {
+        &self.layout
+    }
+
+    /// Raw report bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Number of fan channels
+    pub fn fan_count(&self) -> usize {
+        self.layout.fans.len()
+    }
+
+    /// Settings of fan `channel`, numbered from 0
+    pub fn fan(&self, channel: usize) -> Result<FanControl> {
+        let block = self.fan_block(channel)?;
+        let report = &self.buffer;
+        let mode = *report
+            .get(block + fan_control::MODE)
+            .context("Fan control block is past the end of the report")?;
+        let source = codec::get_u16(report, block + fan_control::TEMPERATURE_SOURCE)?;
+        let curve = (0..fan_control::CURVE_POINTS)
+            .map(|point| {
+                let offset = SENSOR_SIZE * point;
+                let temperature = block + fan_control::CURVE_TEMPERATURES + offset;
+                let duty = block + fan_control::CURVE_DUTIES + offset;
+                Ok((
+                    codec::get_i16(report, temperature)?,
+                    codec::get_u16(report, duty)?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(FanControl {
+            mode: ControlMode::from_raw(mode),
+            duty: codec::get_u16(report, block + fan_control::DUTY)?,
+            source: TemperatureSource::from_raw(&self.layout, source),
+            curve,
+        })
+    }
+
+    /// Change the settings of fan `channel` and recompute the checksum
+    ///
+    /// Curve points past the end of `control.curve` repeat the last one, so
+    /// the firmware holds the curve flat. Fails for channels the device
+    /// doesn't have.
+    pub fn set_fan(&mut self, channel: usize, control: &FanControl) -> Result<()> {
+        let block = self.fan_block(channel)?;
+        let source = control.source.raw(&self.layout);
+        let report = &mut self.buffer;
+        *report
+            .get_mut(block + fan_control::MODE)
+            .context("Fan control block is past the end of the report")? = control.mode.raw();
+        codec::put_u16(report, block + fan_control::DUTY, control.duty)?;
+        codec::put_u16(report, block + fan_control::TEMPERATURE_SOURCE, source)?;
+        let last = control.curve.last().copied().unwrap_or_default();
+        for point in 0..fan_control::CURVE_POINTS {
+            let (temperature, duty) = control.curve.get(point).copied().unwrap_or(last);
+            let offset = SENSOR_SIZE * point;
+            codec::put_i16(
+                report,
+                block + fan_control::CURVE_TEMPERATURES + offset,
+                temperature,
+            )?;
+            codec::put_u16(report, block + fan_control::CURVE_DUTIES + offset, duty)?;
+        }
+        self.layout.checksum.apply(&mut self.buffer);
+        Ok(())
+    }
+
+    /// Flow sensor calibration in impulses per litre
+    pub fn flow_pulses(&self) -> Result<u16> {
+        codec::get_u16(&self.buffer, self.flow_pulses_offset()?)
+    }
+
+    /// Change the flow sensor calibration and recompute the checksum
+    pub fn set_flow_pulses(&mut self, pulses: u16) -> Result<()> {
+        let offset = self.flow_pulses_offset()?;
+        codec::put_u16(&mut self.buffer, offset, pulses)?;
+        self.layout.checksum.apply(&mut self.buffer);
+        Ok(())
+    }
+
+    /// Offset of the flow calibration
+    fn flow_pulses_offset(&self) -> Result<usize> {
+        self.layout
+            .flow_pulses
+            .context("The device has no flow sensor calibration")
+    }
+
+    /// Offset of fan `channel`'s block
+    fn fan_block(&self, channel: usize) -> Result<usize> {
+        self.layout.fans.get(channel).copied().with_context(|| {
+            format!(
+                "Fan channel {channel} doesn't exist, the device has {}",
+                self.fan_count()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod test {
+    use super::{ControlMode, ControlReport, FanControl, TemperatureSource};
+    use crate::{codec, layout::OCTO};
+
+    /// The flow calibration round trips and leaves the fans alone
+    #[test]
+    fn flow_pulses() {
+        let mut report = ControlReport::new(OCTO.control);
+        let fan = report.fan(0).unwrap();
+        report.set_flow_pulses(169).unwrap();
+        assert_eq!(report.flow_pulses().unwrap(), 169);
+        assert_eq!(report.as_bytes()[0x06..0x08], [0, 169]);
+        assert_eq!(report.fan(0).unwrap(), fan);
+        assert!(ControlReport::parse(OCTO.control, report.as_bytes()).is_ok());
+    }
+
+    /// A control report with fan 2 on a curve following virtual sensor 3
+    fn curve_report() -> Vec<u8> {
+        let layout = OCTO.control;
+        let mut report = vec![0; layout.len];
+        report[0] = layout.report_id;
+        let block = layout.fans[2];
+        report[block] = 2;
+        codec::put_u16(&mut report, block + 3, 7).unwrap();
+        // Unused points repeat the last one
+        let points = [(2000, 2000), (4000, 6000)]
+            .into_iter()
+            .chain(std::iter::repeat((5000, 10000)))
+            .take(16);
+        for (index, (temperature, duty)) in points.enumerate() {
+            codec::put_i16(&mut report, block + 0x15 + 2 * index, temperature).unwrap();
+            codec::put_u16(&mut report, block + 0x35 + 2 * index, duty).unwrap();
+        }
+        layout.checksum.apply(&mut report);
+        report
+    }
+
+    /// Mode, source and curve are decoded from the fan's block
+    #[test]
+    fn parse_fan() {
+        let report = ControlReport::parse(OCTO.control, &curve_report()).unwrap();
+        assert_eq!(report.fan_count(), 8);
+        let fan = report.fan(2).unwrap();
+        assert_eq!(fan.mode, ControlMode::Curve);
+        assert_eq!(fan.source, TemperatureSource::VirtualSensor(3));
+        assert_eq!(fan.curve[1], (4000, 6000));
+        assert_eq!(fan.fan_curve().unwrap().points().len(), 3);
+        assert_eq!(report.fan(0).unwrap().mode, ControlMode::Manual);
+        assert!(report.fan(8).is_err());
+    }
+
+    /// The duty cycle follows the curve, or the manual setting
+    #[test]
+    fn duty_at() {
+        let report = ControlReport::parse(OCTO.control, &curve_report()).unwrap();
+        let fan = report.fan(2).unwrap();
+        assert_eq!(fan.duty_at(1000), Some(2000));
+        assert_eq!(fan.duty_at(3000), Some(4000));
+        assert_eq!(fan.duty_at(6000), Some(10000));
+        let manual = FanControl {
+            mode: ControlMode::Manual,
+            duty: 4200,
+            ..fan.clone()
+        };
+        assert_eq!(manual.duty_at(9000), Some(4200));
+        let pid = FanControl {
+            mode: ControlMode::Pid,
+            ..fan
+        };
+        assert_eq!(pid.duty_at(3000), None);
+    }
+
+    /// Sources below the virtual sensors are physical, above are other
+    #[test]
+    fn temperature_sources() {
+        let layout = OCTO.control;
+        assert_eq!(
+            TemperatureSource::from_raw(&layout, 1),
+            TemperatureSource::Sensor(1)
+        );
+        assert_eq!(
+            TemperatureSource::from_raw(&layout, 19),
+            TemperatureSource::VirtualSensor(15)
+        );
+        assert_eq!(
+            TemperatureSource::from_raw(&layout, 20),
+            TemperatureSource::Other(20)
+        );
+    }
+
+    /// Settings written to a block read back the same
+    #[test]
+    fn set_fan_round_trip() {
+        let mut report = ControlReport::new(OCTO.control);
+        let control = FanControl {
+            mode: ControlMode::Curve,
+            duty: 0,
+            source: TemperatureSource::VirtualSensor(3),
+            curve: vec![(2000, 2000), (4000, 6000), (5000, 10000)],
+        };
+        report.set_fan(2, &control).unwrap();
+        assert_eq!(report.as_bytes(), curve_report());
+        let fan = report.fan(2).unwrap();
+        assert_eq!(fan.curve.len(), 16);
+        assert_eq!(fan.curve[15], (5000, 10000));
+        assert_eq!(fan.fan_curve(), control.fan_curve());
+        assert!(report.set_fan(8, &control).is_err());
+    }
+
+    /// Truncated or corrupt reports are errors
+    #[test]
+    fn reject_bad_reports() {
+        let mut report = curve_report();
+        assert!(ControlReport::parse(OCTO.control, &report[..100]).is_err());
+        report[0x100] ^= 1;
+        assert!(ControlReport::parse(OCTO.control, &report).is_err());
+    }
+}
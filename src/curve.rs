@@ -0,0 +1,176 @@
+//! Fan curves evaluated on the host
+//!
+//! Instead of programming a curve into the device, a [`FanCurve`] turns a
+//! temperature from any source into a duty cycle on the host. Duty cycles
+//! are in hundredths of a percent, the unit the device's control report
+//! uses, so 10000 is full speed.
+use crate::error::{Context, Result};
+use crate::OctoError;
+
+/// Full speed as a duty cycle
+pub const FULL_DUTY: u16 = 10000;
+
+/// Temperature to duty cycle curve with hysteresis and limits
+///
+/// The duty cycle is interpolated linearly between points and held flat
+/// beyond the first and last one. It rises as soon as the temperature
+/// does, but only falls once the temperature has dropped by the
+/// hysteresis, so fans don't hunt around a point. A missing temperature
+/// runs the fans at the failsafe duty.
+///
+/// ```
+/// use octo_virtual_sensors::curve::FanCurve;
+/// let mut curve = FanCurve::new(&[(3000, 2000), (4000, 8000)]).unwrap();
+/// assert_eq!(curve.duty(Some(3500)), 5000);
+/// assert_eq!(curve.duty(None), 10000);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FanCurve {
+    points: Vec<(i16, u16)>,
+    min: u16,
+    max: u16,
+    hysteresis: i16,
+    failsafe: u16,
+    reference: Option<i16>,
+}
+
+impl FanCurve {
+    /// Curve through `(centidegrees, duty)` points
+    ///
+    /// Fails without points, with duty cycles over [`FULL_DUTY`], or with
+    /// temperatures that don't strictly increase.
+    pub fn new(points: &[(i16, u16)]) -> Result<Self> {
+        if points.is_empty() {
+            bail!("A fan curve needs at least one point");
+        }
+        if let Some((_, duty)) = points.iter().find(|(_, duty)| *duty > FULL_DUTY) {
+            return Err(OctoError::OutOfRange)
+                .with_context(|| format!("Duty cycle {duty} is over {FULL_DUTY}"));
+        }
+        if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            bail!("Fan curve temperatures must increase");
+        }
+        Ok(Self {
+            points: points.to_vec(),
+            min: 0,
+            max: FULL_DUTY,
+            hysteresis: 0,
+            failsafe: FULL_DUTY,
+            reference: None,
+        })
+    }
+
+    /// Keep the duty cycle within `min..=max`
+    pub fn with_limits(mut self, min: u16, max: u16) -> Self {
+        self.max = max.min(FULL_DUTY);
+        self.min = min.min(self.max);
+        self
+    }
+
+    /// Only slow down once the temperature has fallen by `centidegrees`
+    pub fn with_hysteresis(mut self, centidegrees: i16) -> Self {
+        self.hysteresis = centidegrees.max(0);
+        self
+    }
+
+    /// Duty cycle when there is no temperature, full speed by default
+    pub fn with_failsafe(mut self, duty: u16) -> Self {
+        self.failsafe = duty.min(FULL_DUTY);
+        self
+    }
+
+    /// Points of the curve
+    pub fn points(&self) -> &[(i16, u16)] {
+        &self.points
+    }
+
+    /// Duty cycle for the next temperature reading
+    pub fn duty(&mut self, temperature: Option<i16>) -> u16 {
+        let Some(temperature) = temperature else {
+            self.reference = None;
+            return self.failsafe;
+        };
+        let reference = match self.reference {
+            Some(reference)
+                if temperature < reference
+                    && temperature > reference.saturating_sub(self.hysteresis) =>
+            {
+                reference
+            }
+            _ => temperature,
+        };
+        self.reference = Some(reference);
+        self.interpolate(reference).clamp(self.min, self.max)
+    }
+
+    /// Duty cycle on the curve at `temperature`, ignoring hysteresis
+    pub fn interpolate(&self, temperature: i16) -> u16 {
+        let after = self.points.partition_point(|&(t, _)| t <= temperature);
+        match (
+            after.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(after),
+        ) {
+            (Some((_, duty)), None) | (None, Some(&(_, duty))) => duty,
+            (Some((t0, d0)), Some(&(t1, d1))) => {
+                let span = i32::from(t1) - i32::from(t0);
+                let offset = i32::from(temperature) - i32::from(t0);
+                let duty = i32::from(d0) + (i32::from(d1) - i32::from(d0)) * offset / span;
+                duty as u16
+            }
+            (None, None) => self.failsafe,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FanCurve;
+
+    /// Duty cycles interpolate between points and hold beyond them
+    #[test]
+    fn interpolate() {
+        let curve = FanCurve::new(&[(2500, 2000), (3500, 6000), (4500, 10000)]).unwrap();
+        assert_eq!(curve.interpolate(-1000), 2000);
+        assert_eq!(curve.interpolate(2500), 2000);
+        assert_eq!(curve.interpolate(3000), 4000);
+        assert_eq!(curve.interpolate(3500), 6000);
+        assert_eq!(curve.interpolate(4250), 9000);
+        assert_eq!(curve.interpolate(i16::MAX), 10000);
+        let falling = FanCurve::new(&[(0, 10000), (100, 0)]).unwrap();
+        assert_eq!(falling.interpolate(25), 7500);
+    }
+
+    /// Fans speed up at once but slow down only past the hysteresis
+    #[test]
+    fn hysteresis() {
+        let mut curve = FanCurve::new(&[(3000, 0), (4000, 10000)])
+            .unwrap()
+            .with_hysteresis(200);
+        assert_eq!(curve.duty(Some(3500)), 5000);
+        assert_eq!(curve.duty(Some(3400)), 5000);
+        assert_eq!(curve.duty(Some(3600)), 6000);
+        assert_eq!(curve.duty(Some(3401)), 6000);
+        assert_eq!(curve.duty(Some(3400)), 4000);
+    }
+
+    /// Limits clamp the curve, missing temperatures use the failsafe
+    #[test]
+    fn limits_and_failsafe() {
+        let mut curve = FanCurve::new(&[(3000, 0), (4000, 10000)])
+            .unwrap()
+            .with_limits(2500, 9000)
+            .with_failsafe(7000);
+        assert_eq!(curve.duty(Some(2000)), 2500);
+        assert_eq!(curve.duty(Some(5000)), 9000);
+        assert_eq!(curve.duty(None), 7000);
+    }
+
+    /// Curves that make no sense are refused
+    #[test]
+    fn reject_bad_curves() {
+        assert!(FanCurve::new(&[]).is_err());
+        assert!(FanCurve::new(&[(3000, 10001)]).is_err());
+        assert!(FanCurve::new(&[(3000, 0), (3000, 100)]).is_err());
+        assert!(FanCurve::new(&[(4000, 0), (3000, 100)]).is_err());
+    }
+}
@@ -0,0 +1,582 @@
+//! Long-running sync of sources to virtual sensors
+//!
+//! A [`SyncEngine`] is the loop everyone ends up writing: read each
+//! [`Source`] every interval and publish the values on their slots.
+//! Failing sources disconnect their slot, and an unplugged device backs
+//! off through a [`CircuitBreaker`] instead of failing every tick. On
+//! Linux the engine also notices the system resuming from suspend, see
+//! [`crate::suspend`], and republishes straight away.
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{daemon::SyncEngine, source::HwmonSource, Octo};
+//! use std::time::Duration;
+//! let mut engine = SyncEngine::new(Octo::new().unwrap())
+//!     .with_interval(Duration::from_secs(2))
+//!     .with_source(0, HwmonSource::find("k10temp", "temp1").unwrap())
+//!     .with_source(1, HwmonSource::find("nvme", "temp1").unwrap());
+//! engine.run_until(|| false);
+//! ```
+use crate::error::Result;
+use crate::{
+    breaker::{BreakerEvent, CircuitBreaker},
+    recorder::Recorder,
+    source::{Poller, Source, SourceHealth},
+    state::StateFile,
+    Failsafe, Octo,
+};
+use std::time::{Duration, Instant};
+
+/// Failed sends in a row before the breaker opens
+static BREAKER_THRESHOLD: u32 = 5;
+
+/// Rewrites the polled values before they're published
+type Transform = Box<dyn FnMut(&[Option<i16>]) -> Vec<Option<i16>> + Send>;
+
+/// Publishes sources on virtual sensor slots at a fixed interval
+pub struct SyncEngine {
+    octo: Octo,
+    sources: Poller,
+    transforms: Vec<Transform>,
+    interval: Duration,
+    breaker: CircuitBreaker,
+    recorder: Option<Recorder>,
+    state: Option<StateFile>,
+    restored: Option<Restored>,
+    #[cfg(feature = "prometheus")]
+    metrics: Option<crate::prometheus::Metrics>,
+    #[cfg(feature = "mqtt")]
+    mqtt: Option<crate::mqtt::Mqtt>,
+    #[cfg(target_os = "linux")]
+    notifier: Option<crate::systemd::Notifier>,
+    #[cfg(target_os = "linux")]
+    ready: bool,
+    #[cfg(target_os = "linux")]
+    suspend: Option<crate::suspend::SuspendDetector>,
+}
+
+impl SyncEngine {
+    /// Engine for `octo` with no sources, syncing every second
+    pub fn new(octo: Octo) -> Self {
+        Self {
+            octo,
+            sources: Poller::new(),
+            transforms: Vec::new(),
+            interval: Duration::from_secs(1),
+            breaker: CircuitBreaker::new(BREAKER_THRESHOLD),
+            recorder: None,
+            state: None,
+            restored: None,
+            #[cfg(feature = "prometheus")]
+            metrics: None,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+            #[cfg(target_os = "linux")]
+            notifier: None,
+            #[cfg(target_os = "linux")]
+            ready: false,
+            #[cfg(target_os = "linux")]
+            suspend: crate::suspend::SuspendDetector::new().ok(),
+        }
+    }
+
+    /// Sync every `interval`
+    ///
+    /// Should be well under the device's virtual sensor timeout.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Publish `source` on virtual sensor `slot`, numbered from 0
+    pub fn with_source(mut self, slot: usize, source: impl Source + Send + 'static) -> Self {
+        self.sources.add(slot, source);
+        self
+    }
+
+    /// Publish `source` on `slot`, reading it at most once every `every`
+    ///
+    /// For sources slower than the sync interval, such as commands that
+    /// take a while to run. The last value is published in between.
+    pub fn with_source_every(
+        mut self,
+        slot: usize,
+        source: impl Source + Send + 'static,
+        every: Duration,
+    ) -> Self {
+        self.sources.add_every(slot, source, every);
+        self
+    }
+
+    /// Pass the polled values through `transform` before publishing
+    ///
+    /// Transforms run in the order they're added, e.g.
+    /// [`Interpolation::apply`](crate::transform::Interpolation::apply).
+    pub fn with_transform(
+        mut self,
+        transform: impl FnMut(&[Option<i16>]) -> Vec<Option<i16>> + Send + 'static,
+    ) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// The device being synced
+    pub fn octo(&mut self) -> &mut Octo {
+        &mut self.octo
+    }
+
+    /// How often it syncs
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// How each source has been doing, see [`Poller::health`]
+    pub fn sources(&self) -> Vec<SourceHealth> {
+        self.sources.health()
+    }
+
+    /// Record the published values and device telemetry every tick
+    ///
+    /// Costs a status read per tick.
+    #[cfg(feature = "prometheus")]
+    pub fn with_metrics(mut self, metrics: crate::prometheus::Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Record the published values and a status read every tick
+    ///
+    /// Shares the status read with [`SyncEngine::with_metrics`]. Failing
+    /// writes are printed and the sync carries on.
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Save the published values to `state` and start from its values
+    ///
+    /// The saved values are loaded on the first tick, before any source is
+    /// read, and published at once. After that they stand in for slots
+    /// that haven't read a value since the restart, for as long as the
+    /// state file's maximum age, so slow or MQTT sources don't leave their
+    /// slot disconnected. Failing loads and saves are printed and the sync
+    /// carries on.
+    pub fn with_state(mut self, state: StateFile) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Publish device telemetry over MQTT every tick
+    ///
+    /// Shares the status read with [`SyncEngine::with_metrics`].
+    #[cfg(feature = "mqtt")]
+    pub fn with_mqtt(mut self, mqtt: crate::mqtt::Mqtt) -> Self {
+        self.mqtt = Some(mqtt);
+        self
+    }
+
+    /// Report to systemd through `notifier`
+    ///
+    /// Readiness is sent after the first successful update, and the
+    /// watchdog is only pinged after successful updates, so systemd
+    /// restarts a service whose device stopped answering.
+    #[cfg(target_os = "linux")]
+    pub fn with_notifier(mut self, notifier: crate::systemd::Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Read every source once and publish the values
+    ///
+    /// Returns the values, or `None` if the breaker skipped the send. Send
+    /// errors are returned until the breaker opens.
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(name = "sync", level = "debug", skip_all)
+    )]
+    pub fn tick(&mut self) -> Result<Option<Vec<Option<i16>>>> {
+        #[cfg(target_os = "linux")]
+        self.check_suspend();
+        if self.restored.is_none() {
+            self.restore();
+        }
+        let mut values = self.sources.poll();
+        for transform in &mut self.transforms {
+            values = transform(&values);
+        }
+        if let Some(restored) = &mut self.restored {
+            restored.fill(&mut values);
+        }
+        let octo = &mut self.octo;
+        let sent = self
+            .breaker
+            .call(|| octo.update_centidegrees(&values))?
+            .is_some();
+        for event in self.breaker.take_events() {
+            match event {
+                BreakerEvent::Opened { failures, error } => {
+                    warn!("{failures} failed updates in a row, backing off: {error}")
+                }
+                BreakerEvent::Closed { failures } => {
+                    warn!("Device back after {failures} failed updates")
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if sent {
+            self.notify_sent();
+        }
+        if let (true, Some(state)) = (sent, &self.state) {
+            if let Err(error) = state.save(&values) {
+                warn!("{error:#}");
+            }
+        }
+        self.publish_telemetry(&values, sent);
+        Ok(sent.then_some(values))
+    }
+
+    /// Load and publish the values saved by the previous run
+    fn restore(&mut self) {
+        let mut restored = Restored {
+            values: Vec::new(),
+            until: Instant::now(),
+        };
+        if let Some(state) = &self.state {
+            match state.load() {
+                Ok(Some(values)) => {
+                    if let Err(error) = self.octo.update_centidegrees(&values) {
+                        warn!("Publishing saved values: {error:#}");
+                    }
+                    restored.values = values;
+                    restored.until += state.max_age();
+                }
+                Ok(None) => {}
+                Err(error) => warn!("{error:#}"),
+            }
+        }
+        self.restored = Some(restored);
+    }
+
+    /// Republish if the system slept since the last tick
+    #[cfg(target_os = "linux")]
+    fn check_suspend(&mut self) {
+        let Some(suspend) = &mut self.suspend else {
+            return;
+        };
+        match suspend.check() {
+            Ok(Some(slept)) => self.resumed(slept),
+            Ok(None) => {}
+            Err(error) => warn!("Checking for suspend: {error:#}"),
+        }
+    }
+
+    /// Put the last values back after `slept` suspended
+    ///
+    /// The device timed out every slot while the system slept, and may have
+    /// been re-enumerated, so a failing republish reopens it.
+    #[cfg(target_os = "linux")]
+    fn resumed(&mut self, slept: Duration) {
+        warn!("Resumed after {}s suspended, republishing", slept.as_secs());
+        if let Err(error) = self.octo.republish() {
+            warn!("Republishing after resume: {error:#}, reopening");
+            if let Err(error) = self.octo.reopen() {
+                warn!("{error:#}");
+            }
+        }
+    }
+
+    /// Tell systemd an update went through
+    #[cfg(target_os = "linux")]
+    fn notify_sent(&mut self) {
+        let Some(notifier) = &self.notifier else {
+            return;
+        };
+        let result = if self.ready {
+            notifier.ping()
+        } else {
+            notifier.ready().and_then(|()| notifier.ping())
+        };
+        match result {
+            Ok(()) => self.ready = true,
+            Err(error) => warn!("{error:#}"),
+        }
+    }
+
+    /// Read the status once for everything that wants telemetry
+    fn publish_telemetry(&mut self, values: &[Option<i16>], sent: bool) {
+        let wanted = self.recorder.is_some();
+        #[cfg(feature = "prometheus")]
+        let wanted = wanted || self.metrics.is_some();
+        #[cfg(feature = "mqtt")]
+        let wanted = wanted || self.mqtt.is_some();
+        if !wanted {
+            return;
+        }
+        let status = self.octo.read_status();
+        if let Some(recorder) = &mut self.recorder {
+            let mut result = if sent {
+                recorder.record_sent(values)
+            } else {
+                Ok(())
+            };
+            if let Ok(status) = &status {
+                result = result.and_then(|()| recorder.record_status(status));
+            }
+            if let Err(error) = result {
+                warn!("{error:#}");
+            }
+        }
+        #[cfg(feature = "prometheus")]
+        if let Some(metrics) = &self.metrics {
+            if sent {
+                metrics.record_sent(values);
+            }
+            match &status {
+                Ok(status) => metrics.record_status(status.clone()),
+                Err(_) => metrics.record_down(),
+            }
+            metrics.record_link(self.octo.link_stats());
+        }
+        #[cfg(feature = "mqtt")]
+        if let (Some(mqtt), Ok(status)) = (&mut self.mqtt, &status) {
+            if let Err(error) = mqtt.publish_status(status) {
+                warn!("{error:#}");
+            }
+        }
+        if let Err(error) = status {
+            warn!("Reading status for telemetry: {error:#}");
+        }
+    }
+
+    /// Sync every interval until `stop` returns true
+    ///
+    /// Errors are printed and the loop carries on, so a device that is away
+    /// for a while is picked up again when it answers.
+    pub fn run_until(&mut self, mut stop: impl FnMut() -> bool) {
+        while !stop() {
+            let started = Instant::now();
+            if let Err(error) = self.tick() {
+                warn!("{error:#}");
+            }
+            std::thread::sleep(self.interval.saturating_sub(started.elapsed()));
+        }
+    }
+
+    /// Stop syncing and leave the device as `failsafe` says
+    ///
+    /// [`Failsafe::Disconnect`] clears the virtual sensors, so fans fall
+    /// back to their settings for a missing source, while
+    /// [`Failsafe::DeviceTimeout`] freezes the last values until the
+    /// device's own timeout. Tells systemd the service is stopping first.
+    pub fn shutdown(mut self, failsafe: Failsafe) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(notifier) = &self.notifier {
+            if let Err(error) = notifier.stopping() {
+                warn!("{error:#}");
+            }
+        }
+        self.octo.engage_failsafe(failsafe)
+    }
+}
+
+/// Saved values standing in for slots that haven't read anything yet
+struct Restored {
+    values: Vec<Option<i16>>,
+    until: Instant,
+}
+
+impl Restored {
+    /// Fill disconnected slots of `values`, forgetting slots that read
+    fn fill(&mut self, values: &mut Vec<Option<i16>>) {
+        if Instant::now() >= self.until {
+            self.values.clear();
+        }
+        for (slot, saved) in self.values.iter_mut().enumerate() {
+            match values.get_mut(slot) {
+                Some(Some(_)) => *saved = None,
+                Some(value) => *value = *saved,
+                None if saved.is_some() => {
+                    values.resize(slot, None);
+                    values.push(*saved);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SyncEngine;
+    use crate::{emulator::Emulator, source::Source, Failsafe, Octo};
+    use std::time::Duration;
+
+    /// Source reading a fixed value, or failing
+    struct Fixed(Option<i16>);
+
+    impl Source for Fixed {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        fn read(&mut self) -> crate::Result<Option<i16>> {
+            self.0.map(Some).ok_or_else(|| crate::Error::msg("gone"))
+        }
+    }
+
+    /// Sources end up on their slots, failing ones disconnected
+    #[test]
+    fn tick() {
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut engine = SyncEngine::new(octo)
+            .with_source(2, Fixed(Some(4150)))
+            .with_source(0, Fixed(None));
+        let values = engine.tick().unwrap().unwrap();
+        assert_eq!(values, [None, None, Some(4150)]);
+        assert_eq!(emulator.virtual_sensors()[..3], [None, None, Some(4150)]);
+    }
+
+    /// Transforms see the polled values and publish what they return
+    #[test]
+    fn transform() {
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut engine = SyncEngine::new(octo)
+            .with_source(0, Fixed(Some(4000)))
+            .with_transform(|values| values.iter().map(|v| v.map(|v| v + 100)).collect())
+            .with_transform(|values| [values, &[Some(2000)]].concat());
+        assert_eq!(engine.tick().unwrap().unwrap(), [Some(4100), Some(2000)]);
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(4100), Some(2000)]);
+    }
+
+    /// The loop runs until told to stop and backs off from an unplugged device
+    #[test]
+    fn run_until() {
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut engine = SyncEngine::new(octo)
+            .with_interval(Duration::ZERO)
+            .with_source(0, Fixed(Some(3000)));
+        emulator.disconnect();
+        let mut ticks = 0;
+        engine.run_until(|| {
+            ticks += 1;
+            ticks > 10
+        });
+        assert_eq!(ticks, 11);
+        // Backing off, so the device isn't tried again straight away
+        emulator.reconnect();
+        assert_eq!(engine.tick().unwrap(), None);
+        assert_eq!(emulator.accepted_reports(), 0);
+    }
+
+    /// Saved values are published first and fill slots until they read
+    #[test]
+    fn state() {
+        use crate::state::StateFile;
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateFile::new(dir.path().join("state"));
+        state.save(&[Some(3000), None, Some(3500)]).unwrap();
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let ready = Arc::new(AtomicBool::new(false));
+        let slow = {
+            let ready = ready.clone();
+            crate::source::FnSource::new("slow", move || {
+                Ok(ready.load(Ordering::SeqCst).then_some(4500))
+            })
+        };
+        let mut engine = SyncEngine::new(octo)
+            .with_source(0, Fixed(Some(4000)))
+            .with_source(2, slow)
+            .with_state(state.clone());
+        let values = engine.tick().unwrap().unwrap();
+        assert_eq!(values, [Some(4000), None, Some(3500)]);
+        assert_eq!(emulator.accepted_reports(), 2);
+        assert_eq!(
+            state.load().unwrap().unwrap(),
+            [Some(4000), None, Some(3500)]
+        );
+        ready.store(true, Ordering::SeqCst);
+        assert_eq!(engine.tick().unwrap().unwrap()[2], Some(4500));
+        // Once the slot has read, failing leaves it disconnected again
+        ready.store(false, Ordering::SeqCst);
+        assert_eq!(engine.tick().unwrap().unwrap()[2], None);
+        assert_eq!(state.load().unwrap().unwrap(), [Some(4000)]);
+    }
+
+    /// Resuming republishes, reopening a device that was re-enumerated
+    #[test]
+    fn resumed() {
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut engine = SyncEngine::new(octo).with_source(0, Fixed(Some(3000)));
+        engine.tick().unwrap();
+        emulator.reconnect();
+        engine.resumed(Duration::from_secs(60));
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        assert_eq!(emulator.reopens(), 0);
+        emulator.replug();
+        engine.resumed(Duration::from_secs(60));
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        assert_eq!(emulator.reopens(), 1);
+    }
+
+    /// Each tick records what was sent and what the device read
+    #[test]
+    fn recorder() {
+        use crate::recorder::{Format, Recorder};
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.csv");
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut engine = SyncEngine::new(octo)
+            .with_source(0, Fixed(Some(3000)))
+            .with_recorder(Recorder::create(&path, Format::Csv).unwrap());
+        engine.tick().unwrap();
+        engine.tick().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.matches(",virtual_sensor,0,30\n").count(), 2);
+        assert_eq!(text.matches(",sensor,0,25\n").count(), 2);
+    }
+
+    /// systemd hears about readiness once and a ping per successful update
+    #[test]
+    fn notifier() {
+        use crate::systemd::Notifier;
+        use std::os::unix::net::UnixDatagram;
+        let dir = tempfile::tempdir().unwrap();
+        let systemd = UnixDatagram::bind(dir.path().join("notify")).unwrap();
+        systemd.set_nonblocking(true).unwrap();
+        let notifier =
+            Notifier::connect(systemd.local_addr().unwrap(), Some(Duration::from_secs(5))).unwrap();
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut engine = SyncEngine::new(octo)
+            .with_source(0, Fixed(Some(3000)))
+            .with_notifier(notifier);
+        engine.tick().unwrap();
+        engine.tick().unwrap();
+        emulator.disconnect();
+        assert!(engine.tick().is_err());
+        let mut received = Vec::new();
+        let mut buf = [0; 64];
+        while let Ok(len) = systemd.recv(&mut buf) {
+            received.push(String::from_utf8(buf[..len].to_vec()).unwrap());
+        }
+        assert_eq!(received, ["READY=1", "WATCHDOG=1", "WATCHDOG=1"]);
+        emulator.reconnect();
+        engine.tick().unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        engine.shutdown(Failsafe::Disconnect).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], None);
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+        let len = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+    }
+}
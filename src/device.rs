@@ -0,0 +1,356 @@
+//! One interface over the Aquacomputer devices with virtual sensors
+//!
+//! The family shares its protocol, so [`Octo`] drives any model given its
+//! [layout](crate::layout), see [`OctoBuilder::device`]. The types here
+//! open a specific model, and [`VirtualSensorDevice`] is what they have in
+//! common, for programs that don't care which one is connected:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::device::{self, VirtualSensorDevice};
+//! let mut devices = device::open_all().unwrap();
+//! for device in &mut devices {
+//!     device.update_virtual_sensors(&[42]).unwrap();
+//! }
+//! ```
+use crate::error::{Context, Result};
+use crate::{
+    builder, layout, layout::DeviceLayout, status::Status, Octo, OctoBuilder, OctoInfo, Transport,
+    UsbTransport,
+};
+use rusb::{Device, DeviceList, GlobalContext};
+use std::ops::{Deref, DerefMut};
+
+/// What every device with virtual sensors can do
+pub trait VirtualSensorDevice {
+    /// Layout of the model being talked to
+    fn layout(&self) -> &DeviceLayout;
+
+    /// Set the virtual sensors to whole degrees Celsius, see
+    /// [`Octo::update_virtual_sensors`]
+    fn update_virtual_sensors(&mut self, sensor_values: &[i16]) -> Result<usize>;
+
+    /// Set the virtual sensors in centidegrees, see
+    /// [`Octo::update_centidegrees`]
+    fn update_centidegrees(&mut self, values: &[Option<i16>]) -> Result<usize>;
+
+    /// Read and decode the next status report, see [`Octo::read_status`]
+    fn read_status(&mut self) -> Result<Status>;
+
+    /// Every connected device of this model
+    fn discover() -> Result<Vec<OctoInfo>>
+    where
+        Self: Sized;
+}
+
+impl VirtualSensorDevice for Octo {
+    fn layout(&self) -> &DeviceLayout {
+        self.device()
+    }
+
+    fn update_virtual_sensors(&mut self, sensor_values: &[i16]) -> Result<usize> {
+        Octo::update_virtual_sensors(self, sensor_values)
+    }
+
+    fn update_centidegrees(&mut self, values: &[Option<i16>]) -> Result<usize> {
+        Octo::update_centidegrees(self, values)
+    }
+
+    fn read_status(&mut self) -> Result<Status> {
+        Octo::read_status(self)
+    }
+
+    fn discover() -> Result<Vec<OctoInfo>> {
+        Octo::list()
+    }
+}
+
+/// Define a type opening one model through [`Octo`]
+macro_rules! model {
+    ($(#[$doc:meta])* $name:ident, $layout:path) => {
+        $(#[$doc])*
+        ///
+        /// Dereferences to the [`Octo`] driving it, for everything beyond
+        /// [`VirtualSensorDevice`].
+        pub struct $name {
+            octo: Octo,
+        }
+
+        impl $name {
+            /// Find the connected device and open it
+            pub fn new() -> Result<Self> {
+                Self::open(Octo::builder())
+            }
+
+            /// Open the device with `builder`'s settings
+            pub fn open(builder: OctoBuilder) -> Result<Self> {
+                builder.device($layout).open().map(|octo| Self { octo })
+            }
+
+            /// Create one that talks through the given transport
+            pub fn with_transport(transport: impl Transport + Send + 'static) -> Result<Self> {
+                Octo::builder()
+                    .device($layout)
+                    .with_transport(transport)
+                    .map(|octo| Self { octo })
+            }
+
+            /// The [`Octo`] driving the device
+            pub fn into_inner(self) -> Octo {
+                self.octo
+            }
+        }
+
+        impl Deref for $name {
+            type Target = Octo;
+
+            fn deref(&self) -> &Octo {
+                &self.octo
+            }
+        }
+
+        impl DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut Octo {
+                &mut self.octo
+            }
+        }
+
+        impl VirtualSensorDevice for $name {
+            fn layout(&self) -> &DeviceLayout {
+                self.octo.device()
+            }
+
+            fn update_virtual_sensors(&mut self, sensor_values: &[i16]) -> Result<usize> {
+                self.octo.update_virtual_sensors(sensor_values)
+            }
+
+            fn update_centidegrees(&mut self, values: &[Option<i16>]) -> Result<usize> {
+                self.octo.update_centidegrees(values)
+            }
+
+            fn read_status(&mut self) -> Result<Status> {
+                self.octo.read_status()
+            }
+
+            fn discover() -> Result<Vec<OctoInfo>> {
+                Octo::builder().device($layout).list()
+            }
+        }
+    };
+}
+
+model!(
+    /// An Aquacomputer Quadro
+    Quadro,
+    layout::QUADRO
+);
+
+model!(
+    /// An Aquacomputer D5 Next pump
+    ///
+    /// Its virtual sensors can't be sent yet, see [`layout::D5NEXT`].
+    D5Next,
+    layout::D5NEXT
+);
+
+model!(
+    /// An Aquacomputer Farbwerk 360 RGB controller
+    Farbwerk360,
+    layout::FARBWERK360
+);
+
+impl Farbwerk360 {
+    /// Its physical temperature sensors in centidegrees from the next
+    /// status report, `None` for disconnected ones
+    pub fn read_temperatures(&mut self) -> Result<Vec<Option<i16>>> {
+        Ok(self.octo.read_status()?.sensors)
+    }
+}
+
+model!(
+    /// An Aquacomputer Aquaero 6
+    ///
+    /// Only its status can be read so far, see [`layout::AQUAERO`].
+    Aquaero,
+    layout::AQUAERO
+);
+
+/// Fan channel of the D5 Next's pump
+static PUMP: usize = 0;
+
+/// What a D5 Next says about its pump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PumpStatus {
+    /// Speed in RPM
+    pub rpm: u16,
+    /// Power draw in centiwatts
+    pub power: u16,
+    /// Coolant temperature in centidegrees, `None` if the sensor is
+    /// disconnected
+    pub coolant: Option<i16>,
+}
+
+impl D5Next {
+    /// The pump's speed, power and coolant temperature from the next
+    /// status report
+    pub fn read_pump(&mut self) -> Result<PumpStatus> {
+        let status = self.octo.read_status()?;
+        let pump = status
+            .fans
+            .get(PUMP)
+            .context("The status report has no pump")?;
+        Ok(PumpStatus {
+            rpm: pump.rpm,
+            power: pump.power,
+            coolant: status.sensors.first().copied().flatten(),
+        })
+    }
+}
+
+/// Every connected device of a known model, with its layout
+///
+/// Serial number and firmware are `None` for devices that can't be read,
+/// like in [`OctoBuilder::list`].
+pub fn discover() -> Result<Vec<(&'static DeviceLayout, OctoInfo)>> {
+    Ok(usb_devices()?
+        .into_iter()
+        .map(|(layout, device)| {
+            let (bus, address) = (device.bus_number(), device.address());
+            let mut transport = UsbTransport::new(device);
+            (layout, builder::info(layout, bus, address, &mut transport))
+        })
+        .collect())
+}
+
+/// Open every connected device of a known model
+///
+/// Devices that fail to open, such as ones claimed by another program,
+/// are skipped with a warning.
+pub fn open_all() -> Result<Vec<Box<dyn VirtualSensorDevice + Send>>> {
+    let mut devices: Vec<Box<dyn VirtualSensorDevice + Send>> = Vec::new();
+    for (layout, device) in usb_devices()? {
+        let transport = UsbTransport::new(device);
+        match Octo::builder().device(*layout).with_transport(transport) {
+            Ok(octo) => devices.push(Box::new(octo)),
+            Err(error) => warn!("{}: {error:#}", layout.name),
+        }
+    }
+    Ok(devices)
+}
+
+/// USB devices of a known model
+fn usb_devices() -> Result<Vec<(&'static DeviceLayout, Device<GlobalContext>)>> {
+    let mut found = Vec::new();
+    for device in DeviceList::new().context("Getting USB Device list")?.iter() {
+        let descriptor = device.device_descriptor().context("Getting device ID")?;
+        if let Some(layout) = layout::for_usb_id(descriptor.vendor_id(), descriptor.product_id()) {
+            found.push((layout, device));
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Aquaero, D5Next, Farbwerk360, PumpStatus, Quadro, VirtualSensorDevice};
+    use crate::{
+        checksum::{Checksum, Crc16Usb},
+        codec,
+        emulator::Emulator,
+        layout::{fan, AQUAERO, D5NEXT, FARBWERK360, QUADRO},
+        mock::MockTransport,
+        Octo, OctoError,
+    };
+
+    /// Update through the trait, whichever model it is
+    fn update(device: &mut dyn VirtualSensorDevice) -> usize {
+        device.update_virtual_sensors(&[42]).unwrap()
+    }
+
+    /// A Quadro takes the same virtual sensor report as an Octo
+    #[test]
+    fn quadro() {
+        let mock = MockTransport::new();
+        let mut quadro = Quadro::with_transport(mock.clone()).unwrap();
+        assert_eq!(quadro.layout().name, "Quadro");
+        assert_eq!(quadro.device(), &QUADRO);
+        assert!(update(&mut quadro) > 0);
+        let mut octo = Octo::with_transport(Emulator::new()).unwrap();
+        assert!(update(&mut octo) > 0);
+        assert_eq!(mock.written(), [octo.last_report().as_bytes()]);
+    }
+
+    /// The D5 Next's pump is read from its first fan channel, and its
+    /// virtual sensors can't be sent yet
+    #[test]
+    fn d5next_pump() {
+        let status = D5NEXT.status;
+        let mut report = vec![0; status.len];
+        report[0] = status.report_id;
+        let pump = status.fans[0];
+        codec::put_u16(&mut report, pump + fan::SPEED, 2400).unwrap();
+        codec::put_u16(&mut report, pump + fan::POWER, 850).unwrap();
+        codec::put_temperature(&mut report, status.sensors, Some(3150)).unwrap();
+        Crc16Usb.apply(&mut report);
+        let mock = MockTransport::new();
+        mock.queue_read(report.clone());
+        mock.queue_read(report);
+        let mut d5next = D5Next::with_transport(mock.clone()).unwrap();
+        assert_eq!(
+            d5next.read_pump().unwrap(),
+            PumpStatus {
+                rpm: 2400,
+                power: 850,
+                coolant: Some(3150),
+            }
+        );
+        let error = d5next.update_virtual_sensors(&[1; 8]).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Unsupported));
+        assert!(mock.written().is_empty());
+    }
+
+    /// A Farbwerk 360 has temperatures but no fans
+    #[test]
+    fn farbwerk360() {
+        let status = FARBWERK360.status;
+        let mut report = vec![0; status.len];
+        report[0] = status.report_id;
+        for sensor in 0..status.sensor_count {
+            let value = (sensor < 2).then_some(2000 + sensor as i16);
+            codec::put_temperature(&mut report, status.sensors + 2 * sensor, value).unwrap();
+        }
+        Crc16Usb.apply(&mut report);
+        let mock = MockTransport::new();
+        mock.queue_read(report.clone());
+        mock.queue_read(report.clone());
+        mock.queue_read(report);
+        let mut farbwerk = Farbwerk360::with_transport(mock).unwrap();
+        let temperatures = farbwerk.read_temperatures().unwrap();
+        assert_eq!(temperatures, [Some(2000), Some(2001), None, None]);
+        assert!(farbwerk.read_status().unwrap().fans.is_empty());
+        assert!(farbwerk.update_centidegrees(&[Some(3000)]).unwrap() > 0);
+    }
+
+    /// The Aquaero's status reads with its own fan block order, while
+    /// sending values and settings are refused
+    #[test]
+    fn aquaero() {
+        let status = AQUAERO.status;
+        let mut report = vec![0; status.len];
+        report[0] = status.report_id;
+        codec::put_u16(&mut report, status.fans[1], 1200).unwrap();
+        codec::put_u16(&mut report, status.fans[1] + 0x08, 150).unwrap();
+        let mock = MockTransport::new();
+        mock.queue_read(report.clone());
+        mock.queue_read(report);
+        let mut aquaero = Aquaero::with_transport(mock.clone()).unwrap();
+        let fans = aquaero.read_status().unwrap().fans;
+        assert_eq!((fans.len(), fans[1].rpm, fans[1].power), (4, 1200, 150));
+        let error = aquaero.update_virtual_sensors(&[40]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The Aquaero's virtual sensor report isn't known yet"
+        );
+        assert!(aquaero.read_control().is_err());
+        assert!(mock.written().is_empty());
+    }
+}
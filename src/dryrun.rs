@@ -0,0 +1,267 @@
+//! A backend that decodes reports instead of sending them
+//!
+//! [`DryRun`] stands in for the device: every virtual sensor report an
+//! [`Octo`](crate::Octo) would send is decoded into a [`DecodedReport`],
+//! logged through the [`log`] facade at [`log::Level::Info`] under the
+//! `octo_virtual_sensors` target and kept for inspection. Configurations and
+//! integrations can then be checked on machines without the hardware:
+//!
+//! ```
+//! use octo_virtual_sensors::{dryrun::DryRun, layout::OCTO, Octo};
+//! let dry_run = DryRun::new(OCTO).with_log(false);
+//! let mut octo = Octo::with_transport(dry_run.clone()).unwrap();
+//! octo.set_virtual_sensor(0, 41.2).unwrap();
+//! let sent = dry_run.last().unwrap();
+//! assert_eq!(sent.sensors[0], Some(4120));
+//! assert!(sent.checksum_ok);
+//! ```
+//!
+//! [`Backend::DryRun`](crate::Backend::DryRun) opens one in place of the
+//! device, and `octo-vs --dry-run` does so from the command line.
+//!
+//! Status reads answer with the virtual sensors last sent and nothing
+//! else: physical sensors are disconnected, fans stopped, and the firmware
+//! is the oldest the layout supports. Settings can't be read or written.
+use crate::error::{Context, Result};
+use crate::{
+    codec,
+    layout::{DeviceLayout, VirtualSensorLayout, SENSOR_SIZE},
+    OctoError, Transport,
+};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A virtual sensor report taken apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedReport {
+    /// Report ID, the first byte
+    pub report_id: u8,
+    /// Length in bytes, including report ID and checksum
+    pub len: usize,
+    /// Slot values in centidegrees, `None` for disconnected
+    pub sensors: Vec<Option<i16>>,
+    /// Bytes between the last sensor and the checksum
+    pub trailer: Vec<u8>,
+    /// Name of the layout's checksum, such as `CRC-16/USB`
+    pub checksum_name: &'static str,
+    /// Checksum bytes as sent
+    pub checksum: Vec<u8>,
+    /// Whether the checksum matches the rest of the report
+    pub checksum_ok: bool,
+}
+
+/// Take `report` apart according to `layout`
+///
+/// Fails if its length or report ID don't match; a bad checksum is only
+/// flagged, so the rest can still be looked at.
+pub fn decode(layout: &VirtualSensorLayout, report: &[u8]) -> Result<DecodedReport> {
+    layout.check()?;
+    if report.len() != layout.len {
+        return Err(OctoError::InvalidReport)
+            .with_context(|| format!("Expected {} byte report, got {}", layout.len, report.len()));
+    }
+    let report_id = report.first().copied().unwrap_or_default();
+    if report_id != layout.report_id {
+        return Err(OctoError::InvalidReport)
+            .with_context(|| format!("Expected report ID {}, got {report_id}", layout.report_id));
+    }
+    let sensors = (0..layout.sensor_count)
+        .map(|slot| codec::get_temperature(report, layout.sensor(slot)))
+        .collect::<Result<_>>()?;
+    let trailer = layout.sensor(layout.sensor_count)..layout.checksum_offset();
+    Ok(DecodedReport {
+        report_id,
+        len: report.len(),
+        sensors,
+        trailer: report.get(trailer).unwrap_or_default().to_vec(),
+        checksum_name: layout.checksum.name(),
+        checksum: report
+            .get(layout.checksum_offset()..)
+            .unwrap_or_default()
+            .to_vec(),
+        checksum_ok: layout.checksum.verify(report),
+    })
+}
+
+impl fmt::Display for DecodedReport {
+    /// A header line, then a line per slot and the trailer, in Celsius
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "virtual sensor report {:#04x}, {} bytes, checksum {}",
+            self.report_id, self.len, self.checksum_name
+        )?;
+        if !self.checksum.is_empty() {
+            write!(f, " {}", hex(&self.checksum))?;
+        }
+        writeln!(f, " {}", if self.checksum_ok { "ok" } else { "MISMATCH" })?;
+        for (slot, value) in self.sensors.iter().enumerate() {
+            match value {
+                Some(value) => writeln!(
+                    f,
+                    "  slot {:>2}  {:.2} °C",
+                    slot + 1,
+                    f64::from(*value) / 100.0
+                )?,
+                None => writeln!(f, "  slot {:>2}  disconnected", slot + 1)?,
+            }
+        }
+        write!(f, "  trailer  {}", hex(&self.trailer))
+    }
+}
+
+/// Bytes as space-separated hex
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Transport that decodes what it's sent instead of writing to USB
+///
+/// Clones share the reports, so a clone kept before handing one to
+/// [`Octo::with_transport`](crate::Octo::with_transport) sees everything
+/// sent.
+#[derive(Debug, Clone)]
+pub struct DryRun {
+    device: DeviceLayout,
+    log: bool,
+    sent: Arc<Mutex<Vec<DecodedReport>>>,
+}
+
+impl DryRun {
+    /// Dry run of `device`, logging each report at info
+    pub fn new(device: DeviceLayout) -> Self {
+        Self {
+            device,
+            log: true,
+            sent: Arc::default(),
+        }
+    }
+
+    /// Whether to log each report at info
+    pub fn with_log(mut self, log: bool) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Every report sent so far, oldest first
+    pub fn reports(&self) -> Vec<DecodedReport> {
+        self.lock().clone()
+    }
+
+    /// The last report sent
+    pub fn last(&self) -> Option<DecodedReport> {
+        self.lock().last().cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<DecodedReport>> {
+        self.sent.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A status report holding the virtual sensors last sent
+    fn status_report(&self) -> Result<Vec<u8>> {
+        let status = self.device.status;
+        let mut report = vec![0; status.len];
+        if let Some(id) = report.first_mut() {
+            *id = status.report_id;
+        }
+        codec::put_u16(&mut report, status.firmware, self.device.min_firmware)?;
+        codec::put_u32(&mut report, status.power_cycles, 1)?;
+        for index in 0..status.sensor_count {
+            codec::put_temperature(&mut report, status.sensors + SENSOR_SIZE * index, None)?;
+        }
+        let sent = self.last().map(|report| report.sensors).unwrap_or_default();
+        for index in 0..status.virtual_sensor_count {
+            let value = sent.get(index).copied().flatten();
+            let offset = status.virtual_sensors + SENSOR_SIZE * index;
+            codec::put_temperature(&mut report, offset, value)?;
+        }
+        status.checksum.apply(&mut report);
+        Ok(report)
+    }
+}
+
+impl Transport for DryRun {
+    /// Decode and keep the report, failing if the device wouldn't take it
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        let layout = self.device.virtual_sensors_for(self.device.min_firmware);
+        let decoded = decode(&layout, report)
+            .with_context(|| format!("Dry run: not a valid {} report", self.device.name))?;
+        if self.log {
+            log::info!(target: "octo_virtual_sensors", "{} dry run: {decoded}", self.device.name);
+        }
+        let checksum_ok = decoded.checksum_ok;
+        self.lock().push(decoded);
+        if !checksum_ok {
+            return Err(OctoError::ChecksumMismatch)
+                .context("Dry run: the device would drop this report");
+        }
+        Ok(report.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let report = self.status_report()?;
+        let len = report.len().min(buf.len());
+        buf.get_mut(..len)
+            .context("Dry run: empty read buffer")?
+            .copy_from_slice(report.get(..len).unwrap_or_default());
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, DryRun};
+    use crate::{layout::OCTO, Backend, Octo, OctoError, VirtualSensorReport};
+
+    /// Reports decode into their slots, trailer and checksum
+    #[test]
+    fn decoded() {
+        let mut report = VirtualSensorReport::new(OCTO.virtual_sensors);
+        report.set_values(&[Some(4120), None, Some(-350)]);
+        let decoded = decode(&OCTO.virtual_sensors, report.as_bytes()).unwrap();
+        assert_eq!(decoded.sensors[..4], [Some(4120), None, Some(-350), None]);
+        assert_eq!(decoded.trailer, OCTO.virtual_sensors.trailer);
+        assert!(decoded.checksum_ok);
+        let text = decoded.to_string();
+        assert!(text.starts_with("virtual sensor report 0x04"), "{text}");
+        assert!(text.contains("  slot  1  41.20 °C\n  slot  2  disconnected\n  slot  3  -3.50 °C"));
+
+        let mut bytes = report.as_bytes().to_vec();
+        bytes[1] ^= 1;
+        assert!(!decode(&OCTO.virtual_sensors, &bytes).unwrap().checksum_ok);
+        assert!(decode(&OCTO.virtual_sensors, &bytes[1..]).is_err());
+    }
+
+    /// An Octo on a dry run sends, reads back and rejects bad reports
+    #[test]
+    fn dry_run() {
+        let dry_run = DryRun::new(OCTO).with_log(false);
+        let mut octo = Octo::with_transport(dry_run.clone()).unwrap();
+        octo.update_centidegrees(&[Some(3000), Some(4000)]).unwrap();
+        octo.clear_virtual_sensor(0).unwrap();
+        let reports = dry_run.reports();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].sensors[..2], [Some(3000), Some(4000)]);
+        assert_eq!(reports[1].sensors[..2], [None, Some(4000)]);
+        let status = octo.read_status().unwrap();
+        assert_eq!(status.virtual_sensors[..2], [None, Some(4000)]);
+        assert!(status.sensors.iter().all(Option::is_none));
+
+        let mut bytes = octo.last_report().as_bytes().to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let report = VirtualSensorReport::parse(OCTO.virtual_sensors, &bytes);
+        assert!(report.is_err());
+        let error = octo.send_raw_report(&bytes, None).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::ChecksumMismatch));
+
+        let mut octo = Octo::builder().backend(Backend::DryRun).open().unwrap();
+        octo.set_virtual_sensor(15, 20.0).unwrap();
+    }
+}
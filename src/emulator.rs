@@ -0,0 +1,1119 @@
+//! Userspace Octo emulator
+//!
+//! Behaves like the device on the other side of a [`Transport`]: it accepts
+//! virtual sensor reports, keeps per-slot state that times out like the
+//! firmware does, and answers reads with status reports. Faults such as
+//! timeouts, corruption and unplugging can be injected to exercise recovery paths
+//! without hardware.
+//!
+//! Only built with the `emulator` feature.
+//!
+//! ```
+//! use octo_virtual_sensors::{emulator::Emulator, Octo};
+//! let emulator = Emulator::new();
+//! let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+//! octo.update_virtual_sensors(&[42]).unwrap();
+//! assert_eq!(emulator.virtual_sensors()[0], Some(4200));
+//! ```
+use crate::error::Result;
+use crate::{
+    codec::{self, put_u16},
+    control::{ControlMode, ControlReport, FanControl},
+    layout::{self, fan, OCTO},
+    OctoError, Transport, VirtualSensorReport,
+};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Cloneable handle to an emulated Octo
+///
+/// Every clone shares the same device state, so a test can keep one clone
+/// to inspect and manipulate the device while another is used as the
+/// transport.
+#[derive(Clone)]
+pub struct Emulator {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    firmware: u16,
+    serial: [u16; 2],
+    power_cycles: u32,
+    temperatures: [Option<i16>; 4],
+    virtual_sensors: [Option<i16>; 16],
+    last_update: Option<Instant>,
+    timeout: Duration,
+    flow: u16,
+    fan_rpm: [u16; 8],
+    fan_current: [u16; 8],
+    control: ControlReport,
+    saved_control: ControlReport,
+    output_len: usize,
+    pending_timeouts: usize,
+    pending_corruption: usize,
+    connected: bool,
+    stale: bool,
+    accepted: usize,
+    rejected: usize,
+    resets: usize,
+    reopens: usize,
+    trailer: Option<Vec<u8>>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        let state = State {
+            firmware: 1019,
+            serial: [12345, 6789],
+            power_cycles: 1,
+            temperatures: [Some(2500), None, None, None],
+            virtual_sensors: [None; 16],
+            last_update: None,
+            timeout: Duration::from_secs(10),
+            flow: 0,
+            fan_rpm: [0; 8],
+            fan_current: [0; 8],
+            control: ControlReport::new(OCTO.control),
+            saved_control: ControlReport::new(OCTO.control),
+            output_len: OCTO.virtual_sensors.len,
+            pending_timeouts: 0,
+            pending_corruption: 0,
+            connected: true,
+            stale: false,
+            accepted: 0,
+            rejected: 0,
+            resets: 0,
+            reopens: 0,
+            trailer: None,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+}
+
+impl Emulator {
+    /// Create a connected emulator with default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the firmware version reported in the status report
+    pub fn with_firmware(self, firmware: u16) -> Self {
+        self.lock().firmware = firmware;
+        self
+    }
+
+    /// Set the serial number reported in the status report
+    pub fn with_serial(self, serial: [u16; 2]) -> Self {
+        self.lock().serial = serial;
+        self
+    }
+
+    /// Set how long virtual sensors keep their value without an update
+    pub fn with_virtual_sensor_timeout(self, timeout: Duration) -> Self {
+        self.lock().timeout = timeout;
+        self
+    }
+
+    /// Declare a different virtual sensor report length, as newer firmware might
+    pub fn with_output_report_len(self, len: usize) -> Self {
+        self.lock().output_len = len;
+        self
+    }
+
+    /// Hold `trailer` between the sensors and the checksum of the virtual
+    /// sensor report, as a device configured differently would
+    ///
+    /// The report can then be read back as a feature report, and reports
+    /// with a different trailer are rejected. Without this the report is
+    /// write-only and any trailer is accepted. `trailer` must be as long as
+    /// the layout's.
+    pub fn with_trailer(self, trailer: &[u8]) -> Self {
+        self.lock().trailer = Some(trailer.to_vec());
+        self
+    }
+
+    /// Set a physical temperature sensor in centidegrees, `None` when unplugged
+    pub fn set_temperature(&self, index: usize, centidegrees: Option<i16>) {
+        self.lock().temperatures[index] = centidegrees;
+    }
+
+    /// Set the flow sensor reading in dL/h
+    pub fn set_flow(&self, flow: u16) {
+        self.lock().flow = flow;
+    }
+
+    /// Set the measured speed of a fan channel
+    pub fn set_fan_rpm(&self, channel: usize, rpm: u16) {
+        self.lock().fan_rpm[channel] = rpm;
+    }
+
+    /// Change how a fan channel is driven
+    pub fn set_fan_control(&self, channel: usize, control: &FanControl) {
+        self.lock()
+            .control
+            .set_fan(channel, control)
+            .expect("the Octo has 8 fan channels");
+    }
+
+    /// Set the current a fan channel draws in milliamps
+    ///
+    /// Power is worked out from the current and the 12 V supply.
+    pub fn set_fan_current(&self, channel: usize, milliamps: u16) {
+        self.lock().fan_current[channel] = milliamps;
+    }
+
+    /// How a fan channel is currently driven
+    pub fn fan_control(&self, channel: usize) -> FanControl {
+        self.lock()
+            .control
+            .fan(channel)
+            .expect("the Octo has 8 fan channels")
+    }
+
+    /// Current virtual sensor values in centidegrees, as the firmware sees them
+    ///
+    /// Slots that were never set, or have not been updated within the
+    /// timeout, are `None`.
+    pub fn virtual_sensors(&self) -> [Option<i16>; 16] {
+        self.lock().current_virtual_sensors()
+    }
+
+    /// Settings the device comes back with after a power cycle
+    pub fn saved_control(&self) -> ControlReport {
+        self.lock().saved_control.clone()
+    }
+
+    /// Number of valid reports the device has accepted
+    pub fn accepted_reports(&self) -> usize {
+        self.lock().accepted
+    }
+
+    /// Number of writes the device ignored for being malformed
+    pub fn rejected_reports(&self) -> usize {
+        self.lock().rejected
+    }
+
+    /// Number of USB port resets so far
+    pub fn resets(&self) -> usize {
+        self.lock().resets
+    }
+
+    /// Number of times the device was closed and opened again
+    pub fn reopens(&self) -> usize {
+        self.lock().reopens
+    }
+
+    /// Make the next `count` transfers time out
+    pub fn inject_timeouts(&self, count: usize) {
+        self.lock().pending_timeouts = count;
+    }
+
+    /// Flip a bit in the next `count` transfers, as a bad cable would
+    ///
+    /// Corrupted writes fail the checksum and are dropped by the device,
+    /// corrupted reads fail the host's checksum.
+    pub fn inject_corruption(&self, count: usize) {
+        self.lock().pending_corruption = count;
+    }
+
+    /// Unplug the device; every transfer fails until [`Emulator::reconnect`]
+    pub fn disconnect(&self) {
+        self.lock().connected = false;
+    }
+
+    /// Plug the device back in
+    ///
+    /// Like real hardware this is a reboot: the power cycle counter goes up,
+    /// all virtual sensors are cleared and unsaved settings are lost.
+    pub fn reconnect(&self) {
+        let mut state = self.lock();
+        state.connected = true;
+        state.power_cycles += 1;
+        state.control = state.saved_control.clone();
+        state.virtual_sensors = [None; 16];
+        state.last_update = None;
+    }
+
+    /// Unplug the device and plug it back in, as a real replug looks to a
+    /// program holding it open
+    ///
+    /// The device reboots as with [`Emulator::reconnect`], but transfers
+    /// keep failing as disconnected until the transport is reopened.
+    pub fn replug(&self) {
+        self.reconnect();
+        self.lock().stale = true;
+    }
+
+    /// Build the status report the device would currently send
+    pub fn status_report(&self) -> Vec<u8> {
+        self.lock().status_report()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    /// Fail the transfer if the device is unplugged or a timeout is pending
+    fn check_transfer(&mut self) -> Result<()> {
+        if !self.connected || self.stale {
+            return Err(OctoError::Disconnected.into());
+        }
+        if self.pending_timeouts > 0 {
+            self.pending_timeouts -= 1;
+            return Err(OctoError::Timeout.into());
+        }
+        Ok(())
+    }
+
+    /// Flip a bit in the middle of `report` if corruption is pending
+    fn corrupt(&mut self, report: &mut [u8]) {
+        if self.pending_corruption > 0 && !report.is_empty() {
+            self.pending_corruption -= 1;
+            report[report.len() / 2] ^= 0x10;
+        }
+    }
+
+    /// Layout of the virtual sensor report this device accepts
+    fn virtual_sensor_layout(&self) -> layout::VirtualSensorLayout {
+        OCTO.virtual_sensors
+            .resized(self.output_len)
+            .unwrap_or(OCTO.virtual_sensors)
+    }
+
+    /// Report descriptor declaring the status and virtual sensor reports
+    fn report_descriptor(&self) -> Vec<u8> {
+        let [input_lo, input_hi] = ((OCTO.status.len - 1) as u16).to_le_bytes();
+        let [output_lo, output_hi] = ((self.output_len - 1) as u16).to_le_bytes();
+        vec![
+            0x06,
+            0x00,
+            0xFF, // Usage Page (Vendor)
+            0x09,
+            0x01, // Usage
+            0xA1,
+            0x01, // Collection (Application)
+            0x75,
+            0x08, // Report Size (8)
+            0x85,
+            OCTO.status.report_id, // Report ID
+            0x96,
+            input_lo,
+            input_hi, // Report Count
+            0x09,
+            0x01, // Usage
+            0x81,
+            0x02, // Input
+            0x85,
+            OCTO.virtual_sensors.report_id, // Report ID
+            0x96,
+            output_lo,
+            output_hi, // Report Count
+            0x09,
+            0x01, // Usage
+            0x91,
+            0x02, // Output
+            0xC0, // End Collection
+        ]
+    }
+
+    fn current_virtual_sensors(&self) -> [Option<i16>; 16] {
+        match self.last_update {
+            Some(last) if last.elapsed() < self.timeout => self.virtual_sensors,
+            _ => [None; 16],
+        }
+    }
+
+    fn status_report(&self) -> Vec<u8> {
+        let status = OCTO.status;
+        let mut report = vec![0; status.len];
+        report[0] = status.report_id;
+        self.fill_status(&mut report)
+            .expect("the Octo's status fields fit its report");
+        status.checksum.apply(&mut report);
+        report
+    }
+
+    /// Write the readings into a zeroed status report
+    fn fill_status(&self, report: &mut [u8]) -> Result<()> {
+        let status = OCTO.status;
+        put_u16(report, status.serial, self.serial[0])?;
+        put_u16(report, status.serial + 2, self.serial[1])?;
+        put_u16(report, status.firmware, self.firmware)?;
+        codec::put_u32(report, status.power_cycles, self.power_cycles)?;
+        for (index, value) in self.temperatures.iter().enumerate() {
+            let offset = status.sensors + layout::SENSOR_SIZE * index;
+            codec::put_temperature(report, offset, *value)?;
+        }
+        for (index, value) in self.current_virtual_sensors().iter().enumerate() {
+            let offset = status.virtual_sensors + layout::SENSOR_SIZE * index;
+            codec::put_temperature(report, offset, *value)?;
+        }
+        if let Some(flow) = status.flow {
+            put_u16(report, flow, self.flow)?;
+        }
+        for (channel, (offset, rpm)) in status.fans.iter().zip(self.fan_rpm).enumerate() {
+            let control = self.control.fan(channel)?;
+            if control.mode == ControlMode::Manual {
+                put_u16(report, offset + fan::PERCENT, control.duty)?;
+            }
+            // Fans run off the 12 V rail
+            let current = self.fan_current[channel];
+            let power = u32::from(FAN_VOLTAGE) * u32::from(current) / 1000;
+            put_u16(report, offset + fan::VOLTAGE, FAN_VOLTAGE)?;
+            put_u16(report, offset + fan::CURRENT, current)?;
+            put_u16(report, offset + fan::POWER, power as u16)?;
+            put_u16(report, offset + fan::SPEED, rpm)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fan supply voltage in centivolts
+static FAN_VOLTAGE: u16 = 1200;
+
+impl Transport for Emulator {
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        let mut state = self.lock();
+        state.check_transfer()?;
+        let mut received = report.to_vec();
+        state.corrupt(&mut received);
+        // The firmware silently drops reports it can't validate
+        let layout = state.virtual_sensor_layout();
+        let parsed = VirtualSensorReport::parse(layout, &received)
+            .ok()
+            .filter(|parsed| {
+                state
+                    .trailer
+                    .as_ref()
+                    .is_none_or(|trailer| parsed.trailer() == trailer)
+            });
+        let Some(parsed) = parsed else {
+            state.rejected += 1;
+            return Ok(report.len());
+        };
+        for (slot, value) in state.virtual_sensors.iter_mut().zip(parsed.values()) {
+            *slot = value;
+        }
+        state.last_update = Some(Instant::now());
+        state.accepted += 1;
+        Ok(report.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.lock();
+        state.check_transfer()?;
+        let mut report = state.status_report();
+        state.corrupt(&mut report);
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    fn report_descriptor(&mut self) -> Result<Vec<u8>> {
+        let mut state = self.lock();
+        state.check_transfer()?;
+        Ok(state.report_descriptor())
+    }
+
+    /// The control report, and the virtual sensor report if it has a
+    /// trailer set; others stall
+    fn read_feature_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.lock();
+        state.check_transfer()?;
+        let id = buf.first().copied();
+        let virtual_sensors = match &state.trailer {
+            Some(trailer) if id == Some(OCTO.virtual_sensors.report_id) => {
+                let mut report = VirtualSensorReport::new(state.virtual_sensor_layout());
+                report.trailer_mut().copy_from_slice(trailer);
+                report.set_values(&state.virtual_sensors);
+                Some(report)
+            }
+            _ => None,
+        };
+        if id != Some(OCTO.control.report_id) && virtual_sensors.is_none() {
+            return Err(OctoError::Usb(rusb::Error::Pipe).into());
+        }
+        let mut report = match &virtual_sensors {
+            Some(report) => report.as_bytes().to_vec(),
+            None => state.control.as_bytes().to_vec(),
+        };
+        state.corrupt(&mut report);
+        let len = report.len().min(buf.len());
+        buf[..len].copy_from_slice(&report[..len]);
+        Ok(len)
+    }
+
+    /// Settings that fail to parse are dropped, like virtual sensor reports
+    fn write_feature_report(&mut self, report: &[u8]) -> Result<usize> {
+        let mut state = self.lock();
+        state.check_transfer()?;
+        let mut received = report.to_vec();
+        state.corrupt(&mut received);
+        if received == layout::SAVE_SETTINGS {
+            state.saved_control = state.control.clone();
+            state.accepted += 1;
+            return Ok(report.len());
+        }
+        match ControlReport::parse(OCTO.control, &received) {
+            Ok(control) => {
+                state.control = control;
+                state.accepted += 1;
+            }
+            Err(_) => state.rejected += 1,
+        }
+        Ok(report.len())
+    }
+
+    /// Clears a hang, and the virtual sensors with it
+    fn reset(&mut self) -> Result<()> {
+        let mut state = self.lock();
+        if !state.connected {
+            return Err(OctoError::Disconnected.into());
+        }
+        state.pending_timeouts = 0;
+        state.stale = false;
+        state.virtual_sensors = [None; 16];
+        state.last_update = None;
+        state.resets += 1;
+        Ok(())
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let mut state = self.lock();
+        if !state.connected {
+            return Err(OctoError::Disconnected.into());
+        }
+        state.stale = false;
+        state.reopens += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Emulator;
+    use crate::{
+        checksum::{Checksum, Crc16Usb},
+        codec,
+        control::{ControlMode, FanControl, TemperatureSource},
+        curve::FanCurve,
+        hid::ReportKind,
+        layout::{VirtualSensorLayout, OCTO},
+        Failsafe, FirmwareCheck, Octo, OctoError, Transport, VirtualSensorReport,
+    };
+    use std::time::Duration;
+
+    /// Values written through Octo end up in the emulated slots
+    #[test]
+    fn write_virtual_sensors() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[20, -5, 30]).unwrap();
+        let sensors = emulator.virtual_sensors();
+        assert_eq!(sensors[..4], [Some(2000), Some(-500), Some(3000), None]);
+        assert_eq!(emulator.accepted_reports(), 1);
+    }
+
+    /// Sub-zero values reach the device through every update call
+    #[test]
+    fn negative_temperatures() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[-5, -327]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(-500), Some(-32700)]);
+        octo.update_virtual_sensors_f32(&[-0.25, -40.0]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(-25), Some(-4000)]);
+        octo.update_centidegrees(&[Some(i16::MIN)]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(i16::MIN));
+        let status = octo.read_status().unwrap();
+        assert_eq!(status.virtual_sensors[0], Some(i16::MIN));
+    }
+
+    /// Partial updates keep the slots they don't mention
+    #[test]
+    fn update_slots() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_slots(&[(0, Some(4000))]).unwrap();
+        octo.update_slots(&[(3, Some(3500))]).unwrap();
+        let sensors = emulator.virtual_sensors();
+        assert_eq!(sensors[..4], [Some(4000), None, None, Some(3500)]);
+        assert!(octo.update_slots(&[(16, Some(1))]).is_err());
+        assert_eq!(emulator.accepted_reports(), 2);
+    }
+
+    /// One sensor can be set and cleared without touching the rest
+    #[test]
+    fn single_sensor() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[30, 31]).unwrap();
+        octo.set_virtual_sensor(5, 42.25).unwrap();
+        octo.clear_virtual_sensor(0).unwrap();
+        let sensors = emulator.virtual_sensors();
+        assert_eq!(sensors[..2], [None, Some(3100)]);
+        assert_eq!(sensors[5], Some(4225));
+        assert!(octo.set_virtual_sensor(16, 20.0).is_err());
+        assert!(octo.clear_virtual_sensor(16).is_err());
+    }
+
+    /// Corrupt reports are ignored, not applied
+    #[test]
+    fn reject_bad_checksum() {
+        let mut emulator = Emulator::new();
+        let mut report = vec![0; 51];
+        report[0] = 4;
+        emulator.write_report(&report).unwrap();
+        assert_eq!(emulator.rejected_reports(), 1);
+        assert_eq!(emulator.virtual_sensors(), [None; 16]);
+    }
+
+    /// Slots revert to disconnected when updates stop
+    #[test]
+    fn virtual_sensor_timeout() {
+        let emulator = Emulator::new().with_virtual_sensor_timeout(Duration::from_millis(20));
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[40]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(4000));
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(emulator.virtual_sensors()[0], None);
+    }
+
+    /// Injected timeouts fail that many transfers, then clear
+    #[test]
+    fn injected_timeouts() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        emulator.inject_timeouts(2);
+        for _ in 0..2 {
+            let error = octo.update_virtual_sensors(&[1]).unwrap_err();
+            assert_eq!(error.kind(), Some(OctoError::Timeout));
+        }
+        octo.update_virtual_sensors(&[1]).unwrap();
+    }
+
+    /// Resetting restores the sensors without counting a reboot
+    #[test]
+    fn reset_device() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[30]).unwrap();
+        emulator.inject_timeouts(100);
+        octo.reset_device().unwrap();
+        assert_eq!(emulator.resets(), 1);
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        octo.read_status().unwrap();
+        assert_eq!(octo.reboots(), 0);
+    }
+
+    /// Repeated timeouts reset the device when asked to
+    #[test]
+    fn reset_after_timeouts() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::builder()
+            .reset_after_timeouts(3)
+            .with_transport(emulator.clone())
+            .unwrap();
+        octo.update_virtual_sensors(&[30]).unwrap();
+        emulator.inject_timeouts(100);
+        for _ in 0..2 {
+            assert!(octo.update_virtual_sensors(&[31]).is_err());
+        }
+        assert_eq!(emulator.resets(), 0);
+        assert!(octo.update_virtual_sensors(&[32]).is_err());
+        assert_eq!(emulator.resets(), 1);
+        assert_eq!(emulator.virtual_sensors()[0], Some(3200));
+        octo.update_virtual_sensors(&[33]).unwrap();
+    }
+
+    /// Timeouts are retried as configured, then returned as timeouts
+    #[test]
+    fn retry() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::builder()
+            .retry(2, Duration::ZERO)
+            .with_transport(emulator.clone())
+            .unwrap();
+        emulator.inject_timeouts(2);
+        octo.update_virtual_sensors(&[30]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        emulator.inject_timeouts(3);
+        let error = octo.update_virtual_sensors(&[31]).unwrap_err();
+        assert_eq!(error.to_string(), "Gave up after 3 attempts");
+        assert_eq!(error.kind(), Some(OctoError::Timeout));
+        emulator.disconnect();
+        let error = octo.update_virtual_sensors(&[32]).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Disconnected));
+        assert_ne!(error.to_string(), "Gave up after 3 attempts");
+    }
+
+    /// Keep-alives re-send at half the timeout, so values never time out
+    #[test]
+    fn keep_alive() {
+        let timeout = Duration::from_millis(200);
+        let emulator = Emulator::new().with_virtual_sensor_timeout(timeout);
+        let mut octo = Octo::builder()
+            .virtual_sensor_timeout(timeout)
+            .with_transport(emulator.clone())
+            .unwrap();
+        assert!(!octo.keep_alive().unwrap());
+        octo.update_virtual_sensors(&[40]).unwrap();
+        assert!(!octo.keep_alive().unwrap());
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(octo.keep_alive().unwrap());
+            assert_eq!(emulator.virtual_sensors()[0], Some(4000));
+        }
+        assert_eq!(emulator.accepted_reports(), 4);
+    }
+
+    /// Corrupted status reports are counted and skipped
+    #[test]
+    fn corrupted_status() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        emulator.inject_corruption(2);
+        octo.read_status().unwrap();
+        assert_eq!(octo.link_stats().checksum_errors, 2);
+        emulator.inject_corruption(3);
+        let error = octo.read_status().unwrap_err();
+        assert!(error.to_string().contains("CRC-16/USB checksum"));
+        assert_eq!(error.kind(), Some(OctoError::ChecksumMismatch));
+        assert_eq!(octo.link_stats().checksum_errors, 5);
+    }
+
+    /// Corrupted control reports are read again, up to the set attempts
+    #[test]
+    fn corrupted_control() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::builder()
+            .checksum_attempts(2)
+            .with_transport(emulator.clone())
+            .unwrap();
+        emulator.inject_corruption(1);
+        octo.read_control().unwrap();
+        assert_eq!(octo.link_stats().checksum_errors, 1);
+        emulator.inject_corruption(2);
+        let error = octo.read_control().unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::ChecksumMismatch));
+        assert!(format!("{error:#}").contains("2 control reports in a row"));
+        assert_eq!(octo.link_stats().checksum_errors, 3);
+    }
+
+    /// Unplugging fails transfers and replugging reboots the device
+    #[test]
+    fn disconnect_and_reconnect() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[1]).unwrap();
+        emulator.disconnect();
+        let error = octo.update_virtual_sensors(&[1]).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Disconnected));
+        emulator.reconnect();
+        assert_eq!(emulator.virtual_sensors()[0], None);
+        octo.update_virtual_sensors(&[1]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(100));
+    }
+
+    /// Reopening after a replug restores the sensors and notices the reboot
+    #[test]
+    fn reopen() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[30]).unwrap();
+        emulator.disconnect();
+        assert!(octo.reopen().is_err());
+        emulator.reconnect();
+        octo.reopen().unwrap();
+        assert_eq!(emulator.reopens(), 1);
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        octo.read_status().unwrap();
+        assert_eq!(octo.reboots(), 1);
+    }
+
+    /// With reconnection enabled, updates carry on once the device is back
+    #[test]
+    fn reconnect_automatically() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::builder()
+            .reconnect(Duration::ZERO, Duration::ZERO)
+            .with_transport(emulator.clone())
+            .unwrap();
+        octo.update_virtual_sensors(&[30]).unwrap();
+        emulator.disconnect();
+        let error = octo.update_virtual_sensors(&[31]).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Disconnected));
+        emulator.replug();
+        octo.update_virtual_sensors(&[32]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(3200));
+        assert_eq!(emulator.reopens(), 1);
+        assert_eq!(octo.link_stats().reconnects, 1);
+    }
+
+    /// Without reconnection, a disconnected device stays failed
+    #[test]
+    fn no_reconnect_by_default() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        emulator.replug();
+        assert!(octo.update_virtual_sensors(&[30]).is_err());
+        assert_eq!(emulator.reopens(), 0);
+    }
+
+    /// Bytes outside the sensors come from the device's own report
+    #[test]
+    fn read_modify_write() {
+        let mut trailer = VirtualSensorReport::default().trailer().to_vec();
+        trailer[0] = 1;
+        let emulator = Emulator::new().with_trailer(&trailer);
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[35]).unwrap();
+        assert_eq!(emulator.rejected_reports(), 0);
+        assert_eq!(emulator.virtual_sensors()[0], Some(3500));
+        let mut octo = Octo::builder()
+            .builtin_template()
+            .with_transport(emulator.clone())
+            .unwrap();
+        octo.update_virtual_sensors(&[36]).unwrap();
+        assert_eq!(emulator.rejected_reports(), 1);
+        octo.refresh_report_template().unwrap();
+        octo.update_virtual_sensors(&[36]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(3600));
+    }
+
+    /// Devices that can't be asked keep the built-in template
+    #[test]
+    fn builtin_template_fallback() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        assert!(octo.refresh_report_template().is_err());
+        octo.update_virtual_sensors(&[20]).unwrap();
+        assert_eq!(emulator.rejected_reports(), 0);
+    }
+
+    /// The report is sized from the descriptor the device declares
+    #[test]
+    fn longer_output_report() {
+        let emulator = Emulator::new().with_output_report_len(64);
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        assert_eq!(octo.report_len(), 64);
+        let descriptor = octo.report_descriptor().unwrap();
+        assert_eq!(descriptor.report_len(ReportKind::Input, 1), Some(0x147));
+        octo.update_virtual_sensors(&[7]).unwrap();
+        assert_eq!(emulator.rejected_reports(), 0);
+        assert_eq!(emulator.virtual_sensors()[0], Some(700));
+    }
+
+    /// Device information comes from a fresh status report
+    #[test]
+    fn info() {
+        let emulator = Emulator::new().with_firmware(1120).with_serial([1, 2]);
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let info = octo.info().unwrap();
+        assert_eq!(info.name, "Octo");
+        assert_eq!(info.firmware, 1120);
+        assert_eq!(info.serial, "00001-00002");
+        assert_eq!(info.power_cycles, 1);
+        emulator.disconnect();
+        emulator.reconnect();
+        assert_eq!(octo.info().unwrap().power_cycles, 2);
+        assert_eq!(octo.reboots(), 1);
+    }
+
+    /// The firmware version is read when opening
+    #[test]
+    fn firmware_at_open() {
+        let octo = Octo::with_transport(Emulator::new().with_firmware(1120)).unwrap();
+        assert_eq!(octo.firmware(), Some(1120));
+    }
+
+    /// Reports for another device are refused before reaching the wire
+    #[test]
+    fn reject_foreign_report() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let foreign = VirtualSensorReport::new(VirtualSensorLayout {
+            report_id: 0x05,
+            ..OCTO.virtual_sensors
+        });
+        assert!(octo.send_report(&foreign).is_err());
+        let truncated = VirtualSensorReport::new(OCTO.virtual_sensors.resized(40).unwrap());
+        assert!(octo.send_report(&truncated).is_err());
+        assert_eq!(emulator.accepted_reports() + emulator.rejected_reports(), 0);
+
+        let mut report = VirtualSensorReport::default();
+        report.update(&[12]);
+        octo.send_report(&report).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(1200));
+    }
+
+    /// The serial number is read when opening and can be required
+    #[test]
+    fn serial() {
+        let emulator = Emulator::new().with_serial([12345, 678]);
+        let octo = Octo::with_transport(emulator.clone()).unwrap();
+        assert_eq!(octo.serial(), Some("12345-00678"));
+        let wanted = Octo::builder().serial("12345-00678");
+        assert!(wanted.clone().with_transport(emulator.clone()).is_ok());
+        let other = Octo::builder().serial("12345-00679");
+        let error = other.with_transport(emulator).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Octo has serial 12345-00678, not 12345-00679"
+        );
+    }
+
+    /// Outdated firmware is refused unless told otherwise
+    #[test]
+    fn outdated_firmware() {
+        let error = Octo::with_transport(Emulator::new().with_firmware(1001))
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("firmware 1001 is older than 1010"));
+        for check in [FirmwareCheck::Warn, FirmwareCheck::Ignore] {
+            let octo = Octo::builder()
+                .firmware_check(check)
+                .with_transport(Emulator::new().with_firmware(1001))
+                .unwrap();
+            assert_eq!(octo.firmware(), Some(1001));
+        }
+    }
+
+    /// A reboot is noticed on the next status read and the values restored
+    #[test]
+    fn restore_after_reboot() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.read_status().unwrap();
+        assert_eq!(octo.reboots(), 0);
+        octo.update_virtual_sensors(&[40, 41]).unwrap();
+        emulator.disconnect();
+        emulator.reconnect();
+        assert_eq!(emulator.virtual_sensors()[0], None);
+        assert_eq!(octo.read_status().unwrap().power_cycles, 2);
+        assert_eq!(octo.reboots(), 1);
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(4000), Some(4100)]);
+    }
+
+    /// Curves are previewed from the settings read off the device
+    #[test]
+    fn simulate_curve() {
+        let emulator = Emulator::new();
+        emulator.set_fan_control(
+            1,
+            &FanControl {
+                mode: ControlMode::Curve,
+                duty: 0,
+                source: TemperatureSource::VirtualSensor(0),
+                curve: vec![(3000, 2000), (5000, 10000)],
+            },
+        );
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let fan = octo.read_control().unwrap().fan(1).unwrap();
+        assert_eq!(fan.source, TemperatureSource::VirtualSensor(0));
+        assert_eq!(octo.simulate_curve(1, 4000).unwrap(), Some(6000));
+        assert_eq!(octo.simulate_curve(0, 4000).unwrap(), Some(0));
+        assert!(octo.simulate_curve(8, 4000).is_err());
+        assert_eq!(emulator.accepted_reports(), 0);
+    }
+
+    /// Flow is read from the status and calibrated in the control report
+    #[test]
+    fn flow() {
+        let emulator = Emulator::new();
+        emulator.set_flow(1234);
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        assert_eq!(octo.read_flow().unwrap(), Some(123.4));
+        octo.set_flow_calibration(169).unwrap();
+        assert_eq!(octo.flow_calibration().unwrap(), 169);
+        assert!(octo.set_flow_calibration(5).is_err());
+        assert!(octo.set_flow_calibration(1001).is_err());
+        assert_eq!(octo.flow_calibration().unwrap(), 169);
+    }
+
+    /// Settings survive a power cycle only once saved
+    #[test]
+    fn save_settings() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.set_flow_calibration(169).unwrap();
+        emulator.reconnect();
+        assert_eq!(octo.flow_calibration().unwrap(), 0);
+        octo.set_flow_calibration(169).unwrap();
+        octo.save_settings().unwrap();
+        assert_eq!(emulator.saved_control().flow_pulses().unwrap(), 169);
+        octo.set_flow_calibration(200).unwrap();
+        emulator.reconnect();
+        assert_eq!(octo.flow_calibration().unwrap(), 169);
+    }
+
+    /// Uploaded curves switch the channel to curve mode on the given source
+    #[test]
+    fn fan_curve() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let curve = FanCurve::new(&[(3000, 2000), (4000, 6000), (5000, 10000)]).unwrap();
+        octo.set_fan_curve(2, &curve, TemperatureSource::VirtualSensor(3))
+            .unwrap();
+        let fan = emulator.fan_control(2);
+        assert_eq!(fan.mode, ControlMode::Curve);
+        assert_eq!(fan.source, TemperatureSource::VirtualSensor(3));
+        assert_eq!(fan.curve[..3], curve.points()[..]);
+        assert_eq!(fan.curve[15], (5000, 10000));
+        assert_eq!(octo.simulate_curve(2, 3500).unwrap(), Some(4000));
+        let too_many: Vec<_> = (0..17).map(|point| (point * 100, 5000)).collect();
+        let too_many = FanCurve::new(&too_many).unwrap();
+        assert!(octo
+            .set_fan_curve(2, &too_many, TemperatureSource::Sensor(0))
+            .is_err());
+        assert!(octo
+            .set_fan_curve(2, &curve, TemperatureSource::VirtualSensor(16))
+            .is_err());
+        assert!(octo
+            .set_fan_curve(2, &curve, TemperatureSource::Sensor(4))
+            .is_err());
+        assert!(octo
+            .set_fan_curve(8, &curve, TemperatureSource::Sensor(0))
+            .is_err());
+    }
+
+    /// The failsafe pins fans following virtual sensors until the next update
+    #[test]
+    fn failsafe_fan_power() {
+        let emulator = Emulator::new();
+        emulator.set_fan_control(
+            1,
+            &FanControl {
+                mode: ControlMode::Curve,
+                duty: 0,
+                source: TemperatureSource::VirtualSensor(0),
+                curve: vec![(3000, 2000), (5000, 10000)],
+            },
+        );
+        let before = emulator.fan_control(1);
+        let other = emulator.fan_control(0);
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[25]).unwrap();
+        octo.engage_failsafe(Failsafe::FanPower(80.0)).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], None);
+        let pinned = emulator.fan_control(1);
+        assert_eq!((pinned.mode, pinned.duty), (ControlMode::Manual, 8000));
+        assert_eq!(emulator.fan_control(0), other);
+        octo.engage_failsafe(Failsafe::FanPower(90.0)).unwrap();
+        octo.update_virtual_sensors(&[26]).unwrap();
+        assert_eq!(emulator.fan_control(1), before);
+        assert!(octo.engage_failsafe(Failsafe::FanPower(101.0)).is_err());
+    }
+
+    /// The emergency holds every fan at full power until restored
+    #[test]
+    fn emergency_max_fans() {
+        let emulator = Emulator::new();
+        emulator.set_fan_control(
+            1,
+            &FanControl {
+                mode: ControlMode::Curve,
+                duty: 0,
+                source: TemperatureSource::VirtualSensor(0),
+                curve: vec![(3000, 2000), (5000, 10000)],
+            },
+        );
+        let before: Vec<_> = (0..8)
+            .map(|channel| emulator.fan_control(channel))
+            .collect();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.restore().unwrap();
+        octo.update_virtual_sensors(&[25]).unwrap();
+        octo.engage_failsafe(Failsafe::FanPower(80.0)).unwrap();
+        octo.emergency_max_fans().unwrap();
+        octo.emergency_max_fans().unwrap();
+        assert!(octo.emergency_engaged());
+        octo.update_virtual_sensors(&[26]).unwrap();
+        for channel in 0..8 {
+            let fan = emulator.fan_control(channel);
+            assert_eq!((fan.mode, fan.duty), (ControlMode::Manual, 10000));
+        }
+        octo.restore().unwrap();
+        assert!(!octo.emergency_engaged());
+        let after: Vec<_> = (0..8)
+            .map(|channel| emulator.fan_control(channel))
+            .collect();
+        assert_eq!(after, before);
+    }
+
+    /// Dropping the Octo engages the configured failsafe
+    #[test]
+    fn failsafe_on_drop() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_virtual_sensors(&[25]).unwrap();
+        drop(octo);
+        assert_eq!(emulator.virtual_sensors()[0], Some(2500));
+        let mut octo = Octo::builder()
+            .failsafe(Failsafe::Disconnect)
+            .with_transport(emulator.clone())
+            .unwrap();
+        octo.update_virtual_sensors(&[25]).unwrap();
+        drop(octo);
+        assert_eq!(emulator.virtual_sensors()[0], None);
+    }
+
+    /// Setting fan power switches the channel to manual and keeps its curve
+    #[test]
+    fn set_fan_power() {
+        let emulator = Emulator::new();
+        let curve = FanControl {
+            mode: ControlMode::Curve,
+            duty: 0,
+            source: TemperatureSource::Sensor(0),
+            curve: vec![(3000, 2000), (5000, 10000)],
+        };
+        emulator.set_fan_control(3, &curve);
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.set_fan_power(3, 42.5).unwrap();
+        let fan = emulator.fan_control(3);
+        assert_eq!(fan.mode, ControlMode::Manual);
+        assert_eq!(fan.duty, 4250);
+        assert_eq!(fan.fan_curve(), curve.fan_curve());
+        octo.set_fan_pwm(0, 255).unwrap();
+        assert_eq!(emulator.fan_control(0).duty, 10000);
+        let status = octo.read_status_report().unwrap();
+        assert_eq!(codec::get_u16(&status, OCTO.status.fans[3]).unwrap(), 4250);
+        assert!(octo.set_fan_power(1, 100.5).is_err());
+        assert!(octo.set_fan_power(1, f32::NAN).is_err());
+        assert!(octo.set_fan_pwm(8, 0).is_err());
+        assert_eq!(emulator.accepted_reports(), 2);
+    }
+
+    /// Physical sensors read back in °C
+    #[test]
+    fn read_sensors() {
+        let emulator = Emulator::new();
+        emulator.set_temperature(2, Some(-1050));
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let sensors = octo.read_sensors().unwrap();
+        assert_eq!(sensors, [Some(25.0), None, Some(-10.5), None]);
+    }
+
+    /// Status reports carry the emulated state and a valid checksum
+    #[test]
+    fn status_report() {
+        let mut emulator = Emulator::new().with_firmware(1120);
+        emulator.set_fan_rpm(0, 900);
+        let mut buf = [0; 512];
+        let len = emulator.read_report(&mut buf).unwrap();
+        assert_eq!(len, 0x147);
+        assert_eq!(buf[0], 1);
+        assert_eq!(codec::get_u16(&buf, 0x0D).unwrap(), 1120);
+        assert_eq!(codec::get_u16(&buf, 0x85).unwrap(), 900);
+        assert!(Crc16Usb.verify(&buf[..len]));
+    }
+}
@@ -0,0 +1,319 @@
+//! Failures worth telling apart in code
+//!
+//! Fallible calls return an [`Error`] whose context says what was being
+//! done, over the failure behind it. Failures a caller might handle, such
+//! as an unplugged device or a malformed report, have an [`OctoError`]
+//! kind, so there's no need to inspect messages:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{Octo, OctoError};
+//! let mut octo = Octo::new().unwrap();
+//! if let Err(error) = octo.update_virtual_sensors(&[40]) {
+//!     match error.kind() {
+//!         Some(OctoError::Disconnected) => octo.reopen().unwrap(),
+//!         Some(OctoError::Timeout) => {} // try again next time
+//!         _ => panic!("{error:#}"),
+//!     }
+//! }
+//! ```
+//!
+//! `{error}` shows what was being done, `{error:#}` the whole chain down
+//! to the failure, separated by colons. [`Error`] implements
+//! [`std::error::Error`], so it converts into `anyhow::Error` and
+//! `Box<dyn Error>` with `?`.
+use std::{error::Error as StdError, fmt};
+
+/// `Result` with this crate's [`Error`]
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A failure, the context it happened in and its kind, if it has one
+pub struct Error(Box<Repr>);
+
+enum Repr {
+    /// A failure of a known kind
+    Kind(OctoError),
+    /// A failure only described by its message
+    Message(String),
+    /// An error from a dependency or the operating system
+    Foreign {
+        kind: Option<OctoError>,
+        error: Box<dyn StdError + Send + Sync>,
+    },
+    /// What was being done when `source` happened
+    Context { context: String, source: Error },
+}
+
+impl Error {
+    /// Error wrapping `error`, of no particular kind
+    pub fn new(error: impl StdError + Send + Sync + 'static) -> Self {
+        Self(Box::new(Repr::Foreign {
+            kind: None,
+            error: Box::new(error),
+        }))
+    }
+
+    /// Error of `kind` caused by `error`
+    pub fn with_kind(kind: OctoError, error: impl StdError + Send + Sync + 'static) -> Self {
+        Self(Box::new(Repr::Foreign {
+            kind: Some(kind),
+            error: Box::new(error),
+        }))
+    }
+
+    /// Error with only a message
+    pub fn msg(message: impl fmt::Display) -> Self {
+        Self(Box::new(Repr::Message(message.to_string())))
+    }
+
+    /// This error, with `context` saying what was being done
+    pub fn context(self, context: impl fmt::Display) -> Self {
+        Self(Box::new(Repr::Context {
+            context: context.to_string(),
+            source: self,
+        }))
+    }
+
+    /// The failure behind this error, under any amount of context
+    pub fn kind(&self) -> Option<OctoError> {
+        match &*self.0 {
+            Repr::Kind(kind) => Some(*kind),
+            Repr::Message(_) => None,
+            Repr::Foreign { kind, .. } => *kind,
+            Repr::Context { source, .. } => source.kind(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    /// The outermost context, or with `{:#}` every cause after it
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.0 {
+            Repr::Kind(kind) => write!(f, "{kind}")?,
+            Repr::Message(message) => f.write_str(message)?,
+            Repr::Foreign { error, .. } => write!(f, "{error}")?,
+            Repr::Context { context, .. } => f.write_str(context)?,
+        }
+        if f.alternate() {
+            let mut source = self.source();
+            while let Some(error) = source {
+                write!(f, ": {error}")?;
+                source = error.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Error {
+    /// The whole chain, as `{:#}` shows it
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:#}")
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &*self.0 {
+            Repr::Kind(kind) => kind.source(),
+            Repr::Message(_) => None,
+            Repr::Foreign { error, .. } => error.source(),
+            Repr::Context { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<OctoError> for Error {
+    fn from(kind: OctoError) -> Self {
+        Self(Box::new(Repr::Kind(kind)))
+    }
+}
+
+impl From<rusb::Error> for Error {
+    /// USB errors take the kind callers handle, see [`OctoError::from`]
+    fn from(error: rusb::Error) -> Self {
+        OctoError::from(error).into()
+    }
+}
+
+/// `From` for errors of dependencies and the standard library
+macro_rules! foreign {
+    ($($(#[$attr:meta])* $error:ty),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Self::new(error)
+                }
+            }
+        )*
+    };
+}
+
+foreign!(
+    std::io::Error,
+    std::fmt::Error,
+    std::num::ParseIntError,
+    std::num::ParseFloatError,
+    std::str::Utf8Error,
+    std::string::FromUtf8Error,
+    std::ffi::NulError,
+    std::time::SystemTimeError,
+    std::net::AddrParseError,
+    std::time::TryFromFloatSecsError,
+    std::num::TryFromIntError,
+    std::sync::mpsc::RecvError,
+    std::sync::mpsc::RecvTimeoutError,
+    #[cfg(any(feature = "service", feature = "http"))]
+    serde_json::Error,
+    #[cfg(feature = "service")]
+    toml::de::Error,
+    #[cfg(feature = "service")]
+    toml::ser::Error,
+    #[cfg(feature = "service")]
+    toml_edit::TomlError,
+    #[cfg(feature = "import")]
+    roxmltree::Error,
+);
+
+/// Adding context to a failed [`Result`] or a missing [`Option`]
+///
+/// Like `anyhow::Context`, for this crate's [`Error`].
+pub trait Context<T> {
+    /// Say what was being done if this failed
+    fn context(self, context: impl fmt::Display) -> Result<T>;
+
+    /// [`Context::context`], only making the context if this failed
+    fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.map_err(|error| error.into().context(context))
+    }
+
+    fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.map_err(|error| error.into().context(context()))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    /// An error with only `context` if this is `None`
+    fn context(self, context: impl fmt::Display) -> Result<T> {
+        self.ok_or_else(|| Error::msg(context))
+    }
+
+    fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T> {
+        self.ok_or_else(|| Error::msg(context()))
+    }
+}
+
+/// Kind of failure behind an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum OctoError {
+    /// No matching device is connected
+    #[error("Device not found")]
+    DeviceNotFound,
+    /// Not allowed to open the device, usually for want of a udev rule
+    #[error("Permission denied")]
+    PermissionDenied,
+    /// A kernel driver or another program holds the device
+    #[error("Device is busy")]
+    Busy,
+    /// The device went away, e.g. it was unplugged
+    #[error("Device disconnected")]
+    Disconnected,
+    /// The device didn't answer in time
+    #[error("Timed out")]
+    Timeout,
+    /// A report failed its checksum
+    #[error("Checksum mismatch")]
+    ChecksumMismatch,
+    /// A value doesn't fit what the device accepts
+    #[error("Out of range")]
+    OutOfRange,
+    /// A report or report descriptor has the wrong length, report ID or
+    /// contents
+    #[error("Invalid report")]
+    InvalidReport,
+    /// A layout doesn't fit its report, or isn't the device's
+    #[error("Invalid layout")]
+    InvalidLayout,
+    /// The device, its firmware or the transport can't do this
+    #[error("Not supported")]
+    Unsupported,
+    /// A configuration file or an export to import from can't be used
+    #[error("Invalid configuration")]
+    InvalidConfig,
+    /// Any other USB failure
+    #[error("{0}")]
+    Usb(#[source] rusb::Error),
+    /// Any other failure of the operating system's HID stack, with the
+    /// `hidapi` feature
+    #[error("{0}")]
+    Io(std::io::ErrorKind),
+}
+
+impl From<rusb::Error> for OctoError {
+    fn from(error: rusb::Error) -> Self {
+        match error {
+            rusb::Error::Access => Self::PermissionDenied,
+            rusb::Error::Busy => Self::Busy,
+            rusb::Error::NoDevice => Self::Disconnected,
+            rusb::Error::Timeout => Self::Timeout,
+            error => Self::Usb(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Context, Error, OctoError};
+
+    /// USB errors map to the failures callers handle
+    #[test]
+    fn from_usb() {
+        assert_eq!(
+            OctoError::from(rusb::Error::NoDevice),
+            OctoError::Disconnected
+        );
+        assert_eq!(
+            OctoError::from(rusb::Error::Access),
+            OctoError::PermissionDenied
+        );
+        assert_eq!(
+            OctoError::from(rusb::Error::Pipe),
+            OctoError::Usb(rusb::Error::Pipe)
+        );
+    }
+
+    /// Kinds read as short messages, USB failures as the USB error
+    #[test]
+    fn display() {
+        assert_eq!(OctoError::InvalidReport.to_string(), "Invalid report");
+        let usb = OctoError::Usb(rusb::Error::Pipe);
+        assert_eq!(usb.to_string(), rusb::Error::Pipe.to_string());
+        assert!(std::error::Error::source(&usb).is_some());
+    }
+
+    /// The kind is found under any amount of context
+    #[test]
+    fn kind() {
+        let error = Err::<(), _>(OctoError::Timeout)
+            .context("Sending")
+            .context("Updating")
+            .unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Timeout));
+        assert_eq!(error.to_string(), "Updating");
+        assert_eq!(format!("{error:#}"), "Updating: Sending: Timed out");
+        assert_eq!(Error::msg("other").kind(), None);
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let error = Error::with_kind(OctoError::DeviceNotFound, missing).context("Opening");
+        assert_eq!(error.kind(), Some(OctoError::DeviceNotFound));
+        assert_eq!(format!("{error:#}"), "Opening: entity not found");
+        assert_eq!(
+            None::<()>.context("Nothing").unwrap_err().to_string(),
+            "Nothing"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! C interface
+//!
+//! A stable C ABI over [`Octo`] for C and C++ programs such as fan control
+//! GUIs. The declarations are in `include/octo_virtual_sensors.h`,
+//! generated with
+//!
+//! ```text
+//! cbindgen --config cbindgen.toml --output include/octo_virtual_sensors.h
+//! ```
+//!
+//! and the library is the crate's cdylib, `libocto_virtual_sensors.so`:
+//!
+//! ```c
+//! Octo *octo;
+//! if (octo_new(&octo) != OCTO_STATUS_OK) {
+//!     fprintf(stderr, "%s\n", octo_last_error());
+//!     return 1;
+//! }
+//! octo_set_sensor(octo, 0, 41.2f);
+//! octo_free(octo);
+//! ```
+//!
+//! Every call returns an [`OctoStatus`]; the message of the last failure on
+//! the calling thread is kept for [`octo_last_error`]. Handles may move
+//! between threads but not be used from two at once. Sensors are numbered
+//! from 0.
+//!
+//! Only built with the `ffi` feature.
+use crate::{Octo, OctoError};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+thread_local! {
+    /// Message of the last failure on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Result of a C call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OctoStatus {
+    /// Success
+    Ok = 0,
+    /// A null pointer, bad string or sensor index was passed
+    InvalidArgument = -1,
+    /// No matching device is connected
+    DeviceNotFound = -2,
+    /// Not allowed to open the device
+    PermissionDenied = -3,
+    /// A kernel driver or another program holds the device
+    Busy = -4,
+    /// The device went away; reopen it with [`octo_reopen`]
+    Disconnected = -5,
+    /// The device didn't answer in time
+    Timeout = -6,
+    /// A report failed its checksum
+    ChecksumMismatch = -7,
+    /// A value doesn't fit what the device accepts
+    OutOfRange = -8,
+    /// Any other failure, see [`octo_last_error`]
+    Other = -9,
+    /// The library panicked, a bug
+    Panic = -10,
+    /// A report from the device wasn't what was expected
+    InvalidReport = -11,
+    /// A report doesn't match the device's layout
+    InvalidLayout = -12,
+    /// The device, its firmware or the backend can't do this
+    Unsupported = -13,
+}
+
+impl OctoStatus {
+    /// Status for `error`
+    fn of(error: &crate::Error) -> Self {
+        match error.kind() {
+            Some(OctoError::DeviceNotFound) => Self::DeviceNotFound,
+            Some(OctoError::PermissionDenied) => Self::PermissionDenied,
+            Some(OctoError::Busy) => Self::Busy,
+            Some(OctoError::Disconnected) => Self::Disconnected,
+            Some(OctoError::Timeout) => Self::Timeout,
+            Some(OctoError::ChecksumMismatch) => Self::ChecksumMismatch,
+            Some(OctoError::OutOfRange) => Self::OutOfRange,
+            Some(OctoError::InvalidReport) => Self::InvalidReport,
+            Some(OctoError::InvalidLayout) => Self::InvalidLayout,
+            Some(OctoError::Unsupported) => Self::Unsupported,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Remember `message` for [`octo_last_error`]
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_else(|e| {
+        let nul = e.nul_position();
+        let mut bytes = e.into_vec();
+        bytes.truncate(nul);
+        CString::new(bytes).unwrap_or_default()
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning its error or panic into a status
+fn call(f: impl FnOnce() -> Result<(), (OctoStatus, String)>) -> OctoStatus {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => OctoStatus::Ok,
+        Ok(Err((status, message))) => {
+            set_last_error(message);
+            status
+        }
+        Err(_) => {
+            set_last_error("Panicked, this is a bug".to_owned());
+            OctoStatus::Panic
+        }
+    }
+}
+
+/// Status and message for a failed device operation
+fn device_error(error: crate::Error) -> (OctoStatus, String) {
+    (OctoStatus::of(&error), format!("{error:#}"))
+}
+
+/// Status and message for a bad argument
+fn invalid(message: &str) -> (OctoStatus, String) {
+    (OctoStatus::InvalidArgument, message.to_owned())
+}
+
+/// The device behind `octo`
+///
+/// # Safety
+/// `octo` must be null or a live handle from this library.
+unsafe fn octo_mut<'a>(octo: *mut Octo) -> Result<&'a mut Octo, (OctoStatus, String)> {
+    // SAFETY: per the caller
+    unsafe { octo.as_mut() }.ok_or_else(|| invalid("Null Octo handle"))
+}
+
+/// Store a new handle for the opened device in `*out`
+///
+/// # Safety
+/// `out` must be null or valid for a pointer write.
+unsafe fn open(out: *mut *mut Octo, open: impl FnOnce() -> crate::Result<Octo>) -> OctoStatus {
+    call(|| {
+        if out.is_null() {
+            return Err(invalid("Null output pointer"));
+        }
+        let octo = open().map_err(device_error)?;
+        // SAFETY: checked for null, valid per the caller
+        unsafe { out.write(Box::into_raw(Box::new(octo))) };
+        Ok(())
+    })
+}
+
+/// Open the first OCTO found and store its handle in `*out`
+///
+/// # Safety
+/// `out` must be valid for a pointer write. Free the handle with
+/// [`octo_free`].
+#[no_mangle]
+pub unsafe extern "C" fn octo_new(out: *mut *mut Octo) -> OctoStatus {
+    // SAFETY: per the caller
+    unsafe { open(out, Octo::new) }
+}
+
+/// Open the OCTO with serial number `serial` and store its handle in
+/// `*out`
+///
+/// # Safety
+/// `serial` must be a NUL-terminated string and `out` valid for a pointer
+/// write. Free the handle with [`octo_free`].
+#[no_mangle]
+pub unsafe extern "C" fn octo_open_by_serial(
+    serial: *const c_char,
+    out: *mut *mut Octo,
+) -> OctoStatus {
+    if serial.is_null() {
+        return call(|| Err(invalid("Null serial")));
+    }
+    // SAFETY: per the caller
+    let serial = unsafe { CStr::from_ptr(serial) };
+    let Ok(serial) = serial.to_str() else {
+        return call(|| Err(invalid("Serial isn't UTF-8")));
+    };
+    // SAFETY: per the caller
+    unsafe { open(out, || Octo::open_by_serial(serial)) }
+}
+
+/// Close the device and free `octo`
+///
+/// # Safety
+/// `octo` must be null or a handle from this library not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn octo_free(octo: *mut Octo) {
+    if !octo.is_null() {
+        // SAFETY: per the caller the handle came from Box::into_raw
+        drop(unsafe { Box::from_raw(octo) });
+    }
+}
+
+/// Set virtual sensor `index` to `degrees` Celsius
+///
+/// # Safety
+/// `octo` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn octo_set_sensor(
+    octo: *mut Octo,
+    index: usize,
+    degrees: f32,
+) -> OctoStatus {
+    call(|| {
+        // SAFETY: per the caller
+        let octo = unsafe { octo_mut(octo) }?;
+        if index >= octo.last_report().values().len() {
+            return Err(invalid(&format!("No sensor {index}")));
+        }
+        octo.set_virtual_sensor(index, degrees)
+            .map(drop)
+            .map_err(device_error)
+    })
+}
+
+/// Disconnect virtual sensor `index`
+///
+/// # Safety
+/// `octo` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn octo_clear_sensor(octo: *mut Octo, index: usize) -> OctoStatus {
+    call(|| {
+        // SAFETY: per the caller
+        let octo = unsafe { octo_mut(octo) }?;
+        if index >= octo.last_report().values().len() {
+            return Err(invalid(&format!("No sensor {index}")));
+        }
+        octo.clear_virtual_sensor(index)
+            .map(drop)
+            .map_err(device_error)
+    })
+}
+
+/// Set virtual sensors in order from `len` values in degrees Celsius
+///
+/// NaN and slots past `len` are disconnected.
+///
+/// # Safety
+/// `octo` must be a live handle and `values` point to `len` floats.
+#[no_mangle]
+pub unsafe extern "C" fn octo_update_sensors(
+    octo: *mut Octo,
+    values: *const f32,
+    len: usize,
+) -> OctoStatus {
+    call(|| {
+        // SAFETY: per the caller
+        let octo = unsafe { octo_mut(octo) }?;
+        let values = if len == 0 {
+            &[]
+        } else if values.is_null() {
+            return Err(invalid("Null values"));
+        } else {
+            // SAFETY: per the caller
+            unsafe { std::slice::from_raw_parts(values, len) }
+        };
+        octo.update_virtual_sensors_f32(values)
+            .map(drop)
+            .map_err(device_error)
+    })
+}
+
+/// Read up to `len` physical temperature sensors into `out`, in degrees
+/// Celsius with NaN for unplugged ones
+///
+/// The number of sensors written is stored in `*count` unless it is null.
+///
+/// # Safety
+/// `octo` must be a live handle, `out` valid for `len` floats and `count`
+/// null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn octo_read_sensors(
+    octo: *mut Octo,
+    out: *mut f32,
+    len: usize,
+    count: *mut usize,
+) -> OctoStatus {
+    call(|| {
+        // SAFETY: per the caller
+        let octo = unsafe { octo_mut(octo) }?;
+        if out.is_null() && len > 0 {
+            return Err(invalid("Null output buffer"));
+        }
+        let sensors = octo.read_sensors().map_err(device_error)?;
+        let written = sensors.len().min(len);
+        for (i, value) in sensors.into_iter().take(written).enumerate() {
+            // SAFETY: i < len, valid per the caller
+            unsafe { out.add(i).write(value.unwrap_or(f32::NAN)) };
+        }
+        if !count.is_null() {
+            // SAFETY: checked for null, valid per the caller
+            unsafe { count.write(written) };
+        }
+        Ok(())
+    })
+}
+
+/// Reopen the device after it was unplugged
+///
+/// # Safety
+/// `octo` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn octo_reopen(octo: *mut Octo) -> OctoStatus {
+    call(|| {
+        // SAFETY: per the caller
+        unsafe { octo_mut(octo) }?.reopen().map_err(device_error)
+    })
+}
+
+/// Message of the last failed call on this thread, or null
+///
+/// The string stays valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn octo_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        octo_clear_sensor, octo_free, octo_last_error, octo_read_sensors, octo_set_sensor,
+        octo_update_sensors, OctoStatus,
+    };
+    use crate::{emulator::Emulator, Octo, RangeCheck};
+    use std::ffi::CStr;
+
+    /// Calls through the C ABI reach the device and report failures
+    #[test]
+    fn calls() {
+        let emulator = Emulator::new();
+        let octo = Octo::builder()
+            .range_check(RangeCheck::Reject)
+            .with_transport(emulator.clone())
+            .unwrap();
+        let octo = Box::into_raw(Box::new(octo));
+        unsafe {
+            assert_eq!(octo_set_sensor(octo, 2, 41.2), OctoStatus::Ok);
+            assert_eq!(emulator.virtual_sensors()[2], Some(4120));
+            let values = [30.0, f32::NAN];
+            assert_eq!(
+                octo_update_sensors(octo, values.as_ptr(), 2),
+                OctoStatus::Ok
+            );
+            assert_eq!(emulator.virtual_sensors()[..3], [Some(3000), None, None]);
+            assert_eq!(octo_set_sensor(octo, 0, 900.0), OctoStatus::OutOfRange);
+            assert_eq!(octo_set_sensor(octo, 99, 20.0), OctoStatus::InvalidArgument);
+            let message = CStr::from_ptr(octo_last_error()).to_str().unwrap();
+            assert_eq!(message, "No sensor 99");
+            assert_eq!(octo_clear_sensor(octo, 0), OctoStatus::Ok);
+            assert_eq!(emulator.virtual_sensors()[0], None);
+            let mut sensors = [0.0; 4];
+            let mut count = 0;
+            let status = octo_read_sensors(octo, sensors.as_mut_ptr(), 4, &mut count);
+            assert_eq!(status, OctoStatus::Ok);
+            assert_eq!(count, 4);
+            emulator.disconnect();
+            assert_eq!(octo_set_sensor(octo, 0, 20.0), OctoStatus::Disconnected);
+            octo_free(octo);
+            assert_eq!(
+                octo_set_sensor(std::ptr::null_mut(), 0, 20.0),
+                OctoStatus::InvalidArgument
+            );
+        }
+    }
+
+    /// The checked-in header declares every exported function
+    #[test]
+    fn header() {
+        let header = include_str!("../include/octo_virtual_sensors.h");
+        for name in include_str!("ffi.rs")
+            .lines()
+            .filter_map(|line| line.strip_prefix("pub unsafe extern \"C\" fn "))
+            .chain(
+                include_str!("ffi.rs")
+                    .lines()
+                    .filter_map(|line| line.strip_prefix("pub extern \"C\" fn ")),
+            )
+            .map(|rest| &rest[..rest.find('(').unwrap()])
+        {
+            assert!(header.contains(&format!("{name}(")), "{name} missing");
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! Fire-and-forget updates from any thread
+//!
+//! [`Octo::spawn`] moves the device onto a thread of its own and returns an
+//! [`OctoHandle`]. Clones of the handle can be passed around freely; each
+//! [`OctoHandle::send`] queues values and returns straight away, and the
+//! device thread writes them one report at a time:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::Octo;
+//! let handle = Octo::new().unwrap().spawn().unwrap();
+//! let gpu = handle.clone();
+//! std::thread::spawn(move || gpu.send(&[55]).unwrap());
+//! handle.send(&[42]).unwrap();
+//! ```
+//!
+//! [`Octo::spawn_with`] picks how many updates wait and what happens
+//! when a slow device lets them pile up.
+//!
+//! For replies, use [`AsyncOcto`](crate::nonblocking::AsyncOcto) instead.
+use crate::error::Result;
+use crate::{
+    queue::{Backpressure, Pushed, UpdateQueue},
+    Octo,
+};
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+};
+
+/// Updates [`Octo::spawn`] queues before dropping the oldest
+pub(crate) static CAPACITY: usize = 16;
+
+/// Values for the device thread
+enum Values {
+    /// Whole degrees, see [`Octo::update_virtual_sensors`]
+    Degrees(Vec<i16>),
+    /// Centidegrees, see [`Octo::update_centidegrees`]
+    Centidegrees(Vec<Option<i16>>),
+}
+
+/// Values numbered in the order they were sent
+struct Update {
+    number: u64,
+    values: Values,
+}
+
+/// What the device thread reports back
+#[derive(Debug, Default)]
+struct Shared {
+    errors: u64,
+    last_error: Option<String>,
+    /// Number of the newest update sent
+    sent: u64,
+    /// Number of the newest update that made it into the queue
+    queued: u64,
+    /// Number of the newest update written or coalesced away
+    done: u64,
+    stopped: bool,
+}
+
+/// [`Shared`] and a signal for flushes waiting on it
+#[derive(Debug, Default)]
+struct State {
+    shared: Mutex<Shared>,
+    done: Condvar,
+}
+
+impl State {
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Closes the queue once the last handle is gone, stopping the thread
+struct Sender(UpdateQueue<Update>);
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Cheap, cloneable handle to an [`Octo`] on its own thread
+///
+/// Values queued while the device is busy are coalesced: only the newest
+/// is written, as older ones would be overwritten straight away. The queue
+/// in between is bounded, and what gives when a slow device lets it fill
+/// up is the [`Backpressure`] it was spawned
+/// with. The thread stops and drops the `Octo` once every clone is
+/// dropped.
+#[derive(Clone)]
+pub struct OctoHandle {
+    sender: Arc<Sender>,
+    state: Arc<State>,
+}
+
+impl OctoHandle {
+    /// Move `octo` onto a new device thread, queueing up to `capacity`
+    /// updates for it
+    pub(crate) fn spawn(octo: Octo, capacity: usize, backpressure: Backpressure) -> Result<Self> {
+        let queue = UpdateQueue::new(capacity, backpressure);
+        let state = Arc::new(State::default());
+        let handle = Self {
+            sender: Arc::new(Sender(queue.clone())),
+            state: state.clone(),
+        };
+        thread::Builder::new()
+            .name("octo-vs-updater".to_owned())
+            .spawn(move || run(octo, &queue, &state))?;
+        Ok(handle)
+    }
+
+    /// Queue whole degrees Celsius for the virtual sensors, see
+    /// [`Octo::update_virtual_sensors`]
+    ///
+    /// Returns without waiting for the device, unless the queue is full and
+    /// its backpressure is [`Backpressure::Block`].
+    /// Only fails if the device thread has stopped.
+    pub fn send(&self, values: &[i16]) -> Result<()> {
+        self.post(Values::Degrees(values.to_vec()))
+    }
+
+    /// Queue centidegree values, see [`Octo::update_centidegrees`]
+    pub fn send_centidegrees(&self, values: &[Option<i16>]) -> Result<()> {
+        self.post(Values::Centidegrees(values.to_vec()))
+    }
+
+    /// Wait until everything queued so far has been written
+    pub fn flush(&self) -> Result<()> {
+        let mut shared = self.state.lock();
+        let target = shared.queued;
+        while shared.done < target {
+            if shared.stopped {
+                bail!("The device thread has stopped");
+            }
+            shared = self
+                .state
+                .done
+                .wait(shared)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        Ok(())
+    }
+
+    /// Number of updates the device failed to take
+    pub fn errors(&self) -> u64 {
+        self.state.lock().errors
+    }
+
+    /// The most recent of those failures
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().last_error.clone()
+    }
+
+    /// Updates dropped because the queue was full
+    ///
+    /// Updates coalesced with newer ones aren't counted.
+    pub fn dropped(&self) -> u64 {
+        self.sender.0.dropped()
+    }
+
+    /// Hand `values` to the device thread
+    fn post(&self, values: Values) -> Result<()> {
+        let number = {
+            let mut shared = self.state.lock();
+            shared.sent += 1;
+            shared.sent
+        };
+        match self.sender.0.push(Update { number, values }) {
+            Pushed::Closed => bail!("The device thread has stopped"),
+            Pushed::DroppedNewest => {}
+            Pushed::Queued | Pushed::DroppedOldest => {
+                let mut shared = self.state.lock();
+                shared.queued = shared.queued.max(number);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for OctoHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OctoHandle")
+            .field("queued", &self.sender.0.len())
+            .field("dropped", &self.dropped())
+            .field("errors", &self.errors())
+            .finish()
+    }
+}
+
+/// Marks the thread stopped however it ends, failing waiting flushes
+struct Stopped<'a> {
+    queue: &'a UpdateQueue<Update>,
+    state: &'a State,
+}
+
+impl Drop for Stopped<'_> {
+    fn drop(&mut self) {
+        self.queue.close();
+        self.state.lock().stopped = true;
+        self.state.done.notify_all();
+    }
+}
+
+/// Write queued values until every handle is gone
+fn run(mut octo: Octo, queue: &UpdateQueue<Update>, state: &State) {
+    let _stopped = Stopped { queue, state };
+    while let Some(update) = queue.pop() {
+        // Take whatever else queued up meanwhile, keeping the newest values
+        let newest = std::iter::from_fn(|| queue.try_pop()).fold(update, |_, next| next);
+        let result = match &newest.values {
+            Values::Degrees(values) => octo.update_virtual_sensors(values),
+            Values::Centidegrees(values) => octo.update_centidegrees(values),
+        };
+        let mut shared = state.lock();
+        if let Err(error) = result {
+            shared.errors += 1;
+            shared.last_error = Some(format!("{error:#}"));
+        }
+        shared.done = shared.done.max(newest.number);
+        state.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        layout::AQUAERO, mock::MockTransport, queue::Backpressure, Octo, VirtualSensorReport,
+    };
+    use std::time::Duration;
+
+    /// Values from several threads are written by the device thread
+    #[test]
+    fn send() {
+        let mock = MockTransport::new();
+        let handle = Octo::with_transport(mock.clone()).unwrap().spawn().unwrap();
+        let other = handle.clone();
+        std::thread::spawn(move || other.send(&[40]).unwrap())
+            .join()
+            .unwrap();
+        handle.flush().unwrap();
+        handle.send_centidegrees(&[Some(4150)]).unwrap();
+        handle.flush().unwrap();
+        let mut expected = VirtualSensorReport::default();
+        expected.set_values(&[Some(4150)]);
+        assert_eq!(mock.written().last().unwrap(), expected.as_bytes());
+        assert_eq!(mock.written().len(), 2);
+        assert_eq!(handle.errors(), 0);
+    }
+
+    /// Failures are counted rather than returned
+    #[test]
+    fn errors() {
+        let octo = Octo::builder()
+            .device(AQUAERO)
+            .with_transport(MockTransport::new())
+            .unwrap();
+        let handle = octo.spawn().unwrap();
+        handle.send(&[40]).unwrap();
+        handle.flush().unwrap();
+        assert_eq!(handle.errors(), 1);
+        assert!(handle.last_error().unwrap().contains("isn't known"));
+    }
+
+    /// A slow device drops updates by the queue's policy, or holds senders up
+    #[test]
+    fn slow_consumer() {
+        let report = |value| {
+            let mut report = VirtualSensorReport::default();
+            report.set_values(&[Some(value)]);
+            report.as_bytes().to_vec()
+        };
+        for backpressure in [
+            Backpressure::DropOldest,
+            Backpressure::DropNewest,
+            Backpressure::Block,
+        ] {
+            let mock = MockTransport::new();
+            let octo = Octo::with_transport(mock.clone()).unwrap();
+            mock.set_write_delay(Duration::from_millis(20));
+            let handle = octo.spawn_with(2, backpressure).unwrap();
+            for value in 1..=10 {
+                handle.send_centidegrees(&[Some(value)]).unwrap();
+            }
+            handle.flush().unwrap();
+            let written = mock.written();
+            let last = written.last().unwrap();
+            match backpressure {
+                Backpressure::DropOldest => {
+                    assert!(handle.dropped() > 0);
+                    assert_eq!(*last, report(10));
+                }
+                Backpressure::DropNewest => {
+                    assert!(handle.dropped() > 0);
+                    assert!(*last != report(10));
+                }
+                Backpressure::Block => {
+                    assert_eq!(handle.dropped(), 0);
+                    assert_eq!(*last, report(10));
+                }
+            }
+            assert!(written.len() < 10, "{backpressure:?}");
+        }
+    }
+}
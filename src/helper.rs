@@ -0,0 +1,375 @@
+//! Privilege-separated access to the device
+//!
+//! A small privileged [`Helper`] owns the USB device and listens on a
+//! group-writable Unix socket. Unprivileged processes talk to it through a
+//! [`HelperClient`], which is just another [`Transport`], so the rest of the
+//! library works unchanged:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{helper::HelperClient, Octo};
+//! let client = HelperClient::connect("/run/octo-virtual-sensors/helper.sock").unwrap();
+//! let mut octo = Octo::with_transport(client).unwrap();
+//! octo.update_virtual_sensors(&[42]).unwrap();
+//! ```
+//!
+//! The helper only takes the sensor values out of well formed virtual
+//! sensor reports for the device it owns and encodes its own report from
+//! them, with its calibrations, so socket access doesn't grant arbitrary
+//! USB writes.
+//!
+//! Frames are an op or status byte, a big-endian u16 payload length and
+//! the payload.
+use crate::error::{Context, Result};
+use crate::{Failsafe, SharedOcto, Transport, VirtualSensorReport};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Socket path used when none is given
+pub const DEFAULT_SOCKET: &str = "/run/octo-virtual-sensors/helper.sock";
+
+/// Largest payload either side accepts
+static MAX_PAYLOAD: usize = 4096;
+
+/// Write a virtual sensor report
+static OP_WRITE: u8 = 1;
+/// Read a status report
+static OP_READ: u8 = 2;
+/// Fetch the report descriptor
+static OP_DESCRIPTOR: u8 = 3;
+
+static STATUS_OK: u8 = 0;
+static STATUS_ERROR: u8 = 1;
+
+/// Write one frame
+fn write_frame(stream: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(payload.len())
+        .ok()
+        .filter(|&len| usize::from(len) <= MAX_PAYLOAD)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Payload too large"))?;
+    let mut frame = vec![kind];
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+/// Read one frame, `None` on a clean end of stream
+fn read_frame(stream: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; 3];
+    match stream.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let [kind, len @ ..] = header;
+    let len = usize::from(u16::from_be_bytes(len));
+    if len > MAX_PAYLOAD {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Payload too large",
+        ));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    Ok(Some((kind, payload)))
+}
+
+/// Bind a group-writable socket at `path`
+///
+/// A stale socket left by a previous run is replaced; one that still has a
+/// listener is not. Group ownership comes from the process, e.g. systemd's
+/// `Group=`.
+pub fn bind(path: impl AsRef<Path>) -> Result<UnixListener> {
+    let path = path.as_ref();
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        if UnixStream::connect(path).is_ok() {
+            bail!("Another helper is listening on {}", path.display());
+        }
+        fs::remove_file(path).with_context(|| format!("Removing stale {}", path.display()))?;
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("Binding {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o660))
+        .with_context(|| format!("Setting permissions on {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Serves one device to clients on a Unix socket
+#[derive(Clone)]
+pub struct Helper {
+    octo: SharedOcto,
+    watchdog: Arc<Mutex<Watchdog>>,
+}
+
+/// When clients last wrote, and what to do if they stop
+#[derive(Debug, Default)]
+struct Watchdog {
+    failsafe: Option<(Duration, Failsafe)>,
+    last_write: Option<Instant>,
+    engaged: bool,
+}
+
+impl Helper {
+    /// Take an opened device, which may be shared with other users
+    pub fn new(octo: impl Into<SharedOcto>) -> Self {
+        Self {
+            octo: octo.into(),
+            watchdog: Arc::default(),
+        }
+    }
+
+    /// Engage `failsafe` when no client has written for `timeout`
+    ///
+    /// The helper outlives the daemons feeding it, so this catches one
+    /// that was killed or hung. Nothing happens until the first write, and
+    /// the next write undoes it. Checked from a thread that runs for the
+    /// rest of the process.
+    pub fn with_failsafe(self, timeout: Duration, failsafe: Failsafe) -> Self {
+        self.lock_watchdog().failsafe = Some((timeout, failsafe));
+        let helper = self.clone();
+        let period = (timeout / 4).max(Duration::from_millis(100));
+        thread::spawn(move || loop {
+            thread::sleep(period);
+            helper.check_watchdog_at(Instant::now());
+        });
+        self
+    }
+
+    /// Engage the failsafe if writes stopped long enough before `now`
+    ///
+    /// Returns whether it was engaged by this call.
+    fn check_watchdog_at(&self, now: Instant) -> bool {
+        let mut watchdog = self.lock_watchdog();
+        let (Some((timeout, failsafe)), Some(last_write)) =
+            (watchdog.failsafe, watchdog.last_write)
+        else {
+            return false;
+        };
+        if watchdog.engaged || now.saturating_duration_since(last_write) < timeout {
+            return false;
+        }
+        watchdog.engaged = true;
+        drop(watchdog);
+        warn!("No virtual sensor update for {timeout:?}, engaging the failsafe");
+        let mut octo = self.octo.lock();
+        if let Err(error) = octo.engage_failsafe(failsafe) {
+            warn!("Failsafe: {error:#}");
+        }
+        true
+    }
+
+    fn lock_watchdog(&self) -> MutexGuard<'_, Watchdog> {
+        self.watchdog.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Serve clients until the listener fails, one thread per connection
+    pub fn serve(&self, listener: UnixListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream.context("Accepting connection")?;
+            let helper = self.clone();
+            thread::spawn(move || {
+                if let Err(error) = helper.handle(stream) {
+                    warn!("Helper client: {error}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Answer requests on one connection until the client hangs up
+    pub fn handle(&self, mut stream: UnixStream) -> io::Result<()> {
+        while let Some((op, payload)) = read_frame(&mut stream)? {
+            match self.request(op, &payload) {
+                Ok(response) => write_frame(&mut stream, STATUS_OK, &response)?,
+                Err(error) => {
+                    write_frame(&mut stream, STATUS_ERROR, format!("{error:#}").as_bytes())?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run one request against the device
+    fn request(&self, op: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut octo = self.octo.lock();
+        if op == OP_WRITE {
+            // Nothing but the values crosses over, whatever else the
+            // client put in its report; calibrations apply as usual
+            let values = VirtualSensorReport::parse(*octo.report_layout(), payload)?.values();
+            let written = octo.update_centidegrees(&values)?;
+            let mut watchdog = self.lock_watchdog();
+            watchdog.last_write = Some(Instant::now());
+            watchdog.engaged = false;
+            Ok((written as u32).to_be_bytes().to_vec())
+        } else if op == OP_READ {
+            octo.read_status_report()
+        } else if op == OP_DESCRIPTOR {
+            octo.report_descriptor()
+                .map(|descriptor| descriptor.as_bytes().to_vec())
+                .context("Device has no report descriptor")
+        } else {
+            bail!("Unknown op {op}")
+        }
+    }
+}
+
+/// Transport forwarding reports to a [`Helper`]
+pub struct HelperClient {
+    stream: UnixStream,
+}
+
+impl HelperClient {
+    /// Connect to a helper listening on `path`
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("Connecting to {}", path.display()))?;
+        Ok(Self { stream })
+    }
+
+    /// Send a request and wait for the response payload
+    fn request(&mut self, op: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        write_frame(&mut self.stream, op, payload).context("Sending to helper")?;
+        let (status, response) = read_frame(&mut self.stream)
+            .context("Reading from helper")?
+            .context("Helper closed the connection")?;
+        if status != STATUS_OK {
+            bail!("Helper: {}", String::from_utf8_lossy(&response));
+        }
+        Ok(response)
+    }
+}
+
+impl Transport for HelperClient {
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        let response = self.request(OP_WRITE, report)?;
+        let written: [u8; 4] = response
+            .try_into()
+            .map_err(|_| crate::Error::msg("Malformed helper response"))?;
+        Ok(u32::from_be_bytes(written) as usize)
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let response = self.request(OP_READ, &[])?;
+        let len = response.len().min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+        Ok(len)
+    }
+
+    fn report_descriptor(&mut self) -> Result<Vec<u8>> {
+        self.request(OP_DESCRIPTOR, &[])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bind, Helper, HelperClient, OP_WRITE};
+    use crate::{
+        calibration::Calibration, emulator::Emulator, Failsafe, Octo, Transport,
+        VirtualSensorReport,
+    };
+    use std::{
+        os::unix::fs::PermissionsExt,
+        path::PathBuf,
+        thread,
+        time::{Duration, Instant},
+    };
+    use tempfile::TempDir;
+
+    /// Start a helper for an emulated device, returning its socket in a
+    /// directory removed on drop
+    fn start(emulator: &Emulator) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("helper.sock");
+        let listener = bind(&path).unwrap();
+        let helper = Helper::new(Octo::with_transport(emulator.clone()).unwrap());
+        thread::spawn(move || helper.serve(listener));
+        (dir, path)
+    }
+
+    /// A client Octo updates the helper's device
+    #[test]
+    fn client_updates_device() {
+        let emulator = Emulator::new().with_output_report_len(60);
+        let (_dir, path) = start(&emulator);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let client = HelperClient::connect(&path).unwrap();
+        let mut octo = Octo::with_transport(client).unwrap();
+        assert_eq!(octo.firmware(), Some(1019));
+        assert_eq!(octo.report_len(), 60);
+        octo.update_virtual_sensors(&[33, 44]).unwrap();
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(3300), Some(4400)]);
+    }
+
+    /// Anything but a valid virtual sensor report is refused
+    #[test]
+    fn reject_arbitrary_reports() {
+        let emulator = Emulator::new();
+        let (_dir, path) = start(&emulator);
+        let mut client = HelperClient::connect(&path).unwrap();
+        let error = client.write_report(&[3; 51]).unwrap_err();
+        assert!(error.to_string().starts_with("Helper: "));
+        assert!(client.request(9, &[]).is_err());
+        assert!(client.request(OP_WRITE, &[]).is_err());
+        assert_eq!(emulator.accepted_reports() + emulator.rejected_reports(), 0);
+    }
+
+    /// Clients' values are encoded by the helper's device, with its
+    /// calibrations
+    #[test]
+    fn reencodes_values() {
+        let emulator = Emulator::new();
+        let octo = Octo::builder()
+            .calibration(0, Calibration::new().with_offset(-150))
+            .with_transport(emulator.clone())
+            .unwrap();
+        let helper = Helper::new(octo);
+        let mut report = VirtualSensorReport::default();
+        report.update(&[30]);
+        helper.request(OP_WRITE, report.as_bytes()).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(2850));
+    }
+
+    /// The watchdog engages the failsafe once writes stop, and only once
+    #[test]
+    fn watchdog() {
+        let emulator = Emulator::new();
+        let helper = Helper::new(Octo::with_transport(emulator.clone()).unwrap())
+            .with_failsafe(Duration::from_secs(3600), Failsafe::Disconnect);
+        let mut report = VirtualSensorReport::default();
+        report.update(&[30]);
+        let now = Instant::now();
+        assert!(!helper.check_watchdog_at(now + Duration::from_secs(7200)));
+        helper.request(OP_WRITE, report.as_bytes()).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+        assert!(!helper.check_watchdog_at(Instant::now()));
+        let later = Instant::now() + Duration::from_secs(3600);
+        assert!(helper.check_watchdog_at(later));
+        assert_eq!(emulator.virtual_sensors()[0], None);
+        assert!(!helper.check_watchdog_at(later));
+        helper.request(OP_WRITE, report.as_bytes()).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(3000));
+    }
+
+    /// A live helper's socket isn't stolen by a second one
+    #[test]
+    fn refuse_live_socket() {
+        let emulator = Emulator::new();
+        let (_dir, path) = start(&emulator);
+        assert!(bind(&path).is_err());
+    }
+}
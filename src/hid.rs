@@ -0,0 +1,340 @@
+//! HID report descriptor parsing
+//!
+//! Only as much of the HID item grammar as is needed to work out which
+//! reports a device declares and how long they are.
+use crate::error::{Context, Result};
+use crate::OctoError;
+use std::fmt;
+
+/// Largest report the Linux HID core handles, `HID_MAX_BUFFER_SIZE`
+pub const MAX_REPORT_LEN: usize = 16384;
+
+/// Direction of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    /// Device to host
+    Input,
+    /// Host to device
+    Output,
+    /// Bidirectional configuration
+    Feature,
+}
+
+/// A report declared by the descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportInfo {
+    /// Direction of the report
+    pub kind: ReportKind,
+    /// Report ID, 0 if the device doesn't use report IDs
+    pub id: u8,
+    /// Payload size in bits, excluding the report ID
+    pub bits: usize,
+}
+
+impl ReportInfo {
+    /// Length in bytes as sent on the wire, including the report ID
+    pub fn wire_len(&self) -> usize {
+        let id = usize::from(self.id != 0);
+        id + self.bits.div_ceil(8)
+    }
+}
+
+/// Difference between the descriptor and a report the layout tables expect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The report isn't declared at all
+    Missing {
+        /// Direction of the report
+        kind: ReportKind,
+        /// Report ID
+        id: u8,
+    },
+    /// The report is declared with a different length
+    Length {
+        /// Direction of the report
+        kind: ReportKind,
+        /// Report ID
+        id: u8,
+        /// Length from the layout tables
+        expected: usize,
+        /// Length the descriptor declares
+        declared: usize,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { kind, id } => {
+                write!(f, "{kind:?} report {id} is not in the report descriptor")
+            }
+            Self::Length {
+                kind,
+                id,
+                expected,
+                declared,
+            } => write!(
+                f,
+                "{kind:?} report {id} is {declared} bytes, expected {expected}"
+            ),
+        }
+    }
+}
+
+/// Parsed HID report descriptor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportDescriptor {
+    raw: Vec<u8>,
+    reports: Vec<ReportInfo>,
+}
+
+/// Global items that affect report sizes
+#[derive(Debug, Clone, Copy, Default)]
+struct Globals {
+    id: u8,
+    size: usize,
+    count: usize,
+}
+
+impl ReportDescriptor {
+    /// Parse a raw descriptor
+    ///
+    /// Fails on truncated items. Items that don't affect report layout are
+    /// skipped.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let mut globals = Globals::default();
+        let mut stack = Vec::new();
+        let mut descriptor = Self {
+            raw: bytes.to_vec(),
+            reports: Vec::new(),
+        };
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let prefix = bytes[pos];
+            pos += 1;
+
+            // Long item: size byte, tag byte, data
+            if prefix == 0xFE {
+                let Some(&size) = bytes.get(pos) else {
+                    return Err(OctoError::InvalidReport)
+                        .with_context(|| format!("Truncated long item at {}", pos - 1));
+                };
+                pos += 2 + usize::from(size);
+                if pos > bytes.len() {
+                    return Err(OctoError::InvalidReport).context("Truncated long item");
+                }
+                continue;
+            }
+
+            let size = match prefix & 0x03 {
+                3 => 4,
+                n => usize::from(n),
+            };
+            let Some(data) = bytes.get(pos..pos + size) else {
+                return Err(OctoError::InvalidReport)
+                    .with_context(|| format!("Truncated item at {}", pos - 1));
+            };
+            pos += size;
+            let value = data
+                .iter()
+                .rev()
+                .fold(0_u32, |value, byte| value << 8 | u32::from(*byte));
+
+            match prefix & 0xFC {
+                // Main items
+                0x80 => descriptor.add(ReportKind::Input, globals)?,
+                0x90 => descriptor.add(ReportKind::Output, globals)?,
+                0xB0 => descriptor.add(ReportKind::Feature, globals)?,
+                // Global items
+                0x74 => globals.size = value as usize,
+                0x94 => globals.count = value as usize,
+                0x84 => globals.id = value as u8,
+                0xA4 => stack.push(globals),
+                0xB4 => {
+                    globals = stack
+                        .pop()
+                        .ok_or(OctoError::InvalidReport)
+                        .with_context(|| format!("Pop without push at {}", pos - 1))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(descriptor)
+    }
+
+    /// The descriptor as read from the device
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// All declared reports
+    pub fn reports(&self) -> &[ReportInfo] {
+        &self.reports
+    }
+
+    /// Wire length of a report, if the descriptor declares it
+    pub fn report_len(&self, kind: ReportKind, id: u8) -> Option<usize> {
+        self.reports
+            .iter()
+            .find(|report| report.kind == kind && report.id == id)
+            .map(ReportInfo::wire_len)
+    }
+
+    /// Compare against the reports the layout tables expect
+    ///
+    /// `expected` lists each report's direction, ID and wire length.
+    pub fn mismatches(&self, expected: &[(ReportKind, u8, usize)]) -> Vec<Mismatch> {
+        expected
+            .iter()
+            .filter_map(|&(kind, id, expected)| match self.report_len(kind, id) {
+                None => Some(Mismatch::Missing { kind, id }),
+                Some(declared) if declared != expected => Some(Mismatch::Length {
+                    kind,
+                    id,
+                    expected,
+                    declared,
+                }),
+                Some(_) => None,
+            })
+            .collect()
+    }
+
+    /// Accumulate a main item into its report
+    ///
+    /// Sizes come straight from the device, so a report whose length
+    /// overflows is refused rather than trusted.
+    fn add(&mut self, kind: ReportKind, globals: Globals) -> Result<()> {
+        let overflow = || format!("{kind:?} report {} is too long", globals.id);
+        let bits = globals
+            .size
+            .checked_mul(globals.count)
+            .ok_or(OctoError::InvalidReport)
+            .with_context(overflow)?;
+        match self
+            .reports
+            .iter_mut()
+            .find(|report| report.kind == kind && report.id == globals.id)
+        {
+            Some(report) => {
+                report.bits = report
+                    .bits
+                    .checked_add(bits)
+                    .ok_or(OctoError::InvalidReport)
+                    .with_context(overflow)?;
+            }
+            None => self.reports.push(ReportInfo {
+                kind,
+                id: globals.id,
+                bits,
+            }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mismatch, ReportDescriptor, ReportKind};
+    use crate::layout::OCTO;
+
+    /// Vendor defined collection with an input and an output report
+    static DESCRIPTOR: &[u8] = &[
+        0x06, 0x00, 0xFF, // Usage Page (Vendor)
+        0x09, 0x01, // Usage
+        0xA1, 0x01, // Collection (Application)
+        0x15, 0x00, // Logical Minimum (0)
+        0x26, 0xFF, 0x00, // Logical Maximum (255)
+        0x75, 0x08, // Report Size (8)
+        0x85, 0x01, // Report ID (1)
+        0x96, 0x46, 0x01, // Report Count (326)
+        0x09, 0x01, // Usage
+        0x81, 0x02, // Input
+        0x85, 0x04, // Report ID (4)
+        0x95, 0x20, // Report Count (32)
+        0x09, 0x01, // Usage
+        0x91, 0x02, // Output
+        0x95, 0x12, // Report Count (18)
+        0x09, 0x02, // Usage
+        0x91, 0x02, // Output
+        0xC0, // End Collection
+    ];
+
+    /// Sizes that overflow are refused instead of wrapping or panicking
+    #[test]
+    fn overflow() {
+        let huge = [
+            0x77, 0xFF, 0xFF, 0xFF, 0xFF, // Report Size (0xFFFFFFFF)
+            0x97, 0xFF, 0xFF, 0xFF, 0xFF, // Report Count (0xFFFFFFFF)
+            0x91, 0x02, // Output
+            0x91, 0x02, // Output
+        ];
+        assert!(ReportDescriptor::parse(&huge).is_err());
+        let gigabyte = [
+            0x75, 0x08, // Report Size (8)
+            0x97, 0x00, 0x00, 0x00, 0x40, // Report Count (0x40000000)
+            0x85, 0x04, // Report ID (4)
+            0x91, 0x02, // Output
+        ];
+        let descriptor = ReportDescriptor::parse(&gigabyte).unwrap();
+        let len = descriptor.report_len(ReportKind::Output, 4).unwrap();
+        assert_eq!(OCTO.virtual_sensors.resized(len), None);
+    }
+
+    /// Report lengths include the ID byte and sum every main item
+    #[test]
+    fn report_lengths() {
+        let descriptor = ReportDescriptor::parse(DESCRIPTOR).unwrap();
+        assert_eq!(descriptor.report_len(ReportKind::Input, 1), Some(0x147));
+        assert_eq!(descriptor.report_len(ReportKind::Output, 4), Some(51));
+        assert_eq!(descriptor.report_len(ReportKind::Feature, 3), None);
+    }
+
+    /// Differences from the expected reports are listed
+    #[test]
+    fn mismatches() {
+        let descriptor = ReportDescriptor::parse(DESCRIPTOR).unwrap();
+        let mismatches = descriptor.mismatches(&[
+            (ReportKind::Input, 1, 0x147),
+            (ReportKind::Output, 4, 60),
+            (ReportKind::Feature, 3, 0x65F),
+        ]);
+        assert_eq!(
+            mismatches,
+            [
+                Mismatch::Length {
+                    kind: ReportKind::Output,
+                    id: 4,
+                    expected: 60,
+                    declared: 51
+                },
+                Mismatch::Missing {
+                    kind: ReportKind::Feature,
+                    id: 3
+                },
+            ]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "Output report 4 is 51 bytes, expected 60"
+        );
+    }
+
+    /// Push and pop restore the global state
+    #[test]
+    fn push_pop() {
+        let descriptor = ReportDescriptor::parse(&[
+            0x75, 0x08, 0x95, 0x04, 0xA4, 0x95, 0x10, 0x85, 0x02, 0x81, 0x02, 0xB4, 0x81, 0x02,
+        ])
+        .unwrap();
+        assert_eq!(descriptor.report_len(ReportKind::Input, 2), Some(17));
+        assert_eq!(descriptor.report_len(ReportKind::Input, 0), Some(4));
+    }
+
+    /// Truncated items are an error rather than a panic
+    #[test]
+    fn truncated() {
+        assert!(ReportDescriptor::parse(&[0x96, 0x46]).is_err());
+        assert!(ReportDescriptor::parse(&[0xFE]).is_err());
+        assert!(ReportDescriptor::parse(&[0xB4]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Reports through the operating system's HID stack
+//!
+//! On Windows and macOS the HID class driver owns the device and libusb
+//! bulk writes fight it. [`HidTransport`] sends the same reports as HID
+//! output and feature reports through hidapi instead, which works
+//! wherever the HID driver is bound, Linux hidraw included.
+//!
+//! Machines with more than one device can pick one by
+//! [`OctoBuilder::hid_path`](crate::OctoBuilder::hid_path), which on
+//! Windows also takes the device instance path Device Manager shows, or
+//! there by
+//! [`OctoBuilder::container_id`](crate::OctoBuilder::container_id). Both
+//! work where the HID stack won't hand out serial numbers.
+//!
+//! Only built with the `hidapi` feature, through the [`hidapi`](::hidapi)
+//! crate. On Linux that links against the system `hidapi-hidraw` library,
+//! elsewhere the crate builds hidapi itself.
+use crate::error::{Context, Result};
+use crate::{OctoError, Transport};
+use ::hidapi::{HidApi, HidDevice, HidError};
+use std::{ffi::CString, io::ErrorKind};
+
+/// How long a read waits for an input report, in milliseconds
+static READ_TIMEOUT_MS: i32 = 1000;
+
+/// One HID interface found by [`devices`]
+///
+/// Composite devices list one entry per interface, told apart by usage
+/// page and usage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HidDeviceInfo {
+    /// Platform path to open the interface by
+    pub path: CString,
+    /// USB vendor ID
+    pub vendor_id: u16,
+    /// USB product ID
+    pub product_id: u16,
+    /// USB serial number string, if the device has one
+    pub serial: Option<String>,
+    /// HID usage page of the interface's top-level collection
+    pub usage_page: u16,
+    /// HID usage of the interface's top-level collection
+    pub usage: u16,
+    /// USB interface number, -1 where the platform doesn't say
+    pub interface: i32,
+}
+
+impl HidDeviceInfo {
+    /// Whether this is the interface at `path`
+    ///
+    /// `path` is the path as listed, or on Windows the device instance
+    /// path, such as `HID\VID_0C70&PID_F011\7&1A2B3C4D&0&0000`. Windows
+    /// interface paths are `\\?\`, the instance path with `#` for `\`,
+    /// then the interface class. Case doesn't matter.
+    pub fn is_at(&self, path: &str) -> bool {
+        let listed = self.path.to_string_lossy();
+        if listed.eq_ignore_ascii_case(path) {
+            return true;
+        }
+        let listed = listed.to_ascii_uppercase();
+        let instance = format!("{}#", path.to_ascii_uppercase().replace('\\', "#"));
+        listed
+            .strip_prefix("\\\\?\\")
+            .is_some_and(|listed| listed.starts_with(&instance))
+    }
+}
+
+/// Every HID interface with this vendor and product ID
+pub fn devices(vendor_id: u16, product_id: u16) -> Result<Vec<HidDeviceInfo>> {
+    let api = HidApi::new().map_err(error)?;
+    Ok(api
+        .device_list()
+        .filter(|info| info.vendor_id() == vendor_id && info.product_id() == product_id)
+        .map(|info| HidDeviceInfo {
+            path: info.path().to_owned(),
+            vendor_id: info.vendor_id(),
+            product_id: info.product_id(),
+            serial: info.serial_number().map(str::to_owned),
+            usage_page: info.usage_page(),
+            usage: info.usage(),
+            interface: info.interface_number(),
+        })
+        .collect())
+}
+
+/// `error` with the kind of failure behind it
+///
+/// hidapi's C library only gives a message, which ends in the operating
+/// system's description of the failure, so that is what's matched.
+/// Anything else is [`OctoError::Io`].
+fn error(error: HidError) -> crate::Error {
+    let kind = match &error {
+        HidError::IoError { error } => io_kind(error.kind()),
+        HidError::HidApiError { message } => message_kind(message),
+        _ => OctoError::Io(ErrorKind::Other),
+    };
+    crate::Error::with_kind(kind, error)
+}
+
+/// Kind of an operating system failure
+fn io_kind(kind: ErrorKind) -> OctoError {
+    match kind {
+        ErrorKind::NotFound => OctoError::DeviceNotFound,
+        ErrorKind::PermissionDenied => OctoError::PermissionDenied,
+        ErrorKind::ResourceBusy => OctoError::Busy,
+        ErrorKind::TimedOut => OctoError::Timeout,
+        kind => OctoError::Io(kind),
+    }
+}
+
+/// Kind of the failure a hidapi message describes, as Linux, Windows and
+/// macOS word it
+fn message_kind(message: &str) -> OctoError {
+    let message = message.to_ascii_lowercase();
+    let says = |phrases: &[&str]| phrases.iter().any(|phrase| message.contains(phrase));
+    if says(&["permission denied", "access is denied", "not permitted"]) {
+        OctoError::PermissionDenied
+    } else if says(&["no such device", "not configured", "not connected"]) {
+        OctoError::Disconnected
+    } else if says(&["no such file", "cannot find", "not found"]) {
+        OctoError::DeviceNotFound
+    } else if says(&["busy", "used by another process", "exclusive access"]) {
+        OctoError::Busy
+    } else if says(&["timed out", "timeout"]) {
+        OctoError::Timeout
+    } else {
+        OctoError::Io(ErrorKind::Other)
+    }
+}
+
+/// Transport over an open hidapi device
+pub struct HidTransport {
+    device: HidDevice,
+}
+
+impl HidTransport {
+    /// Open the interface `info` describes
+    ///
+    /// Fails with [`OctoError::DeviceNotFound`] if it went away,
+    /// [`OctoError::PermissionDenied`] without access to it, or
+    /// [`OctoError::Io`] for anything else the operating system refused.
+    pub fn open(info: &HidDeviceInfo) -> Result<Self> {
+        let device = HidApi::new()
+            .and_then(|api| api.open_path(&info.path))
+            .map_err(error)
+            .with_context(|| format!("Opening {}", info.path.to_string_lossy()))?;
+        Ok(Self { device })
+    }
+
+    /// Container ID of the device, as Device Manager shows it
+    ///
+    /// The container ID, such as `{4F2E5A1C-8D3B-11EF-9C6A-0A1B2C3D4E5F}`,
+    /// is the same for every interface of a device and survives replugging
+    /// it into another port.
+    #[cfg(windows)]
+    pub fn container_id(&self) -> Result<String> {
+        let guid = self
+            .device
+            .get_container_id()
+            .map_err(error)
+            .context("Reading the container ID")?;
+        let [a, b, rest @ ..] = guid.data4;
+        let node: String = rest.iter().map(|byte| format!("{byte:02X}")).collect();
+        Ok(format!(
+            "{{{:08X}-{:04X}-{:04X}-{a:02X}{b:02X}-{node}}}",
+            guid.data1, guid.data2, guid.data3
+        ))
+    }
+}
+
+impl Transport for HidTransport {
+    /// Send the report as a HID output report
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        self.device
+            .write(report)
+            .map_err(error)
+            .context("Writing output report")
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self
+            .device
+            .read_timeout(buf, READ_TIMEOUT_MS)
+            .map_err(error)
+            .context("Reading input report")?;
+        match len {
+            0 => Err(OctoError::Timeout.into()),
+            len => Ok(len),
+        }
+    }
+
+    fn read_feature_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let id = *buf.first().context("No room for the report ID")?;
+        self.device
+            .get_feature_report(buf)
+            .map_err(error)
+            .with_context(|| format!("Reading feature report {id}"))
+    }
+
+    fn write_feature_report(&mut self, report: &[u8]) -> Result<usize> {
+        let id = *report.first().context("Feature report is empty")?;
+        self.device
+            .send_feature_report(report)
+            .map_err(error)
+            .with_context(|| format!("Writing feature report {id}"))?;
+        Ok(report.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{error, message_kind};
+    use crate::OctoError;
+    use ::hidapi::HidError;
+    use std::io::ErrorKind;
+
+    /// hidapi's messages on each platform map to the failures callers
+    /// handle
+    #[test]
+    fn message_kinds() {
+        let kinds = [
+            (
+                "Failed to open a device with path '/dev/hidraw3': Permission denied",
+                OctoError::PermissionDenied,
+            ),
+            (
+                "Failed to open a device with path '/dev/hidraw3': No such file or directory",
+                OctoError::DeviceNotFound,
+            ),
+            ("hid_write: No such device", OctoError::Disconnected),
+            (
+                "Failed to open a device: (0x00000005) Access is denied.",
+                OctoError::PermissionDenied,
+            ),
+            (
+                "WriteFile: (0x0000048F) The device is not connected.",
+                OctoError::Disconnected,
+            ),
+            ("Something else went wrong", OctoError::Io(ErrorKind::Other)),
+        ];
+        for (message, kind) in kinds {
+            assert_eq!(message_kind(message), kind, "{message}");
+        }
+    }
+
+    /// Operating system errors keep their kind, the message its text
+    #[test]
+    fn kinds() {
+        let denied = error(HidError::IoError {
+            error: ErrorKind::PermissionDenied.into(),
+        });
+        assert_eq!(denied.kind(), Some(OctoError::PermissionDenied));
+        let broken = error(HidError::IoError {
+            error: ErrorKind::BrokenPipe.into(),
+        });
+        assert_eq!(broken.kind(), Some(OctoError::Io(ErrorKind::BrokenPipe)));
+        let message = error(HidError::HidApiError {
+            message: "hid_read_timeout: Permission denied".to_owned(),
+        });
+        assert_eq!(message.kind(), Some(OctoError::PermissionDenied));
+        assert_eq!(
+            message.to_string(),
+            "hidapi error: hid_read_timeout: Permission denied"
+        );
+    }
+}
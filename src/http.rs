@@ -0,0 +1,407 @@
+//! HTTP API for pushing temperatures from other hosts
+//!
+//! A [`Server`] owns an [`Octo`] and takes virtual sensor values over
+//! HTTP, so containers and machines on the LAN can publish temperatures
+//! without USB access:
+//!
+//! ```text
+//! PUT    /sensors/3   {"celsius": 41.2}   set virtual sensor 3
+//! PUT    /sensors/3   {"celsius": null}   disconnect it
+//! DELETE /sensors/3                       disconnect it
+//! GET    /sensors/3                       {"celsius": 41.2}
+//! GET    /sensors                         {"celsius": [null, null, 41.2, ...]}
+//! GET    /status                          the status report, see Status
+//! ```
+//!
+//! Sensors are numbered from 1 like on the command line. Errors come back
+//! as `{"error": "..."}`. There is no authentication, so bind to an
+//! address only trusted hosts reach. A request may be at most 8 KiB and
+//! must arrive within 5 seconds in all, and at most 16 connections are
+//! served at once; others are turned away with 503. Values time out on the device like
+//! any others, so clients should push more often than its virtual sensor
+//! timeout.
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{http::Server, Octo};
+//! use std::net::TcpListener;
+//! let server = Server::new(Octo::new().unwrap());
+//! server.serve(TcpListener::bind("0.0.0.0:9529").unwrap()).unwrap();
+//! ```
+//!
+//! Only built with the `http` feature.
+use crate::error::{Context, Result};
+use crate::{Octo, OctoError, SharedOcto};
+use serde_json::{json, Value};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a client may take to send its whole request
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request accepted, request line, headers and body together
+static MAX_REQUEST: u64 = 8192;
+
+/// Largest request body accepted
+static MAX_BODY: usize = 4096;
+
+/// Most connections served at once
+static MAX_CONNECTIONS: usize = 16;
+
+/// Serves an [`Octo`] over HTTP
+///
+/// Clones share the device and the connection limit.
+#[derive(Clone)]
+pub struct Server {
+    octo: SharedOcto,
+    connections: Arc<AtomicUsize>,
+}
+
+/// Status line and JSON body of a response
+type Response = (&'static str, Value);
+
+impl Server {
+    /// Server for `octo`, which may be shared with other users
+    pub fn new(octo: impl Into<SharedOcto>) -> Self {
+        Self {
+            octo: octo.into(),
+            connections: Arc::default(),
+        }
+    }
+
+    /// The device, for work between requests such as keep-alives
+    pub fn octo(&self) -> MutexGuard<'_, Octo> {
+        self.octo.lock()
+    }
+
+    /// Serve until the listener fails, one thread per connection
+    ///
+    /// Past [`MAX_CONNECTIONS`] at once, new connections get a 503 and are
+    /// closed without a thread of their own.
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream.context("Accepting connection")?;
+            let Some(connection) = Connection::new(&self.connections) else {
+                // A fresh socket's send buffer takes the short answer
+                // without blocking the accept loop
+                let busy = error("503 Service Unavailable", "Too many connections");
+                if let Err(error) = stream
+                    .set_nonblocking(true)
+                    .and_then(|()| send(&stream, busy))
+                {
+                    warn!("HTTP client: {error}");
+                }
+                continue;
+            };
+            let server = self.clone();
+            thread::spawn(move || {
+                let _connection = connection;
+                if let Err(error) = server.handle(stream) {
+                    warn!("HTTP client: {error}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Answer one HTTP request
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let client = Deadline {
+            stream: &stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        };
+        let mut reader = BufReader::new(client.take(MAX_REQUEST));
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        let mut complete = request.ends_with('\n');
+        let mut content_length = 0;
+        let mut header = String::new();
+        while complete && reader.read_line(&mut header)? > 2 {
+            complete = header.ends_with('\n');
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(usize::MAX);
+                }
+            }
+            header.clear();
+        }
+        let response = if !complete {
+            error("431 Request Header Fields Too Large", "Request too large")
+        } else if content_length > MAX_BODY {
+            error("413 Payload Too Large", "Request body too large")
+        } else {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+            let mut parts = request.split_whitespace();
+            let method = parts.next().unwrap_or_default();
+            let path = parts.next().unwrap_or_default();
+            self.respond(method, path, &String::from_utf8_lossy(&body))
+        };
+        send(&stream, response)
+    }
+
+    /// Response to `method` on `path` with request body `body`
+    fn respond(&self, method: &str, path: &str, body: &str) -> Response {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("GET", ["status"]) => match self.octo().read_status() {
+                Ok(status) => ("200 OK", json!(status)),
+                Err(error) => device_error(&error),
+            },
+            ("GET", ["sensors"]) => {
+                let values = self.octo().last_report().values();
+                let celsius: Vec<_> = values.iter().map(|value| celsius(*value)).collect();
+                ("200 OK", json!({ "celsius": celsius }))
+            }
+            (_, ["sensors", number]) => {
+                let Some(slot) = number
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|number| number.checked_sub(1))
+                else {
+                    return error("404 Not Found", &format!("No sensor {number}"));
+                };
+                self.sensor(method, slot, body)
+            }
+            (_, ["status" | "sensors"]) => error("405 Method Not Allowed", "Only GET"),
+            _ => error("404 Not Found", &format!("Nothing at {path}")),
+        }
+    }
+
+    /// Read, set or clear virtual sensor `slot`, numbered from 0
+    fn sensor(&self, method: &str, slot: usize, body: &str) -> Response {
+        let mut octo = self.octo();
+        if slot >= octo.last_report().values().len() {
+            return error("404 Not Found", &format!("No sensor {}", slot + 1));
+        }
+        let result = match method {
+            "GET" => Ok(()),
+            "DELETE" => octo.clear_virtual_sensor(slot).map(drop),
+            "PUT" => {
+                let degrees = match parse_celsius(body) {
+                    Ok(degrees) => degrees,
+                    Err(error) => return self::error("400 Bad Request", &format!("{error:#}")),
+                };
+                match degrees {
+                    Some(degrees) => octo.set_virtual_sensor(slot, degrees as f32),
+                    None => octo.clear_virtual_sensor(slot),
+                }
+                .map(drop)
+            }
+            _ => return error("405 Method Not Allowed", "Only GET, PUT and DELETE"),
+        };
+        if let Err(error) = result {
+            return device_error(&error);
+        }
+        let value = octo.last_report().values().get(slot).copied().flatten();
+        ("200 OK", json!({ "celsius": celsius(value) }))
+    }
+}
+
+/// Write `response` to the client and close the connection
+fn send(mut stream: &TcpStream, (status, body): Response) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Reads from a client until [`REQUEST_TIMEOUT`] after it connected,
+/// however the request trickles in
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Request took too long",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// One of the connections counted against [`MAX_CONNECTIONS`], until
+/// dropped
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// Count a connection, `None` if there are already as many as allowed
+    fn new(connections: &Arc<AtomicUsize>) -> Option<Self> {
+        connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < MAX_CONNECTIONS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Self(connections.clone()))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Degrees from a `{"celsius": ...}` body, `None` for `null`
+fn parse_celsius(body: &str) -> Result<Option<f64>> {
+    let body: Value = serde_json::from_str(body)?;
+    match body.get("celsius").context("Missing field \"celsius\"")? {
+        Value::Null => Ok(None),
+        value => value.as_f64().context("Expected a number").map(Some),
+    }
+}
+
+/// Centidegrees as degrees Celsius, `None` if disconnected
+fn celsius(value: Option<i16>) -> Option<f64> {
+    value.map(|value| f64::from(value) / 100.0)
+}
+
+/// Error response with `message`
+fn error(status: &'static str, message: &str) -> Response {
+    (status, json!({ "error": message }))
+}
+
+/// Error response for a failed device operation
+fn device_error(error: &crate::Error) -> Response {
+    let status = match error.kind() {
+        Some(OctoError::OutOfRange) => "422 Unprocessable Entity",
+        _ => "503 Service Unavailable",
+    };
+    self::error(status, &format!("{error:#}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Server, MAX_CONNECTIONS, MAX_REQUEST};
+    use crate::{emulator::Emulator, Octo, RangeCheck};
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+    };
+
+    /// Server for an emulated Octo on a free local port
+    fn start() -> SocketAddr {
+        let octo = Octo::with_transport(Emulator::new()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || Server::new(octo).serve(listener));
+        address
+    }
+
+    /// Status line of the response to `request`
+    fn status(address: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or_default().to_owned()
+    }
+
+    /// Requests past the size limits are refused
+    #[test]
+    fn limits() {
+        let address = start();
+        // Still in a header when the limit is reached
+        let mut headers = b"GET /status HTTP/1.1\r\nX-Padding: ".to_vec();
+        headers.resize(MAX_REQUEST as usize, b'a');
+        assert_eq!(
+            status(address, &headers),
+            "HTTP/1.1 431 Request Header Fields Too Large"
+        );
+        let body = b"PUT /sensors/1 HTTP/1.1\r\nContent-Length: 5000\r\n\r\n";
+        assert_eq!(status(address, body), "HTTP/1.1 413 Payload Too Large");
+    }
+
+    /// Connections past the limit are turned away while the others wait
+    #[test]
+    fn connection_limit() {
+        let address = start();
+        let idle: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        assert_eq!(status(address, b""), "HTTP/1.1 503 Service Unavailable");
+        drop(idle);
+        // Closing them frees their slots once their threads notice
+        let served = (0..50).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            status(address, b"GET /status HTTP/1.1\r\n\r\n") == "HTTP/1.1 200 OK"
+        });
+        assert!(served);
+    }
+
+    /// Requests over HTTP set sensors and read the device
+    #[test]
+    fn serve() {
+        let emulator = Emulator::new();
+        let octo = Octo::builder()
+            .range_check(RangeCheck::Reject)
+            .with_transport(emulator.clone())
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(octo);
+        let serving = server.clone();
+        std::thread::spawn(move || serving.serve(listener));
+        let request = |method: &str, path: &str, body: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(
+                stream,
+                "{method} {path} HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            (head.lines().next().unwrap().to_owned(), body.to_owned())
+        };
+        let (status, body) = request("PUT", "/sensors/3", r#"{"celsius": 41.2}"#);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"celsius":41.2}"#);
+        assert_eq!(emulator.virtual_sensors()[2], Some(4120));
+        let (_, body) = request("GET", "/sensors", "");
+        assert!(body.starts_with(r#"{"celsius":[null,null,41.2,null"#));
+        let (status, _) = request("GET", "/status", "");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let (status, _) = request("PUT", "/sensors/3", r#"{"celsius": 900}"#);
+        assert_eq!(status, "HTTP/1.1 422 Unprocessable Entity");
+        let (status, body) = request("PUT", "/sensors/3", r#"{"kelvin": 300}"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(body.starts_with(r#"{"error":"#));
+        assert_eq!(request("DELETE", "/sensors/3", "").1, r#"{"celsius":null}"#);
+        assert_eq!(emulator.virtual_sensors()[2], None);
+        assert_eq!(
+            request("GET", "/sensors/17", "").0,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(request("GET", "/sensors/0", "").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            request("POST", "/status", "").0,
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+        emulator.disconnect();
+        assert_eq!(
+            request("GET", "/status", "").0,
+            "HTTP/1.1 503 Service Unavailable"
+        );
+        drop(server);
+    }
+}
@@ -0,0 +1,208 @@
+//! Writing virtual sensors through the hwmon driver
+//!
+//! Kernels whose `aquacomputer_d5next` driver makes the virtual sensor
+//! channels writable take values through `/sys/class/hwmon`, so no libusb
+//! access, udev rule or driver unbinding is needed. [`HwmonTransport`]
+//! turns each virtual sensor report into writes of the channels'
+//! `tempN_input` attributes, in millidegrees, and disconnects a slot by
+//! writing 0 to its `tempN_enable`. Drivers without `tempN_enable` can't
+//! disconnect a slot, which then keeps its last value until the device's
+//! virtual sensor timeout.
+//!
+//! [`OctoBuilder::backend`](crate::OctoBuilder::backend) picks it: by
+//! default when the attributes are writable, and the USB transport
+//! otherwise. Status reports can't be read through it, so readings, the
+//! serial number and firmware version aren't available; use
+//! [`Backend::Usb`](crate::Backend::Usb) where they're needed.
+use crate::error::{Context, Result};
+use crate::{kernel, layout::DeviceLayout, OctoError, Transport, VirtualSensorReport};
+use std::{
+    fs::{self, OpenOptions},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Sends virtual sensor reports as hwmon attribute writes
+#[derive(Debug)]
+pub struct HwmonTransport {
+    dir: PathBuf,
+    device: DeviceLayout,
+}
+
+impl HwmonTransport {
+    /// The hwmon device of the first `device` with writable virtual
+    /// sensor channels, `None` if there is none
+    pub fn find(device: &DeviceLayout) -> Result<Option<Self>> {
+        Self::find_in(Path::new("/sys/class/hwmon"), device)
+    }
+
+    /// Like [`HwmonTransport::find`], looking under `root`
+    fn find_in(root: &Path, device: &DeviceLayout) -> Result<Option<Self>> {
+        let entries = match fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error).with_context(|| format!("Listing {}", root.display())),
+        };
+        let mut dirs: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        dirs.sort();
+        for dir in dirs {
+            let driver = fs::read_link(dir.join("device/driver")).unwrap_or_default();
+            let name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+            if driver
+                .file_name()
+                .is_none_or(|driver| driver != kernel::HWMON_DRIVER)
+                || name.trim() != device.hwmon_name
+            {
+                continue;
+            }
+            let transport = Self {
+                dir,
+                device: *device,
+            };
+            if transport.writable() {
+                trace!(
+                    Debug,
+                    "Writing virtual sensors through {}",
+                    transport.dir.display()
+                );
+                return Ok(Some(transport));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The hwmon device's sysfs directory
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// hwmon temperature channel of virtual sensor `slot`
+    ///
+    /// The driver numbers physical sensors first, from 1, then the
+    /// virtual ones.
+    fn channel(&self, slot: usize) -> usize {
+        self.device.status.sensor_count + slot + 1
+    }
+
+    /// Whether every virtual sensor channel takes writes from this process
+    ///
+    /// The attribute has to be writable at all, which sysfs enforces even
+    /// for root, and openable for writing by the current user. Opening
+    /// writes nothing.
+    fn writable(&self) -> bool {
+        (0..self.device.virtual_sensors.sensor_count).all(|slot| {
+            let path = self.dir.join(format!("temp{}_input", self.channel(slot)));
+            fs::metadata(&path).is_ok_and(|metadata| metadata.permissions().mode() & 0o222 != 0)
+                && OpenOptions::new().write(true).open(&path).is_ok()
+        })
+    }
+
+    /// Write `value` to the attribute `name`
+    fn write(&self, name: &str, value: &str) -> Result<()> {
+        let path = self.dir.join(name);
+        fs::write(&path, value).with_context(|| format!("Writing {}", path.display()))
+    }
+}
+
+impl Transport for HwmonTransport {
+    /// Write each slot of the report to its channel
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        let report = VirtualSensorReport::parse(self.device.virtual_sensors, report)?;
+        for (slot, value) in report.values().into_iter().enumerate() {
+            let channel = self.channel(slot);
+            let enable = format!("temp{channel}_enable");
+            let has_enable = self.dir.join(&enable).exists();
+            match value {
+                Some(centidegrees) => {
+                    if has_enable {
+                        self.write(&enable, "1")?;
+                    }
+                    let millidegrees = i32::from(centidegrees) * 10;
+                    self.write(&format!("temp{channel}_input"), &millidegrees.to_string())?;
+                }
+                None if has_enable => self.write(&enable, "0")?,
+                None => trace!(
+                    Debug,
+                    "temp{channel} has no enable attribute to disconnect it"
+                ),
+            }
+        }
+        Ok(report.as_bytes().len())
+    }
+
+    fn read_report(&mut self, _buf: &mut [u8]) -> Result<usize> {
+        Err(OctoError::Unsupported).with_context(|| {
+            format!(
+                "Status reports can't be read through hwmon; open the {} with Backend::Usb \
+             (octo-vs picks USB itself for commands that read the device)",
+                self.device.name
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HwmonTransport;
+    use crate::{layout::OCTO, testing::HwmonTree, Octo};
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    /// Fake hwmon tree with an Octo whose virtual sensors are read-only,
+    /// or writable if `writable`
+    fn hwmon_tree(writable: bool) -> HwmonTree {
+        let tree = HwmonTree::new();
+        let dir = tree.chip("hwmon3", "octo", true);
+        let mode = if writable { 0o644 } else { 0o444 };
+        for channel in 1..=OCTO.status.sensor_count + OCTO.virtual_sensors.sensor_count {
+            for attribute in ["input", "enable"] {
+                let path = dir.join(format!("temp{channel}_{attribute}"));
+                fs::write(&path, "0\n").unwrap();
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            }
+        }
+        tree
+    }
+
+    /// Reports become millidegree writes, disconnected slots disabled,
+    /// and read-only channels aren't picked
+    #[test]
+    fn write() {
+        let tree = hwmon_tree(false);
+        assert!(HwmonTransport::find_in(&tree.class(), &OCTO)
+            .unwrap()
+            .is_none());
+
+        let tree = hwmon_tree(true);
+        let transport = HwmonTransport::find_in(&tree.class(), &OCTO)
+            .unwrap()
+            .unwrap();
+        let dir = transport.dir().to_owned();
+        let mut octo = Octo::with_transport(transport).unwrap();
+        octo.update_centidegrees(&[Some(4120), None, Some(-550)])
+            .unwrap();
+        let first = OCTO.status.sensor_count + 1;
+        let read = |channel: usize, attribute: &str| {
+            fs::read_to_string(dir.join(format!("temp{channel}_{attribute}"))).unwrap()
+        };
+        assert_eq!(read(first, "input"), "41200");
+        assert_eq!(read(first, "enable"), "1");
+        assert_eq!(read(first + 1, "enable"), "0");
+        assert_eq!(read(first + 2, "input"), "-5500");
+        assert!(octo.read_status().is_err());
+
+        let tree = hwmon_tree(true);
+        let dir = tree.class().join("hwmon3");
+        for channel in 1..=OCTO.status.sensor_count + OCTO.virtual_sensors.sensor_count {
+            fs::remove_file(dir.join(format!("temp{channel}_enable"))).unwrap();
+        }
+        let transport = HwmonTransport::find_in(&tree.class(), &OCTO)
+            .unwrap()
+            .unwrap();
+        let mut octo = Octo::with_transport(transport).unwrap();
+        octo.update_centidegrees(&[Some(4120), None]).unwrap();
+        let input = fs::read_to_string(dir.join(format!("temp{first}_input"))).unwrap();
+        assert_eq!(input, "41200");
+    }
+}
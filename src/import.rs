@@ -0,0 +1,624 @@
+//! Importing configurations from other fan control software
+//!
+//! Moving from Windows, or dual-booting, shouldn't mean entering every
+//! mapping again. Each importer reads another program's settings and turns
+//! what matters for virtual sensors into a [`Config`], with notes on
+//! whatever couldn't be carried over:
+//!
+//! ```
+//! use octo_virtual_sensors::import::{aquasuite, SourceMap};
+//! let xml = r#"<Profile>
+//!   <VirtualSoftwareSensor Index="0">
+//!     <Name>CPU</Name>
+//!     <DataSource>AMD Ryzen 9 7950X: Tctl/Tdie</DataSource>
+//!   </VirtualSoftwareSensor>
+//! </Profile>"#;
+//! let import = aquasuite(xml, &SourceMap::default()).unwrap();
+//! assert_eq!(import.config.sensors[0].slot, 0);
+//! print!("{}", import.to_toml());
+//! ```
+//!
+//! [`aquasuite`] reads Aquasuite's XML exports and [`fancontrol`] the
+//! `userConfig.json` of FanControl. Files they can't read fail with
+//! [`OctoError::InvalidConfig`](crate::OctoError::InvalidConfig).
+//!
+//! Windows data sources have no fixed Linux equivalent, so sources are
+//! matched by name against a [`SourceMap`]: a few built-in guesses for
+//! common CPUs and NVIDIA GPUs, after any the caller adds. Slots whose
+//! source matches nothing are left out and noted.
+//!
+//! Only built with the `import` feature.
+use crate::error::{Context, Result};
+use crate::{
+    config::{self, Config, SensorConfig, SourceConfig, VERSION},
+    layout,
+    profile::SlotRule,
+    units::Unit,
+};
+use roxmltree::Node;
+use serde_json::Value;
+use std::time::Duration;
+use toml_edit::DocumentMut;
+
+/// Command reading the first NVIDIA GPU's temperature
+static NVIDIA_SMI: &str = "nvidia-smi --query-gpu=temperature.gpu --format=csv,noheader";
+
+/// A configuration read from another program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// What could be carried over
+    pub config: Config,
+    /// Names the other program gave slots, numbered from 0
+    pub names: Vec<(usize, String)>,
+    /// What couldn't be carried over, one sentence each
+    pub notes: Vec<String>,
+}
+
+impl Import {
+    /// The configuration as a file, with names and notes as comments
+    pub fn to_toml(&self) -> String {
+        let text = self.config.to_toml();
+        let Ok(mut document) = text.parse::<DocumentMut>() else {
+            return text;
+        };
+        let mut header = String::new();
+        for note in &self.notes {
+            header.push_str(&format!("# {note}\n"));
+        }
+        if !header.is_empty() {
+            header.push('\n');
+        }
+        if let Some(mut version) = document.as_table_mut().key_mut("version") {
+            version.leaf_decor_mut().set_prefix(header);
+        }
+        if let Some(tables) = document
+            .get_mut("sensor")
+            .and_then(|sensor| sensor.as_array_of_tables_mut())
+        {
+            for (table, sensor) in tables.iter_mut().zip(&self.config.sensors) {
+                let name = self.names.iter().find(|(slot, _)| *slot == sensor.slot);
+                if let Some((_, name)) = name {
+                    table.decor_mut().set_prefix(format!("\n# {name}\n"));
+                }
+            }
+        }
+        document.to_string()
+    }
+}
+
+/// Sources for other programs' data source names
+///
+/// Each rule maps names containing a pattern, ignoring case, to a source.
+/// Rules added with [`SourceMap::with`] are tried before the built-in
+/// ones, in the order they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    rules: Vec<(String, SourceConfig)>,
+    added: usize,
+}
+
+impl Default for SourceMap {
+    /// `k10temp` for AMD CPUs, `coretemp` for Intel ones and `nvidia-smi`
+    /// for NVIDIA GPUs, by their names or LibreHardwareMonitor identifiers
+    fn default() -> Self {
+        let nvidia = || SourceConfig::Command(NVIDIA_SMI.to_owned());
+        let amd = || SourceConfig::Hwmon("k10temp/temp1".to_owned());
+        let intel = || SourceConfig::Hwmon("coretemp/temp1".to_owned());
+        let rules = [
+            ("nvidia", nvidia()),
+            ("geforce", nvidia()),
+            ("tctl", amd()),
+            ("ryzen", amd()),
+            ("/amdcpu/", amd()),
+            ("cpu package", intel()),
+            ("intel core", intel()),
+            ("/intelcpu/", intel()),
+        ];
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(pattern, source)| (pattern.to_owned(), source))
+                .collect(),
+            added: 0,
+        }
+    }
+}
+
+impl SourceMap {
+    /// Map names containing `pattern` to `source`, before the other rules
+    pub fn with(mut self, pattern: impl Into<String>, source: SourceConfig) -> Self {
+        let rule = (pattern.into().to_lowercase(), source);
+        self.rules.insert(self.added, rule);
+        self.added += 1;
+        self
+    }
+
+    /// Source for data source `name`, if a rule matches it
+    pub fn source(&self, name: &str) -> Option<&SourceConfig> {
+        let name = name.to_lowercase();
+        self.rules
+            .iter()
+            .find(|(pattern, _)| name.contains(pattern.as_str()))
+            .map(|(_, source)| source)
+    }
+}
+
+/// Empty configuration for importers to fill in
+fn empty_config() -> Config {
+    Config {
+        version: VERSION,
+        interval: Duration::from_secs(1),
+        units: Unit::Celsius,
+        ramp: None,
+        mqtt: None,
+        state: None,
+        profiles: Vec::new(),
+        sensors: Vec::new(),
+    }
+}
+
+/// Import the virtual sensors of an Aquasuite profile export
+///
+/// Aquasuite doesn't document its export format, so this looks for the
+/// parts below anywhere in the XML and ignores everything else. Each
+/// virtual sensor is a `VirtualSoftwareSensor`, `VirtualSensor` or
+/// `SoftwareSensor` element. The fields below can be child elements or
+/// attributes, in any case:
+///
+/// - `Index` counts the slot from 0, or `Number` from 1;
+/// - `Name` is what the slot was called;
+/// - `DataSource` or `Source` says where its value came from;
+/// - `FallbackValue` or `Fallback` is the value in °C used while the
+///   source is missing;
+/// - `Offset` is added to the value, in °C.
+///
+/// Fan curves, elements ending in `Controller` with a `Fan` and a `Source`
+/// naming a virtual sensor such as `Virtual software sensor 3`, are noted
+/// with the slot they follow. Curves themselves live on the device, see
+/// [`FanCurve`](crate::curve::FanCurve).
+pub fn aquasuite(xml: &str, sources: &SourceMap) -> Result<Import> {
+    read_aquasuite(xml, sources).map_err(config::invalid)
+}
+
+/// [`aquasuite`] without the failure's kind
+fn read_aquasuite(xml: &str, sources: &SourceMap) -> Result<Import> {
+    let document = roxmltree::Document::parse(xml).context("Not an Aquasuite XML export")?;
+    let count = layout::OCTO.virtual_sensors.sensor_count;
+    let mut import = Import {
+        config: empty_config(),
+        names: Vec::new(),
+        notes: Vec::new(),
+    };
+    let is_sensor = |node: &Node| {
+        let name = node.tag_name().name().to_lowercase();
+        matches!(
+            name.as_str(),
+            "virtualsoftwaresensor" | "virtualsensor" | "softwaresensor"
+        )
+    };
+    for node in document.descendants().filter(is_sensor) {
+        let line = document.text_pos_at(node.range().start).row;
+        let slot = match (field(node, &["index"]), field(node, &["number"])) {
+            (Some(index), _) => index.parse::<usize>().ok(),
+            (None, Some(number)) => number.parse::<usize>().ok().and_then(|n| n.checked_sub(1)),
+            (None, None) => None,
+        }
+        .filter(|slot| *slot < count)
+        .with_context(|| format!("Line {line}: virtual sensor without a slot from 1 to {count}"))?;
+        if import.names.iter().any(|(other, _)| *other == slot) {
+            bail!("Line {line}: slot {} is defined twice", slot + 1);
+        }
+        let name = field(node, &["name"]).unwrap_or_default().to_owned();
+        let label = match name.as_str() {
+            "" => format!("slot {}", slot + 1),
+            name => format!("slot {} ({name})", slot + 1),
+        };
+        import.names.push((slot, name));
+        let celsius = |what: &str, keys: &[&str]| -> Result<Option<i16>> {
+            field(node, keys)
+                .map(|value| {
+                    let degrees = value
+                        .parse()
+                        .with_context(|| format!("Line {line}: bad {what} {value:?}"))?;
+                    Unit::Celsius.centidegrees(degrees)
+                })
+                .transpose()
+        };
+        let mut rule = SlotRule::new();
+        if let Some(fallback) = celsius("fallback", &["fallbackvalue", "fallback"])? {
+            rule = rule.with_fallback(fallback);
+        }
+        if let Some(offset) = celsius("offset", &["offset"])? {
+            rule = rule.with_offset(offset);
+        }
+        let Some(data_source) = field(node, &["datasource", "source"]) else {
+            import
+                .notes
+                .push(format!("Aquasuite {label} has no data source, left out."));
+            continue;
+        };
+        let Some(source) = sources.source(data_source) else {
+            import.notes.push(format!(
+                "Aquasuite {label} read {data_source:?}, which has no source here yet: \
+                 add one by hand."
+            ));
+            continue;
+        };
+        import.config.sensors.push(SensorConfig {
+            slot,
+            label: None,
+            source: source.clone(),
+            filter: None,
+            rule,
+        });
+    }
+    import.config.sensors.sort_by_key(|sensor| sensor.slot);
+    for node in document.descendants().filter(|node| {
+        node.tag_name()
+            .name()
+            .to_lowercase()
+            .ends_with("controller")
+    }) {
+        let (Some(fan), Some(source)) = (field(node, &["fan"]), field(node, &["source", "sensor"]))
+        else {
+            continue;
+        };
+        let Some(slot) = virtual_sensor_number(source).and_then(|n| n.checked_sub(1)) else {
+            continue;
+        };
+        let name = import.names.iter().find(|(other, _)| *other == slot);
+        import.notes.push(match name {
+            Some((_, name)) if !name.is_empty() => format!(
+                "Aquasuite drove fan {fan} from slot {} ({name}): set its curve with \
+                 `octo-vs fan-curve`.",
+                slot + 1
+            ),
+            Some(_) => format!(
+                "Aquasuite drove fan {fan} from slot {}: set its curve with `octo-vs fan-curve`.",
+                slot + 1
+            ),
+            None => format!(
+                "Aquasuite drove fan {fan} from slot {}, which the export doesn't define.",
+                slot + 1
+            ),
+        });
+    }
+    Ok(import)
+}
+
+/// Import the custom sensors of a FanControl configuration
+///
+/// FanControl keeps its settings in `userConfig.json`. Custom sensors,
+/// wherever a `CustomSensors` array holds them, fill slots from 1 in the
+/// order they're listed, each named after its `Name`. Sensors with one
+/// `SelectedTempSource` take their source from its `Identifier`, a
+/// LibreHardwareMonitor path such as `/amdcpu/0/temperature/2`, or failing
+/// that from its `Name`, and an `Offset` in °C carries over. Mixes and
+/// file sensors have no equivalent in a configuration file and are noted
+/// instead, as are `FanCurves` whose `SelectedTempSource` is a custom
+/// sensor.
+pub fn fancontrol(json: &str, sources: &SourceMap) -> Result<Import> {
+    read_fancontrol(json, sources).map_err(config::invalid)
+}
+
+/// [`fancontrol`] without the failure's kind
+fn read_fancontrol(json: &str, sources: &SourceMap) -> Result<Import> {
+    let document: Value = serde_json::from_str(json).context("Not a FanControl configuration")?;
+    let custom_sensors = find_array(&document, "CustomSensors")
+        .context("No CustomSensors in this FanControl configuration")?;
+    let count = layout::OCTO.virtual_sensors.sensor_count;
+    let mut import = Import {
+        config: empty_config(),
+        names: Vec::new(),
+        notes: Vec::new(),
+    };
+    let text = |value: &Value, key| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned()
+    };
+    let mut identifiers = Vec::new();
+    for (slot, sensor) in custom_sensors.iter().enumerate() {
+        let name = text(sensor, "Name");
+        if slot >= count {
+            import.notes.push(format!(
+                "FanControl custom sensor {name:?} is past the last slot, {count}, left out."
+            ));
+            continue;
+        }
+        let label = format!("slot {} ({name})", slot + 1);
+        identifiers.push((text(sensor, "Identifier"), slot, name.clone()));
+        import.names.push((slot, name));
+        let Some(source) = sensor.get("SelectedTempSource") else {
+            let kind = text(sensor, "$type");
+            import.notes.push(match kind.split(',').next() {
+                Some(kind) if !kind.is_empty() => format!(
+                    "FanControl {label} is a {kind}, which has no equivalent here: add a \
+                     source by hand."
+                ),
+                _ => format!("FanControl {label} has no single source: add one by hand."),
+            });
+            continue;
+        };
+        let identifier = text(source, "Identifier");
+        let mapped = sources
+            .source(&identifier)
+            .or_else(|| sources.source(&text(source, "Name")));
+        let Some(mapped) = mapped else {
+            import.notes.push(format!(
+                "FanControl {label} read {identifier:?}, which has no source here yet: add \
+                 one by hand."
+            ));
+            continue;
+        };
+        let mut rule = SlotRule::new();
+        if let Some(offset) = sensor.get("Offset").and_then(Value::as_f64) {
+            let offset = Unit::Celsius.delta_centidegrees(offset);
+            rule = rule.with_offset(offset.with_context(|| format!("FanControl {label}"))?);
+        }
+        import.config.sensors.push(SensorConfig {
+            slot,
+            label: None,
+            source: mapped.clone(),
+            filter: None,
+            rule,
+        });
+    }
+    for curve in find_array(&document, "FanCurves").into_iter().flatten() {
+        let source = curve
+            .get("SelectedTempSource")
+            .map(|source| text(source, "Identifier"))
+            .unwrap_or_default();
+        let Some((_, slot, name)) = identifiers
+            .iter()
+            .find(|(identifier, ..)| !identifier.is_empty() && *identifier == source)
+        else {
+            continue;
+        };
+        import.notes.push(format!(
+            "FanControl curve {:?} followed slot {} ({name}): set it with `octo-vs fan-curve`.",
+            text(curve, "Name"),
+            slot + 1
+        ));
+    }
+    Ok(import)
+}
+
+/// The first array under `key` in `value` or anything inside it
+fn find_array<'a>(value: &'a Value, key: &str) -> Option<&'a Vec<Value>> {
+    match value {
+        Value::Object(object) => object
+            .get(key)
+            .and_then(Value::as_array)
+            .or_else(|| object.values().find_map(|value| find_array(value, key))),
+        Value::Array(array) => array.iter().find_map(|value| find_array(value, key)),
+        _ => None,
+    }
+}
+
+/// The first of `keys` set on `node`, as an attribute or a child element,
+/// ignoring case
+fn field<'a>(node: Node<'a, '_>, keys: &[&str]) -> Option<&'a str> {
+    keys.iter().find_map(|key| {
+        let attribute = node
+            .attributes()
+            .find(|attribute| attribute.name().eq_ignore_ascii_case(key))
+            .map(|attribute| attribute.value());
+        attribute.or_else(|| {
+            node.children()
+                .find(|child| child.tag_name().name().eq_ignore_ascii_case(key))
+                .map(|child| child.text().unwrap_or_default().trim())
+        })
+    })
+}
+
+/// Number at the end of a virtual sensor's name, such as 3 for
+/// `Virtual software sensor 3` or `VirtualSensor3`
+fn virtual_sensor_number(name: &str) -> Option<usize> {
+    if !name.to_lowercase().contains("virtual") {
+        return None;
+    }
+    let digits = name.trim_end().len()
+        - name
+            .trim_end()
+            .chars()
+            .rev()
+            .take_while(char::is_ascii_digit)
+            .count();
+    name.trim_end().get(digits..)?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{aquasuite, fancontrol, SourceMap};
+    use crate::{
+        config::{Config, SourceConfig},
+        profile::SlotRule,
+        OctoError,
+    };
+
+    /// A hand-written profile in the shape [`aquasuite`] reads
+    static PROFILE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<DeviceProfile Device="Octo">
+  <VirtualSoftwareSensors>
+    <VirtualSoftwareSensor Index="2">
+      <Name>GPU</Name>
+      <DataSource>NVIDIA GeForce RTX 4080: GPU Temperature</DataSource>
+      <FallbackValue>60</FallbackValue>
+    </VirtualSoftwareSensor>
+    <VirtualSoftwareSensor index="0" name="CPU" source="AMD Ryzen 9 7950X: Tctl/Tdie">
+      <Offset>-5</Offset>
+    </VirtualSoftwareSensor>
+    <VirtualSoftwareSensor Number="4">
+      <Name>Water</Name>
+      <DataSource>ASUS ROG Crosshair: T_Sensor</DataSource>
+    </VirtualSoftwareSensor>
+  </VirtualSoftwareSensors>
+  <Controllers>
+    <CurveController Fan="1" Source="Virtual software sensor 1"/>
+    <CurveController><Fan>3</Fan><Source>VirtualSensor7</Source></CurveController>
+    <CurveController Fan="2" Source="Sensor 1"/>
+  </Controllers>
+</DeviceProfile>"#;
+
+    /// Slots, names, fallbacks and offsets come across, the rest is noted
+    #[test]
+    fn profile() {
+        let import = aquasuite(PROFILE, &SourceMap::default()).unwrap();
+        let sensors = &import.config.sensors;
+        assert_eq!(sensors.len(), 2);
+        assert_eq!(sensors[0].slot, 0);
+        assert_eq!(
+            sensors[0].source,
+            SourceConfig::Hwmon("k10temp/temp1".to_owned())
+        );
+        assert_eq!(sensors[0].rule, SlotRule::new().with_offset(-500));
+        assert_eq!(sensors[1].slot, 2);
+        assert!(matches!(sensors[1].source, SourceConfig::Command(_)));
+        assert_eq!(sensors[1].rule, SlotRule::new().with_fallback(6000));
+        assert_eq!(
+            import.names,
+            [
+                (2, "GPU".to_owned()),
+                (0, "CPU".to_owned()),
+                (3, "Water".to_owned())
+            ]
+        );
+        assert_eq!(
+            import.notes,
+            [
+                "Aquasuite slot 4 (Water) read \"ASUS ROG Crosshair: T_Sensor\", which has no \
+                 source here yet: add one by hand.",
+                "Aquasuite drove fan 1 from slot 1 (CPU): set its curve with `octo-vs fan-curve`.",
+                "Aquasuite drove fan 3 from slot 7, which the export doesn't define.",
+            ]
+        );
+
+        let text = import.to_toml();
+        assert!(text.starts_with("# Aquasuite slot 4 (Water)"), "{text}");
+        assert!(text.contains("\n# CPU\n[[sensor]]\nslot = 1\n"), "{text}");
+        assert!(text.contains("\n# GPU\n[[sensor]]\nslot = 3\n"), "{text}");
+        assert_eq!(Config::parse(&text).unwrap(), import.config);
+    }
+
+    /// Added rules come before the built-in ones
+    #[test]
+    fn source_map() {
+        let water = SourceConfig::Hwmon("nct6798/temp7".to_owned());
+        let sources = SourceMap::default()
+            .with("T_Sensor", water.clone())
+            .with("Ryzen", SourceConfig::Fixed(4000));
+        assert_eq!(sources.source("ASUS: t_sensor"), Some(&water));
+        assert_eq!(
+            sources.source("AMD Ryzen 5: Tctl"),
+            Some(&SourceConfig::Fixed(4000))
+        );
+        let import = aquasuite(PROFILE, &sources).unwrap();
+        assert_eq!(import.config.sensors[2].source, water);
+        assert_eq!(sources.source("Unknown"), None);
+    }
+
+    /// A hand-written `userConfig.json` in the shape [`fancontrol`] reads
+    static USER_CONFIG: &str = r#"{
+  "__VERSION__": "1",
+  "Main": {
+    "Controls": [],
+    "CustomSensors": [
+      {
+        "$type": "FanControl.Models.CustomSensors.OffsetCustomSensor, FanControl",
+        "Name": "CPU",
+        "Identifier": "FanControl/CustomSensor/CPU",
+        "Offset": -5,
+        "SelectedTempSource": {"Name": "Core (Tctl/Tdie)", "Identifier": "/amdcpu/0/temperature/2"}
+      },
+      {
+        "$type": "FanControl.Models.CustomSensors.MixCustomSensor, FanControl",
+        "Name": "Hottest",
+        "Identifier": "FanControl/CustomSensor/Hottest",
+        "SelectedTempSources": [{"Identifier": "/amdcpu/0/temperature/2"}]
+      },
+      {
+        "Name": "GPU",
+        "Identifier": "FanControl/CustomSensor/GPU",
+        "SelectedTempSource": {"Name": "GPU Core", "Identifier": "/gpu-nvidia/0/temperature/0"}
+      },
+      {
+        "Name": "Water",
+        "SelectedTempSource": {"Name": "Temperature #2", "Identifier": "/lpc/nct6798d/temperature/1"}
+      }
+    ],
+    "FanCurves": [
+      {"Name": "Radiator", "SelectedTempSource": {"Identifier": "FanControl/CustomSensor/CPU"}},
+      {"Name": "Case", "SelectedTempSource": {"Identifier": "/lpc/nct6798d/temperature/0"}}
+    ]
+  }
+}"#;
+
+    /// Custom sensors fill slots in order, the rest is noted
+    #[test]
+    fn user_config() {
+        let water = SourceConfig::Hwmon("nct6798/temp2".to_owned());
+        let sources = SourceMap::default().with("/lpc/nct6798d/temperature/1", water.clone());
+        let import = fancontrol(USER_CONFIG, &sources).unwrap();
+        let sensors = &import.config.sensors;
+        let slots: Vec<usize> = sensors.iter().map(|sensor| sensor.slot).collect();
+        assert_eq!(slots, [0, 2, 3]);
+        assert_eq!(
+            sensors[0].source,
+            SourceConfig::Hwmon("k10temp/temp1".to_owned())
+        );
+        assert_eq!(sensors[0].rule, SlotRule::new().with_offset(-500));
+        assert!(matches!(sensors[1].source, SourceConfig::Command(_)));
+        assert_eq!(sensors[2].source, water);
+        assert_eq!(import.names[1], (1, "Hottest".to_owned()));
+        assert_eq!(
+            import.notes,
+            [
+                "FanControl slot 2 (Hottest) is a FanControl.Models.CustomSensors.\
+                 MixCustomSensor, which has no equivalent here: add a source by hand.",
+                "FanControl curve \"Radiator\" followed slot 1 (CPU): set it with \
+                 `octo-vs fan-curve`.",
+            ]
+        );
+        assert_eq!(Config::parse(&import.to_toml()).unwrap(), import.config);
+
+        let import = fancontrol(USER_CONFIG, &SourceMap::default()).unwrap();
+        assert_eq!(import.config.sensors.len(), 2);
+        assert!(import.notes[1].contains("\"/lpc/nct6798d/temperature/1\""));
+        assert!(fancontrol("{\"Main\": {}}", &sources).is_err());
+        assert!(fancontrol("<xml/>", &sources).is_err());
+    }
+
+    /// Files that aren't exports, or define slots badly, are errors
+    #[test]
+    fn errors() {
+        let error = aquasuite("not xml", &SourceMap::default()).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::InvalidConfig));
+        let sensor = |attributes: &str| format!("<P>\n<VirtualSensor {attributes}/></P>");
+        let cases = [
+            (sensor(""), "Line 2: virtual sensor without a slot"),
+            (
+                sensor("Index=\"16\""),
+                "Line 2: virtual sensor without a slot",
+            ),
+            (
+                sensor("Number=\"0\""),
+                "Line 2: virtual sensor without a slot",
+            ),
+            (
+                sensor("Index=\"1\" Source=\"Ryzen\" Fallback=\"hot\""),
+                "Line 2: bad fallback \"hot\"",
+            ),
+            (
+                "<P><VirtualSensor Index=\"1\"/><VirtualSensor Number=\"2\"/></P>".to_owned(),
+                "Line 1: slot 2 is defined twice",
+            ),
+        ];
+        for (xml, expected) in cases {
+            let error = format!("{:#}", aquasuite(&xml, &SourceMap::default()).unwrap_err());
+            assert!(error.starts_with(expected), "{xml:?} gave {error:?}");
+        }
+    }
+}
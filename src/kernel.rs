@@ -0,0 +1,92 @@
+//! Interaction with Linux kernel drivers
+use crate::layout::DeviceLayout;
+use std::{fs, io, path::Path};
+
+/// Name of the mainline hwmon driver for Aquacomputer devices
+pub const HWMON_DRIVER: &str = "aquacomputer_d5next";
+
+/// Where `octo-vs install-udev-rule` puts the rule from [`udev_rule`]
+pub const UDEV_RULE_PATH: &str = "/etc/udev/rules.d/71-octo-virtual-sensors.rules";
+
+/// udev rule letting the logged-in user open `devices`
+///
+/// Covers the USB device for libusb and its hidraw node for the hidapi
+/// backend. Takes effect for devices plugged in after udev reloads its
+/// rules, or after `udevadm trigger`.
+pub fn udev_rule(devices: &[DeviceLayout]) -> String {
+    let mut rule = String::from(
+        "# Generated by octo_virtual_sensors: access to Aquacomputer devices for the\n\
+         # logged-in user, without root\n",
+    );
+    for device in devices {
+        let ids = format!(
+            "ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\"",
+            device.vendor_id, device.product_id
+        );
+        rule += &format!("# {}\n", device.name);
+        rule += &format!("SUBSYSTEM==\"usb\", {ids}, MODE=\"0660\", TAG+=\"uaccess\"\n");
+        rule += &format!("KERNEL==\"hidraw*\", {ids}, MODE=\"0660\", TAG+=\"uaccess\"\n");
+    }
+    rule
+}
+
+/// Whether the hwmon driver is bound to a device with these IDs
+///
+/// Bound HID devices show up in the driver's sysfs directory as
+/// `BUS:VENDOR:PRODUCT.INSTANCE`. A missing driver directory means the
+/// module isn't loaded.
+pub fn hwmon_driver_bound(vendor_id: u16, product_id: u16) -> io::Result<bool> {
+    let dir = Path::new("/sys/bus/hid/drivers").join(HWMON_DRIVER);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    for entry in entries {
+        let name = entry?.file_name();
+        if is_hid_device(&name.to_string_lossy(), vendor_id, product_id) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether a sysfs HID device name refers to the given IDs
+fn is_hid_device(name: &str, vendor_id: u16, product_id: u16) -> bool {
+    let mut parts = name.split([':', '.']);
+    let (Some(_bus), Some(vendor), Some(product)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    u16::from_str_radix(vendor, 16) == Ok(vendor_id)
+        && u16::from_str_radix(product, 16) == Ok(product_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_hid_device, udev_rule};
+    use crate::layout::{DEVICES, OCTO};
+
+    /// Device names are parsed case insensitively, other entries ignored
+    #[test]
+    fn hid_device_names() {
+        assert!(is_hid_device("0003:0C70:F011.0005", 0x0c70, 0xf011));
+        assert!(is_hid_device("0003:0c70:f011.000A", 0x0c70, 0xf011));
+        assert!(!is_hid_device("0003:0C70:F00D.0005", 0x0c70, 0xf011));
+        assert!(!is_hid_device("bind", 0x0c70, 0xf011));
+        assert!(!is_hid_device("module", 0x0c70, 0xf011));
+    }
+
+    /// The rule grants the USB device and its hidraw node for each model
+    #[test]
+    fn udev_rules() {
+        let rule = udev_rule(&[OCTO]);
+        assert!(rule.contains(
+            "SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"0c70\", ATTRS{idProduct}==\"f011\", \
+             MODE=\"0660\", TAG+=\"uaccess\"\n"
+        ));
+        assert!(rule.contains("KERNEL==\"hidraw*\", ATTRS{idVendor}==\"0c70\""));
+        let all = udev_rule(DEVICES);
+        assert_eq!(all.matches("SUBSYSTEM").count(), DEVICES.len());
+    }
+}
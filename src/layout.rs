@@ -0,0 +1,663 @@
+//! Report layouts per device model
+//!
+//! Offsets follow the aquacomputer_d5next hwmon driver. Everything that
+//! touches raw report bytes should go through these tables rather than
+//! literals, so a new device or firmware variant is a new table entry.
+use crate::error::{Context, Result};
+use crate::{
+    checksum::{Checksum, Crc16Usb, NoChecksum},
+    protocol, OctoError,
+};
+
+/// Length of the report ID starting every report
+pub const REPORT_ID_SIZE: usize = 1;
+
+/// Length of an encoded sensor value
+pub const SENSOR_SIZE: usize = 2;
+
+/// Layout of the output report carrying virtual sensor values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualSensorLayout {
+    /// Report ID, the first byte of the report
+    pub report_id: u8,
+    /// Total length including report ID and checksum
+    pub len: usize,
+    /// Offset of the first sensor value
+    pub sensors: usize,
+    /// Number of virtual sensor slots
+    pub sensor_count: usize,
+    /// Fixed bytes between the last sensor and the checksum
+    pub trailer: &'static [u8],
+    /// Checksum trailing the report
+    pub checksum: &'static dyn Checksum,
+}
+
+impl VirtualSensorLayout {
+    /// Offset of the sensor value for `slot`
+    pub const fn sensor(&self, slot: usize) -> usize {
+        self.sensors
+            .saturating_add(SENSOR_SIZE.saturating_mul(slot))
+    }
+
+    /// Offset of the checksum
+    pub fn checksum_offset(&self) -> usize {
+        self.len.saturating_sub(self.checksum.size())
+    }
+
+    /// Check the report ID, sensors, trailer and checksum fit in `len`
+    ///
+    /// Reports built from a layout that fails this can't be parsed.
+    pub fn check(&self) -> Result<()> {
+        let end = self
+            .sensor(self.sensor_count)
+            .saturating_add(self.trailer.len())
+            .saturating_add(self.checksum.size());
+        if self.sensors < REPORT_ID_SIZE || end > self.len {
+            return Err(OctoError::InvalidLayout).with_context(|| {
+                format!(
+                    "{} sensors from offset {} with a {} byte trailer don't fit a {} byte report",
+                    self.sensor_count,
+                    self.sensors,
+                    self.trailer.len(),
+                    self.len
+                )
+            });
+        }
+        Ok(())
+    }
+
+    /// The same layout for a report of a different length
+    ///
+    /// Extra bytes are zero filled and the checksum moves to the end. Fails
+    /// if the report would be too short to hold every sensor, or longer
+    /// than any HID report can be.
+    pub fn resized(self, len: usize) -> Option<Self> {
+        if len > crate::hid::MAX_REPORT_LEN {
+            return None;
+        }
+        let sensors_end = self.sensor(self.sensor_count);
+        let trailer_len = len.checked_sub(sensors_end + self.checksum.size())?;
+        let trailer = &self.trailer[..trailer_len.min(self.trailer.len())];
+        Some(Self {
+            len,
+            trailer,
+            ..self
+        })
+    }
+}
+
+/// Layout of the input report the device sends periodically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusLayout {
+    /// Report ID, the first byte of the report
+    pub report_id: u8,
+    /// Total length including report ID and checksum
+    pub len: usize,
+    /// Offset of the serial number, two big-endian u16 parts
+    pub serial: usize,
+    /// Offset of the firmware version
+    pub firmware: usize,
+    /// Offset of the power cycle counter
+    pub power_cycles: usize,
+    /// Offset of the first physical temperature sensor
+    pub sensors: usize,
+    /// Number of physical temperature sensors
+    pub sensor_count: usize,
+    /// Offset of the first virtual sensor as seen by the firmware
+    pub virtual_sensors: usize,
+    /// Number of virtual sensors
+    pub virtual_sensor_count: usize,
+    /// Offset of the flow sensor, if the device has one
+    pub flow: Option<usize>,
+    /// Offset of each fan channel's block
+    pub fans: &'static [usize],
+    /// Where each fan block keeps its readings
+    pub fan_fields: FanFields,
+    /// Checksum trailing the report
+    pub checksum: &'static dyn Checksum,
+}
+
+/// Offsets of the readings within a fan block of the status report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FanFields {
+    /// Output power in centipercent, if the block carries it
+    pub percent: Option<usize>,
+    /// Voltage in centivolts
+    pub voltage: usize,
+    /// Current in milliamps
+    pub current: usize,
+    /// Power in centiwatts
+    pub power: usize,
+    /// Speed in RPM
+    pub speed: usize,
+}
+
+impl FanFields {
+    /// The order most devices use, see [`fan`]
+    pub const STANDARD: Self = Self {
+        percent: Some(fan::PERCENT),
+        voltage: fan::VOLTAGE,
+        current: fan::CURRENT,
+        power: fan::POWER,
+        speed: fan::SPEED,
+    };
+}
+
+/// Fields within a fan block of the status report
+pub mod fan {
+    /// Output power in centipercent
+    pub const PERCENT: usize = 0x00;
+    /// Voltage in centivolts
+    pub const VOLTAGE: usize = 0x02;
+    /// Current in milliamps
+    pub const CURRENT: usize = 0x04;
+    /// Power in centiwatts
+    pub const POWER: usize = 0x06;
+    /// Speed in RPM
+    pub const SPEED: usize = 0x08;
+}
+
+/// Layout of the feature report holding the device's settings
+///
+/// Read and written whole, so changing one setting means a
+/// read-modify-write of the entire report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlLayout {
+    /// Report ID, the first byte of the report
+    pub report_id: u8,
+    /// Total length including report ID and checksum
+    pub len: usize,
+    /// Offset of each fan channel's control block
+    pub fans: &'static [usize],
+    /// Temperature source index of the first virtual sensor
+    ///
+    /// Lower indices are the physical sensors.
+    pub virtual_sensor_source: u16,
+    /// Number of virtual sensors a fan channel can follow
+    pub virtual_sensor_count: usize,
+    /// Offset of the flow sensor's impulses per litre, if it has one
+    pub flow_pulses: Option<usize>,
+    /// Feature report that has the device keep its settings across power
+    /// cycles, `None` if it isn't known
+    pub save: Option<&'static [u8]>,
+    /// Checksum trailing the report
+    pub checksum: &'static dyn Checksum,
+}
+
+/// Report aquasuite and the hwmon driver send after changing settings
+///
+/// Report ID 2, sent byte for byte as the driver does; what the last two
+/// bytes encode isn't known.
+pub const SAVE_SETTINGS: &[u8] = &[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0x34, 0xC6];
+
+/// Fields within a fan block of the control report
+pub mod fan_control {
+    /// Control mode
+    pub const MODE: usize = 0x00;
+    /// Duty cycle in manual mode, in centipercent
+    pub const DUTY: usize = 0x01;
+    /// Index of the temperature the curve follows
+    pub const TEMPERATURE_SOURCE: usize = 0x03;
+    /// Temperatures of the curve points in centidegrees
+    pub const CURVE_TEMPERATURES: usize = 0x15;
+    /// Duty cycles of the curve points in centipercent
+    pub const CURVE_DUTIES: usize = 0x35;
+    /// Number of points in a curve
+    pub const CURVE_POINTS: usize = 16;
+}
+
+/// Report layouts used from a firmware version onwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareVariant {
+    /// Oldest firmware version using these layouts
+    pub min_firmware: u16,
+    /// Layout of the virtual sensor report
+    pub virtual_sensors: VirtualSensorLayout,
+    /// Layout of the control report, `None` if it hasn't been mapped for
+    /// this firmware
+    pub control: Option<ControlLayout>,
+}
+
+/// Everything needed to talk to one device model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceLayout {
+    /// Human readable model name
+    pub name: &'static str,
+    /// Chip name the hwmon driver registers
+    pub hwmon_name: &'static str,
+    /// USB vendor ID
+    pub vendor_id: u16,
+    /// USB product ID
+    pub product_id: u16,
+    /// Oldest firmware version known to accept virtual sensor reports
+    pub min_firmware: u16,
+    /// Virtual sensor output report
+    pub virtual_sensors: VirtualSensorLayout,
+    /// Whether the virtual sensor report is known, so values can be sent
+    pub virtual_sensors_known: bool,
+    /// Firmware revisions that changed a report layout, oldest first
+    pub firmware_variants: &'static [FirmwareVariant],
+    /// Status input report
+    pub status: StatusLayout,
+    /// Settings feature report
+    pub control: ControlLayout,
+}
+
+impl DeviceLayout {
+    /// Virtual sensor layout for the given firmware version
+    ///
+    /// Picks the newest variant the firmware is at least as new as, falling
+    /// back to the default layout.
+    pub fn virtual_sensors_for(&self, firmware: u16) -> VirtualSensorLayout {
+        self.variant(firmware)
+            .map_or(self.virtual_sensors, |variant| variant.virtual_sensors)
+    }
+
+    /// Control report layout for the given firmware version
+    ///
+    /// Picks the variant like [`DeviceLayout::virtual_sensors_for`], and
+    /// fails if that firmware's control report hasn't been mapped, as
+    /// writing a misaligned one would scramble the device's settings.
+    pub fn control_for(&self, firmware: u16) -> Result<ControlLayout> {
+        let Some(variant) = self.variant(firmware) else {
+            return Ok(self.control);
+        };
+        variant
+            .control
+            .ok_or(OctoError::Unsupported)
+            .with_context(|| {
+                format!(
+                    "The {}'s control report isn't known for firmware {firmware}",
+                    self.name
+                )
+            })
+    }
+
+    /// Newest variant the firmware is at least as new as
+    fn variant(&self, firmware: u16) -> Option<&FirmwareVariant> {
+        self.firmware_variants
+            .iter()
+            .rev()
+            .find(|variant| firmware >= variant.min_firmware)
+    }
+}
+
+/// Aquacomputer USB vendor ID
+pub const AQUACOMPUTER_VENDOR_ID: u16 = 0x0c70;
+
+/// Aquacomputer Octo
+pub const OCTO: DeviceLayout = DeviceLayout {
+    name: "Octo",
+    hwmon_name: "octo",
+    vendor_id: AQUACOMPUTER_VENDOR_ID,
+    product_id: 0xf011,
+    min_firmware: 1010,
+    virtual_sensors: VirtualSensorLayout {
+        report_id: protocol::VIRTUAL_SENSOR_REPORT_ID,
+        len: protocol::VIRTUAL_SENSOR_REPORT_LEN,
+        sensors: protocol::VIRTUAL_SENSORS,
+        sensor_count: protocol::VIRTUAL_SENSOR_COUNT,
+        trailer: &protocol::VIRTUAL_SENSOR_TRAILER,
+        checksum: &Crc16Usb,
+    },
+    virtual_sensors_known: true,
+    firmware_variants: &[],
+    status: StatusLayout {
+        report_id: 0x01,
+        len: 0x147,
+        serial: 0x03,
+        firmware: 0x0D,
+        power_cycles: 0x18,
+        sensors: 0x3D,
+        sensor_count: 4,
+        virtual_sensors: 0x45,
+        virtual_sensor_count: 16,
+        flow: Some(0x7B),
+        fans: &[0x7D, 0x8A, 0x97, 0xA4, 0xB1, 0xBE, 0xCB, 0xD8],
+        fan_fields: FanFields::STANDARD,
+        checksum: &Crc16Usb,
+    },
+    control: ControlLayout {
+        report_id: 0x03,
+        len: 0x65F,
+        fans: &[0x5B, 0xB0, 0x105, 0x15A, 0x1AF, 0x204, 0x259, 0x2AE],
+        virtual_sensor_source: 4,
+        virtual_sensor_count: 16,
+        flow_pulses: Some(0x06),
+        save: Some(SAVE_SETTINGS),
+        checksum: &Crc16Usb,
+    },
+};
+
+/// Aquacomputer Quadro
+///
+/// A fan controller sharing the Octo's protocol with four fans. It takes
+/// the same virtual sensor report; the status and control offsets follow
+/// the hwmon driver.
+pub const QUADRO: DeviceLayout = DeviceLayout {
+    name: "Quadro",
+    hwmon_name: "quadro",
+    vendor_id: AQUACOMPUTER_VENDOR_ID,
+    product_id: 0xf00d,
+    // No firmware is known to lack virtual sensors
+    min_firmware: 0,
+    virtual_sensors: OCTO.virtual_sensors,
+    virtual_sensors_known: true,
+    firmware_variants: &[],
+    status: StatusLayout {
+        report_id: 0x01,
+        len: 0xDC,
+        serial: 0x03,
+        firmware: 0x0D,
+        power_cycles: 0x18,
+        sensors: 0x34,
+        sensor_count: 4,
+        virtual_sensors: 0x3C,
+        virtual_sensor_count: 16,
+        flow: Some(0x6E),
+        fans: &[0x70, 0x7D, 0x8A, 0x97],
+        fan_fields: FanFields::STANDARD,
+        checksum: &Crc16Usb,
+    },
+    control: ControlLayout {
+        report_id: 0x03,
+        len: 0x3C1,
+        fans: &[0x37, 0x8C, 0xE1, 0x136],
+        virtual_sensor_source: 4,
+        virtual_sensor_count: 16,
+        flow_pulses: Some(0x06),
+        save: Some(SAVE_SETTINGS),
+        checksum: &Crc16Usb,
+    },
+};
+
+/// Aquacomputer D5 Next
+///
+/// A pump whose first fan channel is the pump itself and second the fan
+/// header. It has 8 virtual sensors, but its virtual sensor report hasn't
+/// been captured, so sending values fails rather than guess; the layout
+/// below is only the Octo's with the missing slots disconnected, for when
+/// it is. Status and control offsets follow the hwmon driver.
+pub const D5NEXT: DeviceLayout = DeviceLayout {
+    name: "D5 Next",
+    hwmon_name: "d5next",
+    vendor_id: AQUACOMPUTER_VENDOR_ID,
+    product_id: 0xf00e,
+    // No firmware is known to lack virtual sensors
+    min_firmware: 0,
+    virtual_sensors: VirtualSensorLayout {
+        sensor_count: 8,
+        trailer: &[
+            0x7f, 0xff, 0x7f, 0xff, 0x7f, 0xff, 0x7f, 0xff, 0x7f, 0xff, 0x7f, 0xff, 0x7f, 0xff,
+            0x7f, 0xff, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ],
+        ..OCTO.virtual_sensors
+    },
+    virtual_sensors_known: false,
+    firmware_variants: &[],
+    status: StatusLayout {
+        report_id: 0x01,
+        len: 0x9E,
+        serial: 0x03,
+        firmware: 0x0D,
+        power_cycles: 0x18,
+        sensors: 0x57,
+        sensor_count: 1,
+        virtual_sensors: 0x3F,
+        virtual_sensor_count: 8,
+        flow: None,
+        fans: &[0x6C, 0x5F],
+        fan_fields: FanFields::STANDARD,
+        checksum: &Crc16Usb,
+    },
+    control: ControlLayout {
+        report_id: 0x03,
+        len: 0x329,
+        fans: &[0x97, 0x42],
+        virtual_sensor_source: 1,
+        virtual_sensor_count: 8,
+        flow_pulses: None,
+        save: Some(SAVE_SETTINGS),
+        checksum: &Crc16Usb,
+    },
+};
+
+/// Aquacomputer Farbwerk 360
+///
+/// An RGB controller with 4 temperature sensors and 16 virtual sensors
+/// for its lighting to follow, but no fans. It takes the Octo's virtual
+/// sensor report; status and control offsets follow the hwmon driver.
+pub const FARBWERK360: DeviceLayout = DeviceLayout {
+    name: "Farbwerk 360",
+    hwmon_name: "farbwerk360",
+    vendor_id: AQUACOMPUTER_VENDOR_ID,
+    product_id: 0xf010,
+    // No firmware is known to lack virtual sensors
+    min_firmware: 0,
+    virtual_sensors: OCTO.virtual_sensors,
+    virtual_sensors_known: true,
+    firmware_variants: &[],
+    status: StatusLayout {
+        report_id: 0x01,
+        len: 0xB6,
+        serial: 0x03,
+        firmware: 0x0D,
+        power_cycles: 0x18,
+        sensors: 0x32,
+        sensor_count: 4,
+        virtual_sensors: 0x3A,
+        virtual_sensor_count: 16,
+        flow: None,
+        fans: &[],
+        fan_fields: FanFields::STANDARD,
+        checksum: &Crc16Usb,
+    },
+    control: ControlLayout {
+        report_id: 0x03,
+        len: 0x682,
+        fans: &[],
+        virtual_sensor_source: 4,
+        virtual_sensor_count: 16,
+        flow_pulses: None,
+        save: Some(SAVE_SETTINGS),
+        checksum: &Crc16Usb,
+    },
+};
+
+/// Aquacomputer Aquaero 6
+///
+/// An older generation than the rest, with its own status layout: 8
+/// temperature sensors, 8 virtual sensors, two flow sensors of which the
+/// first is read, and fan blocks ordered differently and without the
+/// output power. Offsets follow the hwmon driver; the report lengths
+/// haven't been confirmed on hardware.
+///
+/// How the Aquaero takes virtual sensor values isn't known and its control
+/// report uses another layout, so only the status can be read: sending
+/// values and reading or writing settings fail instead of guessing.
+pub const AQUAERO: DeviceLayout = DeviceLayout {
+    name: "Aquaero",
+    hwmon_name: "aquaero",
+    vendor_id: AQUACOMPUTER_VENDOR_ID,
+    product_id: 0xf001,
+    min_firmware: 0,
+    virtual_sensors: D5NEXT.virtual_sensors,
+    virtual_sensors_known: false,
+    firmware_variants: &[FirmwareVariant {
+        min_firmware: 0,
+        virtual_sensors: D5NEXT.virtual_sensors,
+        control: None,
+    }],
+    status: StatusLayout {
+        report_id: 0x01,
+        len: 0x1E3,
+        serial: 0x07,
+        firmware: 0x0B,
+        power_cycles: 0x18,
+        sensors: 0x65,
+        sensor_count: 8,
+        virtual_sensors: 0x85,
+        virtual_sensor_count: 8,
+        flow: Some(0xF9),
+        fans: &[0x167, 0x173, 0x17F, 0x18B],
+        fan_fields: FanFields {
+            percent: None,
+            voltage: 0x04,
+            current: 0x06,
+            power: 0x08,
+            speed: 0x00,
+        },
+        checksum: &NoChecksum,
+    },
+    control: ControlLayout {
+        report_id: 0x03,
+        len: 0xA93,
+        fans: &[0x20C, 0x220, 0x234, 0x248],
+        virtual_sensor_source: 8,
+        virtual_sensor_count: 8,
+        flow_pulses: None,
+        save: None,
+        checksum: &NoChecksum,
+    },
+};
+
+/// Every device model this crate can drive
+pub const DEVICES: &[DeviceLayout] = &[OCTO, QUADRO, D5NEXT, FARBWERK360, AQUAERO];
+
+/// Layout of the model with this USB ID, if it's one of [`DEVICES`]
+pub fn for_usb_id(vendor_id: u16, product_id: u16) -> Option<&'static DeviceLayout> {
+    DEVICES
+        .iter()
+        .find(|device| device.vendor_id == vendor_id && device.product_id == product_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        for_usb_id, ControlLayout, DeviceLayout, FirmwareVariant, VirtualSensorLayout, DEVICES,
+        OCTO, QUADRO,
+    };
+    use crate::checksum::NoChecksum;
+
+    /// The trailer exactly fills the gap between sensors and checksum
+    #[test]
+    fn virtual_sensor_layout_is_contiguous() {
+        for device in DEVICES {
+            let layout = device.virtual_sensors;
+            let end = layout.sensor(layout.sensor_count) + layout.trailer.len();
+            assert_eq!(end, layout.checksum_offset());
+            assert_eq!(
+                layout.checksum_offset() + layout.checksum.size(),
+                layout.len
+            );
+            assert_eq!(layout.sensor_count, device.status.virtual_sensor_count);
+        }
+    }
+
+    /// Resizing keeps the sensors and moves the checksum
+    #[test]
+    fn resized() {
+        let layout = OCTO.virtual_sensors;
+        let longer = layout.resized(60).unwrap();
+        assert_eq!(longer.checksum_offset(), 58);
+        assert_eq!(longer.trailer, layout.trailer);
+        let shorter = layout.resized(40).unwrap();
+        assert_eq!(shorter.trailer.len(), 5);
+        assert_eq!(layout.resized(34), None);
+        assert_eq!(layout.resized(1 << 30), None);
+        let unchecked = VirtualSensorLayout {
+            checksum: &NoChecksum,
+            ..layout
+        };
+        assert_eq!(unchecked.resized(33).unwrap().checksum_offset(), 33);
+    }
+
+    /// The newest matching firmware variant wins
+    #[test]
+    fn firmware_variants() {
+        static LONGER: VirtualSensorLayout = VirtualSensorLayout {
+            len: 55,
+            trailer: &[0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4],
+            ..OCTO.virtual_sensors
+        };
+        static LONGEST: VirtualSensorLayout = VirtualSensorLayout { len: 60, ..LONGER };
+        static VARIANTS: &[FirmwareVariant] = &[
+            FirmwareVariant {
+                min_firmware: 1100,
+                virtual_sensors: LONGER,
+                control: Some(MOVED),
+            },
+            FirmwareVariant {
+                min_firmware: 1200,
+                virtual_sensors: LONGEST,
+                control: None,
+            },
+        ];
+        static MOVED: ControlLayout = ControlLayout {
+            len: 0x700,
+            ..OCTO.control
+        };
+        let device = DeviceLayout {
+            firmware_variants: VARIANTS,
+            ..OCTO
+        };
+        assert_eq!(device.virtual_sensors_for(1019), OCTO.virtual_sensors);
+        assert_eq!(device.virtual_sensors_for(1100), LONGER);
+        assert_eq!(device.virtual_sensors_for(1199), LONGER);
+        assert_eq!(device.virtual_sensors_for(1300), LONGEST);
+        assert_eq!(device.control_for(1019).unwrap(), OCTO.control);
+        assert_eq!(device.control_for(1199).unwrap(), MOVED);
+        let error = device.control_for(1300).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The Octo's control report isn't known for firmware 1300"
+        );
+    }
+
+    /// Every status field fits inside the report before the checksum
+    #[test]
+    fn status_fields_fit() {
+        for device in DEVICES {
+            let status = device.status;
+            let fields = status.fan_fields;
+            let last_field = [fields.voltage, fields.current, fields.power, fields.speed]
+                .into_iter()
+                .chain(fields.percent)
+                .max()
+                .unwrap();
+            let fans_end = status
+                .fans
+                .iter()
+                .max()
+                .map_or(0, |fan| fan + last_field + 2);
+            assert!(fans_end <= status.len - status.checksum.size());
+            let sensors_end = status.sensors + 2 * status.sensor_count;
+            assert!(sensors_end <= status.len - status.checksum.size());
+            assert!(status.virtual_sensors + 2 * status.virtual_sensor_count <= status.len);
+        }
+    }
+
+    /// Every fan control block fits inside the report before the checksum
+    #[test]
+    fn control_fields_fit() {
+        use super::fan_control::{CURVE_DUTIES, CURVE_POINTS};
+        for device in DEVICES {
+            let control = device.control;
+            let fans_end = control
+                .fans
+                .iter()
+                .max()
+                .map_or(0, |fan| fan + CURVE_DUTIES + 2 * CURVE_POINTS);
+            assert!(fans_end <= control.len - control.checksum.size());
+            assert_eq!(control.fans.len(), device.status.fans.len());
+        }
+    }
+
+    /// Models are found by their USB ID
+    #[test]
+    fn usb_ids() {
+        assert_eq!(for_usb_id(0x0c70, 0xf00d), Some(&QUADRO));
+        assert_eq!(for_usb_id(0x0c70, 0xf011), Some(&OCTO));
+        assert_eq!(for_usb_id(0x0c70, 0xffff), None);
+    }
+}
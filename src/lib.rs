@@ -1,153 +1,2078 @@
 //! Octo Virtual Sensors
-//! 
+//!
 //! Update the virtual sensors on an Aqua computer Octo
 //!
 //! Usage:
-//! ```
+//! ```no_run
 //! use octo_virtual_sensors::Octo;
 //! let mut octo = Octo::new().unwrap();
 //! octo.update_virtual_sensors(&[1, 2, 3]).unwrap();
 //! ```
 //!
-use anyhow::{Context, Result};
-use rusb::{Device, DeviceList, GlobalContext};
-use std::time::Duration;
+//! aquasuite calls the same 16 slots software sensors. The Octo has no
+//! separate software sensor region; both names mean the values written
+//! here.
+//!
+use crate::error::Context;
+use std::time::{Duration, Instant};
+
+/// Return early with an [`Error`] made from a format string
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::Error::msg(format!($($arg)*)))
+    };
+}
+
+/// Report a recoverable problem without failing the operation, see
+/// [`logging`]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        log::warn!(target: "octo_virtual_sensors", $($arg)*)
+    };
+}
 
+/// Record what the crate is doing as a `tracing` event at `Debug` or
+/// `Trace`, see [`trace`]
+///
+/// Compiles to nothing without the `trace` feature.
+macro_rules! trace {
+    (Debug, $($arg:tt)*) => {
+        trace!(@event DEBUG, $($arg)*)
+    };
+    (Trace, $($arg:tt)*) => {
+        trace!(@event TRACE, $($arg)*)
+    };
+    (@event $level:ident, $($arg:tt)*) => {{
+        #[cfg(feature = "trace")]
+        tracing::event!(target: "octo_virtual_sensors", tracing::Level::$level, $($arg)*);
+        #[cfg(not(feature = "trace"))]
+        {
+            // Count the arguments as used without evaluating them
+            let _ = || format!($($arg)*);
+        }
+    }};
+}
+
+#[cfg(feature = "service")]
+pub mod breaker;
+mod builder;
+pub mod calibration;
+pub mod checksum;
+pub mod codec;
+#[cfg(feature = "service")]
+pub mod config;
+pub mod control;
+pub mod curve;
+#[cfg(feature = "service")]
+pub mod daemon;
+pub mod device;
+pub mod dryrun;
+#[cfg(any(test, feature = "emulator"))]
+pub mod emulator;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod handle;
+#[cfg(all(unix, feature = "service"))]
+pub mod helper;
+pub mod hid;
+#[cfg(feature = "hidapi")]
+pub mod hidapi;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(target_os = "linux")]
+pub mod hwmon;
+#[cfg(feature = "import")]
+pub mod import;
+pub mod kernel;
+pub mod layout;
+#[cfg(feature = "service")]
+pub mod lmsensors;
+pub mod logging;
+#[cfg(feature = "service")]
+pub mod mirror;
+#[cfg(any(test, feature = "emulator"))]
+pub mod mock;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+#[cfg(all(unix, feature = "nvml"))]
+pub mod nvml;
+#[cfg(feature = "service")]
+pub mod profile;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod protocol;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod queue;
+#[cfg(feature = "service")]
+pub mod recorder;
+#[cfg(feature = "service")]
+pub mod schedule;
+pub mod shared;
+#[cfg(feature = "service")]
+pub mod source;
+#[cfg(feature = "service")]
+pub mod state;
+pub mod status;
+pub mod stream;
+#[cfg(all(target_os = "linux", feature = "service"))]
+pub mod suspend;
+#[cfg(all(target_os = "linux", feature = "service"))]
+pub mod systemd;
+#[cfg(all(test, unix))]
+mod testing;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod transaction;
+pub mod transform;
+mod transport;
+pub mod units;
+pub mod watch;
+
+pub use builder::{Backend, FirmwareCheck, OctoBuilder, RangeCheck, USB_IDS_ENV};
+pub use error::{Error, OctoError, Result};
+pub use handle::OctoHandle;
+use hid::{ReportDescriptor, ReportKind};
+use layout::{DeviceLayout, VirtualSensorLayout};
+use queue::Backpressure;
+pub use shared::SharedOcto;
+pub use transport::{StallPolicy, Transport, UsbTransport, WriteStrategy};
+
+/// What a device says about itself, from [`Octo::info`]
+///
+/// Only what the status report is known to carry. Bootloader version and
+/// uptime aren't among the documented fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DeviceInfo {
+    /// Model name, such as `Octo`
+    pub name: &'static str,
+    /// Firmware version
+    pub firmware: u16,
+    /// Serial number, such as `12345-06789`
+    pub serial: String,
+    /// Times the device has been powered on
+    pub power_cycles: u32,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for DeviceInfo {
+    /// Fails for models this crate doesn't know
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        /// The fields, with the name not yet looked up
+        #[derive(serde::Deserialize)]
+        struct Fields {
+            name: String,
+            firmware: u16,
+            serial: String,
+            power_cycles: u32,
+        }
+        let fields = Fields::deserialize(deserializer)?;
+        let device = layout::DEVICES
+            .iter()
+            .find(|device| device.name == fields.name)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown device {:?}", fields.name)))?;
+        Ok(Self {
+            name: device.name,
+            firmware: fields.firmware,
+            serial: fields.serial,
+            power_cycles: fields.power_cycles,
+        })
+    }
+}
+
+/// What to do when virtual sensor updates stop
+///
+/// The device disconnects virtual sensors by itself once its virtual
+/// sensor timeout passes, but how fans react to a disconnected source is
+/// up to their settings, and the timeout itself isn't part of the known
+/// protocol. A failsafe acts from the host instead: when the `Octo` is
+/// dropped, see [`OctoBuilder::failsafe`], or when a watchdog such as
+/// [`Helper::with_failsafe`](crate::helper::Helper::with_failsafe) sees
+/// updates stop.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Failsafe {
+    /// Do nothing and leave it to the device's own timeout
+    #[default]
+    DeviceTimeout,
+    /// Disconnect every virtual sensor straight away
+    Disconnect,
+    /// Disconnect the virtual sensors and run every fan following one at
+    /// a fixed power, in percent
+    ///
+    /// The fans' settings are restored by the next update.
+    FanPower(f32),
+}
 
 /// Simple interface to update the 'Virtual sensors on the Aquacomputer Octo
 pub struct Octo {
-    device: Device<GlobalContext>,
+    transport: Box<dyn Transport + Send>,
+    device: DeviceLayout,
+    report: VirtualSensorReport,
+    firmware: Option<u16>,
+    descriptor: Option<ReportDescriptor>,
+    power_cycles: Option<u32>,
+    sent: bool,
+    reboots: u32,
+    link: LinkStats,
+    reset_after_timeouts: Option<u32>,
+    timeouts: u32,
+    retry: Option<(u32, Duration)>,
+    checksum_attempts: usize,
+    virtual_sensor_timeout: Option<Duration>,
+    reconnect: Option<Reconnect>,
+    last_sent: Option<Instant>,
+    cadence_warned: bool,
+    serial: Option<String>,
+    failsafe: Failsafe,
+    failsafe_saved: Vec<(usize, control::FanControl)>,
+    emergency_saved: Option<Vec<(usize, control::FanControl)>>,
+    deadband: Option<Deadband>,
+    range_check: RangeCheck,
+    calibrations: Vec<(usize, calibration::Calibration)>,
+    control: std::result::Result<layout::ControlLayout, String>,
+}
+
+/// A connected device found by [`Octo::list`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OctoInfo {
+    /// USB bus number
+    pub bus: u8,
+    /// Address on the bus
+    pub address: u8,
+    /// Serial number, if the status report could be read
+    pub serial: Option<String>,
+    /// Firmware version, if the status report could be read
+    pub firmware: Option<u16>,
+}
+
+/// Counters for problems on the link to the device
+///
+/// A flaky cable or hub shows up here long before it shows up as anything
+/// but mysteriously ignored updates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkStats {
+    /// Status, control and virtual sensor reports read with a bad checksum
+    /// and thrown away
+    pub checksum_errors: u64,
+    /// Virtual sensor reports sent again because they didn't read back
+    pub resends: u64,
+    /// Times the device went away and was reopened automatically
+    pub reconnects: u64,
+    /// Updates not sent because no value moved past the deadband
+    pub skipped: u64,
+}
+
+/// Threshold and interval below which updates aren't sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Deadband {
+    threshold: u16,
+    interval: Duration,
+    sent: Vec<Option<i16>>,
+}
+
+impl Deadband {
+    /// Whether `values` can go unsent, `elapsed` after the last send
+    ///
+    /// Connecting or disconnecting a slot always counts as a change.
+    fn skip(&self, values: &[Option<i16>], elapsed: Option<Duration>) -> bool {
+        if elapsed.is_none_or(|elapsed| elapsed >= self.interval) {
+            return false;
+        }
+        values.len() == self.sent.len()
+            && values
+                .iter()
+                .zip(&self.sent)
+                .all(|(value, sent)| match (value, sent) {
+                    (Some(value), Some(sent)) => value.abs_diff(*sent) <= self.threshold,
+                    (value, sent) => value == sent,
+                })
+    }
+}
+
+/// Backoff between attempts to reopen a device that went away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Reconnect {
+    initial: Duration,
+    max: Duration,
+    delay: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl Reconnect {
+    /// First retry after `initial`, doubling up to `max`
+    fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            delay: initial,
+            next_attempt: None,
+        }
+    }
+
+    /// Whether an attempt is due at `now`
+    fn due(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|next| now >= next)
+    }
+
+    /// Wait longer before the next attempt
+    fn failed(&mut self, now: Instant) {
+        self.next_attempt = Some(now + self.delay);
+        self.delay = self.delay.saturating_mul(2).min(self.max);
+    }
+
+    /// Start over for the next time the device goes away
+    fn succeeded(&mut self) {
+        self.delay = self.initial;
+        self.next_attempt = None;
+    }
+}
+
+/// Virtual sensor report as sent to the Octo
+///
+/// Holds the raw bytes of the output report, starting with the report ID
+/// and ending with the layout's checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualSensorReport {
+    layout: VirtualSensorLayout,
     buffer: Vec<u8>,
 }
 
-/// Header offset
-static HEADER: usize = 1;
+impl Default for VirtualSensorReport {
+    fn default() -> Self {
+        Self::new(layout::OCTO.virtual_sensors)
+    }
+}
+
+#[deny(clippy::indexing_slicing)]
+impl VirtualSensorReport {
+    /// Report ID of the Octo's virtual sensor report
+    pub const ID: u8 = layout::OCTO.virtual_sensors.report_id;
+
+    /// Length of the Octo's report in bytes, including ID and checksum
+    pub const LEN: usize = layout::OCTO.virtual_sensors.len;
+
+    /// Create a report with every sensor disconnected
+    ///
+    /// Parts of a layout that fail [`VirtualSensorLayout::check`] are left
+    /// out rather than panicking.
+    pub fn new(layout: VirtualSensorLayout) -> Self {
+        let mut buffer = vec![0; layout.len];
+        if let Some(id) = buffer.first_mut() {
+            *id = layout.report_id;
+        }
+        let start = layout.sensor(layout.sensor_count);
+        let end = start.saturating_add(layout.trailer.len());
+        if let Some(trailer) = buffer.get_mut(start..end) {
+            trailer.copy_from_slice(layout.trailer);
+        }
+        let mut report = Self { layout, buffer };
+        report.update(&[]);
+        report
+    }
+
+    /// Parse an Octo report from raw bytes
+    ///
+    /// Fails if the length, report ID or checksum don't match.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::parse(layout::OCTO.virtual_sensors, bytes)
+    }
+
+    /// Parse a report with the given layout from raw bytes
+    pub fn parse(layout: VirtualSensorLayout, bytes: &[u8]) -> Result<Self> {
+        layout.check()?;
+        if bytes.len() != layout.len {
+            return Err(OctoError::InvalidReport).with_context(|| {
+                format!("Expected {} byte report, got {}", layout.len, bytes.len())
+            });
+        }
+        let id = bytes.first().copied().unwrap_or_default();
+        if id != layout.report_id {
+            return Err(OctoError::InvalidReport)
+                .with_context(|| format!("Expected report ID {}, got {id}", layout.report_id));
+        }
+        if !layout.checksum.verify(bytes) {
+            return Err(OctoError::ChecksumMismatch)
+                .with_context(|| format!("{} checksum mismatch", layout.checksum.name()));
+        }
+        Ok(Self {
+            layout,
+            buffer: bytes.to_vec(),
+        })
+    }
+
+    /// Layout the report was built with
+    pub fn layout(&self) -> &VirtualSensorLayout {
+        &self.layout
+    }
+
+    /// Raw report bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Bytes between the sensors and the checksum
+    ///
+    /// Newer firmware may carry extra fields here. They are never touched by
+    /// [`VirtualSensorReport::update`], so values parsed from a captured
+    /// report or set through [`VirtualSensorReport::trailer_mut`] are kept.
+    pub fn trailer(&self) -> &[u8] {
+        let start = self.layout.sensor(self.layout.sensor_count);
+        let end = self.layout.checksum_offset();
+        self.buffer.get(start..end).unwrap_or_default()
+    }
+
+    /// Mutable access to the bytes between the sensors and the checksum
+    ///
+    /// The checksum is recomputed on the next update.
+    pub fn trailer_mut(&mut self) -> &mut [u8] {
+        let start = self.layout.sensor(self.layout.sensor_count);
+        let end = self.layout.checksum_offset();
+        self.buffer.get_mut(start..end).unwrap_or_default()
+    }
+
+    /// Take the bytes between the sensors and the checksum from `current`
+    ///
+    /// `current` is the report as the device holds it, so writing this one
+    /// back changes only the sensors. Fails if the layouts differ.
+    pub fn adopt_trailer(&mut self, current: &VirtualSensorReport) -> Result<()> {
+        if current.layout != self.layout {
+            return Err(OctoError::InvalidLayout)
+                .context("Can't take the trailer of a report with another layout");
+        }
+        self.trailer_mut().copy_from_slice(current.trailer());
+        self.layout.checksum.apply(&mut self.buffer);
+        Ok(())
+    }
+
+    /// Sensor values in centidegrees, `None` for disconnected slots
+    pub fn values(&self) -> Vec<Option<i16>> {
+        (0..self.layout.sensor_count)
+            .map(|slot| {
+                codec::get_temperature(&self.buffer, self.layout.sensor(slot))
+                    .ok()
+                    .flatten()
+            })
+            .collect()
+    }
+
+    /// Update the sensors values in the report and recompute the checksum
+    ///
+    /// Values are whole degrees. Ones too large for the encoding saturate
+    /// at its limits.
+    pub fn update(&mut self, sensor_values: &[i16]) {
+        let values: Vec<Option<i16>> = sensor_values
+            .iter()
+            .map(|&value| Some(codec::saturating_centidegrees(value)))
+            .collect();
+        self.set_values(&values);
+    }
+
+    /// Update the sensors from fractional degrees and recompute the checksum
+    ///
+    /// Values are rounded to the nearest centidegree and saturate at the
+    /// encoding's limits. NaN disconnects its slot.
+    pub fn update_f32(&mut self, sensor_values: &[f32]) {
+        let values: Vec<Option<i16>> = sensor_values
+            .iter()
+            .map(|&value| codec::saturating_centidegrees_f32(value))
+            .collect();
+        self.set_values(&values);
+    }
+
+    /// Like [`VirtualSensorReport::update`], failing with
+    /// [`OctoError::OutOfRange`] instead of saturating
+    ///
+    /// Nothing changes if any value is out of range.
+    pub fn try_update(&mut self, sensor_values: &[i16]) -> Result<()> {
+        let values = sensor_values
+            .iter()
+            .enumerate()
+            .map(|(slot, &value)| {
+                codec::checked_centidegrees(value)
+                    .map(Some)
+                    .with_context(|| format!("Virtual sensor {slot}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.set_values(&values);
+        Ok(())
+    }
+
+    /// Like [`VirtualSensorReport::update_f32`], failing with
+    /// [`OctoError::OutOfRange`] instead of saturating
+    ///
+    /// Nothing changes if any value is out of range.
+    pub fn try_update_f32(&mut self, sensor_values: &[f32]) -> Result<()> {
+        let values = sensor_values
+            .iter()
+            .enumerate()
+            .map(|(slot, &value)| {
+                codec::checked_centidegrees_f32(value)
+                    .with_context(|| format!("Virtual sensor {slot}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.set_values(&values);
+        Ok(())
+    }
+
+    /// Set every sensor in centidegrees and recompute the checksum
+    ///
+    /// `None` and slots past the end of `values` are disconnected, and
+    /// [`codec::DISCONNECTED`] saturates rather than disconnecting.
+    pub fn set_values(&mut self, values: &[Option<i16>]) {
+        for slot in 0..self.layout.sensor_count {
+            let value = values.get(slot).copied().flatten().map(saturate);
+            // Only fails for layouts that don't fit their report
+            let _ = codec::put_temperature(&mut self.buffer, self.layout.sensor(slot), value);
+        }
+        self.layout.checksum.apply(&mut self.buffer);
+    }
+
+    /// Set only the given `(slot, centidegrees)` pairs and recompute the checksum
+    ///
+    /// Other slots keep their values, and [`codec::DISCONNECTED`]
+    /// saturates like in [`VirtualSensorReport::set_values`]. Fails without
+    /// changing anything if a slot is out of range.
+    pub fn set_slots(&mut self, values: &[(usize, Option<i16>)]) -> Result<()> {
+        let count = self.layout.sensor_count;
+        if let Some((slot, _)) = values.iter().find(|(slot, _)| *slot >= count) {
+            return Err(OctoError::OutOfRange).with_context(|| {
+                format!("Slot {slot} is out of range, there are {count} virtual sensors")
+            });
+        }
+        for &(slot, value) in values {
+            let value = value.map(saturate);
+            codec::put_temperature(&mut self.buffer, self.layout.sensor(slot), value)?;
+        }
+        self.layout.checksum.apply(&mut self.buffer);
+        Ok(())
+    }
+}
 
 impl Octo {
     /// Create a new Octo
     ///
     /// Tries to find the connected Octo. Fails if unable to find it based on vendor_id and product_id
     pub fn new() -> Result<Self> {
-        for device in DeviceList::new().context("Getting USB Device list")?.iter() {
-            let dd = &device.device_descriptor().context("Getting device ID")?;
-            
-            static VENDOR_ID: u16 = 3184;
-            static PRODUCT_ID: u16 = 61457;
+        OctoBuilder::new().open()
+    }
+
+    /// Every connected Octo, for picking one on machines with several
+    pub fn list() -> Result<Vec<OctoInfo>> {
+        OctoBuilder::new().list()
+    }
+
+    /// Open the Octo with the given serial number
+    ///
+    /// See [`OctoBuilder::serial`].
+    pub fn open_by_serial(serial: &str) -> Result<Self> {
+        OctoBuilder::new().serial(serial).open()
+    }
+
+    /// Builder to configure how the Octo is opened
+    pub fn builder() -> OctoBuilder {
+        OctoBuilder::new()
+    }
+
+    /// Create an Octo that talks through the given transport
+    ///
+    /// The report layout is picked by the firmware version in the device's
+    /// status report, and its length taken from the HID report descriptor
+    /// when available, so firmware that grows the report keeps working.
+    pub fn with_transport(transport: impl Transport + Send + 'static) -> Result<Self> {
+        OctoBuilder::new().with_transport(transport)
+    }
+
+    /// udev rule letting the logged-in user open the Octo without root
+    ///
+    /// Also covers the other Aquacomputer models the crate opens. Goes in
+    /// [`kernel::UDEV_RULE_PATH`] or any other file under
+    /// `/etc/udev/rules.d`; opening fails with
+    /// [`OctoError::PermissionDenied`] until it is installed.
+    pub fn udev_rule() -> String {
+        kernel::udev_rule(layout::DEVICES)
+    }
+
+    /// Move the Octo onto a thread of its own
+    ///
+    /// The returned handle can be cloned into any thread and queues values
+    /// without waiting for the device, see [`handle`]. Up to 16 updates
+    /// are queued, dropping the oldest when the device falls behind.
+    pub fn spawn(self) -> Result<OctoHandle> {
+        self.spawn_with(handle::CAPACITY, Backpressure::DropOldest)
+    }
+
+    /// [`Octo::spawn`], queueing up to `capacity` updates and applying
+    /// `backpressure` when the device falls behind
+    pub fn spawn_with(self, capacity: usize, backpressure: Backpressure) -> Result<OctoHandle> {
+        OctoHandle::spawn(self, capacity, backpressure)
+    }
+
+    /// Put the Octo behind a lock that clones can share between threads
+    ///
+    /// Unlike [`Octo::spawn`], calls wait for the device, see [`shared`].
+    pub fn share(self) -> SharedOcto {
+        SharedOcto::new(self)
+    }
+
+    /// Probe the device behind `transport` and set up the report
+    pub(crate) fn open_transport(
+        transport: Box<dyn Transport + Send>,
+        options: &OctoBuilder,
+    ) -> Result<Self> {
+        #[cfg(feature = "trace")]
+        let transport: Box<dyn Transport + Send> = Box::new(trace::Traced::new(transport));
+        let mut transport = transport;
+        let device = options.layout();
+        let status = match (read_status(transport.as_mut(), &device), &options.serial) {
+            (Ok(status), _) => Some(status),
+            // Without a status report there's no telling whether this is
+            // the device asked for, so the reason can't be dropped
+            (Err(error), Some(_)) => {
+                return Err(error)
+                    .with_context(|| format!("Reading the {}'s serial number", device.name))
+            }
+            (Err(_), None) => None,
+        };
+        let firmware = status
+            .as_deref()
+            .and_then(|status| firmware(status, &device));
+        let serial = status.as_deref().and_then(|status| serial(status, &device));
+        if let Some(wanted) = &options.serial {
+            if serial.as_ref() != Some(wanted) {
+                return Err(OctoError::DeviceNotFound).with_context(|| {
+                    format!(
+                        "{} has serial {}, not {wanted}",
+                        device.name,
+                        serial.as_deref().unwrap_or("unknown")
+                    )
+                });
+            }
+        }
+        let power_cycles = status
+            .as_ref()
+            .and_then(|status| codec::get_u32(status, device.status.power_cycles).ok());
+        if let Some(firmware) = firmware {
+            check_firmware(&device, firmware, options.firmware_check)?;
+        }
+        let layout = firmware.map_or(device.virtual_sensors, |firmware| {
+            device.virtual_sensors_for(firmware)
+        });
+        trace!(
+            Debug,
+            "Opening {} with firmware {firmware:?} and serial {serial:?}, {} byte report",
+            device.name,
+            layout.len
+        );
+        let descriptor = read_descriptor(transport.as_mut());
+        if let Some(descriptor) = &descriptor {
+            let expected = [
+                (ReportKind::Output, layout.report_id, layout.len),
+                (
+                    ReportKind::Input,
+                    device.status.report_id,
+                    device.status.len,
+                ),
+            ];
+            for mismatch in descriptor.mismatches(&expected) {
+                warn!("{}: {mismatch}", device.name);
+            }
+        }
+        let layout = descriptor
+            .as_ref()
+            .map_or(layout, |descriptor| size_layout(descriptor, layout));
+        let control = control_layout(&device, firmware, descriptor.as_ref())
+            .map_err(|error| format!("{error:#}"));
+        if let Err(error) = &control {
+            trace!(Debug, "{error}");
+        }
+        let mut octo = Self {
+            transport,
+            device,
+            report: VirtualSensorReport::new(layout),
+            firmware,
+            descriptor,
+            power_cycles,
+            sent: false,
+            reboots: 0,
+            link: LinkStats::default(),
+            reset_after_timeouts: options.reset_after_timeouts,
+            timeouts: 0,
+            retry: options.retry,
+            checksum_attempts: options.checksum_attempts.unwrap_or(CHECKSUM_ATTEMPTS),
+            virtual_sensor_timeout: options.virtual_sensor_timeout,
+            reconnect: options
+                .reconnect
+                .map(|(initial, max)| Reconnect::new(initial, max)),
+            last_sent: None,
+            cadence_warned: false,
+            serial,
+            failsafe: options.failsafe,
+            failsafe_saved: Vec::new(),
+            emergency_saved: None,
+            deadband: options.deadband.map(|(threshold, interval)| Deadband {
+                threshold,
+                interval,
+                sent: Vec::new(),
+            }),
+            range_check: options.range_check,
+            calibrations: options.calibrations.clone(),
+            control,
+        };
+        if !options.builtin_template {
+            // Devices that can't be asked keep the built-in template
+            let _ = octo.refresh_report_template();
+        }
+        Ok(octo)
+    }
+
+    /// Layout of the device being talked to
+    pub fn device(&self) -> &DeviceLayout {
+        &self.device
+    }
+
+    /// Layout of the control report for the device's firmware
+    ///
+    /// Fails if the firmware's control report isn't known or doesn't match
+    /// the length the device declares, in which case settings can't be
+    /// read or written.
+    pub fn control_layout(&self) -> Result<layout::ControlLayout> {
+        self.control.clone().map_err(crate::Error::msg)
+    }
+
+    /// Firmware version read when the device was opened
+    pub fn firmware(&self) -> Option<u16> {
+        self.firmware
+    }
+
+    /// Serial number read when the device was opened
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// HID report descriptor read when the device was opened
+    pub fn report_descriptor(&self) -> Option<&ReportDescriptor> {
+        self.descriptor.as_ref()
+    }
+
+    /// Length of the virtual sensor report sent to the device
+    pub fn report_len(&self) -> usize {
+        self.report.as_bytes().len()
+    }
+
+    /// Layout of the virtual sensor report sent to the device
+    pub fn report_layout(&self) -> &VirtualSensorLayout {
+        self.report.layout()
+    }
+
+    /// The virtual sensor report most recently built by
+    /// [`Octo::update_virtual_sensors`]
+    pub fn last_report(&self) -> &VirtualSensorReport {
+        &self.report
+    }
+
+    /// Read the next raw status report from the device
+    ///
+    /// The checksum isn't checked, see [`Octo::read_status`] for that.
+    pub fn read_status_report(&mut self) -> Result<Vec<u8>> {
+        self.transfer(|octo| read_status(octo.transport.as_mut(), &octo.device))
+    }
+
+    /// Read and decode the next status report
+    ///
+    /// Reports with a bad checksum are counted in [`Octo::link_stats`] and
+    /// skipped. A changed power cycle count means the device rebooted and
+    /// forgot the virtual sensors, so the last values sent are sent again.
+    pub fn read_status(&mut self) -> Result<status::Status> {
+        let report = self.read_checked_status()?;
+        status::Status::parse(&self.device.status, &report)
+    }
+
+    /// Firmware version, serial number and power cycle count, read fresh
+    ///
+    /// Reads a status report like [`Octo::read_status`].
+    pub fn info(&mut self) -> Result<DeviceInfo> {
+        let report = self.read_checked_status()?;
+        let layout = &self.device.status;
+        Ok(DeviceInfo {
+            name: self.device.name,
+            firmware: firmware(&report, &self.device).context("Reading the firmware version")?,
+            serial: serial(&report, &self.device).context("Reading the serial number")?,
+            power_cycles: codec::get_u32(&report, layout.power_cycles)?,
+        })
+    }
+
+    /// Next status report with a good checksum, restoring the virtual
+    /// sensors if the device rebooted
+    fn read_checked_status(&mut self) -> Result<Vec<u8>> {
+        let checksum = self.device.status.checksum;
+        let report = self.read_verified("status", checksum, Self::read_status_report)?;
+        let power_cycles = codec::get_u32(&report, self.device.status.power_cycles)?;
+        let previous = self.power_cycles.replace(power_cycles);
+        if previous.is_some_and(|previous| previous != power_cycles) {
+            self.reboots += 1;
+            if self.sent {
+                warn!("{} rebooted, restoring virtual sensors", self.device.name);
+                self.send()
+                    .context("Restoring virtual sensors after reboot")?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Read reports with `read` until one passes `checksum`
+    ///
+    /// Bad reports are counted in [`Octo::link_stats`]. Fails with
+    /// [`OctoError::ChecksumMismatch`] after the attempts set with
+    /// [`OctoBuilder::checksum_attempts`].
+    fn read_verified(
+        &mut self,
+        kind: &str,
+        checksum: &dyn checksum::Checksum,
+        mut read: impl FnMut(&mut Self) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let mut attempts = 1;
+        loop {
+            let report = read(self)?;
+            if checksum.verify(&report) {
+                return Ok(report);
+            }
+            self.link.checksum_errors += 1;
+            trace!(
+                Debug,
+                "Bad {} checksum on {kind} report {attempts}",
+                checksum.name()
+            );
+            if attempts >= self.checksum_attempts {
+                return Err(OctoError::ChecksumMismatch).with_context(|| {
+                    format!(
+                        "{attempts} {kind} reports in a row failed their {} checksum",
+                        checksum.name()
+                    )
+                });
+            }
+            attempts += 1;
+        }
+    }
+
+    /// Read a feature report of `len` bytes with ID `report_id`
+    fn read_feature(&mut self, report_id: u8, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        if let Some(id) = buf.first_mut() {
+            *id = report_id;
+        }
+        let len = self.transfer(|octo| octo.transport.read_feature_report(&mut buf))?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Read the physical temperature sensors in °C
+    ///
+    /// Reads a status report like [`Octo::read_status`], checksum included.
+    /// Unplugged sensors are `None`.
+    pub fn read_sensors(&mut self) -> Result<Vec<Option<f32>>> {
+        let status = self.read_status()?;
+        Ok(status
+            .sensors
+            .iter()
+            .map(|value| value.map(|centidegrees| f32::from(centidegrees) / 100.0))
+            .collect())
+    }
+
+    /// Read the flow sensor in litres per hour
+    ///
+    /// `None` for devices without one. How many impulses make a litre is
+    /// set with [`Octo::set_flow_calibration`].
+    pub fn read_flow(&mut self) -> Result<Option<f32>> {
+        Ok(self.read_status()?.flow_litres_per_hour())
+    }
+
+    /// Flow sensor calibration in impulses per litre
+    pub fn flow_calibration(&mut self) -> Result<u16> {
+        self.read_control()?.flow_pulses()
+    }
+
+    /// Set the flow sensor calibration in impulses per litre
+    ///
+    /// Read-modify-writes the control report, keeping every other setting.
+    /// Accepts 10 to 1000, the range the hwmon driver allows. The sensor's
+    /// datasheet gives the value.
+    pub fn set_flow_calibration(&mut self, pulses: u16) -> Result<()> {
+        if !FLOW_PULSES.contains(&pulses) {
+            return Err(OctoError::OutOfRange).with_context(|| {
+                format!(
+                    "{pulses} impulses per litre is not between {} and {}",
+                    FLOW_PULSES.start(),
+                    FLOW_PULSES.end()
+                )
+            });
+        }
+        let mut report = self.read_control()?;
+        report.set_flow_pulses(pulses)?;
+        self.write_control(&report)?;
+        Ok(())
+    }
+
+    /// Device reboots noticed by [`Octo::read_status`]
+    pub fn reboots(&self) -> u32 {
+        self.reboots
+    }
+
+    /// Checksum errors and resends so far
+    pub fn link_stats(&self) -> LinkStats {
+        self.link
+    }
+
+    /// Reset the device's USB port and restore the virtual sensors
+    ///
+    /// Does what replugging the cable would, for controllers that stopped
+    /// answering. The last values sent are sent again, as the device may
+    /// have forgotten them.
+    pub fn reset_device(&mut self) -> Result<()> {
+        self.transport
+            .reset()
+            .with_context(|| format!("Resetting {}", self.device.name))?;
+        self.timeouts = 0;
+        let status = read_status(self.transport.as_mut(), &self.device)
+            .context("Reading status after reset")?;
+        self.power_cycles = codec::get_u32(&status, self.device.status.power_cycles).ok();
+        if self.sent {
+            self.send()
+                .context("Restoring virtual sensors after reset")?;
+        }
+        Ok(())
+    }
+
+    /// Base the virtual sensor report on the one the device holds
+    ///
+    /// Reads the report back as a feature report and keeps everything but
+    /// the sensors, so settings outside them that differ between firmware
+    /// versions or configurations aren't overwritten with the built-in
+    /// template. Done when the device is opened, unless
+    /// [`OctoBuilder::builtin_template`] says otherwise. Fails, keeping the
+    /// current template, if the device doesn't answer with a valid report.
+    pub fn refresh_report_template(&mut self) -> Result<()> {
+        self.check_virtual_sensors_known()?;
+        let layout = self.report.layout;
+        let buf = self.read_verified("virtual sensor", layout.checksum, |octo| {
+            octo.read_feature(layout.report_id, layout.len)
+        })?;
+        let current = VirtualSensorReport::parse(layout, &buf)
+            .with_context(|| format!("Reading the {}'s virtual sensor report", self.device.name))?;
+        self.report.adopt_trailer(&current)
+    }
+
+    /// Close the device and open it again, restoring the virtual sensors
+    ///
+    /// For recovering after the device was replugged or another program
+    /// held it, without building a new `Octo`. The last values sent are
+    /// sent again, as a replugged device has forgotten them.
+    pub fn reopen(&mut self) -> Result<()> {
+        self.transport
+            .reopen()
+            .with_context(|| format!("Reopening {}", self.device.name))?;
+        self.timeouts = 0;
+        // A replugged device may have come back with other settings
+        let _ = self.refresh_report_template();
+        if self.sent {
+            self.send()
+                .context("Restoring virtual sensors after reopening")?;
+        }
+        Ok(())
+    }
+
+    /// Run `transfer`, reopening the device and retrying once if it went
+    /// away, and keep count of timeouts
+    fn transfer<T>(&mut self, mut transfer: impl FnMut(&mut Self) -> Result<T>) -> Result<T> {
+        let (retries, mut backoff) = self.retry.unwrap_or_default();
+        let mut attempts = 1;
+        loop {
+            let mut result = transfer(self);
+            if let Err(error) = &result {
+                if self.reconnect_at(error, Instant::now()) {
+                    result = transfer(self);
+                }
+            }
+            let result = self.track_timeouts(result);
+            match &result {
+                Err(error) if attempts <= retries && retryable(error) => {
+                    trace!(Debug, "Retrying in {backoff:?}: {error:#}");
+                    std::thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    attempts += 1;
+                }
+                Err(_) if attempts > 1 => {
+                    return result.with_context(|| format!("Gave up after {attempts} attempts"))
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    /// Reopen a device that went away, as often as the backoff allows
+    ///
+    /// Returns whether it's back, making the failed transfer worth
+    /// retrying. Off unless enabled with [`OctoBuilder::reconnect`].
+    fn reconnect_at(&mut self, error: &crate::Error, now: Instant) -> bool {
+        if error.kind() != Some(OctoError::Disconnected) {
+            return false;
+        }
+        let Some(reconnect) = &mut self.reconnect else {
+            return false;
+        };
+        if !reconnect.due(now) {
+            return false;
+        }
+        match self.transport.reopen() {
+            Ok(()) => {
+                reconnect.succeeded();
+                self.timeouts = 0;
+                self.link.reconnects += 1;
+                warn!("{} is back, reconnected", self.device.name);
+                true
+            }
+            Err(error) => {
+                trace!(Debug, "Reopening {}: {error:#}", self.device.name);
+                reconnect.failed(now);
+                false
+            }
+        }
+    }
+
+    /// Count timeouts in a row, resetting the device once there are too many
+    ///
+    /// The failed transfer's error is still returned; the reset is for the
+    /// next one.
+    fn track_timeouts<T>(&mut self, result: Result<T>) -> Result<T> {
+        let Err(error) = &result else {
+            self.timeouts = 0;
+            return result;
+        };
+        if error.kind() != Some(OctoError::Timeout) {
+            return result;
+        }
+        self.timeouts += 1;
+        if self
+            .reset_after_timeouts
+            .is_some_and(|limit| self.timeouts >= limit)
+        {
+            warn!(
+                "{} timeouts in a row, resetting {}",
+                self.timeouts, self.device.name
+            );
+            if let Err(reset) = self.reset_device() {
+                warn!("{reset:#}");
+            }
+        }
+        result
+    }
+
+    /// Read the device's settings
+    pub fn read_control(&mut self) -> Result<control::ControlReport> {
+        let layout = self.control_layout()?;
+        let buf = self
+            .read_verified("control", layout.checksum, |octo| {
+                octo.read_feature(layout.report_id, layout.len)
+            })
+            .with_context(|| format!("Reading the {}'s control report", self.device.name))?;
+        control::ControlReport::parse(layout, &buf)
+            .with_context(|| format!("Reading the {}'s control report", self.device.name))
+    }
+
+    /// Duty cycle fan `channel` would run at with its source at `temperature`
+    ///
+    /// Reads the channel's mode and curve from the device, so the effect
+    /// of publishing a virtual temperature can be previewed before it is
+    /// sent. The temperature is taken as the reading of whichever source
+    /// the channel follows, see [`control::FanControl::source`]. `None` if
+    /// the channel's mode doesn't map one temperature to one duty cycle.
+    pub fn simulate_curve(&mut self, channel: usize, temperature: i16) -> Result<Option<u16>> {
+        let fan = self.read_control()?.fan(channel)?;
+        Ok(fan.duty_at(temperature))
+    }
+
+    /// Write the device's settings
+    ///
+    /// Reports for another device's layout are refused without being sent.
+    /// Settings take effect straight away but are lost at power off unless
+    /// [`Octo::save_settings`] follows.
+    pub fn write_control(&mut self, report: &control::ControlReport) -> Result<usize> {
+        if report.layout() != &self.control_layout()? {
+            return Err(OctoError::InvalidLayout).with_context(|| {
+                format!(
+                    "Control report doesn't match the {}'s layout",
+                    self.device.name
+                )
+            });
+        }
+        self.transfer(|octo| octo.transport.write_feature_report(report.as_bytes()))
+            .with_context(|| format!("Writing the {}'s control report", self.device.name))
+    }
+
+    /// Have the device keep its current settings across power cycles
+    ///
+    /// Settings written over USB, such as fan curves, the flow
+    /// calibration and profile, are otherwise lost at power off. Sends
+    /// the report aquasuite sends after a change, so the device stores its
+    /// settings in flash.
+    ///
+    /// Flash wears out after a limited number of writes: save once after
+    /// a batch of changes, never from a loop or every update. Virtual
+    /// sensor values aren't settings and don't need saving.
+    pub fn save_settings(&mut self) -> Result<()> {
+        let layout = self.control_layout()?;
+        let report = layout.save.with_context(|| {
+            format!(
+                "How the {} saves its settings isn't known",
+                self.device.name
+            )
+        })?;
+        self.transfer(|octo| octo.transport.write_feature_report(report))
+            .with_context(|| format!("Saving the {}'s settings", self.device.name))?;
+        Ok(())
+    }
+
+    /// Run fan `channel` at a fixed power in percent
+    ///
+    /// Switches the channel to manual mode, keeping its curve and every
+    /// other setting. Channels are numbered from 0.
+    pub fn set_fan_power(&mut self, channel: usize, percent: f32) -> Result<()> {
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(OctoError::OutOfRange)
+                .with_context(|| format!("Fan power {percent}% is not between 0 and 100%"));
+        }
+        self.set_fan_duty(channel, (percent * 100.0).round() as u16)
+    }
+
+    /// Run fan `channel` at a fixed 8 bit PWM duty cycle
+    ///
+    /// Like [`Octo::set_fan_power`] with 255 as full power.
+    pub fn set_fan_pwm(&mut self, channel: usize, pwm: u8) -> Result<()> {
+        self.set_fan_duty(channel, codec::pwm_to_centipercent(pwm))
+    }
+
+    /// Read-modify-write fan `channel` into manual mode at `duty` centipercent
+    fn set_fan_duty(&mut self, channel: usize, duty: u16) -> Result<()> {
+        let mut report = self.read_control()?;
+        let mut fan = report.fan(channel)?;
+        fan.mode = control::ControlMode::Manual;
+        fan.duty = duty;
+        report.set_fan(channel, &fan)?;
+        self.write_control(&report)?;
+        Ok(())
+    }
+
+    /// Program fan `channel` to follow `source` along `curve`
+    ///
+    /// The firmware then drives the fan by itself, so a virtual sensor
+    /// source turns the temperatures this crate writes into fan speeds
+    /// without the host in the loop. Only the curve's points are uploaded;
+    /// limits, hysteresis and the failsafe duty are host-side settings.
+    /// Fails for curves with more points than the device stores or sources
+    /// the device doesn't have.
+    pub fn set_fan_curve(
+        &mut self,
+        channel: usize,
+        curve: &curve::FanCurve,
+        source: control::TemperatureSource,
+    ) -> Result<()> {
+        if curve.points().len() > layout::fan_control::CURVE_POINTS {
+            return Err(OctoError::OutOfRange).with_context(|| {
+                format!(
+                    "{} curve points given, the {} stores {}",
+                    curve.points().len(),
+                    self.device.name,
+                    layout::fan_control::CURVE_POINTS
+                )
+            });
+        }
+        let exists = match source {
+            control::TemperatureSource::Sensor(sensor) => {
+                usize::from(sensor) < self.device.status.sensor_count
+            }
+            control::TemperatureSource::VirtualSensor(slot) => {
+                slot < self.control_layout()?.virtual_sensor_count
+            }
+            control::TemperatureSource::Other(_) => true,
+        };
+        if !exists {
+            return Err(OctoError::Unsupported)
+                .with_context(|| format!("The {} has no {source:?}", self.device.name));
+        }
+        let mut report = self.read_control()?;
+        let mut fan = report.fan(channel)?;
+        fan.mode = control::ControlMode::Curve;
+        fan.source = source;
+        fan.curve = curve.points().to_vec();
+        report.set_fan(channel, &fan)?;
+        self.write_control(&report)?;
+        Ok(())
+    }
 
-            if dd.vendor_id() == VENDOR_ID && dd.product_id() == PRODUCT_ID {
-                let buffer = vec![
-                    4, 127, 255, 127, 255, 127, 255, 127, 255, 127, 255, 127, 255, 127, 255, 127,
-                    255, 127, 255, 127, 255, 127, 255, 127, 255, 127, 255, 127, 255, 127, 255, 127,
-                    255, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255,
-                ];
-                return Ok(Self { device, buffer });
+    /// Act as if updates had stopped, see [`Failsafe`]
+    ///
+    /// Fans are only switched once: engaging again before an update keeps
+    /// the settings saved the first time.
+    pub fn engage_failsafe(&mut self, failsafe: Failsafe) -> Result<()> {
+        let percent = match failsafe {
+            Failsafe::DeviceTimeout => return Ok(()),
+            Failsafe::Disconnect => None,
+            Failsafe::FanPower(percent) if (0.0..=100.0).contains(&percent) => Some(percent),
+            Failsafe::FanPower(percent) => {
+                return Err(OctoError::OutOfRange)
+                    .with_context(|| format!("Fan power {percent}% is not between 0 and 100%"))
             }
+        };
+        self.report.set_values(&[]);
+        self.transfer(|octo| octo.transport.write_report(octo.report.as_bytes()))
+            .context("Disconnecting virtual sensors for the failsafe")?;
+        if let Some(deadband) = &mut self.deadband {
+            // Whatever comes next differs from what the device now holds
+            deadband.sent.clear();
+        }
+        let Some(percent) = percent else {
+            return Ok(());
+        };
+        let mut report = self.read_control()?;
+        let mut saved = Vec::new();
+        for channel in 0..report.fan_count() {
+            let fan = report.fan(channel)?;
+            let follows_virtual =
+                matches!(fan.source, control::TemperatureSource::VirtualSensor(_));
+            if !follows_virtual || fan.mode == control::ControlMode::Manual {
+                continue;
+            }
+            let mut failsafe = fan.clone();
+            failsafe.mode = control::ControlMode::Manual;
+            failsafe.duty = (percent * 100.0).round() as u16;
+            report.set_fan(channel, &failsafe)?;
+            saved.push((channel, fan));
+        }
+        if saved.is_empty() {
+            return Ok(());
+        }
+        self.write_control(&report)
+            .context("Switching fans to the failsafe power")?;
+        warn!(
+            "Failsafe: {} fans following virtual sensors run at {percent}%",
+            saved.len()
+        );
+        if self.failsafe_saved.is_empty() {
+            self.failsafe_saved = saved;
+        }
+        Ok(())
+    }
+
+    /// Put back fan settings a failsafe replaced
+    fn restore_after_failsafe(&mut self) -> Result<()> {
+        if self.failsafe_saved.is_empty() {
+            return Ok(());
+        }
+        if let Some(saved) = &mut self.emergency_saved {
+            // Fans stay at full speed, and go back to the settings from
+            // before the failsafe once restored
+            for (channel, fan) in self.failsafe_saved.drain(..) {
+                match saved.iter_mut().find(|(saved, _)| *saved == channel) {
+                    Some((_, saved)) => *saved = fan,
+                    None => saved.push((channel, fan)),
+                }
+            }
+            return Ok(());
+        }
+        let mut report = self.read_control()?;
+        for (channel, fan) in &self.failsafe_saved {
+            report.set_fan(*channel, fan)?;
+        }
+        self.write_control(&report)
+            .context("Restoring fan settings after the failsafe")?;
+        self.failsafe_saved.clear();
+        Ok(())
+    }
+
+    /// Run every fan channel at full power until [`Octo::restore`]
+    ///
+    /// The big red button for when a source misbehaves: each channel is
+    /// switched to manual mode at 100%, whatever its curve follows and
+    /// whatever is published meanwhile. The settings are kept to be put
+    /// back, and pressing it again keeps those from the first time.
+    /// Nothing is saved to flash, so a power cycle also undoes it.
+    pub fn emergency_max_fans(&mut self) -> Result<()> {
+        let mut report = self.read_control()?;
+        let mut saved = Vec::with_capacity(report.fan_count());
+        for channel in 0..report.fan_count() {
+            let fan = report.fan(channel)?;
+            let mut full = fan.clone();
+            full.mode = control::ControlMode::Manual;
+            full.duty = curve::FULL_DUTY;
+            report.set_fan(channel, &full)?;
+            saved.push((channel, fan));
+        }
+        self.write_control(&report)
+            .context("Switching every fan to full power")?;
+        warn!("Emergency: {} fans run at 100%", saved.len());
+        self.emergency_saved.get_or_insert(saved);
+        Ok(())
+    }
+
+    /// Whether fans are held at full power by [`Octo::emergency_max_fans`]
+    pub fn emergency_engaged(&self) -> bool {
+        self.emergency_saved.is_some()
+    }
+
+    /// Put back the fan settings [`Octo::emergency_max_fans`] replaced
+    ///
+    /// Does nothing if it wasn't engaged.
+    pub fn restore(&mut self) -> Result<()> {
+        if self.emergency_saved.is_none() {
+            return Ok(());
+        }
+        let mut report = self.read_control()?;
+        for (channel, fan) in self.emergency_saved.iter().flatten() {
+            report.set_fan(*channel, fan)?;
         }
-        anyhow::bail!("Could not find Aquastream Octo");
+        self.write_control(&report)
+            .context("Restoring fan settings after the emergency")?;
+        self.emergency_saved = None;
+        Ok(())
+    }
+
+    /// Virtual sensor timeout set with
+    /// [`OctoBuilder::virtual_sensor_timeout`]
+    pub fn virtual_sensor_timeout(&self) -> Option<Duration> {
+        self.virtual_sensor_timeout
+    }
+
+    /// How often to re-send values so the device doesn't time them out
+    ///
+    /// Half the virtual sensor timeout, leaving room for a late update, or
+    /// `None` if the timeout isn't known.
+    pub fn keep_alive_interval(&self) -> Option<Duration> {
+        self.virtual_sensor_timeout.map(|timeout| timeout / 2)
+    }
+
+    /// Re-send the last values if the keep-alive interval has passed
+    ///
+    /// Call it from the update loop so slow sources don't let the sensors
+    /// time out. Does nothing before the first update or without a known
+    /// timeout. Returns whether the values were sent.
+    pub fn keep_alive(&mut self) -> Result<bool> {
+        let due = self
+            .last_sent
+            .zip(self.keep_alive_interval())
+            .is_some_and(|(sent, interval)| sent.elapsed() >= interval);
+        if due {
+            self.send()?;
+        }
+        Ok(due)
+    }
+
+    /// Send the last values again because they didn't read back
+    pub(crate) fn resend(&mut self) -> Result<usize> {
+        self.link.resends += 1;
+        self.send()
     }
 
     /// Update virtual sensors
     ///
     /// Takes a slice of sensor with each values index being used as
-    /// the virtual sensors output number
+    /// the virtual sensors output number. Values are whole degrees Celsius
+    /// and may be negative, for chillers and outdoor probes.
+    #[doc(alias = "update_software_sensors")]
     pub fn update_virtual_sensors(&mut self, sensor_values: &[i16]) -> Result<usize> {
-        self.update_buffer(sensor_values);
+        match self.range_check {
+            RangeCheck::Saturate => self.report.update(sensor_values),
+            RangeCheck::Reject => self.report.try_update(sensor_values)?,
+        }
+        self.calibrate_report();
+        self.send_update()
+    }
+
+    /// Update virtual sensors from fractional degrees
+    ///
+    /// Like [`Octo::update_virtual_sensors`], but keeps the hundredths of a
+    /// degree the device can carry, so 42.37 °C isn't rounded to 42.
+    pub fn update_virtual_sensors_f32(&mut self, sensor_values: &[f32]) -> Result<usize> {
+        match self.range_check {
+            RangeCheck::Saturate => self.report.update_f32(sensor_values),
+            RangeCheck::Reject => self.report.try_update_f32(sensor_values)?,
+        }
+        self.calibrate_report();
+        self.send_update()
+    }
+
+    /// Send the last values again
+    ///
+    /// For when the device may have forgotten them, such as after system
+    /// suspend.
+    pub fn republish(&mut self) -> Result<usize> {
         self.send()
     }
 
-    /// Update the sensors values in the existing buffer
-    fn update_buffer(&mut self, sensor_values: &[i16]) {
-        for index in 0..16 {
-            let sensor_offset = HEADER + 2 * index;            
-            if let Some(value) = sensor_values.get(index) {
-                let value = (100_i16 * value).to_be_bytes();
-                self.buffer[sensor_offset] = value[0];
-                self.buffer[sensor_offset + 1] = value[1];
-            } else {
-                let value = i16::MAX.to_be_bytes();
-                self.buffer[sensor_offset] = value[0];
-                self.buffer[sensor_offset + 1] = value[1];
+    /// Update virtual sensors from centidegree values
+    ///
+    /// `None` and slots past the end of `values` are disconnected. Use
+    /// [`Octo::update_slots`] to leave other slots alone.
+    pub fn update_centidegrees(&mut self, values: &[Option<i16>]) -> Result<usize> {
+        self.check_range(values.iter().copied().enumerate())?;
+        let values: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(slot, value)| self.calibrate(slot, *value))
+            .collect();
+        self.report.set_values(&values);
+        self.send_update()
+    }
+
+    /// Update virtual sensors from temperatures in any unit
+    ///
+    /// Like [`Octo::update_centidegrees`], with the conversion done when
+    /// each [`units::Temperature`] was made.
+    pub fn update_temperatures(&mut self, values: &[Option<units::Temperature>]) -> Result<usize> {
+        let values: Vec<_> = values
+            .iter()
+            .map(|value| value.map(units::Temperature::centidegrees))
+            .collect();
+        self.update_centidegrees(&values)
+    }
+
+    /// Update only the given `(slot, centidegrees)` pairs
+    ///
+    /// Slots not mentioned keep the last value sent through this `Octo`,
+    /// so independent parts of a program can each own a few slots. `None`
+    /// disconnects a slot.
+    pub fn update_slots(&mut self, values: &[(usize, Option<i16>)]) -> Result<usize> {
+        self.check_range(values.iter().copied())?;
+        let values: Vec<_> = values
+            .iter()
+            .map(|&(slot, value)| (slot, self.calibrate(slot, value)))
+            .collect();
+        self.report.set_slots(&values)?;
+        self.send_update()
+    }
+
+    /// Set one virtual sensor in degrees Celsius, keeping the others
+    ///
+    /// Rounded to the nearest centidegree like
+    /// [`Octo::update_virtual_sensors_f32`]; NaN disconnects the slot.
+    /// Fails if `index` is out of range.
+    pub fn set_virtual_sensor(&mut self, index: usize, degrees: f32) -> Result<usize> {
+        let value = match self.range_check {
+            RangeCheck::Saturate => codec::saturating_centidegrees_f32(degrees),
+            RangeCheck::Reject => codec::checked_centidegrees_f32(degrees)
+                .with_context(|| format!("Virtual sensor {index}"))?,
+        };
+        self.update_slots(&[(index, value)])
+    }
+
+    /// With [`RangeCheck::Reject`], fail for `(slot, centidegrees)` pairs
+    /// the encoding can't carry
+    fn check_range(&self, values: impl IntoIterator<Item = (usize, Option<i16>)>) -> Result<()> {
+        if self.range_check == RangeCheck::Reject {
+            for (slot, value) in values {
+                codec::checked_temperature(value)
+                    .with_context(|| format!("Virtual sensor {slot}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `value` for `slot` as corrected by its calibration, if it has one
+    fn calibrate(&self, slot: usize, value: Option<i16>) -> Option<i16> {
+        let calibration = self
+            .calibrations
+            .iter()
+            .find(|(calibrated, _)| *calibrated == slot);
+        match (value, calibration) {
+            (Some(value), Some((_, calibration))) => Some(calibration.apply(value)),
+            (value, _) => value,
+        }
+    }
+
+    /// Calibrate every slot of a freshly encoded report
+    fn calibrate_report(&mut self) {
+        if self.calibrations.is_empty() {
+            return;
+        }
+        let values: Vec<_> = self
+            .report
+            .values()
+            .into_iter()
+            .enumerate()
+            .map(|(slot, value)| self.calibrate(slot, value))
+            .collect();
+        self.report.set_values(&values);
+    }
+
+    /// Put `values` back as they were sent before, without calibrating
+    /// them again
+    pub(crate) fn restore_values(&mut self, values: &[Option<i16>]) -> Result<usize> {
+        self.report.set_values(values);
+        self.send_update()
+    }
+
+    /// Disconnect one virtual sensor, keeping the others
+    ///
+    /// Fails if `index` is out of range.
+    pub fn clear_virtual_sensor(&mut self, index: usize) -> Result<usize> {
+        self.update_slots(&[(index, None)])
+    }
+
+    /// Send a prebuilt report
+    ///
+    /// Reports whose ID or length don't match what this device expects are
+    /// rejected without being sent, so a report built for another model
+    /// never reaches the wire.
+    pub fn send_report(&mut self, report: &VirtualSensorReport) -> Result<usize> {
+        let expected = self.report.layout();
+        if report.layout().report_id != expected.report_id {
+            return Err(OctoError::InvalidLayout).with_context(|| {
+                format!(
+                    "Report ID {} is not a virtual sensor report for the {} (expected {})",
+                    report.layout().report_id,
+                    self.device.name,
+                    expected.report_id
+                )
+            });
+        }
+        if report.as_bytes().len() != expected.len {
+            return Err(OctoError::InvalidLayout).with_context(|| {
+                format!(
+                    "{} byte report doesn't match the {}'s {} byte report",
+                    report.as_bytes().len(),
+                    self.device.name,
+                    expected.len
+                )
+            });
+        }
+        self.restore_after_failsafe()?;
+        self.transfer(|octo| octo.transport.write_report(report.as_bytes()))
+    }
+
+    /// Send an output report exactly as given, for experimenting with
+    /// parts of the protocol the crate doesn't cover
+    ///
+    /// With a `checksum`, such as [`Crc16Usb`](checksum::Crc16Usb), it is
+    /// filled in over the last bytes first. Nothing is validated, so a
+    /// malformed report goes to the device as is.
+    pub fn send_raw_report(
+        &mut self,
+        report: &[u8],
+        checksum: Option<&dyn checksum::Checksum>,
+    ) -> Result<usize> {
+        let report = with_checksum(report, checksum);
+        self.transfer(|octo| octo.transport.write_report(&report))
+    }
+
+    /// Read the next input report with ID `report_id`
+    ///
+    /// Reports with other IDs are skipped, up to a few. With a
+    /// `checksum`, a report that fails it is an
+    /// [`OctoError::ChecksumMismatch`].
+    pub fn read_raw_report(
+        &mut self,
+        report_id: u8,
+        checksum: Option<&dyn checksum::Checksum>,
+    ) -> Result<Vec<u8>> {
+        for _ in 0..RAW_READ_ATTEMPTS {
+            let mut buf = vec![0; RAW_REPORT_MAX];
+            let len = self.transfer(|octo| octo.transport.read_report(&mut buf))?;
+            buf.truncate(len);
+            if buf.first() == Some(&report_id) {
+                return verified(buf, checksum);
             }
         }
+        Err(OctoError::InvalidReport)
+            .with_context(|| format!("No input report {report_id} in {RAW_READ_ATTEMPTS} reads"))
+    }
 
-        let crc_ = crc::Crc::<u16>::new(&crc::CRC_16_USB);
-        let mut digest = crc_.digest();
+    /// Write a feature report exactly as given, see
+    /// [`Octo::send_raw_report`]
+    pub fn send_raw_feature_report(
+        &mut self,
+        report: &[u8],
+        checksum: Option<&dyn checksum::Checksum>,
+    ) -> Result<usize> {
+        let report = with_checksum(report, checksum);
+        self.transfer(|octo| octo.transport.write_feature_report(&report))
+    }
 
-        static CHECKSUM: usize = 2;
-        digest.update(&self.buffer[HEADER..self.buffer.len() - CHECKSUM]);
+    /// Read feature report `report_id`, `len` bytes including the ID, see
+    /// [`Octo::read_raw_report`]
+    pub fn read_raw_feature_report(
+        &mut self,
+        report_id: u8,
+        len: usize,
+        checksum: Option<&dyn checksum::Checksum>,
+    ) -> Result<Vec<u8>> {
+        let mut buf = vec![0; len.max(1)];
+        if let Some(id) = buf.first_mut() {
+            *id = report_id;
+        }
+        let len = self.transfer(|octo| octo.transport.read_feature_report(&mut buf))?;
+        buf.truncate(len);
+        verified(buf, checksum)
+    }
 
-        let checksum = digest.finalize().to_be_bytes();
+    /// Send the buffer unless the deadband says it can wait
+    ///
+    /// Returns 0 for a skipped update, as nothing was written.
+    fn send_update(&mut self) -> Result<usize> {
+        self.send_update_at(Instant::now())
+    }
 
-        self.buffer[49] = checksum[0];
-        self.buffer[50] = checksum[1];
+    /// Send the buffer unless the deadband says it can wait at `now`
+    fn send_update_at(&mut self, now: Instant) -> Result<usize> {
+        if let Some(deadband) = &self.deadband {
+            let elapsed = self
+                .last_sent
+                .map(|sent| now.saturating_duration_since(sent));
+            if deadband.skip(&self.report.values(), elapsed) {
+                self.link.skipped += 1;
+                trace!(Debug, "Skipping an update inside the deadband");
+                return Ok(0);
+            }
+        }
+        self.send()
     }
 
-    /// Send the buffer to the device via a USB bulk write
+    /// Send the buffer to the device
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(name = "write", level = "debug", skip_all, fields(device = self.device.name))
+    )]
     fn send(&mut self) -> Result<usize> {
-        let open = self.device.open().context("Opening USB device")?;
-        static TIMEOUT: Duration = Duration::from_secs(1);        
-        open
-            .write_bulk(2, &self.buffer, TIMEOUT)
-            .context("Sending bulk transfer to Octo")
+        self.check_virtual_sensors_known()?;
+        self.restore_after_failsafe()?;
+        trace!(Debug, "Sending virtual sensors {:?}", self.report.values());
+        let written = self.transfer(|octo| octo.transport.write_report(octo.report.as_bytes()))?;
+        if let Some(deadband) = &mut self.deadband {
+            deadband.sent = self.report.values();
+        }
+        self.sent = true;
+        self.check_cadence(Instant::now());
+        Ok(written)
+    }
+
+    /// Fail for models whose virtual sensor report isn't known
+    fn check_virtual_sensors_known(&self) -> Result<()> {
+        if !self.device.virtual_sensors_known {
+            return Err(OctoError::Unsupported).with_context(|| {
+                format!(
+                    "The {}'s virtual sensor report isn't known yet",
+                    self.device.name
+                )
+            });
+        }
+        Ok(())
+    }
+
+    /// Warn once if updates come slower than the virtual sensor timeout
+    ///
+    /// Otherwise the only symptom is sensors flipping to disconnected
+    /// between updates.
+    fn check_cadence(&mut self, now: Instant) {
+        let previous = self.last_sent.replace(now);
+        let (Some(previous), Some(timeout)) = (previous, self.virtual_sensor_timeout) else {
+            return;
+        };
+        let period = now.saturating_duration_since(previous);
+        if period > timeout && !self.cadence_warned {
+            self.cadence_warned = true;
+            warn!(
+                "{:.1} s between updates is longer than the {}'s {:.1} s virtual sensor \
+                 timeout, sensors will read as disconnected in between. Call \
+                 Octo::keep_alive more often or raise the timeout",
+                period.as_secs_f32(),
+                self.device.name,
+                timeout.as_secs_f32()
+            );
+        }
+    }
+}
+
+impl Drop for Octo {
+    fn drop(&mut self) {
+        if let Err(error) = self.engage_failsafe(self.failsafe) {
+            warn!("Failsafe: {error:#}");
+        }
+    }
+}
+
+/// Whether a failed transfer is worth trying again, see
+/// [`OctoBuilder::retry`]
+fn retryable(error: &crate::Error) -> bool {
+    matches!(error.kind(), Some(OctoError::Timeout | OctoError::Usb(_)))
+}
+
+/// Input reports [`Octo::read_raw_report`] reads looking for its ID
+static RAW_READ_ATTEMPTS: usize = 8;
+
+/// Room for the largest input report, as for full speed interrupt
+/// transfers
+static RAW_REPORT_MAX: usize = 1024;
+
+/// `report` with `checksum` filled in, if there is one
+fn with_checksum(report: &[u8], checksum: Option<&dyn checksum::Checksum>) -> Vec<u8> {
+    let mut report = report.to_vec();
+    if let Some(checksum) = checksum {
+        checksum.apply(&mut report);
+    }
+    report
+}
+
+/// `report` if it passes `checksum`, or there is none
+fn verified(report: Vec<u8>, checksum: Option<&dyn checksum::Checksum>) -> Result<Vec<u8>> {
+    match checksum {
+        Some(checksum) if !checksum.verify(&report) => Err(OctoError::ChecksumMismatch)
+            .with_context(|| format!("Report failed its {} checksum", checksum.name())),
+        _ => Ok(report),
+    }
+}
+
+/// Status reports read before giving up on a bad checksum
+static CHECKSUM_ATTEMPTS: usize = 3;
+
+/// Flow calibrations the firmware accepts, in impulses per litre
+static FLOW_PULSES: std::ops::RangeInclusive<u16> = 10..=1000;
+
+/// Centidegrees clamped below [`codec::DISCONNECTED`], so a value that
+/// happens to equal it doesn't read as a disconnected sensor
+fn saturate(centidegrees: i16) -> i16 {
+    centidegrees.min(codec::MAX_CENTIDEGREES)
+}
+
+/// Apply the firmware check policy
+fn check_firmware(device: &DeviceLayout, firmware: u16, check: FirmwareCheck) -> Result<()> {
+    if firmware >= device.min_firmware || check == FirmwareCheck::Ignore {
+        return Ok(());
     }
+    let message = format!(
+        "{} firmware {firmware} is older than {}, the oldest known to support virtual sensors",
+        device.name, device.min_firmware
+    );
+    if check == FirmwareCheck::Refuse {
+        return Err(OctoError::Unsupported).context(message);
+    }
+    warn!("{message}");
+    Ok(())
+}
+
+/// Control report layout for `firmware`, checked against the length the
+/// descriptor declares
+///
+/// Without a firmware version the oldest firmware's layout is assumed.
+fn control_layout(
+    device: &DeviceLayout,
+    firmware: Option<u16>,
+    descriptor: Option<&ReportDescriptor>,
+) -> Result<layout::ControlLayout> {
+    let control = device.control_for(firmware.unwrap_or_default())?;
+    let declared = descriptor
+        .and_then(|descriptor| descriptor.report_len(ReportKind::Feature, control.report_id));
+    if let Some(declared) = declared.filter(|&declared| declared != control.len) {
+        return Err(OctoError::InvalidLayout).with_context(|| {
+            format!(
+                "The {} declares a {declared} byte control report, {} bytes were expected",
+                device.name, control.len
+            )
+        });
+    }
+    Ok(control)
+}
+
+/// Read the next status report, skipping any other input reports
+fn read_status(transport: &mut dyn Transport, device: &DeviceLayout) -> Result<Vec<u8>> {
+    let status = device.status;
+    let mut buf = vec![0; status.len];
+    for _ in 0..3 {
+        let len = transport.read_report(&mut buf)?;
+        if len == status.len && buf.first() == Some(&status.report_id) {
+            return Ok(buf);
+        }
+    }
+    Err(OctoError::InvalidReport).context("No status report from device")
+}
+
+/// Firmware version from a status report
+fn firmware(status: &[u8], device: &DeviceLayout) -> Option<u16> {
+    codec::get_u16(status, device.status.firmware).ok()
+}
+
+/// Serial number from a status report, formatted like the hwmon driver
+fn serial(status: &[u8], device: &DeviceLayout) -> Option<String> {
+    let offset = device.status.serial;
+    let first = codec::get_u16(status, offset).ok()?;
+    let second = codec::get_u16(status, offset + 2).ok()?;
+    Some(format!("{first:05}-{second:05}"))
+}
+
+/// Read and parse the device's report descriptor
+///
+/// Transports without a descriptor are normal, a descriptor that doesn't
+/// parse is worth a warning.
+fn read_descriptor(transport: &mut dyn Transport) -> Option<ReportDescriptor> {
+    let bytes = transport.report_descriptor().ok()?;
+    ReportDescriptor::parse(&bytes)
+        .map_err(|error| warn!("Ignoring report descriptor: {error}"))
+        .ok()
+}
+
+/// Size the virtual sensor report from the device's report descriptor
+///
+/// Keeps the built-in length if the descriptor doesn't declare the report,
+/// or declares one too short to hold the sensors.
+fn size_layout(descriptor: &ReportDescriptor, layout: VirtualSensorLayout) -> VirtualSensorLayout {
+    descriptor
+        .report_len(ReportKind::Output, layout.report_id)
+        .and_then(|len| layout.resized(len))
+        .unwrap_or(layout)
 }
 
 #[cfg(test)]
 mod test {
-    use anyhow::Result;
-    use std::{process::Command, time::Duration};
+    use super::{
+        calibration::Calibration,
+        checksum::{Checksum, Crc16Usb},
+        layout::OCTO,
+        mock::MockTransport,
+        Octo, OctoError, RangeCheck, Reconnect, ReportDescriptor, VirtualSensorReport,
+    };
+    use std::time::{Duration, Instant};
+
+    /// Small changes wait for the interval, bigger ones go straight out
+    #[test]
+    fn deadband() {
+        let mock = MockTransport::new();
+        let mut octo = Octo::builder()
+            .deadband(50, Duration::from_secs(10))
+            .with_transport(mock.clone())
+            .unwrap();
+        let now = Instant::now();
+        assert!(octo.update_centidegrees(&[Some(4000)]).unwrap() > 0);
+        octo.report.set_values(&[Some(4050)]);
+        assert_eq!(octo.send_update_at(now).unwrap(), 0);
+        octo.report.set_values(&[Some(3950), None]);
+        assert_eq!(octo.send_update_at(now).unwrap(), 0);
+        assert_eq!(octo.link_stats().skipped, 2);
+        octo.report.set_values(&[Some(4000), Some(2000)]);
+        assert!(octo.send_update_at(now).unwrap() > 0);
+        octo.report.set_values(&[Some(4051), Some(2000)]);
+        assert!(octo.send_update_at(now).unwrap() > 0);
+        octo.report.set_values(&[Some(4052), Some(2000)]);
+        assert_eq!(octo.send_update_at(now).unwrap(), 0);
+        assert!(octo.send_update_at(now + Duration::from_secs(11)).unwrap() > 0);
+        assert_eq!(mock.written().len(), 4);
+        assert!(octo.republish().unwrap() > 0);
+    }
+
+    /// A control report length the device doesn't declare is refused
+    #[test]
+    fn control_layout() {
+        let [count_lo, count_hi] = ((OCTO.control.len - 1) as u16).to_le_bytes();
+        let feature = |count_lo| {
+            ReportDescriptor::parse(&[0x75, 0x08, 0x85, 0x03, 0x96, count_lo, count_hi, 0xB1, 0x02])
+                .unwrap()
+        };
+        let layout = super::control_layout(&OCTO, Some(1100), Some(&feature(count_lo))).unwrap();
+        assert_eq!(layout, OCTO.control);
+        assert_eq!(
+            super::control_layout(&OCTO, None, None).unwrap(),
+            OCTO.control
+        );
+        let error = super::control_layout(&OCTO, None, Some(&feature(0))).unwrap_err();
+        assert!(error.to_string().contains("1537 byte control report"));
+    }
+
+    /// Reconnect attempts back off exponentially up to the maximum
+    #[test]
+    fn reconnect_backoff() {
+        let now = Instant::now();
+        let mut reconnect = Reconnect::new(Duration::from_secs(1), Duration::from_secs(3));
+        assert!(reconnect.due(now));
+        reconnect.failed(now);
+        assert!(!reconnect.due(now));
+        assert!(reconnect.due(now + Duration::from_secs(1)));
+        reconnect.failed(now);
+        assert!(!reconnect.due(now + Duration::from_secs(1)));
+        assert!(reconnect.due(now + Duration::from_secs(2)));
+        reconnect.failed(now);
+        reconnect.failed(now);
+        assert!(reconnect.due(now + Duration::from_secs(3)));
+        reconnect.succeeded();
+        assert!(reconnect.due(now));
+    }
 
     /// Test the buffer looks correct
     #[test]
-    fn update_buffer() -> Result<()> {
-        let mut octo = super::Octo::new()?;
-        octo.update_buffer(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
+    fn update_buffer() {
+        let mut report = VirtualSensorReport::default();
+        report.update(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]);
         let expected = vec![
             4, 0, 100, 0, 200, 1, 44, 1, 144, 1, 244, 2, 88, 2, 188, 3, 32, 3, 132, 3, 232, 4, 76,
             4, 176, 5, 20, 5, 120, 5, 220, 6, 64, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             218, 118,
         ];
-        for (expected, result) in expected.iter().zip(octo.buffer.iter()) {
-            assert_eq!(expected, result);
-        }
-        Ok(())
+        assert_eq!(expected, report.as_bytes());
     }
 
-    /// Test sensors actually update
+    /// Centidegree values round-trip through the report
     #[test]
-    fn update_virtual_sensors() -> Result<()> {
-        let mut octo = super::Octo::new()?;
-        octo.update_virtual_sensors(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])?;
-        // Wait for sensors to update
-        std::thread::sleep(Duration::from_secs(1));
-        let sensors = Command::new("/usr/bin/sensors").output()?.stdout;
-        let sensors = String::from_utf8(sensors)?;
-        let sensors_txt = sensors
-            .lines()
-            .filter(|line| line.contains("Virtual sensor"))
-            .map(|l| l.trim().to_string())
-            .collect::<Vec<String>>();
-        let expected = [
-            "Virtual sensor 1:    +1.0°C",
-            "Virtual sensor 2:    +2.0°C",
-            "Virtual sensor 3:    +3.0°C",
-            "Virtual sensor 4:    +4.0°C",
-            "Virtual sensor 5:    +5.0°C",
-            "Virtual sensor 6:    +6.0°C",
-            "Virtual sensor 7:    +7.0°C",
-            "Virtual sensor 8:    +8.0°C",
-            "Virtual sensor 9:    +9.0°C",
-            "Virtual sensor 10:  +10.0°C",
-            "Virtual sensor 11:  +11.0°C",
-            "Virtual sensor 12:  +12.0°C",
-            "Virtual sensor 13:  +13.0°C",
-            "Virtual sensor 14:  +14.0°C",
-            "Virtual sensor 15:  +15.0°C",
-            "Virtual sensor 16:  +16.0°C",
-        ];
-        for (expected, sensor) in expected.iter().zip(sensors_txt.iter()) {
-            assert_eq!(expected, sensor);
-        }
-        Ok(())
+    fn set_values() {
+        let mut report = VirtualSensorReport::default();
+        report.set_values(&[Some(4150), None, Some(-250)]);
+        let values = report.values();
+        assert_eq!(values[..4], [Some(4150), None, Some(-250), None]);
+        assert!(VirtualSensorReport::from_bytes(report.as_bytes()).is_ok());
+    }
+
+    /// Partial updates leave other slots alone and refuse unknown slots
+    #[test]
+    fn set_slots() {
+        let mut report = VirtualSensorReport::default();
+        report.set_values(&[Some(4150), Some(3000)]);
+        report.set_slots(&[(1, None), (15, Some(-100))]).unwrap();
+        let values = report.values();
+        assert_eq!(values[..2], [Some(4150), None]);
+        assert_eq!(values[15], Some(-100));
+        assert!(report.set_slots(&[(0, Some(1)), (16, Some(1))]).is_err());
+        assert_eq!(report.values()[0], Some(4150));
+        assert!(VirtualSensorReport::from_bytes(report.as_bytes()).is_ok());
+    }
+
+    /// Extra fields in the trailer survive updating the sensors
+    #[test]
+    fn update_preserves_trailer() {
+        let mut report = VirtualSensorReport::default();
+        report.trailer_mut()[10] = 0x42;
+        report.update(&[1, 2]);
+        let mut parsed = VirtualSensorReport::from_bytes(report.as_bytes()).unwrap();
+        parsed.update(&[3]);
+        assert_eq!(parsed.trailer()[4], 3);
+        assert_eq!(parsed.trailer()[10], 0x42);
+    }
+
+    /// Whole degrees too large for the encoding saturate instead of overflowing
+    #[test]
+    fn update_saturates() {
+        let mut report = VirtualSensorReport::default();
+        report.update(&[i16::MAX, i16::MIN, 328]);
+        let values = report.values();
+        assert_eq!(values[..3], [Some(32766), Some(i16::MIN), Some(32766)]);
+        report.set_values(&[Some(i16::MAX)]);
+        assert_eq!(report.values()[0], Some(32766));
+    }
+
+    /// Rejecting out of range values sends nothing and says which slot
+    #[test]
+    fn range_check() {
+        let mock = MockTransport::new();
+        let mut octo = Octo::builder()
+            .range_check(RangeCheck::Reject)
+            .with_transport(mock.clone())
+            .unwrap();
+        let error = octo.update_virtual_sensors(&[40, 700]).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::OutOfRange));
+        assert_eq!(
+            format!("{error:#}"),
+            "Virtual sensor 1: 700 °C is outside -327.68 to 327.66 °C: Out of range"
+        );
+        assert!(octo.update_virtual_sensors_f32(&[f32::INFINITY]).is_err());
+        assert!(octo.update_centidegrees(&[Some(i16::MAX)]).is_err());
+        assert!(octo.update_slots(&[(3, Some(i16::MAX))]).is_err());
+        assert!(octo.set_virtual_sensor(0, 327.67).is_err());
+        assert!(mock.written().is_empty());
+        assert!(octo.update_virtual_sensors(&[-327, 327]).unwrap() > 0);
+        assert!(octo.set_virtual_sensor(0, f32::NAN).unwrap() > 0);
+        assert_eq!(octo.report.values()[..2], [None, Some(32700)]);
+    }
+
+    /// Every update path calibrates its slots once
+    #[test]
+    fn calibration() {
+        let mock = MockTransport::new();
+        let mut octo = Octo::builder()
+            .calibration(0, Calibration::new().with_offset(-150))
+            .calibration(2, Calibration::new().with_clamp(1000, 5000))
+            .with_transport(mock.clone())
+            .unwrap();
+        octo.update_virtual_sensors(&[40, 30, 60]).unwrap();
+        assert_eq!(
+            octo.report.values()[..3],
+            [Some(3850), Some(3000), Some(5000)]
+        );
+        octo.update_virtual_sensors_f32(&[41.5, f32::NAN, 2.0])
+            .unwrap();
+        assert_eq!(octo.report.values()[..3], [Some(4000), None, Some(1000)]);
+        octo.update_centidegrees(&[Some(4150), Some(2000)]).unwrap();
+        assert_eq!(octo.report.values()[..3], [Some(4000), Some(2000), None]);
+        octo.set_virtual_sensor(2, 45.0).unwrap();
+        octo.update_slots(&[(1, Some(3300))]).unwrap();
+        octo.republish().unwrap();
+        assert_eq!(
+            octo.report.values()[..3],
+            [Some(4000), Some(3300), Some(4500)]
+        );
+        octo.clear_virtual_sensor(0).unwrap();
+        assert_eq!(octo.report.values()[0], None);
+    }
+
+    /// Fractional degrees keep their hundredths, NaN disconnects
+    #[test]
+    fn update_f32() {
+        let mut report = VirtualSensorReport::default();
+        report.update_f32(&[42.37, f32::NAN, -3.5]);
+        let values = report.values();
+        assert_eq!(values[..4], [Some(4237), None, Some(-350), None]);
+    }
+
+    /// Layouts that don't fit their report are refused, not a panic
+    #[test]
+    fn bad_layout() {
+        let mut layout = OCTO.virtual_sensors;
+        layout.sensor_count = 1000;
+        assert!(layout.check().is_err());
+        let mut report = VirtualSensorReport::new(layout);
+        report.set_values(&[Some(1); 1000]);
+        assert!(report.trailer().is_empty());
+        assert!(VirtualSensorReport::parse(layout, report.as_bytes()).is_err());
+        layout.len = 0;
+        assert!(VirtualSensorReport::new(layout).as_bytes().is_empty());
+        assert!(VirtualSensorReport::parse(layout, &[]).is_err());
+        assert!(OCTO.virtual_sensors.check().is_ok());
+    }
+
+    /// Raw reports go out as given, with the checksum filled in on request,
+    /// and come back only with the wanted ID
+    #[test]
+    fn raw_reports() {
+        let mock = MockTransport::new();
+        let mut octo = Octo::with_transport(mock.clone()).unwrap();
+        mock.clear_written();
+        octo.send_raw_report(&[9, 1, 2, 0, 0], None).unwrap();
+        octo.send_raw_report(&[9, 1, 2, 0, 0], Some(&Crc16Usb))
+            .unwrap();
+        let written = mock.written();
+        assert_eq!(written[0], [9, 1, 2, 0, 0]);
+        assert_eq!(written[1][..3], [9, 1, 2]);
+        assert!(Crc16Usb.verify(&written[1]));
+        let mut good = vec![7, 5, 6, 0, 0];
+        Crc16Usb.apply(&mut good);
+        mock.queue_read(vec![1, 0, 0]);
+        mock.queue_read(good.clone());
+        assert_eq!(octo.read_raw_report(7, Some(&Crc16Usb)).unwrap(), good);
+        mock.queue_read(vec![7, 5, 6, 0, 0]);
+        let error = octo.read_raw_report(7, Some(&Crc16Usb)).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::ChecksumMismatch));
+        mock.queue_feature_report(good.clone());
+        assert_eq!(octo.read_raw_feature_report(7, 5, None).unwrap(), good);
+        octo.send_raw_feature_report(&good, None).unwrap();
+        assert_eq!(mock.feature_reports_written(), [good]);
     }
 }
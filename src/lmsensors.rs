@@ -0,0 +1,108 @@
+//! lm-sensors configuration for labelled virtual sensors
+//!
+//! The hwmon driver names virtual sensors "Virtual sensor N". A `sensors.d`
+//! snippet can relabel them after what they carry:
+//!
+//! ```
+//! use octo_virtual_sensors::{layout::OCTO, lmsensors};
+//! let config = lmsensors::config(&OCTO, [(8, "GPU hotspot")]).unwrap();
+//! assert!(config.contains("label temp13 \"GPU hotspot\""));
+//! ```
+use crate::error::{Context, Result};
+use crate::layout::DeviceLayout;
+use std::{fs, path::Path};
+
+/// hwmon temperature channel of a virtual sensor slot
+///
+/// The driver numbers physical sensors first, from 1, then the virtual
+/// ones.
+pub fn temp_channel(device: &DeviceLayout, slot: usize) -> usize {
+    device.status.sensor_count + slot + 1
+}
+
+/// `sensors.d` snippet labelling virtual sensor slots
+///
+/// Fails on slots the device doesn't have.
+pub fn config<S: AsRef<str>>(
+    device: &DeviceLayout,
+    labels: impl IntoIterator<Item = (usize, S)>,
+) -> Result<String> {
+    let mut config = format!(
+        "# Generated by octo_virtual_sensors, changes will be overwritten\nchip \"{}-*\"\n",
+        device.hwmon_name
+    );
+    for (slot, label) in labels {
+        if slot >= device.status.virtual_sensor_count {
+            bail!("{} has no virtual sensor slot {slot}", device.name);
+        }
+        let channel = temp_channel(device, slot);
+        config += &format!("    label temp{channel} \"{}\"\n", escape(label.as_ref()));
+    }
+    Ok(config)
+}
+
+/// Write the snippet to `path`
+///
+/// The file is replaced in one step, so `sensors` never reads half of it.
+pub fn write_config<S: AsRef<str>>(
+    path: impl AsRef<Path>,
+    device: &DeviceLayout,
+    labels: impl IntoIterator<Item = (usize, S)>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let config = config(device, labels)?;
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, config).with_context(|| format!("Writing {}", path.display()))?;
+    fs::rename(&temporary, path).with_context(|| format!("Replacing {}", path.display()))
+}
+
+/// Quote a label for the config file's string syntax
+fn escape(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => format!("\\{c}"),
+            c if c.is_control() => " ".to_owned(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{config, temp_channel, write_config};
+    use crate::layout::OCTO;
+
+    /// Virtual slots follow the four physical sensors
+    #[test]
+    fn channels() {
+        assert_eq!(temp_channel(&OCTO, 0), 5);
+        assert_eq!(temp_channel(&OCTO, 15), 20);
+    }
+
+    /// Labels are listed under the chip, quoted
+    #[test]
+    fn generate() {
+        let config = config(&OCTO, [(0, "Water"), (8, "GPU \"hot\"\nspot")]).unwrap();
+        assert_eq!(
+            config,
+            "# Generated by octo_virtual_sensors, changes will be overwritten\n\
+             chip \"octo-*\"\n    \
+             label temp5 \"Water\"\n    \
+             label temp13 \"GPU \\\"hot\\\" spot\"\n"
+        );
+        assert!(super::config(&OCTO, [(16, "Nope")]).is_err());
+    }
+
+    /// Writing replaces the whole file
+    #[test]
+    fn write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("octo-vs.conf");
+        std::fs::write(&path, "old contents that are longer than the new ones").unwrap();
+        write_config(&path, &OCTO, [(1, "CPU")]).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.ends_with("    label temp6 \"CPU\"\n"));
+    }
+}
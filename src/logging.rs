@@ -0,0 +1,89 @@
+//! Where the crate's warnings go
+//!
+//! Recoverable problems, such as a device that rebooted, a reconnect or a
+//! failsafe engaging, are reported through the [`log`] facade under the
+//! `octo_virtual_sensors` target at [`log::Level::Warn`]. Nothing is
+//! printed unless the application installs a logger, so libraries and
+//! GUIs decide for themselves; any `log` backend works:
+//!
+//! ```no_run
+//! // Print the crate's warnings as the command line tools do
+//! octo_virtual_sensors::logging::log_to_stderr().unwrap();
+//! ```
+//!
+//! [`log_to_stderr_at`] also prints the crate's own records below that,
+//! such as the reports of a [dry run](crate::dryrun) at info or the
+//! [`trace`](crate::trace) feature's events at debug.
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// Logger printing warnings and errors to stderr, one line each, and the
+/// crate's own records down to [`log::max_level`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Warn
+            || (metadata.target().starts_with("octo_virtual_sensors")
+                && metadata.level() <= log::max_level())
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", line(record));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install [`StderrLogger`] as the process's logger
+///
+/// Fails if a logger is already installed.
+pub fn log_to_stderr() -> Result<(), SetLoggerError> {
+    log_to_stderr_at(LevelFilter::Warn)
+}
+
+/// Install [`StderrLogger`], printing the crate's records down to `level`
+///
+/// Other crates' records are still printed at warnings and above only.
+/// Fails if a logger is already installed.
+pub fn log_to_stderr_at(level: LevelFilter) -> Result<(), SetLoggerError> {
+    static LOGGER: StderrLogger = StderrLogger;
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(level.max(LevelFilter::Warn));
+    Ok(())
+}
+
+/// `record` as printed, e.g. `octo_virtual_sensors: warning: Octo rebooted`
+fn line(record: &Record<'_>) -> String {
+    let level = match record.level() {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    };
+    let target = record.target().split("::").next().unwrap_or_default();
+    format!("{target}: {level}: {}", record.args())
+}
+
+#[cfg(test)]
+mod test {
+    use super::line;
+    use log::{Level, Record};
+
+    /// Lines name the crate and the level the way the tools always have
+    #[test]
+    fn lines() {
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("octo_virtual_sensors::daemon")
+            .args(format_args!("Octo rebooted"))
+            .build();
+        assert_eq!(
+            line(&record),
+            "octo_virtual_sensors: warning: Octo rebooted"
+        );
+    }
+}
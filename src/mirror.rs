@@ -0,0 +1,265 @@
+//! Writing the same virtual sensors to two controllers
+//!
+//! Rigs with dual controllers can keep both fed with the same values, so
+//! losing one doesn't leave its fans without a control source.
+use crate::error::Result;
+use crate::Octo;
+
+/// Change in a mirrored device's health
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirrorEvent {
+    /// A device stopped accepting reports
+    Failed {
+        /// Index of the device, 0 for the primary
+        device: usize,
+        /// Error the write failed with
+        error: String,
+    },
+    /// A device reads back different values than were written
+    Diverged {
+        /// Index of the device, 0 for the primary
+        device: usize,
+        /// First slot that differs
+        slot: usize,
+        /// Value written, in centidegrees
+        written: Option<i16>,
+        /// Value read back, in centidegrees
+        read: Option<i16>,
+    },
+    /// A failed or diverged device is back in sync
+    Recovered {
+        /// Index of the device, 0 for the primary
+        device: usize,
+    },
+}
+
+/// What is wrong with a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    Write,
+    Readback,
+}
+
+struct Member {
+    octo: Octo,
+    fault: Option<Fault>,
+}
+
+/// Two devices written with the same values
+///
+/// Every update goes to both devices, including one that has failed, so
+/// it picks up again as soon as it answers. Health changes are queued as
+/// [`MirrorEvent`]s for [`Mirror::take_events`].
+///
+/// ```no_run
+/// use octo_virtual_sensors::{layout::OCTO, mirror::Mirror, Octo, UsbTransport};
+/// let devices = rusb::devices().unwrap();
+/// let mut octos = devices
+///     .iter()
+///     .filter(|device| {
+///         let descriptor = device.device_descriptor().unwrap();
+///         (descriptor.vendor_id(), descriptor.product_id()) == (OCTO.vendor_id, OCTO.product_id)
+///     })
+///     .map(|device| Octo::with_transport(UsbTransport::new(device)).unwrap());
+/// let mut mirror = Mirror::new(octos.next().unwrap(), octos.next().unwrap());
+/// mirror.update_virtual_sensors(&[42]).unwrap();
+/// for event in mirror.take_events() {
+///     eprintln!("{event:?}");
+/// }
+/// ```
+pub struct Mirror {
+    members: [Member; 2],
+    events: Vec<MirrorEvent>,
+}
+
+impl Mirror {
+    /// Mirror updates across two opened devices
+    pub fn new(primary: Octo, secondary: Octo) -> Self {
+        let member = |octo| Member { octo, fault: None };
+        Self {
+            members: [member(primary), member(secondary)],
+            events: Vec::new(),
+        }
+    }
+
+    /// One of the devices, 0 for the primary
+    pub fn device(&self, device: usize) -> Option<&Octo> {
+        self.members.get(device).map(|member| &member.octo)
+    }
+
+    /// Whether a device accepted the last update and matched the last
+    /// readback
+    pub fn is_healthy(&self, device: usize) -> bool {
+        self.members
+            .get(device)
+            .is_some_and(|member| member.fault.is_none())
+    }
+
+    /// Update the virtual sensors on both devices
+    ///
+    /// Returns how many devices accepted the update. Fails only if neither
+    /// did.
+    pub fn update_virtual_sensors(&mut self, sensor_values: &[i16]) -> Result<usize> {
+        let mut updated = 0;
+        let mut last_error = None;
+        for device in 0..self.members.len() {
+            match self.members[device]
+                .octo
+                .update_virtual_sensors(sensor_values)
+            {
+                Ok(_) => {
+                    updated += 1;
+                    if self.members[device].fault == Some(Fault::Write) {
+                        self.set_fault(device, None, MirrorEvent::Recovered { device });
+                    }
+                }
+                Err(error) => {
+                    let event = MirrorEvent::Failed {
+                        device,
+                        error: format!("{error:#}"),
+                    };
+                    self.set_fault(device, Some(Fault::Write), event);
+                    last_error = Some(error);
+                }
+            }
+        }
+        match last_error {
+            Some(error) if updated == 0 => {
+                Err(error.context("No mirrored device accepted the update"))
+            }
+            _ => Ok(updated),
+        }
+    }
+
+    /// Cross-check each device's readback against what was last written
+    ///
+    /// Devices whose last write failed are skipped. The readback lags the
+    /// write by up to one status report, so call this a while after
+    /// updating rather than straight away.
+    pub fn verify(&mut self) {
+        for device in 0..self.members.len() {
+            let member = &mut self.members[device];
+            if member.fault == Some(Fault::Write) {
+                continue;
+            }
+            let status = match member.octo.read_status() {
+                Ok(status) => status,
+                Err(error) => {
+                    let event = MirrorEvent::Failed {
+                        device,
+                        error: format!("{error:#}"),
+                    };
+                    self.set_fault(device, Some(Fault::Write), event);
+                    continue;
+                }
+            };
+            let written = member.octo.last_report().values();
+            let difference = written
+                .iter()
+                .zip(&status.virtual_sensors)
+                .position(|(written, read)| written != read);
+            match difference {
+                Some(slot) => {
+                    let event = MirrorEvent::Diverged {
+                        device,
+                        slot,
+                        written: written[slot],
+                        read: status.virtual_sensors[slot],
+                    };
+                    self.set_fault(device, Some(Fault::Readback), event);
+                }
+                None if member.fault.is_some() => {
+                    self.set_fault(device, None, MirrorEvent::Recovered { device });
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Health changes since the last call
+    pub fn take_events(&mut self) -> Vec<MirrorEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Record a device's fault, queueing `event` if the fault changed
+    fn set_fault(&mut self, device: usize, fault: Option<Fault>, event: MirrorEvent) {
+        let member = &mut self.members[device];
+        if member.fault != fault {
+            member.fault = fault;
+            self.events.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mirror, MirrorEvent};
+    use crate::{emulator::Emulator, Octo};
+    use std::time::Duration;
+
+    /// A mirror over two emulators, returned alongside them
+    fn mirror() -> (Mirror, Emulator, Emulator) {
+        let (primary, secondary) = (Emulator::new(), Emulator::new());
+        let mirror = Mirror::new(
+            Octo::with_transport(primary.clone()).unwrap(),
+            Octo::with_transport(secondary.clone()).unwrap(),
+        );
+        (mirror, primary, secondary)
+    }
+
+    /// Both devices get every update
+    #[test]
+    fn writes_both() {
+        let (mut mirror, primary, secondary) = mirror();
+        assert_eq!(mirror.update_virtual_sensors(&[30, 31]).unwrap(), 2);
+        assert_eq!(primary.virtual_sensors(), secondary.virtual_sensors());
+        assert_eq!(secondary.virtual_sensors()[1], Some(3100));
+    }
+
+    /// A failing device raises one event and the other keeps updating
+    #[test]
+    fn failover() {
+        let (mut mirror, primary, secondary) = mirror();
+        primary.disconnect();
+        assert_eq!(mirror.update_virtual_sensors(&[40]).unwrap(), 1);
+        assert_eq!(mirror.update_virtual_sensors(&[41]).unwrap(), 1);
+        assert_eq!(secondary.virtual_sensors()[0], Some(4100));
+        assert!(!mirror.is_healthy(0));
+        let events = mirror.take_events();
+        assert!(matches!(
+            events[..],
+            [MirrorEvent::Failed { device: 0, .. }]
+        ));
+
+        primary.reconnect();
+        assert_eq!(mirror.update_virtual_sensors(&[42]).unwrap(), 2);
+        assert_eq!(mirror.take_events(), [MirrorEvent::Recovered { device: 0 }]);
+        assert!(mirror.is_healthy(0));
+
+        secondary.disconnect();
+        primary.disconnect();
+        assert!(mirror.update_virtual_sensors(&[43]).is_err());
+    }
+
+    /// Readback that doesn't match what was written is reported
+    #[test]
+    fn divergence() {
+        let primary = Emulator::new();
+        let secondary = Emulator::new().with_virtual_sensor_timeout(Duration::ZERO);
+        let mut mirror = Mirror::new(
+            Octo::with_transport(primary).unwrap(),
+            Octo::with_transport(secondary).unwrap(),
+        );
+        mirror.update_virtual_sensors(&[25]).unwrap();
+        mirror.verify();
+        let diverged = MirrorEvent::Diverged {
+            device: 1,
+            slot: 0,
+            written: Some(2500),
+            read: None,
+        };
+        assert_eq!(mirror.take_events(), [diverged]);
+        assert!(mirror.is_healthy(0));
+        assert!(!mirror.is_healthy(1));
+    }
+}
@@ -0,0 +1,179 @@
+//! A transport that records what it's given
+//!
+//! Where the [`Emulator`](crate::emulator::Emulator) behaves like an Octo,
+//! [`MockTransport`] only captures the bytes written to it and plays back
+//! queued reads, for tests of exactly what goes on the wire.
+//!
+//! ```
+//! use octo_virtual_sensors::{mock::MockTransport, Octo, VirtualSensorReport};
+//! let mock = MockTransport::new();
+//! let mut octo = Octo::with_transport(mock.clone()).unwrap();
+//! octo.update_virtual_sensors(&[42]).unwrap();
+//! let mut expected = VirtualSensorReport::default();
+//! expected.update(&[42]);
+//! assert_eq!(mock.written(), [expected.as_bytes()]);
+//! ```
+//!
+//! Only built with the `emulator` feature.
+use crate::error::Result;
+use crate::{OctoError, Transport};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+/// Transport capturing writes and answering reads from a queue
+///
+/// Clones share their state, so a test can keep one and hand another to
+/// [`Octo`](crate::Octo). Reads with nothing queued time out.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<State>>,
+}
+
+/// Everything a [`MockTransport`] has seen or will answer
+#[derive(Debug, Default)]
+struct State {
+    written: Vec<Vec<u8>>,
+    feature_written: Vec<Vec<u8>>,
+    reads: VecDeque<Vec<u8>>,
+    feature_reads: VecDeque<Vec<u8>>,
+    write_delay: Duration,
+}
+
+impl MockTransport {
+    /// Transport with nothing written or queued
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Output reports written so far, oldest first
+    pub fn written(&self) -> Vec<Vec<u8>> {
+        self.lock().written.clone()
+    }
+
+    /// Feature reports written so far, oldest first
+    pub fn feature_reports_written(&self) -> Vec<Vec<u8>> {
+        self.lock().feature_written.clone()
+    }
+
+    /// Forget everything written so far
+    pub fn clear_written(&self) {
+        let mut state = self.lock();
+        state.written.clear();
+        state.feature_written.clear();
+    }
+
+    /// Answer a future input report read with `report`
+    pub fn queue_read(&self, report: impl Into<Vec<u8>>) {
+        self.lock().reads.push_back(report.into());
+    }
+
+    /// Answer a future read of this feature report, identified by its first byte
+    pub fn queue_feature_report(&self, report: impl Into<Vec<u8>>) {
+        self.lock().feature_reads.push_back(report.into());
+    }
+
+    /// Make every output report write take `delay`, as a slow device would
+    pub fn set_write_delay(&self, delay: Duration) {
+        self.lock().write_delay = delay;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Copy as much of `report` as fits into `buf`
+fn copy_into(buf: &mut [u8], report: &[u8]) -> usize {
+    let len = report.len().min(buf.len());
+    buf[..len].copy_from_slice(&report[..len]);
+    len
+}
+
+impl Transport for MockTransport {
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        let delay = self.lock().write_delay;
+        thread::sleep(delay);
+        self.lock().written.push(report.to_vec());
+        Ok(report.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let report = self.lock().reads.pop_front().ok_or(OctoError::Timeout)?;
+        Ok(copy_into(buf, &report))
+    }
+
+    /// Answer with the first queued report with the requested ID, or stall
+    fn read_feature_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut state = self.lock();
+        let id = buf.first().copied();
+        let index = state
+            .feature_reads
+            .iter()
+            .position(|report| report.first().copied() == id)
+            .ok_or(OctoError::Usb(rusb::Error::Pipe))?;
+        let report = state.feature_reads.remove(index).unwrap_or_default();
+        Ok(copy_into(buf, &report))
+    }
+
+    fn write_feature_report(&mut self, report: &[u8]) -> Result<usize> {
+        self.lock().feature_written.push(report.to_vec());
+        Ok(report.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MockTransport;
+    use crate::{
+        control::ControlReport, emulator::Emulator, layout::OCTO, Octo, OctoError, Transport,
+        VirtualSensorReport,
+    };
+
+    /// Opening without answers falls back to the built-in layout
+    #[test]
+    fn silent_device() {
+        let mock = MockTransport::new();
+        let mut octo = Octo::with_transport(mock.clone()).unwrap();
+        assert_eq!(octo.firmware(), None);
+        octo.update_centidegrees(&[Some(4150), None, Some(-250)])
+            .unwrap();
+        let mut expected = VirtualSensorReport::default();
+        expected.set_values(&[Some(4150), None, Some(-250)]);
+        assert_eq!(mock.written(), [expected.as_bytes()]);
+        let error = octo.read_status().unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Timeout));
+    }
+
+    /// Queued reports are read back in order
+    #[test]
+    fn queued_reads() {
+        let mock = MockTransport::new();
+        mock.queue_read(Emulator::new().with_firmware(1120).status_report());
+        let octo = Octo::with_transport(mock.clone()).unwrap();
+        assert_eq!(octo.firmware(), Some(1120));
+        let mut buf = [0; 4];
+        let mut transport = mock.clone();
+        assert!(transport.read_report(&mut buf).is_err());
+        mock.clear_written();
+        assert!(mock.written().is_empty());
+    }
+
+    /// Feature reports are answered by ID and captured when written
+    #[test]
+    fn feature_reports() {
+        let mock = MockTransport::new();
+        let control = ControlReport::new(OCTO.control);
+        mock.queue_feature_report(control.as_bytes());
+        let mut octo = Octo::with_transport(mock.clone()).unwrap();
+        octo.set_fan_power(0, 50.0).unwrap();
+        let written = mock.feature_reports_written();
+        assert_eq!(written.len(), 1);
+        let written = ControlReport::parse(OCTO.control, &written[0]).unwrap();
+        assert_eq!(written.fan(0).unwrap().duty, 5000);
+        assert!(octo.read_control().is_err());
+    }
+}
@@ -0,0 +1,747 @@
+//! MQTT bridge for Home Assistant and other smart-home setups
+//!
+//! [`Mqtt`] is a small MQTT 3.1.1 client. [`Mqtt::source`] subscribes to a
+//! topic and turns the temperatures published there into a [`Source`] for a
+//! virtual sensor, and [`Mqtt::publish_status`] publishes the device's
+//! telemetry along with Home Assistant discovery payloads, so the Octo shows
+//! up as a device without any YAML:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{daemon::SyncEngine, mqtt::MqttOptions, units::Unit, Octo};
+//! let mut mqtt = MqttOptions::new("localhost:1883", "octo").connect().unwrap();
+//! let room = mqtt.source("home/livingroom/temperature", Unit::Celsius).unwrap();
+//! let mut engine = SyncEngine::new(Octo::new().unwrap())
+//!     .with_source(0, room)
+//!     .with_mqtt(mqtt);
+//! engine.run_until(|| false);
+//! ```
+//!
+//! Topics may be filters with `+` and `#` wildcards, in which case the
+//! latest value on any matching topic is used. Readings the device doesn't
+//! have, such as a disconnected sensor, are published as `null` and show
+//! as unavailable in Home Assistant.
+//!
+//! Messages are sent and received at QoS 0. The connection isn't made
+//! again if the broker goes away; sources then go stale and publish
+//! nothing.
+//!
+//! Only built with the `mqtt` feature.
+use crate::error::{Context, Result};
+use crate::{source::Source, status::Status, units::Unit, OctoError};
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Port brokers listen on when the address doesn't say
+static DEFAULT_PORT: u16 = 1883;
+
+/// MQTT 3.1.1
+static PROTOCOL_LEVEL: u8 = 4;
+
+/// How long the broker may take to accept the connection
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long received values are used for when nothing new arrives
+static DEFAULT_MAX_AGE: Duration = Duration::from_secs(300);
+
+static CONNECT: u8 = 1;
+static CONNACK: u8 = 2;
+static PUBLISH: u8 = 3;
+static SUBSCRIBE: u8 = 8;
+static SUBACK: u8 = 9;
+static PINGREQ: u8 = 12;
+static DISCONNECT: u8 = 14;
+
+/// Latest payload received on each subscribed topic
+type Readings = Arc<Mutex<HashMap<String, (String, Instant)>>>;
+
+/// Lock `mutex`, whatever a panicking thread left behind
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Where and how to connect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttOptions {
+    broker: String,
+    node_id: String,
+    credentials: Option<(String, String)>,
+    topic_prefix: String,
+    discovery_prefix: String,
+    keep_alive: Duration,
+}
+
+impl MqttOptions {
+    /// Connect to `broker`, `host` or `host:port`, as device `node_id`
+    ///
+    /// The node ID names the device in topics and in Home Assistant, so
+    /// it should be unique on the broker, e.g. [`DeviceInfo::serial`](crate::DeviceInfo::serial).
+    /// Telemetry goes to `octo/<node_id>` and discovery payloads under
+    /// `homeassistant`.
+    pub fn new(broker: impl Into<String>, node_id: impl Into<String>) -> Self {
+        let node_id: String = node_id.into();
+        Self {
+            broker: broker.into(),
+            topic_prefix: format!("octo/{node_id}"),
+            node_id,
+            credentials: None,
+            discovery_prefix: "homeassistant".to_owned(),
+            keep_alive: Duration::from_secs(30),
+        }
+    }
+
+    /// Log in with a user name and password
+    pub fn with_credentials(
+        mut self,
+        user: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Publish telemetry under `prefix` instead of `octo/<node_id>`
+    pub fn with_topic_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.topic_prefix = prefix.into();
+        self
+    }
+
+    /// Publish discovery payloads under `prefix` instead of `homeassistant`
+    pub fn with_discovery_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.discovery_prefix = prefix.into();
+        self
+    }
+
+    /// Connect to the broker
+    pub fn connect(self) -> Result<Mqtt> {
+        Mqtt::connect(self)
+    }
+
+    /// Topic telling Home Assistant whether the bridge is online
+    fn availability_topic(&self) -> String {
+        format!("{}/availability", self.topic_prefix)
+    }
+
+    /// Topic the telemetry is published on
+    fn state_topic(&self) -> String {
+        format!("{}/state", self.topic_prefix)
+    }
+}
+
+/// Connection to an MQTT broker
+///
+/// Received messages are handled on a thread of their own, which also keeps
+/// the connection alive. Dropping the connection disconnects cleanly.
+pub struct Mqtt {
+    options: MqttOptions,
+    stream: Arc<Mutex<TcpStream>>,
+    readings: Readings,
+    packet_id: u16,
+    discovered: bool,
+}
+
+impl Mqtt {
+    /// Connect as `options` say and start handling messages
+    fn connect(options: MqttOptions) -> Result<Self> {
+        let address = if options.broker.contains(':') {
+            options.broker.clone()
+        } else {
+            format!("{}:{DEFAULT_PORT}", options.broker)
+        };
+        let mut stream =
+            TcpStream::connect(&address).with_context(|| format!("Connecting to {address}"))?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        write_packet(&mut stream, CONNECT << 4, &connect_body(&options)?)
+            .context("Sending CONNECT")?;
+        let (header, body) = read_packet(&mut stream).context("Waiting for CONNACK")?;
+        if header >> 4 != CONNACK {
+            bail!("Broker answered CONNECT with packet type {}", header >> 4);
+        }
+        match body.get(1) {
+            Some(0) => {}
+            Some(code) => bail!("Broker refused the connection: {}", refusal(*code)),
+            None => bail!("Malformed CONNACK"),
+        }
+        stream.set_read_timeout(None)?;
+        let readings = Readings::default();
+        let reader = stream.try_clone().context("Cloning the connection")?;
+        {
+            let readings = readings.clone();
+            thread::spawn(move || receive(reader, readings));
+        }
+        let stream = Arc::new(Mutex::new(stream));
+        {
+            let stream = Arc::downgrade(&stream);
+            let interval = options.keep_alive / 2;
+            thread::spawn(move || keep_alive(stream, interval));
+        }
+        let mqtt = Self {
+            options,
+            stream,
+            readings,
+            packet_id: 0,
+            discovered: false,
+        };
+        mqtt.publish(&mqtt.options.availability_topic(), "online", true)?;
+        Ok(mqtt)
+    }
+
+    /// Subscribe to temperatures published on `topic`, in `unit`
+    ///
+    /// Payloads are plain numbers, as Home Assistant sensor states are.
+    /// Anything else, such as `unavailable`, publishes nothing. `topic` may
+    /// be a filter such as `home/+/temperature`; one with a misplaced
+    /// wildcard fails with [`OctoError::InvalidConfig`].
+    pub fn source(&mut self, topic: &str, unit: Unit) -> Result<MqttSource> {
+        check_filter(topic)?;
+        self.packet_id = self.packet_id.wrapping_add(1).max(1);
+        let mut body = self.packet_id.to_be_bytes().to_vec();
+        put_string(&mut body, topic)?;
+        body.push(0);
+        write_packet(&mut *lock(&self.stream), SUBSCRIBE << 4 | 0b10, &body)
+            .with_context(|| format!("Subscribing to {topic}"))?;
+        Ok(MqttSource {
+            topic: topic.to_owned(),
+            readings: self.readings.clone(),
+            unit,
+            max_age: DEFAULT_MAX_AGE,
+        })
+    }
+
+    /// Publish `payload` on `topic`
+    pub fn publish(&self, topic: &str, payload: &str, retain: bool) -> Result<()> {
+        let mut body = Vec::new();
+        put_string(&mut body, topic)?;
+        body.extend_from_slice(payload.as_bytes());
+        write_packet(
+            &mut *lock(&self.stream),
+            PUBLISH << 4 | u8::from(retain),
+            &body,
+        )
+        .with_context(|| format!("Publishing to {topic}"))
+    }
+
+    /// Publish the readings in `status` as one JSON state message
+    ///
+    /// The first call also publishes the Home Assistant discovery payloads,
+    /// retained, for every reading in it.
+    pub fn publish_status(&mut self, status: &Status) -> Result<()> {
+        let entities = entities(status);
+        if !self.discovered {
+            for entity in &entities {
+                let topic = format!(
+                    "{}/sensor/{}/{}/config",
+                    self.options.discovery_prefix, self.options.node_id, entity.key
+                );
+                self.publish(&topic, &self.discovery(entity), true)?;
+            }
+            self.discovered = true;
+        }
+        let state: Map<String, Value> = entities
+            .iter()
+            .map(|entity| {
+                // JSON has no NaN or infinity, and neither is a reading
+                let value = entity.value.filter(|value| value.is_finite());
+                (entity.key.clone(), json!(value))
+            })
+            .collect();
+        self.publish(
+            &self.options.state_topic(),
+            &Value::Object(state).to_string(),
+            false,
+        )
+    }
+
+    /// Home Assistant discovery payload for `entity`
+    ///
+    /// The entity is available while the bridge is online and its value
+    /// in the state message isn't `null`.
+    fn discovery(&self, entity: &Entity) -> String {
+        let node = &self.options.node_id;
+        let key = &entity.key;
+        let mut payload = json!({
+            "name": entity.name,
+            "unique_id": format!("octo_{node}_{key}"),
+            "state_topic": self.options.state_topic(),
+            "value_template": format!("{{{{ value_json.{key} }}}}"),
+            "availability": [
+                { "topic": self.options.availability_topic() },
+                {
+                    "topic": self.options.state_topic(),
+                    "value_template": format!(
+                        "{{{{ 'offline' if value_json.{key} is none else 'online' }}}}"
+                    ),
+                },
+            ],
+            "availability_mode": "all",
+            "unit_of_measurement": entity.unit,
+            "state_class": "measurement",
+            "device": {
+                "identifiers": [format!("octo_{node}")],
+                "name": "Octo",
+                "manufacturer": "Aqua Computer",
+                "model": "Octo",
+            },
+        });
+        if let Some(class) = entity.device_class {
+            payload["device_class"] = json!(class);
+        }
+        payload.to_string()
+    }
+}
+
+impl Drop for Mqtt {
+    fn drop(&mut self) {
+        let _ = self.publish(&self.options.availability_topic(), "offline", true);
+        let mut stream = lock(&self.stream);
+        let _ = write_packet(&mut *stream, DISCONNECT << 4, &[]);
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Values received on a subscribed topic, from [`Mqtt::source`]
+#[derive(Debug, Clone)]
+pub struct MqttSource {
+    topic: String,
+    readings: Readings,
+    unit: Unit,
+    max_age: Duration,
+}
+
+impl MqttSource {
+    /// Publish nothing once the last value is older than `max_age`
+    ///
+    /// Five minutes by default, so a sensor that stops reporting doesn't
+    /// hold its last temperature forever.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+}
+
+impl Source for MqttSource {
+    fn name(&self) -> &str {
+        &self.topic
+    }
+
+    fn read(&mut self) -> Result<Option<i16>> {
+        let readings = lock(&self.readings);
+        let latest = match readings.get(&self.topic) {
+            Some(reading) => Some(reading),
+            None => readings
+                .iter()
+                .filter(|(topic, _)| matches(&self.topic, topic))
+                .map(|(_, reading)| reading)
+                .max_by_key(|(_, received)| *received),
+        };
+        let Some((payload, received)) = latest else {
+            return Ok(None);
+        };
+        if received.elapsed() > self.max_age {
+            return Ok(None);
+        }
+        match payload.trim().parse() {
+            Ok(degrees) => self.unit.centidegrees(degrees).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// One reading offered to Home Assistant
+struct Entity {
+    key: String,
+    name: String,
+    unit: &'static str,
+    device_class: Option<&'static str>,
+    value: Option<f64>,
+}
+
+/// Every reading in `status`
+fn entities(status: &Status) -> Vec<Entity> {
+    let degrees = |value: Option<i16>| value.map(|value| f64::from(value) / 100.0);
+    let mut entities = Vec::new();
+    for (index, value) in status.sensors.iter().enumerate() {
+        entities.push(Entity {
+            key: format!("temperature{}", index + 1),
+            name: format!("Temperature {}", index + 1),
+            unit: "°C",
+            device_class: Some("temperature"),
+            value: degrees(*value),
+        });
+    }
+    for (index, value) in status.virtual_sensors.iter().enumerate() {
+        entities.push(Entity {
+            key: format!("virtual{}", index + 1),
+            name: format!("Virtual sensor {}", index + 1),
+            unit: "°C",
+            device_class: Some("temperature"),
+            value: degrees(*value),
+        });
+    }
+    if let Some(flow) = status.flow {
+        entities.push(Entity {
+            key: "flow".to_owned(),
+            name: "Flow".to_owned(),
+            unit: "L/h",
+            device_class: None,
+            value: Some(f64::from(flow) / 10.0),
+        });
+    }
+    for (index, fan) in status.fans.iter().enumerate() {
+        let fan_entity = |key: &str, name: &str, unit, device_class, value: f64| Entity {
+            key: format!("fan{}_{key}", index + 1),
+            name: format!("Fan {} {name}", index + 1),
+            unit,
+            device_class,
+            value: Some(value),
+        };
+        entities.push(fan_entity(
+            "speed",
+            "speed",
+            "RPM",
+            None,
+            f64::from(fan.rpm),
+        ));
+        entities.push(fan_entity(
+            "duty",
+            "duty",
+            "%",
+            None,
+            f64::from(fan.duty) / 100.0,
+        ));
+        entities.push(fan_entity(
+            "power",
+            "power",
+            "W",
+            Some("power"),
+            f64::from(fan.power) / 100.0,
+        ));
+    }
+    entities
+}
+
+/// Fail unless `filter` is a valid topic filter
+///
+/// `#` may only be a whole last level and `+` only a whole level.
+fn check_filter(filter: &str) -> Result<()> {
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (index, level) in levels.iter().enumerate() {
+        let misplaced = match *level {
+            "#" => index + 1 != levels.len(),
+            "+" => false,
+            level => level.contains(['#', '+']),
+        };
+        if filter.is_empty() || misplaced {
+            return Err(OctoError::InvalidConfig).with_context(|| {
+                format!(
+                    "{filter:?} isn't an MQTT topic filter: \
+                     + and # must be whole levels, and # the last one"
+                )
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether `topic` matches the filter `filter`
+///
+/// Wildcards at the start don't match topics starting with `$`, which
+/// brokers keep for their own.
+fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut topic = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(name)) if level == name => {}
+            _ => return false,
+        }
+    }
+    topic.next().is_none()
+}
+
+/// Variable header and payload of a CONNECT packet
+fn connect_body(options: &MqttOptions) -> Result<Vec<u8>> {
+    // Clean session, and a retained "offline" will for Home Assistant
+    let mut flags = 0b0010_0110;
+    if options.credentials.is_some() {
+        flags |= 0b1100_0000;
+    }
+    let mut body = Vec::new();
+    put_string(&mut body, "MQTT")?;
+    body.push(PROTOCOL_LEVEL);
+    body.push(flags);
+    let keep_alive = u16::try_from(options.keep_alive.as_secs()).unwrap_or(u16::MAX);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    put_string(&mut body, &format!("octo-vs-{}", options.node_id))?;
+    put_string(&mut body, &options.availability_topic())?;
+    put_string(&mut body, "offline")?;
+    if let Some((user, password)) = &options.credentials {
+        put_string(&mut body, user)?;
+        put_string(&mut body, password)?;
+    }
+    Ok(body)
+}
+
+/// Why the broker refused a connection, from its CONNACK return code
+fn refusal(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client ID rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorised",
+        _ => "unknown reason",
+    }
+}
+
+/// Append a length-prefixed UTF-8 string
+fn put_string(body: &mut Vec<u8>, string: &str) -> Result<()> {
+    let len = u16::try_from(string.len()).context("String too long for MQTT")?;
+    body.extend_from_slice(&len.to_be_bytes());
+    body.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+/// Write one packet with its fixed header
+fn write_packet(stream: &mut impl Write, header: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    stream.write_all(&packet)
+}
+
+/// Read one packet's fixed header byte and body
+fn read_packet(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+    let mut len = 0;
+    for shift in 0..4 {
+        stream.read_exact(&mut byte)?;
+        len |= usize::from(byte[0] & 0x7f) << (7 * shift);
+        if byte[0] & 0x80 == 0 {
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body)?;
+            return Ok((header, body));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Malformed remaining length",
+    ))
+}
+
+/// Topic and payload of a QoS 0 PUBLISH body
+fn parse_publish(header: u8, body: &[u8]) -> Option<(String, String)> {
+    let (len, rest) = body.split_first_chunk::<2>()?;
+    let (topic, mut payload) = rest.split_at_checked(usize::from(u16::from_be_bytes(*len)))?;
+    if header & 0b0110 != 0 {
+        // QoS 1 and 2 carry a packet ID first
+        payload = payload.get(2..)?;
+    }
+    Some((
+        String::from_utf8_lossy(topic).into_owned(),
+        String::from_utf8_lossy(payload).into_owned(),
+    ))
+}
+
+/// Handle packets from the broker until the connection closes
+fn receive(mut stream: TcpStream, readings: Readings) {
+    loop {
+        let (header, body) = match read_packet(&mut stream) {
+            Ok(packet) => packet,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::UnexpectedEof {
+                    warn!("MQTT connection lost: {error}");
+                }
+                return;
+            }
+        };
+        if header >> 4 == PUBLISH {
+            if let Some((topic, payload)) = parse_publish(header, &body) {
+                lock(&readings).insert(topic, (payload, Instant::now()));
+            }
+        } else if header >> 4 == SUBACK && body.get(2) == Some(&0x80) {
+            warn!("MQTT broker refused a subscription");
+        }
+    }
+}
+
+/// Ping the broker every `interval` while the connection is open
+fn keep_alive(stream: Weak<Mutex<TcpStream>>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(stream) = stream.upgrade() else {
+            return;
+        };
+        if write_packet(&mut *lock(&stream), PINGREQ << 4, &[]).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        check_filter, matches, read_packet, write_packet, MqttOptions, CONNACK, PUBLISH, SUBACK,
+    };
+    use crate::{emulator::Emulator, source::Source, units::Unit, Octo};
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    /// Long remaining lengths round trip
+    #[test]
+    fn remaining_length() {
+        let body = vec![7; 20_000];
+        let mut packet = Vec::new();
+        write_packet(&mut packet, 0x30, &body).unwrap();
+        assert_eq!(packet[1..4], [0xa0, 0x9c, 0x01]);
+        let (header, read) = read_packet(&mut packet.as_slice()).unwrap();
+        assert_eq!(header, 0x30);
+        assert_eq!(read, body);
+    }
+
+    /// Wait for the next PUBLISH from the client
+    fn next_publish(stream: &mut TcpStream) -> (String, String) {
+        loop {
+            let (header, body) = read_packet(stream).unwrap();
+            if header >> 4 == PUBLISH {
+                return super::parse_publish(header, &body).unwrap();
+            }
+        }
+    }
+
+    /// Subscribed temperatures become a source and telemetry is published
+    #[test]
+    fn bridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (header, _) = read_packet(&mut stream).unwrap();
+            assert_eq!(header >> 4, 1);
+            write_packet(&mut stream, CONNACK << 4, &[0, 0]).unwrap();
+            assert_eq!(
+                next_publish(&mut stream),
+                ("octo/test/availability".into(), "online".into())
+            );
+            let (header, body) = read_packet(&mut stream).unwrap();
+            assert_eq!(header, 0x82);
+            write_packet(&mut stream, SUBACK << 4, &[body[0], body[1], 0]).unwrap();
+            let mut publish = vec![0, 4];
+            publish.extend_from_slice(b"room21.5");
+            write_packet(&mut stream, PUBLISH << 4, &publish).unwrap();
+            let mut published = Vec::new();
+            loop {
+                let (topic, payload) = next_publish(&mut stream);
+                let done = topic == "octo/test/state";
+                published.push((topic, payload));
+                if done {
+                    return published;
+                }
+            }
+        });
+        let mut mqtt = MqttOptions::new(address, "test").connect().unwrap();
+        let mut source = mqtt.source("room", Unit::Celsius).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while source.read().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "no value received");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(source.read().unwrap(), Some(2150));
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator).unwrap();
+        octo.update_virtual_sensors(&[40]).unwrap();
+        mqtt.publish_status(&octo.read_status().unwrap()).unwrap();
+        let published = broker.join().unwrap();
+        let (topic, discovery) = &published[0];
+        assert_eq!(topic, "homeassistant/sensor/test/temperature1/config");
+        let discovery: serde_json::Value = serde_json::from_str(discovery).unwrap();
+        assert_eq!(discovery["unique_id"], "octo_test_temperature1");
+        assert_eq!(discovery["device_class"], "temperature");
+        assert_eq!(discovery["availability_mode"], "all");
+        let (_, state) = published.last().unwrap();
+        let state: serde_json::Value = serde_json::from_str(state).unwrap();
+        assert_eq!(state["virtual1"], 40.0);
+        assert_eq!(state["virtual2"], serde_json::Value::Null);
+        assert!(state["fan1_speed"].is_number());
+    }
+
+    /// Wildcards match whole levels, and misplaced ones are refused
+    #[test]
+    fn filters() {
+        assert!(matches("home/+/temperature", "home/kitchen/temperature"));
+        assert!(!matches("home/+/temperature", "home/kitchen/humidity"));
+        assert!(!matches("home/+", "home/kitchen/temperature"));
+        assert!(matches("home/#", "home/kitchen/temperature"));
+        assert!(matches("home/#", "home"));
+        assert!(matches("#", "home/kitchen"));
+        assert!(!matches("#", "$SYS/uptime"));
+        assert!(matches("room", "room"));
+        assert!(!matches("room", "room/1"));
+        for filter in ["home/+/temperature", "#", "home/#", "+"] {
+            assert!(check_filter(filter).is_ok(), "{filter}");
+        }
+        for filter in ["home/#/temperature", "home/kit+chen", "home#", ""] {
+            let error = check_filter(filter).unwrap_err();
+            assert_eq!(error.kind(), Some(crate::OctoError::InvalidConfig));
+        }
+    }
+
+    /// Stale and non-numeric payloads publish nothing, and filters read
+    /// matching topics
+    #[test]
+    fn stale() {
+        let mut source = super::MqttSource {
+            topic: "room".into(),
+            readings: Default::default(),
+            unit: Unit::Celsius,
+            max_age: Duration::from_secs(60),
+        };
+        let received = Instant::now();
+        let readings = source.readings.clone();
+        readings
+            .lock()
+            .unwrap()
+            .insert("room".into(), ("unavailable".into(), received));
+        assert_eq!(source.read().unwrap(), None);
+        readings
+            .lock()
+            .unwrap()
+            .insert("room".into(), ("20".into(), received));
+        assert_eq!(source.read().unwrap(), Some(2000));
+        let mut wildcard = super::MqttSource {
+            topic: "home/+/temperature".into(),
+            ..source.clone()
+        };
+        assert_eq!(wildcard.read().unwrap(), None);
+        readings
+            .lock()
+            .unwrap()
+            .insert("home/kitchen/temperature".into(), ("22".into(), received));
+        assert_eq!(wildcard.read().unwrap(), Some(2200));
+        source = source.with_max_age(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(source.read().unwrap(), None);
+    }
+}
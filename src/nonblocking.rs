@@ -0,0 +1,388 @@
+//! Non-blocking access to an [`Octo`]
+//!
+//! USB transfers block for up to a second. [`AsyncOcto`] moves the device
+//! onto a thread of its own and hands back futures, so async code doesn't
+//! have to wrap every call in `spawn_blocking`. The futures only use
+//! [`std::task`] and work with any executor, tokio included.
+//!
+//! Calls wait in a bounded [`UpdateQueue`]. By default a call made while 64
+//! are waiting fails at once with [`OctoError::Busy`] rather than blocking
+//! the executor; [`AsyncOcto::with_queue`] picks another size or
+//! [`Backpressure`].
+//!
+//! Only built with the `async` feature.
+use crate::error::Result;
+use crate::{
+    control::TemperatureSource,
+    curve::FanCurve,
+    queue::{Backpressure, Pushed, UpdateQueue},
+    status::Status,
+    DeviceInfo, Octo, OctoError,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+/// Calls [`AsyncOcto::new`] lets wait for the device
+static CAPACITY: usize = 64;
+
+/// Work for the device thread
+type Job = Box<dyn FnOnce(&mut Octo) + Send>;
+
+/// Closes the queue once the last clone is gone, stopping the thread
+/// after the calls still waiting
+struct Jobs(UpdateQueue<Job>);
+
+impl Drop for Jobs {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Fails the calls still waiting if the device thread ends early
+struct Stopped(UpdateQueue<Job>);
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        self.0.close();
+        while self.0.try_pop().is_some() {}
+    }
+}
+
+/// An [`Octo`] driven from its own thread
+///
+/// Calls are queued and run in order. Clones share the device; the thread
+/// stops once every clone is dropped.
+///
+/// ```no_run
+/// use octo_virtual_sensors::{nonblocking::AsyncOcto, Octo};
+/// # async fn publish() -> octo_virtual_sensors::Result<()> {
+/// let octo = AsyncOcto::new(Octo::new()?)?;
+/// octo.update_virtual_sensors(&[42]).await?;
+/// let status = octo.read_status().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncOcto {
+    jobs: Arc<Jobs>,
+}
+
+impl AsyncOcto {
+    /// Move `octo` onto a new device thread
+    ///
+    /// Up to 64 calls wait for the device, later ones fail with
+    /// [`OctoError::Busy`] until it catches up.
+    pub fn new(octo: Octo) -> Result<Self> {
+        Self::with_queue(octo, CAPACITY, Backpressure::DropNewest)
+    }
+
+    /// [`AsyncOcto::new`], letting up to `capacity` calls wait and applying
+    /// `backpressure` when more come in
+    ///
+    /// [`Backpressure::DropOldest`] fails the longest waiting call instead
+    /// of the new one, and [`Backpressure::Block`] holds the caller up,
+    /// which blocks the executor thread it runs on.
+    pub fn with_queue(octo: Octo, capacity: usize, backpressure: Backpressure) -> Result<Self> {
+        let queue = UpdateQueue::<Job>::new(capacity, backpressure);
+        let jobs = queue.clone();
+        thread::Builder::new()
+            .name("octo-vs-device".to_owned())
+            .spawn(move || {
+                let _stopped = Stopped(jobs.clone());
+                let mut octo = octo;
+                while let Some(job) = jobs.pop() {
+                    job(&mut octo);
+                }
+            })?;
+        Ok(Self {
+            jobs: Arc::new(Jobs(queue)),
+        })
+    }
+
+    /// Calls dropped so far because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.jobs.0.dropped()
+    }
+
+    /// Run `call` on the device thread
+    ///
+    /// The escape hatch for everything without its own method here.
+    pub fn run<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(&mut Octo) -> Result<T> + Send + 'static,
+    ) -> Reply<T> {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let completer = Completer {
+            shared: shared.clone(),
+        };
+        let job: Job = Box::new(move |octo| {
+            lock(&completer.shared).started = true;
+            completer.complete(call(octo));
+        });
+        // Dropped jobs drop their completer, failing the reply
+        let error = match self.jobs.0.push(job) {
+            Pushed::Queued | Pushed::DroppedOldest => None,
+            Pushed::DroppedNewest => Some(
+                crate::Error::from(OctoError::Busy)
+                    .context("The device's queue is full, the call was dropped"),
+            ),
+            Pushed::Closed => Some(crate::Error::msg("The device thread stopped")),
+        };
+        if let Some(error) = error {
+            lock(&shared).result = Some(Err(error));
+        }
+        Reply { shared }
+    }
+
+    /// See [`Octo::update_virtual_sensors`]
+    pub fn update_virtual_sensors(&self, sensor_values: &[i16]) -> Reply<usize> {
+        let values = sensor_values.to_vec();
+        self.run(move |octo| octo.update_virtual_sensors(&values))
+    }
+
+    /// See [`Octo::update_virtual_sensors_f32`]
+    pub fn update_virtual_sensors_f32(&self, sensor_values: &[f32]) -> Reply<usize> {
+        let values = sensor_values.to_vec();
+        self.run(move |octo| octo.update_virtual_sensors_f32(&values))
+    }
+
+    /// See [`Octo::update_centidegrees`]
+    pub fn update_centidegrees(&self, values: &[Option<i16>]) -> Reply<usize> {
+        let values = values.to_vec();
+        self.run(move |octo| octo.update_centidegrees(&values))
+    }
+
+    /// See [`Octo::update_slots`]
+    pub fn update_slots(&self, values: &[(usize, Option<i16>)]) -> Reply<usize> {
+        let values = values.to_vec();
+        self.run(move |octo| octo.update_slots(&values))
+    }
+
+    /// See [`Octo::set_virtual_sensor`]
+    pub fn set_virtual_sensor(&self, index: usize, degrees: f32) -> Reply<usize> {
+        self.run(move |octo| octo.set_virtual_sensor(index, degrees))
+    }
+
+    /// See [`Octo::clear_virtual_sensor`]
+    pub fn clear_virtual_sensor(&self, index: usize) -> Reply<usize> {
+        self.run(move |octo| octo.clear_virtual_sensor(index))
+    }
+
+    /// See [`Octo::read_status`]
+    pub fn read_status(&self) -> Reply<Status> {
+        self.run(Octo::read_status)
+    }
+
+    /// See [`Octo::info`]
+    pub fn info(&self) -> Reply<DeviceInfo> {
+        self.run(Octo::info)
+    }
+
+    /// See [`Octo::read_sensors`]
+    pub fn read_sensors(&self) -> Reply<Vec<Option<f32>>> {
+        self.run(Octo::read_sensors)
+    }
+
+    /// See [`Octo::read_flow`]
+    pub fn read_flow(&self) -> Reply<Option<f32>> {
+        self.run(Octo::read_flow)
+    }
+
+    /// See [`Octo::set_flow_calibration`]
+    pub fn set_flow_calibration(&self, pulses: u16) -> Reply<()> {
+        self.run(move |octo| octo.set_flow_calibration(pulses))
+    }
+
+    /// See [`Octo::save_settings`]
+    pub fn save_settings(&self) -> Reply<()> {
+        self.run(Octo::save_settings)
+    }
+
+    /// See [`Octo::emergency_max_fans`]
+    pub fn emergency_max_fans(&self) -> Reply<()> {
+        self.run(Octo::emergency_max_fans)
+    }
+
+    /// See [`Octo::restore`]
+    pub fn restore(&self) -> Reply<()> {
+        self.run(Octo::restore)
+    }
+
+    /// See [`Octo::set_fan_curve`]
+    pub fn set_fan_curve(
+        &self,
+        channel: usize,
+        curve: &FanCurve,
+        source: TemperatureSource,
+    ) -> Reply<()> {
+        let curve = curve.clone();
+        self.run(move |octo| octo.set_fan_curve(channel, &curve, source))
+    }
+}
+
+/// State shared between a [`Reply`] and the job completing it
+struct Shared<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+    started: bool,
+    done: bool,
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Self {
+            result: None,
+            waker: None,
+            started: false,
+            done: false,
+        }
+    }
+}
+
+/// Lock shared state, ignoring poisoning
+fn lock<T>(shared: &Mutex<Shared<T>>) -> MutexGuard<'_, Shared<T>> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Completes a [`Reply`], or fails it when dropped without completing
+struct Completer<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Completer<T> {
+    /// Hand `result` to the reply
+    fn complete(self, result: Result<T>) {
+        lock(&self.shared).result = Some(result);
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let mut shared = lock(&self.shared);
+        shared.done = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Result of a call on the device thread
+///
+/// Fails if the device thread stopped before answering, which only
+/// happens if a call panicked, or if the call was dropped from a full
+/// queue, with [`OctoError::Busy`] when it was the new call.
+pub struct Reply<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = lock(&self.shared);
+        if !shared.done {
+            shared.waker = Some(context.waker().clone());
+            return Poll::Pending;
+        }
+        let started = shared.started;
+        Poll::Ready(shared.result.take().unwrap_or_else(|| {
+            Err(crate::Error::msg(if started {
+                "The device thread stopped"
+            } else {
+                "The call was dropped before it ran"
+            }))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsyncOcto;
+    use crate::{emulator::Emulator, mock::MockTransport, queue::Backpressure, Octo, OctoError};
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+        time::Duration,
+    };
+
+    /// Wakes the thread running [`block_on`]
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor, so the tests don't need a runtime
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    /// Calls run on the device thread and their results come back
+    #[test]
+    fn update_and_read() {
+        let emulator = Emulator::new();
+        let octo = AsyncOcto::new(Octo::with_transport(emulator.clone()).unwrap()).unwrap();
+        block_on(octo.update_virtual_sensors_f32(&[42.5])).unwrap();
+        assert_eq!(emulator.virtual_sensors()[0], Some(4250));
+        let status = block_on(octo.read_status()).unwrap();
+        assert_eq!(status.virtual_sensors[0], Some(4250));
+        emulator.disconnect();
+        assert!(block_on(octo.update_virtual_sensors(&[1])).is_err());
+    }
+
+    /// A panicking call fails its reply and the ones after it
+    #[test]
+    fn device_thread_stops() {
+        let octo = AsyncOcto::new(Octo::with_transport(Emulator::new()).unwrap()).unwrap();
+        let reply = octo.run(|_| -> crate::Result<()> { panic!("bug in a call") });
+        let error = block_on(reply).unwrap_err();
+        assert_eq!(error.to_string(), "The device thread stopped");
+        assert!(block_on(octo.read_sensors()).is_err());
+    }
+
+    /// Calls made while the device is behind fail instead of piling up
+    #[test]
+    fn slow_consumer() {
+        let mock = MockTransport::new();
+        let octo = Octo::with_transport(mock.clone()).unwrap();
+        mock.set_write_delay(Duration::from_millis(20));
+        let octo = AsyncOcto::with_queue(octo, 2, Backpressure::DropNewest).unwrap();
+        let replies: Vec<_> = (1..=10)
+            .map(|value| octo.update_centidegrees(&[Some(value)]))
+            .collect();
+        let results: Vec<_> = replies.into_iter().map(block_on).collect();
+        assert!(results[0].is_ok());
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::Busy));
+        let failed = results.iter().filter(|result| result.is_err()).count();
+        assert_eq!(failed as u64, octo.dropped());
+        assert_eq!(mock.written().len(), 10 - failed);
+
+        let octo = Octo::with_transport(mock.clone()).unwrap();
+        let octo = AsyncOcto::with_queue(octo, 2, Backpressure::DropOldest).unwrap();
+        let replies: Vec<_> = (1..=10)
+            .map(|value| octo.update_centidegrees(&[Some(value)]))
+            .collect();
+        let results: Vec<_> = replies.into_iter().map(block_on).collect();
+        assert!(results.last().unwrap().is_ok());
+        assert!(octo.dropped() > 0);
+    }
+}
@@ -0,0 +1,377 @@
+//! NVIDIA GPU temperatures through NVML
+//!
+//! The proprietary NVIDIA driver doesn't register its GPUs with hwmon, so
+//! [`HwmonSource`](crate::source::HwmonSource) can't see them. An
+//! [`NvmlSource`] reads them through NVML, the management library that
+//! ships with the driver, and publishes them like any other
+//! [`Source`]:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{daemon::SyncEngine, nvml::{GpuSensor, NvmlSource}, Octo};
+//! let mut engine = SyncEngine::new(Octo::new().unwrap())
+//!     .with_source(0, NvmlSource::new(0, GpuSensor::Core).unwrap())
+//!     .with_source(1, NvmlSource::new(0, GpuSensor::Memory).unwrap());
+//! engine.run_until(|| false);
+//! ```
+//!
+//! `libnvidia-ml.so.1` is loaded when the first source is created rather
+//! than linked, so programs built with the `nvml` feature still run on
+//! machines without an NVIDIA driver. NVML has no public query for the
+//! hot spot temperature, so only the core and memory ones are offered.
+use crate::error::{Context, Result};
+use crate::{codec, source::Source};
+use std::{
+    ffi::{c_char, c_uint, c_void, CStr},
+    fmt,
+    str::FromStr,
+    sync::OnceLock,
+};
+
+/// Declarations from `nvml.h`
+mod ffi {
+    use std::ffi::{c_char, c_int, c_uint, c_void};
+
+    /// `nvmlReturn_t`
+    pub type Return = c_int;
+    /// `NVML_SUCCESS`
+    pub const SUCCESS: Return = 0;
+    /// `nvmlDevice_t`
+    pub type Device = *mut c_void;
+    /// `NVML_TEMPERATURE_GPU`
+    pub const TEMPERATURE_GPU: c_int = 0;
+    /// `NVML_FI_DEV_MEMORY_TEMP`
+    pub const FI_DEV_MEMORY_TEMP: c_uint = 82;
+    /// `NVML_DEVICE_NAME_V2_BUFFER_SIZE`
+    pub const DEVICE_NAME_LEN: usize = 96;
+
+    /// `NVML_VALUE_TYPE_DOUBLE`
+    pub const VALUE_TYPE_DOUBLE: c_int = 0;
+    /// `NVML_VALUE_TYPE_UNSIGNED_INT`
+    pub const VALUE_TYPE_UNSIGNED_INT: c_int = 1;
+    /// `NVML_VALUE_TYPE_UNSIGNED_LONG`
+    pub const VALUE_TYPE_UNSIGNED_LONG: c_int = 2;
+    /// `NVML_VALUE_TYPE_UNSIGNED_LONG_LONG`
+    pub const VALUE_TYPE_UNSIGNED_LONG_LONG: c_int = 3;
+    /// `NVML_VALUE_TYPE_SIGNED_LONG_LONG`
+    pub const VALUE_TYPE_SIGNED_LONG_LONG: c_int = 4;
+    /// `NVML_VALUE_TYPE_SIGNED_INT`
+    pub const VALUE_TYPE_SIGNED_INT: c_int = 5;
+
+    /// `nvmlFieldValue_t`
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FieldValue {
+        pub field_id: c_uint,
+        pub scope_id: c_uint,
+        pub timestamp: i64,
+        pub latency_usec: i64,
+        pub value_type: c_int,
+        pub nvml_return: Return,
+        /// `nvmlValue_t`, a union of 8 bytes
+        pub value: [u8; 8],
+    }
+
+    pub type Init = unsafe extern "C" fn() -> Return;
+    pub type Shutdown = unsafe extern "C" fn() -> Return;
+    pub type ErrorString = unsafe extern "C" fn(Return) -> *const c_char;
+    pub type DeviceCount = unsafe extern "C" fn(*mut c_uint) -> Return;
+    pub type DeviceByIndex = unsafe extern "C" fn(c_uint, *mut Device) -> Return;
+    pub type DeviceName = unsafe extern "C" fn(Device, *mut c_char, c_uint) -> Return;
+    pub type Temperature = unsafe extern "C" fn(Device, c_int, *mut c_uint) -> Return;
+    pub type FieldValues = unsafe extern "C" fn(Device, c_int, *mut FieldValue) -> Return;
+}
+
+/// The library the NVIDIA driver installs
+static LIBRARY: &CStr = c"libnvidia-ml.so.1";
+
+/// Which of a GPU's temperatures to read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GpuSensor {
+    /// The core, what `nvidia-smi` shows
+    #[default]
+    Core,
+    /// The memory, only reported by some GPUs
+    Memory,
+}
+
+impl fmt::Display for GpuSensor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Core => "core",
+            Self::Memory => "memory",
+        })
+    }
+}
+
+impl FromStr for GpuSensor {
+    type Err = crate::Error;
+
+    /// Parse `core` or `memory`
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "core" => Ok(Self::Core),
+            "memory" => Ok(Self::Memory),
+            _ => bail!("Unknown GPU sensor {s:?}, expected core or memory"),
+        }
+    }
+}
+
+/// NVML's entry points
+struct Api {
+    init: ffi::Init,
+    shutdown: ffi::Shutdown,
+    error_string: ffi::ErrorString,
+    device_count: ffi::DeviceCount,
+    device_by_index: ffi::DeviceByIndex,
+    device_name: ffi::DeviceName,
+    temperature: ffi::Temperature,
+    field_values: ffi::FieldValues,
+}
+
+impl Api {
+    /// Load `library` and look up the entry points
+    fn load(library: &CStr) -> Result<Self> {
+        // SAFETY: the name is NUL-terminated; the library is never unloaded,
+        // so the entry points stay valid
+        let handle = unsafe { libc::dlopen(library.as_ptr(), libc::RTLD_NOW) };
+        if handle.is_null() {
+            bail!(
+                "Loading {}, is the NVIDIA driver installed? {}",
+                library.to_string_lossy(),
+                dlerror()
+            );
+        }
+        // SAFETY: each type matches the declaration in nvml.h
+        unsafe {
+            Ok(Self {
+                init: symbol(handle, c"nvmlInit_v2")?,
+                shutdown: symbol(handle, c"nvmlShutdown")?,
+                error_string: symbol(handle, c"nvmlErrorString")?,
+                device_count: symbol(handle, c"nvmlDeviceGetCount_v2")?,
+                device_by_index: symbol(handle, c"nvmlDeviceGetHandleByIndex_v2")?,
+                device_name: symbol(handle, c"nvmlDeviceGetName")?,
+                temperature: symbol(handle, c"nvmlDeviceGetTemperature")?,
+                field_values: symbol(handle, c"nvmlDeviceGetFieldValues")?,
+            })
+        }
+    }
+
+    /// Turn an NVML status into an error about `what`
+    fn check(&self, status: ffi::Return, what: &str) -> Result<()> {
+        if status == ffi::SUCCESS {
+            return Ok(());
+        }
+        // SAFETY: nvmlErrorString returns a static string for any status
+        let message = unsafe { CStr::from_ptr((self.error_string)(status)) };
+        bail!("{what}: {}", message.to_string_lossy())
+    }
+
+    /// Initialise NVML, counted, so every call needs a [`Api::shutdown`]
+    fn init(&self) -> Result<()> {
+        // SAFETY: nvmlInit has no preconditions
+        self.check(unsafe { (self.init)() }, "Initialising NVML")
+    }
+
+    /// Undo one [`Api::init`]
+    fn shutdown(&self) {
+        // SAFETY: only called after a successful init
+        unsafe { (self.shutdown)() };
+    }
+}
+
+/// Entry point `name` of the library behind `handle`
+///
+/// # Safety
+///
+/// `handle` must be a library from dlopen and `T` the symbol's function
+/// pointer type.
+unsafe fn symbol<T>(handle: *mut c_void, name: &CStr) -> Result<T> {
+    let symbol = libc::dlsym(handle, name.as_ptr());
+    if symbol.is_null() {
+        bail!(
+            "{} has no {}",
+            LIBRARY.to_string_lossy(),
+            name.to_string_lossy()
+        );
+    }
+    Ok(std::mem::transmute_copy::<*mut c_void, T>(&symbol))
+}
+
+/// The last dynamic loader error
+fn dlerror() -> String {
+    // SAFETY: dlerror returns null or a NUL-terminated string
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        return String::new();
+    }
+    // SAFETY: checked for null above
+    unsafe { CStr::from_ptr(error) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// NVML, loaded once per process
+fn api() -> Result<&'static Api> {
+    static API: OnceLock<std::result::Result<Api, String>> = OnceLock::new();
+    API.get_or_init(|| Api::load(LIBRARY).map_err(|error| format!("{error:#}")))
+        .as_ref()
+        .map_err(crate::Error::msg)
+}
+
+/// Number of NVIDIA GPUs NVML can see
+pub fn gpu_count() -> Result<u32> {
+    let api = api()?;
+    api.init()?;
+    let mut count = 0;
+    // SAFETY: NVML is initialised and `count` is valid to write
+    let status = unsafe { (api.device_count)(&mut count) };
+    api.shutdown();
+    api.check(status, "Counting GPUs")?;
+    Ok(count)
+}
+
+/// Whole degrees from NVML as centidegrees
+fn centidegrees(degrees: i64) -> i16 {
+    codec::saturating_centidegrees(degrees.clamp(i16::MIN.into(), i16::MAX.into()) as i16)
+}
+
+/// Centidegrees from a field value, `None` if the GPU doesn't report it
+fn field_centidegrees(field: &ffi::FieldValue) -> Result<Option<i16>> {
+    if field.nvml_return != ffi::SUCCESS {
+        return Ok(None);
+    }
+    let [a, b, c, d, ..] = field.value;
+    let degrees = match field.value_type {
+        ffi::VALUE_TYPE_DOUBLE => f64::from_ne_bytes(field.value) as i64,
+        ffi::VALUE_TYPE_UNSIGNED_INT => u32::from_ne_bytes([a, b, c, d]).into(),
+        ffi::VALUE_TYPE_SIGNED_INT => i32::from_ne_bytes([a, b, c, d]).into(),
+        ffi::VALUE_TYPE_UNSIGNED_LONG | ffi::VALUE_TYPE_UNSIGNED_LONG_LONG => {
+            i64::try_from(u64::from_ne_bytes(field.value)).unwrap_or(i64::MAX)
+        }
+        ffi::VALUE_TYPE_SIGNED_LONG_LONG => i64::from_ne_bytes(field.value),
+        kind => bail!("Unexpected NVML value type {kind}"),
+    };
+    Ok(Some(centidegrees(degrees)))
+}
+
+/// One temperature of one NVIDIA GPU
+///
+/// Named after the GPU and sensor, e.g. `NVIDIA GeForce RTX 4080/core`.
+pub struct NvmlSource {
+    api: &'static Api,
+    device: ffi::Device,
+    sensor: GpuSensor,
+    name: String,
+}
+
+// SAFETY: NVML is thread-safe and its device handles aren't tied to the
+// thread that looked them up
+unsafe impl Send for NvmlSource {}
+
+impl NvmlSource {
+    /// Source for `sensor` of GPU `index`, numbered from 0 as by
+    /// `nvidia-smi`
+    pub fn new(index: u32, sensor: GpuSensor) -> Result<Self> {
+        let api = api()?;
+        api.init()?;
+        Self::open(api, index, sensor).inspect_err(|_| api.shutdown())
+    }
+
+    /// Look up GPU `index` with NVML initialised
+    fn open(api: &'static Api, index: u32, sensor: GpuSensor) -> Result<Self> {
+        let mut device = std::ptr::null_mut();
+        // SAFETY: NVML is initialised and `device` is valid to write
+        let status = unsafe { (api.device_by_index)(index, &mut device) };
+        api.check(status, &format!("Opening GPU {index}"))?;
+        let mut name = [0 as c_char; ffi::DEVICE_NAME_LEN];
+        // SAFETY: the buffer is valid for its length
+        let status = unsafe { (api.device_name)(device, name.as_mut_ptr(), name.len() as c_uint) };
+        let name = if status == ffi::SUCCESS {
+            // SAFETY: NVML NUL-terminates the name on success
+            unsafe { CStr::from_ptr(name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            format!("GPU {index}")
+        };
+        Ok(Self {
+            api,
+            device,
+            sensor,
+            name: format!("{name}/{sensor}"),
+        })
+    }
+}
+
+impl Drop for NvmlSource {
+    fn drop(&mut self) {
+        self.api.shutdown();
+    }
+}
+
+impl Source for NvmlSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&mut self) -> Result<Option<i16>> {
+        match self.sensor {
+            GpuSensor::Core => {
+                let mut degrees = 0;
+                // SAFETY: the device handle is valid while NVML is
+                // initialised, which it is until drop
+                let status = unsafe {
+                    (self.api.temperature)(self.device, ffi::TEMPERATURE_GPU, &mut degrees)
+                };
+                self.api
+                    .check(status, &format!("Reading {}", self.name))
+                    .map(|()| Some(centidegrees(degrees.into())))
+            }
+            GpuSensor::Memory => {
+                let mut field = ffi::FieldValue {
+                    field_id: ffi::FI_DEV_MEMORY_TEMP,
+                    ..Default::default()
+                };
+                // SAFETY: as above, and `field` is one valid field value
+                let status = unsafe { (self.api.field_values)(self.device, 1, &mut field) };
+                self.api.check(status, &format!("Reading {}", self.name))?;
+                field_centidegrees(&field).with_context(|| format!("Reading {}", self.name))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ffi, field_centidegrees, Api, GpuSensor};
+
+    /// A missing library is a clear error, not a crash
+    #[test]
+    fn missing_library() {
+        let error = Api::load(c"libnvidia-ml-missing.so.1").err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("Loading libnvidia-ml-missing.so.1, is the NVIDIA driver installed?"));
+    }
+
+    /// Field values decode by their type, unsupported ones disconnect
+    #[test]
+    fn field_values() {
+        let mut field = ffi::FieldValue {
+            value_type: ffi::VALUE_TYPE_UNSIGNED_INT,
+            value: [0; 8],
+            ..Default::default()
+        };
+        field.value[..4].copy_from_slice(&72u32.to_ne_bytes());
+        assert_eq!(field_centidegrees(&field).unwrap(), Some(7200));
+        field.value_type = ffi::VALUE_TYPE_DOUBLE;
+        field.value = 68.9f64.to_ne_bytes();
+        assert_eq!(field_centidegrees(&field).unwrap(), Some(6800));
+        field.value_type = 99;
+        assert!(field_centidegrees(&field).is_err());
+        field.nvml_return = 3;
+        assert_eq!(field_centidegrees(&field).unwrap(), None);
+        assert_eq!("memory".parse::<GpuSensor>().unwrap(), GpuSensor::Memory);
+        assert!("hotspot".parse::<GpuSensor>().is_err());
+    }
+}
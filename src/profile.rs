@@ -0,0 +1,392 @@
+//! Profiles that change what gets published while they're active
+//!
+//! A [`Profile`] rewrites the values about to be sent to the virtual
+//! sensors: a slot can take its value from another slot, be offset, or
+//! fall back to a fixed value when it has none. Profiles activate on a
+//! [`Trigger`], checked against a snapshot of the system's [`Conditions`];
+//! the first active one in a list wins.
+//!
+//! Values are centidegrees, `None` for a disconnected sensor.
+//!
+//! ```
+//! use octo_virtual_sensors::profile::{self, Conditions, Processes, Profile, SlotRule, Trigger};
+//! use octo_virtual_sensors::schedule::TimeOfDay;
+//! // Publish slot 0 (GPU hotspot) + 5 °C on slot 3 while the benchmark runs
+//! let benchmark = Profile::new("benchmark", Trigger::Process("superposition".into()))
+//!     .with_slot(3, SlotRule::new().with_source(0).with_offset(500));
+//! let profiles = [benchmark];
+//! let conditions = Conditions::new(Processes::from_names(["superposition"]), TimeOfDay::MIDNIGHT);
+//! let active = profile::active(&profiles, &conditions).unwrap();
+//! assert_eq!(active.apply(&[Some(7000)])[3], Some(7500));
+//! ```
+use crate::error::Result;
+use crate::{
+    codec::DISCONNECTED,
+    schedule::{Schedule, TimeOfDay},
+};
+use std::{collections::HashSet, fs, io, path::Path};
+
+/// When a profile is active
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Trigger {
+    /// While a process with this executable name is running
+    Process(String),
+    /// Every day during this window of local time
+    Schedule(Schedule),
+}
+
+/// How one slot's value is produced while a profile is active
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlotRule {
+    source: Option<usize>,
+    offset: i16,
+    cap: Option<i16>,
+    fallback: Option<i16>,
+}
+
+impl SlotRule {
+    /// Keep the slot's own value unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the value from another slot
+    pub fn with_source(mut self, slot: usize) -> Self {
+        self.source = Some(slot);
+        self
+    }
+
+    /// Add `centidegrees` to the value
+    pub fn with_offset(mut self, centidegrees: i16) -> Self {
+        self.offset = centidegrees;
+        self
+    }
+
+    /// Publish at most `centidegrees`, applied after the offset
+    pub fn with_cap(mut self, centidegrees: i16) -> Self {
+        self.cap = Some(centidegrees);
+        self
+    }
+
+    /// Publish `centidegrees` when there's no value
+    ///
+    /// The fallback is published as is, without the offset or cap.
+    pub fn with_fallback(mut self, centidegrees: i16) -> Self {
+        self.fallback = Some(centidegrees);
+        self
+    }
+
+    /// Slot the value is taken from, `None` for the slot's own
+    pub fn source(&self) -> Option<usize> {
+        self.source
+    }
+
+    /// Centidegrees added to the value
+    pub fn offset(&self) -> i16 {
+        self.offset
+    }
+
+    /// Most the slot publishes, in centidegrees
+    pub fn cap(&self) -> Option<i16> {
+        self.cap
+    }
+
+    /// What the slot publishes when there's no value, in centidegrees
+    pub fn fallback(&self) -> Option<i16> {
+        self.fallback
+    }
+
+    /// Value for `slot` given the incoming values
+    ///
+    /// Offsets saturate short of the disconnected sentinel.
+    pub fn apply(&self, slot: usize, values: &[Option<i16>]) -> Option<i16> {
+        let value = values.get(self.source.unwrap_or(slot)).copied().flatten();
+        let cap = self.cap.unwrap_or(i16::MAX).min(DISCONNECTED - 1);
+        value
+            .map(|value| value.saturating_add(self.offset).min(cap))
+            .or(self.fallback)
+    }
+}
+
+/// A named set of slot rules with a trigger
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Profile {
+    name: String,
+    trigger: Trigger,
+    slots: Vec<(usize, SlotRule)>,
+    others: Option<SlotRule>,
+}
+
+impl Profile {
+    /// Profile without any rules
+    pub fn new(name: impl Into<String>, trigger: Trigger) -> Self {
+        Self {
+            name: name.into(),
+            trigger,
+            slots: Vec::new(),
+            others: None,
+        }
+    }
+
+    /// Set the rule for `slot`, replacing any earlier one
+    pub fn with_slot(mut self, slot: usize, rule: SlotRule) -> Self {
+        self.slots.retain(|&(existing, _)| existing != slot);
+        self.slots.push((slot, rule));
+        self
+    }
+
+    /// Set the rule for every slot without a rule of its own
+    ///
+    /// Handy for profile-wide offsets and caps, like quiet hours.
+    pub fn with_other_slots(mut self, rule: SlotRule) -> Self {
+        self.others = Some(rule);
+        self
+    }
+
+    /// Name of the profile
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What activates the profile
+    pub fn trigger(&self) -> &Trigger {
+        &self.trigger
+    }
+
+    /// Slots with a rule of their own and their rules
+    pub fn slots(&self) -> &[(usize, SlotRule)] {
+        &self.slots
+    }
+
+    /// Rule for every other slot, see [`Profile::with_other_slots`]
+    pub fn other_slots(&self) -> Option<SlotRule> {
+        self.others
+    }
+
+    /// Whether the trigger holds under `conditions`
+    pub fn is_active(&self, conditions: &Conditions) -> bool {
+        match &self.trigger {
+            Trigger::Process(name) => conditions.processes.contains(name),
+            Trigger::Schedule(schedule) => schedule.contains(conditions.time),
+        }
+    }
+
+    /// Rewrite `values` by the profile's rules
+    ///
+    /// The result is long enough for every slot with a rule; slots without
+    /// one keep their incoming value.
+    pub fn apply(&self, values: &[Option<i16>]) -> Vec<Option<i16>> {
+        let len = self
+            .slots
+            .iter()
+            .map(|&(slot, _)| slot + 1)
+            .fold(values.len(), usize::max);
+        (0..len)
+            .map(|slot| {
+                let rule = self
+                    .slots
+                    .iter()
+                    .find(|&&(existing, _)| existing == slot)
+                    .map(|&(_, rule)| rule)
+                    .or(self.others)
+                    .unwrap_or_default();
+                rule.apply(slot, values)
+            })
+            .collect()
+    }
+}
+
+/// First profile in `profiles` whose trigger holds
+pub fn active<'a>(profiles: &'a [Profile], conditions: &Conditions) -> Option<&'a Profile> {
+    profiles
+        .iter()
+        .find(|profile| profile.is_active(conditions))
+}
+
+/// What profile triggers are checked against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conditions {
+    processes: Processes,
+    time: TimeOfDay,
+}
+
+impl Conditions {
+    /// Conditions from known values
+    pub fn new(processes: Processes, time: TimeOfDay) -> Self {
+        Self { processes, time }
+    }
+
+    /// Snapshot of the running processes and local time
+    pub fn now() -> Result<Self> {
+        Ok(Self::new(Processes::scan()?, TimeOfDay::now()?))
+    }
+}
+
+/// Names of the running processes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Processes {
+    names: HashSet<String>,
+}
+
+impl Processes {
+    /// Snapshot the processes visible in `/proc`
+    ///
+    /// Each process is known by its kernel name and by the file name of
+    /// its first argument, which isn't cut to 15 characters and names the
+    /// Windows executable for programs run under Wine.
+    pub fn scan() -> io::Result<Self> {
+        let mut names = HashSet::new();
+        for entry in fs::read_dir("/proc")? {
+            let path = entry?.path();
+            let is_pid = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().bytes().all(|b| b.is_ascii_digit()));
+            if is_pid {
+                names.extend(process_names(&path));
+            }
+        }
+        Ok(Self { names })
+    }
+
+    /// Process list from known names
+    pub fn from_names<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            names: names.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Whether a process with this name is running
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+}
+
+/// Names a process in `/proc/PID` is known by
+///
+/// Processes that exit mid-scan or hide their details yield nothing.
+fn process_names(dir: &Path) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(comm) = fs::read_to_string(dir.join("comm")) {
+        names.push(comm.trim_end().to_owned());
+    }
+    if let Ok(cmdline) = fs::read(dir.join("cmdline")) {
+        if let Some(name) = executable_name(&cmdline) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// File name of the first argument of a NUL separated command line
+fn executable_name(cmdline: &[u8]) -> Option<String> {
+    let argv0 = cmdline.split(|&b| b == 0).next()?;
+    let argv0 = String::from_utf8_lossy(argv0);
+    let name = argv0.rsplit(['/', '\\']).next()?;
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{active, executable_name, Conditions, Processes, Profile, SlotRule, Trigger};
+    use crate::{codec::DISCONNECTED, schedule::TimeOfDay};
+
+    /// Conditions with these processes at midnight
+    fn running(names: &[&str]) -> Conditions {
+        Conditions::new(
+            Processes::from_names(names.iter().copied()),
+            TimeOfDay::MIDNIGHT,
+        )
+    }
+
+    /// Sources, offsets and fallbacks rewrite their slots only
+    #[test]
+    fn apply_rules() {
+        let profile = Profile::new("test", Trigger::Process("bench".into()))
+            .with_slot(1, SlotRule::new().with_source(0).with_offset(500))
+            .with_slot(2, SlotRule::new().with_fallback(9000))
+            .with_slot(5, SlotRule::new().with_source(0));
+        let values = [Some(4000), Some(1), None, Some(3000)];
+        assert_eq!(
+            profile.apply(&values),
+            [
+                Some(4000),
+                Some(4500),
+                Some(9000),
+                Some(3000),
+                None,
+                Some(4000)
+            ]
+        );
+        assert_eq!(
+            profile.apply(&[None, None, Some(10)])[..3],
+            [None, None, Some(10)]
+        );
+    }
+
+    /// Offsets never produce the disconnected sentinel
+    #[test]
+    fn offset_saturates() {
+        let rule = SlotRule::new().with_offset(i16::MAX);
+        assert_eq!(rule.apply(0, &[Some(100)]), Some(DISCONNECTED - 1));
+        let rule = SlotRule::new().with_offset(i16::MIN);
+        assert_eq!(rule.apply(0, &[Some(-100)]), Some(i16::MIN));
+    }
+
+    /// The first profile whose process runs is active
+    #[test]
+    fn process_activation() {
+        let profiles = [
+            Profile::new("game", Trigger::Process("game.exe".into())),
+            Profile::new("bench", Trigger::Process("bench".into())),
+        ];
+        let conditions = running(&["bash", "bench", "game.exe"]);
+        assert_eq!(active(&profiles, &conditions).unwrap().name(), "game");
+        let conditions = running(&["bash", "bench"]);
+        assert_eq!(active(&profiles, &conditions).unwrap().name(), "bench");
+        assert!(active(&profiles, &running(&[])).is_none());
+    }
+
+    /// Quiet hours cap and offset every slot overnight only
+    #[test]
+    fn quiet_hours() {
+        let quiet = Profile::new("quiet", Trigger::Schedule("23:00-07:00".parse().unwrap()))
+            .with_other_slots(SlotRule::new().with_offset(-500).with_cap(4000))
+            .with_slot(2, SlotRule::new());
+        let profiles = [quiet];
+        let night = Conditions::new(Processes::default(), "02:30".parse().unwrap());
+        let day = Conditions::new(Processes::default(), "12:00".parse().unwrap());
+        assert!(active(&profiles, &day).is_none());
+        let values = [Some(3000), Some(6000), Some(6000), None];
+        let published = active(&profiles, &night).unwrap().apply(&values);
+        assert_eq!(published, [Some(2500), Some(4000), Some(6000), None]);
+    }
+
+    /// Executable names come from Unix and Wine style paths
+    #[test]
+    fn executable_names() {
+        assert_eq!(
+            executable_name(b"/usr/bin/glxgears\0-info\0").unwrap(),
+            "glxgears"
+        );
+        assert_eq!(
+            executable_name(b"C:\\Games\\Game.exe\0").unwrap(),
+            "Game.exe"
+        );
+        assert_eq!(executable_name(b"bench").unwrap(), "bench");
+        assert_eq!(executable_name(b""), None);
+        assert_eq!(executable_name(b"/usr/bin/\0"), None);
+    }
+
+    /// Scanning sees the test process itself
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn scan_self() {
+        let processes = Processes::scan().unwrap();
+        let own = std::fs::read_to_string("/proc/self/comm").unwrap();
+        assert!(processes.contains(own.trim_end()));
+    }
+}
@@ -0,0 +1,350 @@
+//! Metrics for Prometheus to scrape
+//!
+//! [`Metrics`] holds the values last pushed to the virtual sensors and the
+//! telemetry last read back from the device, and [`Metrics::serve`] hands
+//! them out in the Prometheus text format on `/metrics`:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{prometheus::Metrics, Octo};
+//! use std::net::TcpListener;
+//! let metrics = Metrics::new();
+//! let server = metrics.clone();
+//! let listener = TcpListener::bind("127.0.0.1:9528").unwrap();
+//! std::thread::spawn(move || server.serve(listener));
+//! let mut octo = Octo::new().unwrap();
+//! octo.update_virtual_sensors(&[42]).unwrap();
+//! metrics.record_sent(&[Some(4200)]);
+//! metrics.record_octo(&mut octo);
+//! ```
+//!
+//! Only built with the `prometheus` feature.
+use crate::error::{Context, Result};
+use crate::{
+    status::{FanStatus, Status},
+    LinkStats, Octo,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+/// How long a scrape may take to send its request
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Content type of the text exposition format
+static CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Latest readings, shared between the loop updating them and the server
+///
+/// Clones share their state.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    state: Arc<Mutex<State>>,
+}
+
+/// Everything [`Metrics`] reports
+#[derive(Debug, Default)]
+struct State {
+    sent: Vec<Option<i16>>,
+    status: Option<Status>,
+    link: LinkStats,
+    up: bool,
+}
+
+impl Metrics {
+    /// Metrics with nothing recorded yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the values last pushed, in centidegrees
+    pub fn record_sent(&self, values: &[Option<i16>]) {
+        self.lock().sent = values.to_vec();
+    }
+
+    /// Remember a status report read back from the device
+    pub fn record_status(&self, status: Status) {
+        let mut state = self.lock();
+        state.status = Some(status);
+        state.up = true;
+    }
+
+    /// Remember the link counters
+    pub fn record_link(&self, link: LinkStats) {
+        self.lock().link = link;
+    }
+
+    /// Remember that the device didn't answer, keeping the last telemetry
+    pub fn record_down(&self) {
+        self.lock().up = false;
+    }
+
+    /// Read the device's status and link counters
+    ///
+    /// A failed read sets `octo_up` to 0 and keeps the last telemetry.
+    pub fn record_octo(&self, octo: &mut Octo) {
+        match octo.read_status() {
+            Ok(status) => self.record_status(status),
+            Err(error) => {
+                warn!("Reading status for metrics: {error:#}");
+                self.record_down();
+            }
+        }
+        self.record_link(octo.link_stats());
+    }
+
+    /// Everything recorded, in the Prometheus text format
+    pub fn render(&self) -> String {
+        let state = self.lock();
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "octo_up",
+            "gauge",
+            "Whether the last status read worked",
+        );
+        sample(&mut out, "octo_up", "", u8::from(state.up));
+        metric(
+            &mut out,
+            "octo_virtual_sensor_sent_celsius",
+            "gauge",
+            "Temperature last pushed to a virtual sensor",
+        );
+        for (slot, value) in slots(&state.sent) {
+            let labels = format!("slot=\"{slot}\"");
+            sample(
+                &mut out,
+                "octo_virtual_sensor_sent_celsius",
+                &labels,
+                celsius(value),
+            );
+        }
+        if let Some(status) = &state.status {
+            render_status(&mut out, status);
+        }
+        let link = state.link;
+        for (name, help, value) in [
+            (
+                "octo_checksum_errors_total",
+                "Status reports thrown away for a bad checksum",
+                link.checksum_errors,
+            ),
+            (
+                "octo_resends_total",
+                "Virtual sensor reports sent again",
+                link.resends,
+            ),
+            (
+                "octo_reconnects_total",
+                "Times the device was reopened after going away",
+                link.reconnects,
+            ),
+            (
+                "octo_skipped_updates_total",
+                "Updates not sent because no value moved past the deadband",
+                link.skipped,
+            ),
+        ] {
+            metric(&mut out, name, "counter", help);
+            sample(&mut out, name, "", value);
+        }
+        out
+    }
+
+    /// Serve `/metrics` until the listener fails, one thread per connection
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream.context("Accepting connection")?;
+            let metrics = self.clone();
+            thread::spawn(move || {
+                if let Err(error) = metrics.handle(stream) {
+                    warn!("Metrics client: {error}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Answer one HTTP request
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // The headers don't matter, but the client expects them read
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let mut parts = request.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), Some(_)) => ("404 Not Found", "Not found\n".to_owned()),
+            _ => ("405 Method Not Allowed", "Only GET\n".to_owned()),
+        };
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        stream.flush()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Metric name, help, divisor to base units and the reading, per fan
+type FanField = (&'static str, &'static str, f64, fn(&FanStatus) -> u16);
+
+/// Telemetry from a status report
+fn render_status(out: &mut String, status: &Status) {
+    metric(
+        out,
+        "octo_power_cycles",
+        "gauge",
+        "Times the device has been powered on",
+    );
+    sample(out, "octo_power_cycles", "", status.power_cycles);
+    metric(
+        out,
+        "octo_virtual_sensor_celsius",
+        "gauge",
+        "Virtual sensor temperature as the device sees it",
+    );
+    for (slot, value) in slots(&status.virtual_sensors) {
+        let labels = format!("slot=\"{slot}\"");
+        sample(out, "octo_virtual_sensor_celsius", &labels, celsius(value));
+    }
+    metric(
+        out,
+        "octo_temperature_celsius",
+        "gauge",
+        "Physical temperature sensor",
+    );
+    for (sensor, value) in slots(&status.sensors) {
+        let labels = format!("sensor=\"{sensor}\"");
+        sample(out, "octo_temperature_celsius", &labels, celsius(value));
+    }
+    if let Some(flow) = status.flow {
+        metric(out, "octo_flow_liters_per_hour", "gauge", "Coolant flow");
+        sample(out, "octo_flow_liters_per_hour", "", f64::from(flow) / 10.0);
+    }
+    let fields: [FanField; 5] = [
+        ("octo_fan_rpm", "Fan speed", 1.0, |fan| fan.rpm),
+        ("octo_fan_duty_ratio", "Fan output power", 10_000.0, |fan| {
+            fan.duty
+        }),
+        ("octo_fan_voltage_volts", "Fan voltage", 100.0, |fan| {
+            fan.voltage
+        }),
+        ("octo_fan_current_amperes", "Fan current", 1000.0, |fan| {
+            fan.current
+        }),
+        ("octo_fan_power_watts", "Fan power", 100.0, |fan| fan.power),
+    ];
+    for (name, help, scale, read) in fields {
+        metric(out, name, "gauge", help);
+        for (index, fan) in status.fans.iter().enumerate() {
+            let labels = format!("fan=\"{}\"", index + 1);
+            sample(out, name, &labels, f64::from(read(fan)) / scale);
+        }
+    }
+}
+
+/// Connected values with their 1-based numbers, as aquasuite counts them
+fn slots(values: &[Option<i16>]) -> impl Iterator<Item = (usize, i16)> + '_ {
+    values
+        .iter()
+        .enumerate()
+        .filter_map(|(index, value)| value.map(|value| (index + 1, value)))
+}
+
+/// Centidegrees as degrees Celsius
+fn celsius(centidegrees: i16) -> f64 {
+    f64::from(centidegrees) / 100.0
+}
+
+/// `# HELP` and `# TYPE` lines
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+/// One sample line
+fn sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+    use crate::{emulator::Emulator, Octo};
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// Pushed values and telemetry both end up in the output
+    #[test]
+    fn render() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_centidegrees(&[Some(4150), None, Some(-250)])
+            .unwrap();
+        let metrics = Metrics::new();
+        metrics.record_sent(&[Some(4150), None, Some(-250)]);
+        metrics.record_octo(&mut octo);
+        let out = metrics.render();
+        assert!(out.contains("octo_up 1\n"));
+        assert!(out.contains("octo_virtual_sensor_sent_celsius{slot=\"1\"} 41.5\n"));
+        assert!(!out.contains("octo_virtual_sensor_sent_celsius{slot=\"2\"}"));
+        assert!(out.contains("octo_virtual_sensor_sent_celsius{slot=\"3\"} -2.5\n"));
+        assert!(out.contains("octo_virtual_sensor_celsius{slot=\"1\"} 41.5\n"));
+        assert!(out.contains("# TYPE octo_fan_rpm gauge\n"));
+        assert!(out.contains("octo_fan_rpm{fan=\"8\"}"));
+        assert!(out.contains("octo_resends_total 0\n"));
+    }
+
+    /// A failed read marks the device down
+    #[test]
+    fn down() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        emulator.disconnect();
+        let metrics = Metrics::new();
+        metrics.record_octo(&mut octo);
+        assert!(metrics.render().contains("octo_up 0\n"));
+    }
+
+    /// The server answers scrapes over HTTP
+    #[test]
+    fn serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = Metrics::new();
+        metrics.record_sent(&[Some(3000)]);
+        let server = metrics.clone();
+        std::thread::spawn(move || server.serve(listener));
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&metrics.render()));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+}
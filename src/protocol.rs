@@ -0,0 +1,167 @@
+//! The Octo's wire format as pure functions
+//!
+//! Encoding the virtual sensor report, the temperature encoding and the
+//! CRC, with no I/O, no allocation and nothing beyond `core` and the `crc`
+//! crate, so the module can be lifted into `no_std` firmware or tested
+//! without a device:
+//!
+//! ```
+//! use octo_virtual_sensors::protocol;
+//! let report = protocol::encode_virtual_sensor_report(&[Some(4150), None]);
+//! assert!(protocol::verify_crc(&report));
+//! assert_eq!(protocol::decode_virtual_sensor_report(&report)[..2], [Some(4150), None]);
+//! ```
+//!
+//! [`VirtualSensorReport`](crate::VirtualSensorReport) and the
+//! [layout tables](crate::layout) build on it and add firmware variants
+//! and error reporting.
+#![deny(
+    clippy::indexing_slicing,
+    clippy::std_instead_of_core,
+    clippy::std_instead_of_alloc,
+    clippy::alloc_instead_of_core
+)]
+
+/// Raw value of a disconnected or timed out sensor
+pub const DISCONNECTED: i16 = i16::MAX;
+
+/// Report ID of the virtual sensor report
+pub const VIRTUAL_SENSOR_REPORT_ID: u8 = 0x04;
+
+/// Length of the virtual sensor report, report ID and CRC included
+pub const VIRTUAL_SENSOR_REPORT_LEN: usize = 51;
+
+/// Number of virtual sensors
+pub const VIRTUAL_SENSOR_COUNT: usize = 16;
+
+/// Offset of the first virtual sensor
+pub const VIRTUAL_SENSORS: usize = 0x01;
+
+/// Fixed bytes between the last virtual sensor and the CRC
+pub const VIRTUAL_SENSOR_TRAILER: [u8; 16] = [0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// Length of the CRC ending each report
+pub const CRC_SIZE: usize = 2;
+
+static CRC_16_USB: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_USB);
+
+/// Raw sensor value for a temperature in centidegrees
+///
+/// `Some(DISCONNECTED)` can't be told apart from `None` on the wire.
+pub const fn encode_temperature(centidegrees: Option<i16>) -> i16 {
+    match centidegrees {
+        Some(centidegrees) => centidegrees,
+        None => DISCONNECTED,
+    }
+}
+
+/// Temperature in centidegrees for a raw sensor value
+pub const fn decode_temperature(raw: i16) -> Option<i16> {
+    if raw == DISCONNECTED {
+        None
+    } else {
+        Some(raw)
+    }
+}
+
+/// The whole virtual sensor report for centidegree `values`
+///
+/// `None` and slots past the end of `values` are disconnected, and values
+/// past the sixteenth are ignored.
+pub fn encode_virtual_sensor_report(values: &[Option<i16>]) -> [u8; VIRTUAL_SENSOR_REPORT_LEN] {
+    let mut report = [0; VIRTUAL_SENSOR_REPORT_LEN];
+    report[0] = VIRTUAL_SENSOR_REPORT_ID;
+    let sensors = report.iter_mut().skip(VIRTUAL_SENSORS);
+    let raw = (0..VIRTUAL_SENSOR_COUNT)
+        .flat_map(|slot| encode_temperature(values.get(slot).copied().flatten()).to_be_bytes())
+        .chain(VIRTUAL_SENSOR_TRAILER);
+    for (byte, value) in sensors.zip(raw) {
+        *byte = value;
+    }
+    apply_crc(&mut report);
+    report
+}
+
+/// Centidegree values of a virtual sensor report, `None` if disconnected
+///
+/// Doesn't check the CRC; see [`verify_crc`].
+pub fn decode_virtual_sensor_report(
+    report: &[u8; VIRTUAL_SENSOR_REPORT_LEN],
+) -> [Option<i16>; VIRTUAL_SENSOR_COUNT] {
+    let mut values = [None; VIRTUAL_SENSOR_COUNT];
+    let sensors = report.get(VIRTUAL_SENSORS..).unwrap_or_default();
+    for (value, raw) in values.iter_mut().zip(sensors.chunks_exact(2)) {
+        if let [high, low] = *raw {
+            *value = decode_temperature(i16::from_be_bytes([high, low]));
+        }
+    }
+    values
+}
+
+/// CRC-16/USB over everything between the report ID and the CRC
+///
+/// Reports too short to carry a CRC yield `None`.
+pub fn crc(report: &[u8]) -> Option<u16> {
+    let end = report.len().checked_sub(CRC_SIZE)?;
+    let data = report.get(1..end)?;
+    Some(CRC_16_USB.checksum(data))
+}
+
+/// Write the CRC into the last two bytes of `report`, big-endian
+pub fn apply_crc(report: &mut [u8]) {
+    let Some(checksum) = crc(report) else {
+        return;
+    };
+    if let Some(stored) = report.rchunks_exact_mut(CRC_SIZE).next() {
+        stored.copy_from_slice(&checksum.to_be_bytes());
+    }
+}
+
+/// Whether `report` ends with a valid CRC
+pub fn verify_crc(report: &[u8]) -> bool {
+    let stored = report.rchunks_exact(CRC_SIZE).next();
+    crc(report).is_some_and(|checksum| stored == Some(&checksum.to_be_bytes()[..]))
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod test {
+    use super::{
+        decode_virtual_sensor_report, encode_virtual_sensor_report, verify_crc, DISCONNECTED,
+    };
+    use crate::VirtualSensorReport;
+
+    /// The pure encoding matches the report the device API sends
+    #[test]
+    fn matches_report() {
+        let values = [Some(4150), None, Some(-250), Some(0)];
+        let mut report = VirtualSensorReport::default();
+        report.set_values(&values);
+        let encoded = encode_virtual_sensor_report(&values);
+        assert_eq!(encoded[..], *report.as_bytes());
+        assert_eq!(
+            decode_virtual_sensor_report(&encoded)[..],
+            report.values()[..]
+        );
+    }
+
+    /// Extra values are dropped and missing ones disconnected
+    #[test]
+    fn slots() {
+        let report = encode_virtual_sensor_report(&[Some(1); 20]);
+        assert_eq!(decode_virtual_sensor_report(&report), [Some(1); 16]);
+        let report = encode_virtual_sensor_report(&[]);
+        assert_eq!(report[1..3], DISCONNECTED.to_be_bytes());
+        assert_eq!(decode_virtual_sensor_report(&report), [None; 16]);
+    }
+
+    /// A flipped bit fails the CRC
+    #[test]
+    fn crc() {
+        let mut report = encode_virtual_sensor_report(&[Some(3000)]);
+        assert!(verify_crc(&report));
+        report[5] ^= 1;
+        assert!(!verify_crc(&report));
+        assert!(!verify_crc(&[0x04]));
+    }
+}
@@ -0,0 +1,260 @@
+//! Python bindings
+//!
+//! Exposes [`Octo`] as a Python extension module, so monitoring scripts can
+//! publish temperatures without a subprocess per update:
+//!
+//! ```python
+//! import octo_virtual_sensors
+//! octo = octo_virtual_sensors.Octo()
+//! octo.update_virtual_sensors([41.2, None, 35.0])
+//! print(octo.read_status()["sensors"])
+//! ```
+//!
+//! Temperatures are degrees Celsius as floats, unplugged sensors `None`.
+//! Device errors raise `ConnectionError` when unplugged, `TimeoutError`,
+//! `PermissionError`, `ValueError` for values out of range,
+//! `NotImplementedError` for what the device can't do and `RuntimeError`
+//! otherwise. USB transfers release the GIL.
+//!
+//! Only built with the `python` feature; build the module with `maturin
+//! build --release`, which turns on `pyo3/extension-module` as set in
+//! `pyproject.toml`.
+use crate::{status::Status, Octo, OctoError, SharedOcto};
+use pyo3::{
+    exceptions::{
+        PyConnectionError, PyNotImplementedError, PyPermissionError, PyRuntimeError,
+        PyTimeoutError, PyValueError,
+    },
+    prelude::*,
+    types::{PyDict, PyList},
+};
+use std::sync::MutexGuard;
+
+/// An Aquacomputer OCTO, as the Python class `Octo`
+#[pyclass(name = "Octo", module = "octo_virtual_sensors")]
+pub struct PyOcto {
+    octo: SharedOcto,
+}
+
+impl PyOcto {
+    /// Python object for an already open `octo`, which may be shared
+    /// with Rust code
+    pub fn new(octo: impl Into<SharedOcto>) -> Self {
+        Self { octo: octo.into() }
+    }
+
+    /// The device
+    fn octo(&self) -> MutexGuard<'_, Octo> {
+        self.octo.lock()
+    }
+
+    /// Run `f` on the device with the GIL released
+    fn with_octo<T: Send>(
+        &self,
+        py: Python<'_>,
+        f: impl FnOnce(&mut Octo) -> crate::Result<T> + Send,
+    ) -> PyResult<T> {
+        py.detach(|| f(&mut self.octo())).map_err(to_py_err)
+    }
+}
+
+#[pymethods]
+impl PyOcto {
+    /// Open the first OCTO found, or the one with `serial`
+    #[new]
+    #[pyo3(signature = (serial = None))]
+    fn open(py: Python<'_>, serial: Option<String>) -> PyResult<Self> {
+        py.detach(|| match serial {
+            Some(serial) => Octo::open_by_serial(&serial),
+            None => Octo::new(),
+        })
+        .map(Self::new)
+        .map_err(to_py_err)
+    }
+
+    /// Connected OCTOs as dicts of bus, address, serial and firmware
+    #[staticmethod]
+    fn list(py: Python<'_>) -> PyResult<Bound<'_, PyList>> {
+        let devices = py.detach(Octo::list).map_err(to_py_err)?;
+        let list = PyList::empty(py);
+        for device in devices {
+            let dict = PyDict::new(py);
+            dict.set_item("bus", device.bus)?;
+            dict.set_item("address", device.address)?;
+            dict.set_item("serial", device.serial)?;
+            dict.set_item("firmware", device.firmware)?;
+            list.append(dict)?;
+        }
+        Ok(list)
+    }
+
+    /// Firmware version, if known
+    #[getter]
+    fn firmware(&self) -> Option<u16> {
+        self.octo().firmware()
+    }
+
+    /// Serial number, if known
+    #[getter]
+    fn serial(&self) -> Option<String> {
+        self.octo().serial().map(str::to_owned)
+    }
+
+    /// Set virtual sensors in order from degrees, `None` disconnects
+    ///
+    /// Slots past the end of `values` are disconnected. Returns the bytes
+    /// written.
+    fn update_virtual_sensors(&self, py: Python<'_>, values: Vec<Option<f32>>) -> PyResult<usize> {
+        let values: Vec<f32> = values
+            .into_iter()
+            .map(|value| value.unwrap_or(f32::NAN))
+            .collect();
+        self.with_octo(py, |octo| octo.update_virtual_sensors_f32(&values))
+    }
+
+    /// Set virtual sensor `index`, counted from 0, to `degrees`
+    fn set_virtual_sensor(&self, py: Python<'_>, index: usize, degrees: f32) -> PyResult<usize> {
+        self.with_octo(py, |octo| octo.set_virtual_sensor(index, degrees))
+    }
+
+    /// Disconnect virtual sensor `index`, counted from 0
+    fn clear_virtual_sensor(&self, py: Python<'_>, index: usize) -> PyResult<usize> {
+        self.with_octo(py, |octo| octo.clear_virtual_sensor(index))
+    }
+
+    /// Virtual sensor values last sent, in degrees
+    fn virtual_sensors(&self) -> Vec<Option<f32>> {
+        celsius(&self.octo().last_report().values())
+    }
+
+    /// Physical temperature sensors in degrees
+    fn read_sensors(&self, py: Python<'_>) -> PyResult<Vec<Option<f32>>> {
+        self.with_octo(py, Octo::read_sensors)
+    }
+
+    /// Read the status report as a dict
+    ///
+    /// Keys are `power_cycles`, `sensors` and `virtual_sensors` in degrees,
+    /// `flow` in litres per hour or `None`, and `fans`, a list of dicts
+    /// with `percent`, `volts`, `amps`, `watts` and `rpm`.
+    fn read_status<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let status = self.with_octo(py, Octo::read_status)?;
+        status_dict(py, &status)
+    }
+
+    /// Send the last values again
+    fn republish(&self, py: Python<'_>) -> PyResult<usize> {
+        self.with_octo(py, Octo::republish)
+    }
+
+    /// Reopen the device after it was unplugged
+    fn reopen(&self, py: Python<'_>) -> PyResult<()> {
+        self.with_octo(py, Octo::reopen)
+    }
+
+    /// `Octo(serial=...)`
+    fn __repr__(&self) -> String {
+        match self.octo().serial() {
+            Some(serial) => format!("Octo(serial={serial:?})"),
+            None => "Octo()".to_owned(),
+        }
+    }
+}
+
+/// Centidegrees as degrees
+fn celsius(values: &[Option<i16>]) -> Vec<Option<f32>> {
+    values
+        .iter()
+        .map(|value| value.map(|value| f32::from(value) / 100.0))
+        .collect()
+}
+
+/// `status` as a Python dict with values in plain units
+fn status_dict<'py>(py: Python<'py>, status: &Status) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("power_cycles", status.power_cycles)?;
+    dict.set_item("sensors", celsius(&status.sensors))?;
+    dict.set_item("virtual_sensors", celsius(&status.virtual_sensors))?;
+    dict.set_item("flow", status.flow.map(|flow| f32::from(flow) / 10.0))?;
+    let fans = PyList::empty(py);
+    for fan in &status.fans {
+        let entry = PyDict::new(py);
+        entry.set_item("percent", f32::from(fan.duty) / 100.0)?;
+        entry.set_item("volts", f32::from(fan.voltage) / 100.0)?;
+        entry.set_item("amps", f32::from(fan.current) / 1000.0)?;
+        entry.set_item("watts", f32::from(fan.power) / 100.0)?;
+        entry.set_item("rpm", fan.rpm)?;
+        fans.append(entry)?;
+    }
+    dict.set_item("fans", fans)?;
+    Ok(dict)
+}
+
+/// The Python exception for a device error
+fn to_py_err(error: crate::Error) -> PyErr {
+    let message = format!("{error:#}");
+    match error.kind() {
+        Some(OctoError::Disconnected) => PyConnectionError::new_err(message),
+        Some(OctoError::Timeout) => PyTimeoutError::new_err(message),
+        Some(OctoError::PermissionDenied) => PyPermissionError::new_err(message),
+        Some(OctoError::OutOfRange) => PyValueError::new_err(message),
+        Some(OctoError::Unsupported) => PyNotImplementedError::new_err(message),
+        _ => PyRuntimeError::new_err(message),
+    }
+}
+
+/// The `octo_virtual_sensors` Python module
+#[pymodule]
+fn octo_virtual_sensors(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyOcto>()
+}
+
+#[cfg(test)]
+mod test {
+    use super::PyOcto;
+    use crate::{emulator::Emulator, Octo, RangeCheck};
+    use pyo3::{
+        exceptions::{PyConnectionError, PyValueError},
+        prelude::*,
+        types::PyDict,
+    };
+
+    /// Python calls reach the device and errors map to Python exceptions
+    #[test]
+    fn bindings() {
+        let emulator = Emulator::new();
+        let octo = Octo::builder()
+            .range_check(RangeCheck::Reject)
+            .with_transport(emulator.clone())
+            .unwrap();
+        Python::initialize();
+        Python::attach(|py| {
+            let octo = Bound::new(py, PyOcto::new(octo)).unwrap();
+            octo.call_method1(
+                "update_virtual_sensors",
+                (vec![Some(41.2), None, Some(35.0)],),
+            )
+            .unwrap();
+            assert_eq!(
+                emulator.virtual_sensors()[..3],
+                [Some(4120), None, Some(3500)]
+            );
+            let values: Vec<Option<f32>> = octo
+                .call_method0("virtual_sensors")
+                .unwrap()
+                .extract()
+                .unwrap();
+            assert_eq!(values[..3], [Some(41.2), None, Some(35.0)]);
+            let status = octo.call_method0("read_status").unwrap();
+            let status = status.cast::<PyDict>().unwrap();
+            assert!(status.contains("fans").unwrap());
+            let error = octo
+                .call_method1("set_virtual_sensor", (0, 900.0))
+                .unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+            emulator.disconnect();
+            let error = octo.call_method0("read_status").unwrap_err();
+            assert!(error.is_instance_of::<PyConnectionError>(py));
+        });
+    }
+}
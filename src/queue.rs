@@ -0,0 +1,294 @@
+//! Bounded queue between update producers and the device
+//!
+//! Producers can outpace USB writes, in bursts or for good. An
+//! [`UpdateQueue`] holds at most a fixed number of updates, and its
+//! [`Backpressure`] policy decides what gives when it is full. Dropped
+//! updates are counted so the loss shows up somewhere.
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// What happens to a push when the queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Drop the oldest queued update to make room, keeping data fresh
+    #[default]
+    DropOldest,
+    /// Drop the update being pushed, keeping what is already queued
+    DropNewest,
+    /// Wait for room, slowing the producer down
+    Block,
+}
+
+/// Outcome of a push
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pushed {
+    /// The update was queued without losing anything
+    Queued,
+    /// The update was queued and the oldest one dropped
+    DroppedOldest,
+    /// The update was dropped
+    DroppedNewest,
+    /// The queue is closed, the update was dropped
+    Closed,
+}
+
+struct Queue<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+struct Shared<T> {
+    queue: Mutex<Queue<T>>,
+    /// Signalled when an item is pushed or the queue closes
+    not_empty: Condvar,
+    /// Signalled when an item is popped or the queue closes
+    not_full: Condvar,
+    capacity: usize,
+    policy: Backpressure,
+    dropped: AtomicU64,
+}
+
+/// Cloneable handle to a bounded queue of updates
+///
+/// Every clone refers to the same queue, so producers and the consumer
+/// each keep one.
+///
+/// ```
+/// use octo_virtual_sensors::queue::{Backpressure, UpdateQueue};
+/// let queue = UpdateQueue::new(2, Backpressure::DropOldest);
+/// for update in 1..=3 {
+///     queue.push(update);
+/// }
+/// assert_eq!(queue.try_pop(), Some(2));
+/// assert_eq!(queue.dropped(), 1);
+/// ```
+pub struct UpdateQueue<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for UpdateQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> UpdateQueue<T> {
+    /// Queue holding at most `capacity` updates, at least one
+    pub fn new(capacity: usize, policy: Backpressure) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(Queue {
+                    items: VecDeque::with_capacity(capacity),
+                    closed: false,
+                }),
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+                capacity,
+                policy,
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Queue an update, applying the backpressure policy if full
+    pub fn push(&self, item: T) -> Pushed {
+        let shared = &*self.shared;
+        let mut queue = self.lock();
+        if shared.policy == Backpressure::Block {
+            while !queue.closed && queue.items.len() >= shared.capacity {
+                queue = shared
+                    .not_full
+                    .wait(queue)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        let pushed = if queue.closed {
+            Pushed::Closed
+        } else if queue.items.len() < shared.capacity {
+            Pushed::Queued
+        } else if shared.policy == Backpressure::DropNewest {
+            Pushed::DroppedNewest
+        } else {
+            queue.items.pop_front();
+            Pushed::DroppedOldest
+        };
+        if pushed != Pushed::Queued {
+            shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        if matches!(pushed, Pushed::Queued | Pushed::DroppedOldest) {
+            queue.items.push_back(item);
+            shared.not_empty.notify_one();
+        }
+        pushed
+    }
+
+    /// Take the oldest update without waiting
+    pub fn try_pop(&self) -> Option<T> {
+        let item = self.lock().items.pop_front();
+        if item.is_some() {
+            self.shared.not_full.notify_one();
+        }
+        item
+    }
+
+    /// Take the oldest update, waiting up to `timeout` for one
+    ///
+    /// Returns `None` on timeout, or once the queue is closed and empty.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.lock();
+        loop {
+            if let Some(item) = queue.items.pop_front() {
+                self.shared.not_full.notify_one();
+                return Some(item);
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            if queue.closed {
+                return None;
+            }
+            queue = self
+                .shared
+                .not_empty
+                .wait_timeout(queue, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Take the oldest update, waiting for one
+    ///
+    /// Returns `None` once the queue is closed and empty.
+    pub fn pop(&self) -> Option<T> {
+        let mut queue = self.lock();
+        loop {
+            if let Some(item) = queue.items.pop_front() {
+                self.shared.not_full.notify_one();
+                return Some(item);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self
+                .shared
+                .not_empty
+                .wait(queue)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Refuse further pushes and wake everyone waiting
+    ///
+    /// Updates already queued can still be popped.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
+    }
+
+    /// Number of queued updates
+    pub fn len(&self) -> usize {
+        self.lock().items.len()
+    }
+
+    /// Whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Most updates the queue holds
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    /// Updates dropped so far, by the policy or because the queue was closed
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Queue<T>> {
+        self.shared.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Backpressure, Pushed, UpdateQueue};
+    use std::{thread, time::Duration};
+
+    /// Full queues drop the oldest update and count it
+    #[test]
+    fn drop_oldest() {
+        let queue = UpdateQueue::new(2, Backpressure::DropOldest);
+        assert_eq!(queue.push(1), Pushed::Queued);
+        assert_eq!(queue.push(2), Pushed::Queued);
+        assert_eq!(queue.push(3), Pushed::DroppedOldest);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.try_pop(), Some(2));
+        assert_eq!(queue.try_pop(), Some(3));
+        assert_eq!(queue.try_pop(), None);
+    }
+
+    /// Full queues can refuse the new update instead
+    #[test]
+    fn drop_newest() {
+        let queue = UpdateQueue::new(1, Backpressure::DropNewest);
+        assert_eq!(queue.push(1), Pushed::Queued);
+        assert_eq!(queue.push(2), Pushed::DroppedNewest);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.try_pop(), Some(1));
+    }
+
+    /// Blocking pushes wait until the consumer makes room
+    #[test]
+    fn block() {
+        let queue = UpdateQueue::new(1, Backpressure::Block);
+        queue.push(1);
+        let producer = queue.clone();
+        let pushing = thread::spawn(move || producer.push(2));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(pushing.join().unwrap(), Pushed::Queued);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    /// Closing drains what's queued, then stops everyone waiting
+    #[test]
+    fn close() {
+        let queue = UpdateQueue::new(1, Backpressure::Block);
+        queue.push(1);
+        let producer = queue.clone();
+        let pushing = thread::spawn(move || producer.push(2));
+        thread::sleep(Duration::from_millis(20));
+        queue.close();
+        assert_eq!(pushing.join().unwrap(), Pushed::Closed);
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.pop_timeout(Duration::from_secs(1)), None);
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    /// Waiting for an update gives up after the timeout
+    #[test]
+    fn pop_timeout() {
+        let queue = UpdateQueue::<u8>::new(1, Backpressure::default());
+        assert_eq!(queue.pop_timeout(Duration::from_millis(10)), None);
+        let producer = queue.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            producer.push(7);
+        });
+        assert_eq!(queue.pop_timeout(Duration::from_secs(5)), Some(7));
+    }
+}
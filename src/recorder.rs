@@ -0,0 +1,342 @@
+//! Recording published values and device readings to a file
+//!
+//! A [`Recorder`] appends one record per published report and per status
+//! read, timestamped, as CSV or JSON lines. Tuning a fan curve or finding
+//! out what happened before a thermal shutdown then means reading back
+//! what the device was told and what it measured:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{daemon::SyncEngine, recorder::{Format, Recorder}, Octo};
+//! let recorder = Recorder::create("/var/log/octo/telemetry.csv", Format::Csv)
+//!     .unwrap()
+//!     .with_rotation(10_000_000, 5);
+//! let mut engine = SyncEngine::new(Octo::new().unwrap()).with_recorder(recorder);
+//! engine.run_until(|| false);
+//! ```
+//!
+//! CSV files have one value per row, `time,kind,channel,value`, with time
+//! in Unix seconds, channels counted from 0 and values in degrees Celsius,
+//! litres per hour, percent, RPM or watts. Disconnected values are left
+//! empty. JSON lines hold `time` and either `virtual_sensors` in
+//! centidegrees or `status` as [`Status`] serializes.
+//!
+//! Every record is written straight through, so nothing is lost when the
+//! machine goes down.
+use crate::error::{Context, Result};
+use crate::status::Status;
+use serde::Serialize;
+use std::{
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// First line of every CSV file
+static CSV_HEADER: &str = "time,kind,channel,value\n";
+
+/// How records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// One value per row
+    #[default]
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+impl Format {
+    /// JSON lines for `.jsonl` and `.json` files, CSV otherwise
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("jsonl" | "json") => Self::JsonLines,
+            _ => Self::Csv,
+        }
+    }
+
+    /// What every new file starts with
+    fn header(self) -> &'static str {
+        match self {
+            Self::Csv => CSV_HEADER,
+            Self::JsonLines => "",
+        }
+    }
+}
+
+/// Appends timestamped records to a file, rotating it when it grows
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    format: Format,
+    file: File,
+    len: u64,
+    rotation: Option<(u64, usize)>,
+}
+
+impl Recorder {
+    /// Append to `path`, creating it if needed
+    pub fn create(path: impl Into<PathBuf>, format: Format) -> Result<Self> {
+        let path = path.into();
+        let (file, len) = Self::open(&path, format)?;
+        Ok(Self {
+            path,
+            format,
+            file,
+            len,
+            rotation: None,
+        })
+    }
+
+    /// Start a new file once this one passes `max_bytes`, keeping `keep`
+    /// old ones as `PATH.1` (newest) to `PATH.<keep>`
+    ///
+    /// Files grow without limit otherwise. At least one old file is kept.
+    pub fn with_rotation(mut self, max_bytes: u64, keep: usize) -> Self {
+        self.rotation = Some((max_bytes, keep.max(1)));
+        self
+    }
+
+    /// The file being written
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record the values sent to the virtual sensors, in centidegrees
+    pub fn record_sent(&mut self, values: &[Option<i16>]) -> Result<()> {
+        self.record_sent_at(SystemTime::now(), values)
+    }
+
+    /// Record a status read from the device
+    pub fn record_status(&mut self, status: &Status) -> Result<()> {
+        self.record_status_at(SystemTime::now(), status)
+    }
+
+    /// [`Recorder::record_sent`] at `time`
+    fn record_sent_at(&mut self, time: SystemTime, values: &[Option<i16>]) -> Result<()> {
+        let time = seconds(time);
+        let record = match self.format {
+            Format::Csv => {
+                let mut rows = String::new();
+                csv_temperatures(&mut rows, time, "virtual_sensor", values);
+                rows
+            }
+            Format::JsonLines => json_line(time, "virtual_sensors", values)?,
+        };
+        self.write(&record)
+    }
+
+    /// [`Recorder::record_status`] at `time`
+    fn record_status_at(&mut self, time: SystemTime, status: &Status) -> Result<()> {
+        let time = seconds(time);
+        let record = match self.format {
+            Format::Csv => {
+                let mut rows = String::new();
+                csv_temperatures(&mut rows, time, "sensor", &status.sensors);
+                if let Some(flow) = status.flow {
+                    csv_row(&mut rows, time, "flow", 0, Some(f64::from(flow) / 10.0));
+                }
+                for (channel, fan) in status.fans.iter().enumerate() {
+                    let mut row =
+                        |kind, value: f64| csv_row(&mut rows, time, kind, channel, Some(value));
+                    row("fan_percent", f64::from(fan.duty) / 100.0);
+                    row("fan_rpm", f64::from(fan.rpm));
+                    row("fan_watts", f64::from(fan.power) / 100.0);
+                }
+                rows
+            }
+            Format::JsonLines => json_line(time, "status", status)?,
+        };
+        self.write(&record)
+    }
+
+    /// Append `record`, rotating first if it would take the file past its
+    /// limit
+    fn write(&mut self, record: &str) -> Result<()> {
+        if let Some((max_bytes, keep)) = self.rotation {
+            let header = self.format.header().len() as u64;
+            if self.len > header && self.len + record.len() as u64 > max_bytes {
+                self.rotate(keep)?;
+            }
+        }
+        self.file
+            .write_all(record.as_bytes())
+            .with_context(|| format!("Recording to {}", self.path.display()))?;
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Shift the old files along and start an empty one
+    fn rotate(&mut self, keep: usize) -> Result<()> {
+        let numbered = |index: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{index}"));
+            PathBuf::from(path)
+        };
+        for index in (1..keep).rev() {
+            let from = numbered(index);
+            if from.exists() {
+                fs::rename(&from, numbered(index + 1))
+                    .with_context(|| format!("Rotating {}", from.display()))?;
+            }
+        }
+        fs::rename(&self.path, numbered(1))
+            .with_context(|| format!("Rotating {}", self.path.display()))?;
+        (self.file, self.len) = Self::open(&self.path, self.format)?;
+        Ok(())
+    }
+
+    /// Open `path` for appending, writing the header if it's empty, and
+    /// return it with its length
+    fn open(path: &Path, format: Format) -> Result<(File, u64)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Opening {}", path.display()))?;
+        let mut len = file
+            .metadata()
+            .with_context(|| format!("Opening {}", path.display()))?
+            .len();
+        if len == 0 {
+            let header = format.header();
+            file.write_all(header.as_bytes())
+                .with_context(|| format!("Writing {}", path.display()))?;
+            len = header.len() as u64;
+        }
+        Ok((file, len))
+    }
+}
+
+/// Seconds since the Unix epoch
+fn seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Append a CSV row, leaving the value empty for `None`
+fn csv_row(rows: &mut String, time: f64, kind: &str, channel: usize, value: Option<f64>) {
+    let value = value.map(|value| value.to_string()).unwrap_or_default();
+    let _ = writeln!(rows, "{time:.3},{kind},{channel},{value}");
+}
+
+/// Append a row per centidegree value, in degrees
+fn csv_temperatures(rows: &mut String, time: f64, kind: &str, values: &[Option<i16>]) {
+    for (channel, value) in values.iter().enumerate() {
+        let degrees = value.map(|value| f64::from(value) / 100.0);
+        csv_row(rows, time, kind, channel, degrees);
+    }
+}
+
+/// A JSON line with the time and one other member
+fn json_line(time: f64, key: &str, value: &(impl Serialize + ?Sized)) -> Result<String> {
+    let time = serde_json::to_string(&((time * 1000.0).round() / 1000.0))?;
+    let key = serde_json::to_string(key)?;
+    let value = serde_json::to_string(value)?;
+    Ok(format!("{{\"time\":{time},{key}:{value}}}\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Format, Recorder};
+    use crate::status::{FanStatus, Status};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    /// A status with one sensor, a flow reading and one fan
+    fn status() -> Status {
+        Status {
+            power_cycles: 3,
+            sensors: vec![Some(3150), None],
+            virtual_sensors: vec![None],
+            flow: Some(1234),
+            fans: vec![FanStatus {
+                duty: 4550,
+                voltage: 1200,
+                current: 100,
+                power: 120,
+                rpm: 900,
+            }],
+        }
+    }
+
+    /// CSV rows carry one value each in plain units
+    #[test]
+    fn csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.csv");
+        assert_eq!(Format::for_path(&path), Format::Csv);
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+        let mut recorder = Recorder::create(&path, Format::Csv).unwrap();
+        recorder.record_sent_at(time, &[Some(4120), None]).unwrap();
+        recorder.record_status_at(time, &status()).unwrap();
+        drop(recorder);
+        Recorder::create(&path, Format::Csv).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "time,kind,channel,value\n\
+             1700000000.250,virtual_sensor,0,41.2\n\
+             1700000000.250,virtual_sensor,1,\n\
+             1700000000.250,sensor,0,31.5\n\
+             1700000000.250,sensor,1,\n\
+             1700000000.250,flow,0,123.4\n\
+             1700000000.250,fan_percent,0,45.5\n\
+             1700000000.250,fan_rpm,0,900\n\
+             1700000000.250,fan_watts,0,1.2\n"
+        );
+    }
+
+    /// JSON lines read back as the values recorded
+    #[test]
+    fn json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.jsonl");
+        assert_eq!(Format::for_path(&path), Format::JsonLines);
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut recorder = Recorder::create(&path, Format::JsonLines).unwrap();
+        recorder.record_sent_at(time, &[Some(4120), None]).unwrap();
+        recorder.record_status_at(time, &status()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["time"].as_f64().unwrap(), 1.7e9);
+        assert_eq!(lines[0]["virtual_sensors"].to_string(), "[4120,null]");
+        let read: Status = serde_json::from_value(lines[1]["status"].clone()).unwrap();
+        assert_eq!(read, status());
+    }
+
+    /// Full files move to numbered ones and the oldest is dropped
+    #[test]
+    fn rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("telemetry.csv");
+        let mut recorder = Recorder::create(&path, Format::Csv)
+            .unwrap()
+            .with_rotation(100, 2);
+        for value in 0..20 {
+            recorder.record_sent(&[Some(value)]).unwrap();
+        }
+        let numbered = |index| PathBuf::from(format!("{}.{index}", path.display()));
+        let files = [path.clone(), numbered(1), numbered(2)];
+        for file in &files {
+            let text = fs::read_to_string(file).unwrap();
+            assert!(text.starts_with("time,kind,channel,value\n"));
+            assert!(
+                text.len() <= 100,
+                "{} has {} bytes",
+                file.display(),
+                text.len()
+            );
+        }
+        assert!(!Path::new(&numbered(3)).exists());
+        let newest = fs::read_to_string(&path).unwrap();
+        assert!(newest.ends_with(",virtual_sensor,0,0.19\n"));
+    }
+}
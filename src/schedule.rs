@@ -0,0 +1,188 @@
+//! Daily time windows
+//!
+//! A [`Schedule`] is a window of local wall-clock time that repeats every
+//! day, such as quiet hours from 23:00 to 07:00.
+use crate::error::{Context, Result};
+use std::{fmt, str::FromStr};
+
+/// Minutes in a day
+static MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Wall-clock time to the minute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimeOfDay {
+    minutes: u16,
+}
+
+impl TimeOfDay {
+    /// Midnight
+    pub const MIDNIGHT: Self = Self { minutes: 0 };
+
+    /// Time from hours and minutes, `None` if out of range
+    pub fn new(hour: u8, minute: u8) -> Option<Self> {
+        (hour < 24 && minute < 60).then(|| Self {
+            minutes: u16::from(hour) * 60 + u16::from(minute),
+        })
+    }
+
+    /// Current local time
+    pub fn now() -> Result<Self> {
+        // SAFETY: time accepts a null pointer, `tm` is plain data that
+        // localtime_r fills in on success
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+            bail!("Local time is unavailable");
+        }
+        Self::new(tm.tm_hour as u8, tm.tm_min as u8).context("Local time out of range")
+    }
+
+    /// Hour, 0 to 23
+    pub fn hour(self) -> u8 {
+        (self.minutes / 60) as u8
+    }
+
+    /// Minute, 0 to 59
+    pub fn minute(self) -> u8 {
+        (self.minutes % 60) as u8
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour(), self.minute())
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = crate::Error;
+
+    /// Parse `HH:MM` in 24 hour time
+    fn from_str(s: &str) -> Result<Self> {
+        let (hour, minute) = s
+            .split_once(':')
+            .with_context(|| format!("Expected HH:MM, got {s:?}"))?;
+        let hour = hour.parse().with_context(|| format!("Bad hour in {s:?}"))?;
+        let minute = minute
+            .parse()
+            .with_context(|| format!("Bad minute in {s:?}"))?;
+        Self::new(hour, minute).with_context(|| format!("{s:?} is not a time of day"))
+    }
+}
+
+/// A daily window from `start` up to, not including, `end`
+///
+/// Windows with `end` before `start` run past midnight. Equal ends cover
+/// the whole day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Schedule {
+    /// First minute inside the window
+    pub start: TimeOfDay,
+    /// First minute after the window
+    pub end: TimeOfDay,
+}
+
+impl Schedule {
+    /// Window from `start` to `end`
+    pub fn new(start: TimeOfDay, end: TimeOfDay) -> Self {
+        Self { start, end }
+    }
+
+    /// Whether `time` falls inside the window
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        let length = (self.end.minutes + MINUTES_PER_DAY - self.start.minutes) % MINUTES_PER_DAY;
+        let elapsed = (time.minutes + MINUTES_PER_DAY - self.start.minutes) % MINUTES_PER_DAY;
+        length == 0 || elapsed < length
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = crate::Error;
+
+    /// Parse `HH:MM-HH:MM`
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once('-')
+            .with_context(|| format!("Expected HH:MM-HH:MM, got {s:?}"))?;
+        Ok(Self::new(start.trim().parse()?, end.trim().parse()?))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Schedule {
+    /// The form [`Schedule::from_str`] parses, such as `"23:00-07:00"`
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Schedule {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+        text.parse()
+            .map_err(|error| serde::de::Error::custom(format!("{error:#}")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Schedule, TimeOfDay};
+
+    /// Parse a time, panicking on bad test input
+    fn at(s: &str) -> TimeOfDay {
+        s.parse().unwrap()
+    }
+
+    /// Times parse and print as HH:MM
+    #[test]
+    fn parse_time() {
+        assert_eq!(at("07:05"), TimeOfDay::new(7, 5).unwrap());
+        assert_eq!(at("23:59").to_string(), "23:59");
+        assert_eq!(at("0:00"), TimeOfDay::MIDNIGHT);
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!("12:60".parse::<TimeOfDay>().is_err());
+        assert!("noon".parse::<TimeOfDay>().is_err());
+    }
+
+    /// Windows past midnight include both evening and morning
+    #[test]
+    fn overnight_window() {
+        let quiet: Schedule = "23:00-07:00".parse().unwrap();
+        assert!(quiet.contains(at("23:00")));
+        assert!(quiet.contains(at("00:00")));
+        assert!(quiet.contains(at("06:59")));
+        assert!(!quiet.contains(at("07:00")));
+        assert!(!quiet.contains(at("22:59")));
+        assert_eq!(quiet.to_string(), "23:00-07:00");
+    }
+
+    /// Daytime windows and whole-day windows
+    #[test]
+    fn daytime_window() {
+        let work: Schedule = "09:00 - 17:30".parse().unwrap();
+        assert!(work.contains(at("09:00")));
+        assert!(work.contains(at("17:29")));
+        assert!(!work.contains(at("17:30")));
+        assert!(!work.contains(at("08:59")));
+        let always = Schedule::new(at("12:00"), at("12:00"));
+        assert!(always.contains(at("03:00")));
+    }
+
+    /// Local time is available
+    #[test]
+    fn now() {
+        TimeOfDay::now().unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! One device shared between threads
+//!
+//! [`Octo::share`] wraps the device in a lock and returns a [`SharedOcto`]
+//! that is `Send`, `Sync` and cheap to clone, so a web server, a metrics
+//! scraper and an update loop can use the same device:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::Octo;
+//! let octo = Octo::new().unwrap().share();
+//! let scraper = octo.clone();
+//! std::thread::spawn(move || println!("{:?}", scraper.read_status()));
+//! octo.update_centidegrees(&[Some(4120)]).unwrap();
+//! ```
+//!
+//! Every call holds the lock for its whole exchange with the device, so
+//! reports and the replies to them never interleave. [`SharedOcto::lock`]
+//! holds it across several calls. Unlike [`OctoHandle`](crate::OctoHandle)
+//! calls wait for the device and return its answer.
+use crate::error::Result;
+use crate::{status::Status, Octo};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Cloneable, thread-safe handle to an [`Octo`]
+///
+/// The device is closed once every clone is dropped.
+#[derive(Clone)]
+pub struct SharedOcto {
+    octo: Arc<Mutex<Octo>>,
+}
+
+impl SharedOcto {
+    /// Share `octo`
+    pub fn new(octo: Octo) -> Self {
+        Self {
+            octo: Arc::new(Mutex::new(octo)),
+        }
+    }
+
+    /// Exclusive use of the device until the guard is dropped
+    ///
+    /// A thread that panicked while holding the lock leaves the device
+    /// usable, as every report is rebuilt in full before it is sent.
+    pub fn lock(&self) -> MutexGuard<'_, Octo> {
+        self.octo.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// See [`Octo::update_virtual_sensors`]
+    pub fn update_virtual_sensors(&self, sensor_values: &[i16]) -> Result<usize> {
+        self.lock().update_virtual_sensors(sensor_values)
+    }
+
+    /// See [`Octo::update_virtual_sensors_f32`]
+    pub fn update_virtual_sensors_f32(&self, sensor_values: &[f32]) -> Result<usize> {
+        self.lock().update_virtual_sensors_f32(sensor_values)
+    }
+
+    /// See [`Octo::update_centidegrees`]
+    pub fn update_centidegrees(&self, values: &[Option<i16>]) -> Result<usize> {
+        self.lock().update_centidegrees(values)
+    }
+
+    /// See [`Octo::update_slots`]
+    pub fn update_slots(&self, values: &[(usize, Option<i16>)]) -> Result<usize> {
+        self.lock().update_slots(values)
+    }
+
+    /// See [`Octo::set_virtual_sensor`]
+    pub fn set_virtual_sensor(&self, index: usize, degrees: f32) -> Result<usize> {
+        self.lock().set_virtual_sensor(index, degrees)
+    }
+
+    /// See [`Octo::clear_virtual_sensor`]
+    pub fn clear_virtual_sensor(&self, index: usize) -> Result<usize> {
+        self.lock().clear_virtual_sensor(index)
+    }
+
+    /// Values of the last report sent, in centidegrees
+    pub fn virtual_sensors(&self) -> Vec<Option<i16>> {
+        self.lock().last_report().values()
+    }
+
+    /// See [`Octo::read_status`]
+    pub fn read_status(&self) -> Result<Status> {
+        self.lock().read_status()
+    }
+
+    /// See [`Octo::read_sensors`]
+    pub fn read_sensors(&self) -> Result<Vec<Option<f32>>> {
+        self.lock().read_sensors()
+    }
+
+    /// See [`Octo::keep_alive`]
+    pub fn keep_alive(&self) -> Result<bool> {
+        self.lock().keep_alive()
+    }
+
+    /// See [`Octo::reopen`]
+    pub fn reopen(&self) -> Result<()> {
+        self.lock().reopen()
+    }
+}
+
+impl From<Octo> for SharedOcto {
+    fn from(octo: Octo) -> Self {
+        Self::new(octo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SharedOcto;
+    use crate::{emulator::Emulator, Octo};
+    use std::thread;
+
+    /// Clones used from several threads write whole reports
+    #[test]
+    fn share() {
+        fn send_sync<T: Send + Sync + Clone>() {}
+        send_sync::<SharedOcto>();
+        let emulator = Emulator::new();
+        let octo = Octo::with_transport(emulator.clone()).unwrap().share();
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let octo = octo.clone();
+                thread::spawn(move || {
+                    for value in 0..25 {
+                        let value = Some(writer * 1000 + value);
+                        octo.update_centidegrees(&[value, value]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        octo.read_status().unwrap();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(emulator.accepted_reports(), 100);
+        let values = emulator.virtual_sensors();
+        assert_eq!(values[0], values[1]);
+        assert_eq!(octo.virtual_sensors()[..2], values[..2]);
+    }
+}
@@ -0,0 +1,591 @@
+//! Where published values come from
+//!
+//! A [`Source`] produces one value in centidegrees. [`poll`] reads a set of
+//! sources into slot-indexed values ready for
+//! [`Octo::update_centidegrees`](crate::Octo::update_centidegrees).
+//!
+//! A [`Poller`] keeps sources on their slots and reads each at its own
+//! interval, for sources too slow to run on every update.
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{source, Octo};
+//! let mut octo = Octo::new().unwrap();
+//! // Forward every channel of the other Aquacomputer devices in the loop
+//! let mut sources = source::aquacomputer_sources().unwrap();
+//! let values = source::poll(sources.iter_mut().enumerate().map(|(slot, source)| {
+//!     (slot, source as &mut dyn source::Source)
+//! }));
+//! octo.update_centidegrees(&values).unwrap();
+//! ```
+use crate::error::{Context, Result};
+use crate::{kernel, units::Unit};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// Produces a value to publish
+pub trait Source {
+    /// Name shown in diagnostics
+    fn name(&self) -> &str;
+
+    /// Current value in centidegrees, `None` if there is none right now
+    fn read(&mut self) -> Result<Option<i16>>;
+}
+
+/// Read `sources` into slot-indexed values
+///
+/// Sources that fail publish nothing for their slot, with a warning, so
+/// one broken source doesn't hold back the others.
+pub fn poll<'a>(
+    sources: impl IntoIterator<Item = (usize, &'a mut dyn Source)>,
+) -> Vec<Option<i16>> {
+    let mut values = Vec::new();
+    for (slot, source) in sources {
+        let value = read_or_warn(source).unwrap_or_default();
+        if values.len() <= slot {
+            values.resize(slot + 1, None);
+        }
+        values[slot] = value;
+    }
+    values
+}
+
+/// Read `source`, warning if it fails
+fn read_or_warn(source: &mut dyn Source) -> Result<Option<i16>, String> {
+    source.read().map_err(|error| {
+        warn!("Reading {}: {error:#}", source.name());
+        format!("{error:#}")
+    })
+}
+
+/// How a source on a [`Poller`] has been doing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceHealth {
+    /// Slot it publishes on, numbered from 0
+    pub slot: usize,
+    /// Its [`Source::name`]
+    pub name: String,
+    /// Value it last published
+    pub value: Option<i16>,
+    /// Why its last read failed, `None` if it didn't
+    pub error: Option<String>,
+    /// Reads that failed in a row
+    pub failures: u32,
+}
+
+/// Sources on their slots, each read at its own interval
+///
+/// Sources added without an interval are read on every
+/// [`Poller::poll`]. The others are read when their interval has passed
+/// and publish their last value in between.
+#[derive(Default)]
+pub struct Poller {
+    sources: Vec<Scheduled>,
+}
+
+/// A source with its slot and when to read it next
+struct Scheduled {
+    slot: usize,
+    source: Box<dyn Source + Send>,
+    every: Option<Duration>,
+    next: Option<Instant>,
+    last: Option<i16>,
+    error: Option<String>,
+    failures: u32,
+}
+
+impl Poller {
+    /// Poller without sources
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `source` on every poll and publish it on `slot`
+    pub fn add(&mut self, slot: usize, source: impl Source + Send + 'static) {
+        self.push(slot, Box::new(source), None);
+    }
+
+    /// Read `source` at most once every `every` and publish it on `slot`
+    pub fn add_every(
+        &mut self,
+        slot: usize,
+        source: impl Source + Send + 'static,
+        every: Duration,
+    ) {
+        self.push(slot, Box::new(source), Some(every));
+    }
+
+    fn push(&mut self, slot: usize, source: Box<dyn Source + Send>, every: Option<Duration>) {
+        self.sources.push(Scheduled {
+            slot,
+            source,
+            every,
+            next: None,
+            last: None,
+            error: None,
+            failures: 0,
+        });
+    }
+
+    /// Number of sources
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Whether there are no sources
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// How each source has been doing, in the order they were added
+    pub fn health(&self) -> Vec<SourceHealth> {
+        self.sources
+            .iter()
+            .map(|scheduled| SourceHealth {
+                slot: scheduled.slot,
+                name: scheduled.source.name().to_owned(),
+                value: scheduled.last,
+                error: scheduled.error.clone(),
+                failures: scheduled.failures,
+            })
+            .collect()
+    }
+
+    /// Read the sources that are due into slot-indexed values, like
+    /// [`poll`]
+    pub fn poll(&mut self) -> Vec<Option<i16>> {
+        self.poll_at(Instant::now())
+    }
+
+    /// [`Poller::poll`] as if it were `now`
+    fn poll_at(&mut self, now: Instant) -> Vec<Option<i16>> {
+        for scheduled in &mut self.sources {
+            if scheduled.next.is_some_and(|next| now < next) {
+                continue;
+            }
+            scheduled.next = scheduled.every.map(|every| now + every);
+            match read_or_warn(scheduled.source.as_mut()) {
+                Ok(value) => {
+                    scheduled.last = value;
+                    scheduled.error = None;
+                    scheduled.failures = 0;
+                }
+                Err(error) => {
+                    scheduled.last = None;
+                    scheduled.error = Some(error);
+                    scheduled.failures = scheduled.failures.saturating_add(1);
+                }
+            }
+        }
+        let mut values = Vec::new();
+        for scheduled in &self.sources {
+            if values.len() <= scheduled.slot {
+                values.resize(scheduled.slot + 1, None);
+            }
+            values[scheduled.slot] = scheduled.last;
+        }
+        values
+    }
+}
+
+/// One hwmon sysfs channel, such as `temp1_input`
+///
+/// Temperatures publish as is. Other readings are published as if they
+/// were degrees, the way virtual sensors are commonly used for flow or
+/// pump speed, scaled to fit below 327.67: voltages, currents and power in
+/// V, A and W, fan speeds in hundreds of RPM, and flow, which the driver
+/// labels in dL/h, in l/h.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HwmonSource {
+    name: String,
+    input: PathBuf,
+    divisor: i64,
+}
+
+impl HwmonSource {
+    /// Source for channel `channel`, e.g. `temp1`, in hwmon directory `dir`
+    ///
+    /// Fails for channel types without a known unit.
+    pub fn new(dir: impl AsRef<Path>, channel: &str) -> Result<Self> {
+        let dir = dir.as_ref();
+        let kind = channel.trim_end_matches(|c: char| c.is_ascii_digit());
+        let chip = fs::read_to_string(dir.join("name")).unwrap_or_default();
+        let label = fs::read_to_string(dir.join(format!("{channel}_label")))
+            .unwrap_or_else(|_| channel.to_owned());
+        // sysfs units: millidegrees, RPM or dL/h, millivolts, milliamps,
+        // microwatts
+        let divisor = match kind {
+            "temp" | "in" | "curr" => 1000,
+            "fan" if label.contains("dL/h") => 10,
+            "fan" => 100,
+            "power" => 1_000_000,
+            _ => bail!("Unsupported hwmon channel {channel}"),
+        };
+        Ok(Self {
+            name: format!("{}/{}", chip.trim(), label.trim()),
+            input: dir.join(format!("{channel}_input")),
+            divisor,
+        })
+    }
+}
+
+impl HwmonSource {
+    /// Source for a `*_input` file, e.g. `/sys/class/hwmon/hwmon2/temp1_input`
+    pub fn from_input(input: impl AsRef<Path>) -> Result<Self> {
+        let input = input.as_ref();
+        let channel = input
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix("_input"))
+            .with_context(|| format!("{} is not a hwmon *_input file", input.display()))?;
+        Self::new(input.parent().unwrap_or(Path::new(".")), channel)
+    }
+
+    /// Source for `channel` of the hwmon chip named `chip`, e.g. `k10temp`
+    ///
+    /// hwmon directory numbers change between boots, chip names don't.
+    /// Fails if no chip has the name.
+    pub fn find(chip: &str, channel: &str) -> Result<Self> {
+        Self::find_in(Path::new("/sys/class/hwmon"), chip, channel)
+    }
+
+    /// Source from a path to a `*_input` file, or `CHIP/CHANNEL`
+    ///
+    /// The form taken by the command line and configuration files.
+    pub fn from_spec(spec: &str) -> Result<Self> {
+        if spec.starts_with('/') {
+            return Self::from_input(spec);
+        }
+        let (chip, channel) = spec
+            .split_once('/')
+            .with_context(|| format!("Expected a path or CHIP/CHANNEL, got {spec:?}"))?;
+        Self::find(chip, channel)
+    }
+
+    /// [`HwmonSource::find`] under `root`
+    fn find_in(root: &Path, chip: &str, channel: &str) -> Result<Self> {
+        for entry in fs::read_dir(root).with_context(|| format!("Listing {}", root.display()))? {
+            let dir = entry?.path();
+            let name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+            if name.trim() == chip {
+                return Self::new(dir, channel);
+            }
+        }
+        bail!("No hwmon chip named {chip}")
+    }
+}
+
+impl Source for HwmonSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&mut self) -> Result<Option<i16>> {
+        let raw = fs::read_to_string(&self.input)
+            .with_context(|| format!("Reading {}", self.input.display()))?;
+        let raw: i64 = raw
+            .trim()
+            .parse()
+            .with_context(|| format!("Parsing {}", self.input.display()))?;
+        let centi = raw.saturating_mul(100) / self.divisor;
+        Ok(i16::try_from(centi).ok().filter(|&value| value != i16::MAX))
+    }
+}
+
+/// A value that never changes, such as a placeholder for a missing sensor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedSource(pub i16);
+
+impl Source for FixedSource {
+    fn name(&self) -> &str {
+        "fixed"
+    }
+
+    fn read(&mut self) -> Result<Option<i16>> {
+        Ok(Some(self.0))
+    }
+}
+
+/// The first number a shell command prints, in `unit`
+///
+/// For sensors without an hwmon driver, e.g. `nvidia-smi
+/// --query-gpu=temperature.gpu --format=csv,noheader`. The command runs
+/// through `sh -c` on every read and must exit successfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSource {
+    command: String,
+    unit: Unit,
+}
+
+impl CommandSource {
+    /// Source running `command`, whose output is in `unit`
+    pub fn new(command: impl Into<String>, unit: Unit) -> Self {
+        Self {
+            command: command.into(),
+            unit,
+        }
+    }
+}
+
+impl Source for CommandSource {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn read(&mut self) -> Result<Option<i16>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .with_context(|| format!("Running {}", self.command))?;
+        if !output.status.success() {
+            bail!("{} exited with {}", self.command, output.status);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let degrees: f64 = stdout
+            .split_whitespace()
+            .next()
+            .with_context(|| format!("{} printed nothing", self.command))?
+            .parse()
+            .with_context(|| format!("Parsing the output of {}", self.command))?;
+        self.unit.centidegrees(degrees).map(Some)
+    }
+}
+
+/// Values from a closure, for readings another crate already has
+///
+/// ```
+/// use octo_virtual_sensors::source::{FnSource, Source};
+/// let mut gpu = FnSource::new("gpu", || Ok(Some(5500)));
+/// assert_eq!(gpu.read().unwrap(), Some(5500));
+/// ```
+pub struct FnSource<F> {
+    name: String,
+    read: F,
+}
+
+impl<F: FnMut() -> Result<Option<i16>>> FnSource<F> {
+    /// Source named `name` reading centidegrees from `read`
+    pub fn new(name: impl Into<String>, read: F) -> Self {
+        Self {
+            name: name.into(),
+            read,
+        }
+    }
+}
+
+impl<F: FnMut() -> Result<Option<i16>>> Source for FnSource<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&mut self) -> Result<Option<i16>> {
+        (self.read)()
+    }
+}
+
+/// Channels of every Aquacomputer device except the Octo itself
+///
+/// Finds hwmon devices bound to the aquacomputer_d5next driver, so D5 Next
+/// coolant temperatures or High Flow Next readings can be forwarded to the
+/// Octo's virtual sensors.
+pub fn aquacomputer_sources() -> Result<Vec<HwmonSource>> {
+    aquacomputer_sources_in(
+        Path::new("/sys/class/hwmon"),
+        crate::layout::OCTO.hwmon_name,
+    )
+}
+
+/// Aquacomputer channels under `root`, skipping chips named `exclude`
+fn aquacomputer_sources_in(root: &Path, exclude: &str) -> Result<Vec<HwmonSource>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(root).with_context(|| format!("Listing {}", root.display()))? {
+        let dir = entry?.path();
+        let driver = fs::read_link(dir.join("device/driver")).unwrap_or_default();
+        let name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+        if driver
+            .file_name()
+            .is_some_and(|d| d == kernel::HWMON_DRIVER)
+            && name.trim() != exclude
+        {
+            dirs.push(dir);
+        }
+    }
+    dirs.sort();
+    let mut sources = Vec::new();
+    for dir in dirs {
+        let mut channels: Vec<String> = fs::read_dir(&dir)?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.strip_suffix("_input").map(str::to_owned)
+            })
+            .collect();
+        channels.sort_by_key(|channel| channel_order(channel));
+        sources.extend(
+            channels
+                .iter()
+                .filter_map(|channel| HwmonSource::new(&dir, channel).ok()),
+        );
+    }
+    Ok(sources)
+}
+
+/// Sort key putting channels in type then numeric order
+fn channel_order(channel: &str) -> (String, u32) {
+    let split = channel.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (kind, number) = channel.split_at(split);
+    (kind.to_owned(), number.parse().unwrap_or(0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        aquacomputer_sources_in, poll, CommandSource, FnSource, HwmonSource, Poller, Source,
+    };
+    use crate::{testing::HwmonTree, units::Unit};
+    use std::{
+        fs,
+        path::Path,
+        sync::{
+            atomic::{AtomicI16, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    /// Fake hwmon tree with a D5 Next, an Octo and an unrelated chip
+    fn hwmon_tree() -> HwmonTree {
+        let tree = HwmonTree::new();
+        let chips = [
+            ("hwmon0", "d5next", true),
+            ("hwmon1", "octo", true),
+            ("hwmon2", "k10temp", false),
+            ("hwmon3", "highflownext", true),
+        ];
+        for (dir, chip, aquacomputer) in chips {
+            let dir = tree.chip(dir, chip, aquacomputer);
+            fs::write(dir.join("temp1_input"), "31250\n").unwrap();
+        }
+        let d5next = tree.class().join("hwmon0");
+        fs::write(d5next.join("temp1_label"), "Coolant temp\n").unwrap();
+        fs::write(d5next.join("fan1_input"), "2400\n").unwrap();
+        fs::write(d5next.join("in10_input"), "12100\n").unwrap();
+        let highflow = tree.class().join("hwmon3");
+        fs::write(highflow.join("fan1_label"), "Flow [dL/h]\n").unwrap();
+        fs::write(highflow.join("fan1_input"), "1500\n").unwrap();
+        tree
+    }
+
+    /// Only other Aquacomputer chips are found, channels in order
+    #[test]
+    fn discover() {
+        let tree = hwmon_tree();
+        let mut sources = aquacomputer_sources_in(&tree.class(), "octo").unwrap();
+        let names: Vec<&str> = sources.iter().map(|source| source.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "d5next/fan1",
+                "d5next/in10",
+                "d5next/Coolant temp",
+                "highflownext/Flow [dL/h]",
+                "highflownext/temp1",
+            ]
+        );
+        let values: Vec<_> = sources
+            .iter_mut()
+            .map(|source| source.read().unwrap())
+            .collect();
+        assert_eq!(
+            values,
+            [Some(2400), Some(1210), Some(3125), Some(15000), Some(3125)]
+        );
+    }
+
+    /// Readings that don't fit are disconnected, unknown channels refused
+    #[test]
+    fn scaling() {
+        let tree = hwmon_tree();
+        let dir = tree.class().join("hwmon0");
+        let mut fan = HwmonSource::new(&dir, "fan1").unwrap();
+        assert_eq!(fan.read().unwrap(), Some(2400));
+        fs::write(dir.join("fan1_input"), "40000\n").unwrap();
+        assert_eq!(fan.read().unwrap(), None);
+        let mut volts = HwmonSource::new(&dir, "in10").unwrap();
+        fs::write(dir.join("in10_input"), "400000\n").unwrap();
+        assert_eq!(volts.read().unwrap(), None);
+        assert!(HwmonSource::new(&dir, "pwm1").is_err());
+    }
+
+    /// Sources can be named by input file or by chip name
+    #[test]
+    fn locate() {
+        let tree = hwmon_tree();
+        let mut by_path = HwmonSource::from_input(tree.class().join("hwmon2/temp1_input")).unwrap();
+        assert_eq!(by_path.name(), "k10temp/temp1");
+        assert_eq!(by_path.read().unwrap(), Some(3125));
+        let by_chip = HwmonSource::find_in(&tree.class(), "d5next", "temp1").unwrap();
+        assert_eq!(by_chip.name(), "d5next/Coolant temp");
+        assert!(HwmonSource::find_in(&tree.class(), "nvme", "temp1").is_err());
+        assert!(HwmonSource::from_input(Path::new("/sys/class/hwmon/hwmon0/name")).is_err());
+        assert!(HwmonSource::from_spec("k10temp").is_err());
+    }
+
+    /// Failing sources leave their slot disconnected
+    #[test]
+    fn poll_failures() {
+        let tree = hwmon_tree();
+        let dir = tree.class().join("hwmon0");
+        let mut good = HwmonSource::new(&dir, "temp1").unwrap();
+        let mut gone = HwmonSource::new(&dir, "temp9").unwrap();
+        let values = poll([(2, &mut good as &mut dyn Source), (0, &mut gone)]);
+        assert_eq!(values, [None, None, Some(3125)]);
+    }
+
+    /// Commands publish the first number they print
+    #[test]
+    fn command() {
+        let mut celsius = CommandSource::new("echo 41.5 C", Unit::Celsius);
+        assert_eq!(celsius.read().unwrap(), Some(4150));
+        let mut fahrenheit = CommandSource::new("printf '212\\n'", Unit::Fahrenheit);
+        assert_eq!(fahrenheit.read().unwrap(), Some(10000));
+        assert!(CommandSource::new("echo hot", Unit::Celsius)
+            .read()
+            .is_err());
+        assert!(CommandSource::new("true", Unit::Celsius).read().is_err());
+        assert!(CommandSource::new("echo 40; false", Unit::Celsius)
+            .read()
+            .is_err());
+    }
+
+    /// Sources with an interval publish their last value until they're due
+    #[test]
+    fn poller() {
+        let reading = Arc::new(AtomicI16::new(3000));
+        let slow = reading.clone();
+        let mut poller = Poller::new();
+        poller.add(0, FnSource::new("fast", || Ok(Some(2000))));
+        poller.add_every(
+            2,
+            FnSource::new("slow", move || Ok(Some(slow.load(Ordering::Relaxed)))),
+            Duration::from_secs(10),
+        );
+        poller.add(1, FnSource::new("broken", || bail!("gone")));
+        let start = Instant::now();
+        assert_eq!(poller.poll_at(start), [Some(2000), None, Some(3000)]);
+        reading.store(3500, Ordering::Relaxed);
+        let later = start + Duration::from_secs(5);
+        assert_eq!(poller.poll_at(later), [Some(2000), None, Some(3000)]);
+        let due = start + Duration::from_secs(10);
+        assert_eq!(poller.poll_at(due), [Some(2000), None, Some(3500)]);
+        assert_eq!(poller.len(), 3);
+        let health = poller.health();
+        assert_eq!((health[1].slot, health[1].name.as_str()), (2, "slow"));
+        assert_eq!((health[1].value, health[1].failures), (Some(3500), 0));
+        assert_eq!(health[2].error.as_deref(), Some("gone"));
+        assert_eq!((health[2].value, health[2].failures), (None, 3));
+    }
+}
@@ -0,0 +1,159 @@
+//! Last published values kept across restarts
+//!
+//! Between a restart and the first poll of every source, the virtual
+//! sensors read as disconnected and fan curves fall back to their
+//! failsafe, which usually means a spike to full speed. Republishing the
+//! values saved by the previous run closes that window.
+//!
+//! The file is plain text, one `SLOT CENTIDEGREES` line per connected
+//! slot after a line recording when it was saved:
+//!
+//! ```text
+//! saved 1712345678
+//! 0 4150
+//! 3 -250
+//! ```
+use crate::error::{Context, Result};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// State file holding the last published values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateFile {
+    path: PathBuf,
+    max_age: Duration,
+}
+
+impl StateFile {
+    /// State kept at `path`, trusted for ten minutes after saving
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_age: Duration::from_secs(600),
+        }
+    }
+
+    /// Ignore saved values older than `max_age`
+    ///
+    /// After a long power-off the saved temperatures say little about the
+    /// loop, so they shouldn't be republished.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// How long saved values are trusted for
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// Path of the state file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Save slot-indexed centidegree values
+    ///
+    /// The file is replaced in one step, so a crash mid-write leaves the
+    /// previous state.
+    pub fn save(&self, values: &[Option<i16>]) -> Result<()> {
+        let saved = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut contents = format!("saved {saved}\n");
+        for (slot, value) in values.iter().enumerate() {
+            if let Some(value) = value {
+                contents += &format!("{slot} {value}\n");
+            }
+        }
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, contents)
+            .with_context(|| format!("Writing {}", self.path.display()))?;
+        fs::rename(&temporary, &self.path)
+            .with_context(|| format!("Replacing {}", self.path.display()))
+    }
+
+    /// Load the saved values, `None` if there are none or they're too old
+    pub fn load(&self) -> Result<Option<Vec<Option<i16>>>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).with_context(|| format!("Reading {}", self.path.display()))
+            }
+        };
+        let (saved, values) =
+            parse(&contents).with_context(|| format!("Parsing {}", self.path.display()))?;
+        let age = SystemTime::now()
+            .duration_since(saved)
+            .unwrap_or(Duration::MAX);
+        Ok((age <= self.max_age).then_some(values))
+    }
+}
+
+/// Save time and values of a state file
+fn parse(contents: &str) -> Result<(SystemTime, Vec<Option<i16>>)> {
+    let mut lines = contents.lines();
+    let saved = lines
+        .next()
+        .and_then(|line| line.strip_prefix("saved "))
+        .and_then(|seconds| seconds.parse().ok())
+        .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+        .context("Missing save time")?;
+    let mut values = Vec::new();
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let parsed = line
+            .split_once(' ')
+            .and_then(|(slot, value)| Some((slot.parse::<usize>().ok()?, value.parse().ok()?)));
+        let Some((slot, value)) = parsed.filter(|&(slot, _)| slot < 256) else {
+            bail!("Bad line {line:?}");
+        };
+        if values.len() <= slot {
+            values.resize(slot + 1, None);
+        }
+        values[slot] = Some(value);
+    }
+    Ok((saved, values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, StateFile};
+    use std::time::Duration;
+
+    /// Saved values load back
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateFile::new(dir.path().join("state"));
+        assert_eq!(state.load().unwrap(), None);
+        state.save(&[Some(4150), None, None, Some(-250)]).unwrap();
+        let loaded = state.load().unwrap().unwrap();
+        assert_eq!(loaded, [Some(4150), None, None, Some(-250)]);
+    }
+
+    /// Old state isn't trusted
+    #[test]
+    fn too_old() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateFile::new(dir.path().join("state")).with_max_age(Duration::from_secs(60));
+        std::fs::write(state.path(), "saved 1000\n0 4000\n").unwrap();
+        assert_eq!(state.load().unwrap(), None);
+    }
+
+    /// Corrupt files are errors
+    #[test]
+    fn corrupt() {
+        assert!(parse("").is_err());
+        assert!(parse("saved soon\n").is_err());
+        assert!(parse("saved 1\n0\n").is_err());
+        assert!(parse("saved 1\n0 40000\n").is_err());
+        assert!(parse("saved 1\n9999 1\n").is_err());
+        assert_eq!(parse("saved 1\n\n2 5\n").unwrap().1, [None, None, Some(5)]);
+    }
+}
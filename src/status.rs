@@ -0,0 +1,183 @@
+//! Decoding the device's status report
+#![deny(clippy::indexing_slicing)]
+use crate::error::{Context, Result};
+use crate::{
+    codec,
+    layout::{FanFields, StatusLayout, SENSOR_SIZE},
+    OctoError,
+};
+
+/// Readings from one status report
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Status {
+    /// Times the device has been powered on, changes when it reboots
+    pub power_cycles: u32,
+    /// Physical temperature sensors in centidegrees
+    pub sensors: Vec<Option<i16>>,
+    /// Virtual sensors as the firmware sees them, in centidegrees
+    pub virtual_sensors: Vec<Option<i16>>,
+    /// Flow in dL/h, if the device has a flow sensor
+    pub flow: Option<u16>,
+    /// Readings of each fan channel
+    pub fans: Vec<FanStatus>,
+}
+
+/// Readings of one fan channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FanStatus {
+    /// Output power in centipercent, 0 for devices whose status report
+    /// doesn't carry it
+    pub duty: u16,
+    /// Voltage in centivolts
+    pub voltage: u16,
+    /// Current in milliamps
+    pub current: u16,
+    /// Power in centiwatts
+    pub power: u16,
+    /// Speed in RPM
+    pub rpm: u16,
+}
+
+impl FanStatus {
+    /// Decode the fan block at `offset`
+    fn parse(report: &[u8], offset: usize, fields: &FanFields) -> Result<Self> {
+        let duty = fields
+            .percent
+            .map(|percent| codec::get_u16(report, offset + percent))
+            .transpose()?;
+        Ok(Self {
+            duty: duty.unwrap_or_default(),
+            voltage: codec::get_u16(report, offset + fields.voltage)?,
+            current: codec::get_u16(report, offset + fields.current)?,
+            power: codec::get_u16(report, offset + fields.power)?,
+            rpm: codec::get_u16(report, offset + fields.speed)?,
+        })
+    }
+}
+
+impl Status {
+    /// Flow in litres per hour, if the device has a flow sensor
+    pub fn flow_litres_per_hour(&self) -> Option<f32> {
+        self.flow.map(|flow| f32::from(flow) / 10.0)
+    }
+
+    /// Decode a raw status report
+    ///
+    /// Fails if the length, report ID or checksum don't match the layout.
+    pub fn parse(layout: &StatusLayout, report: &[u8]) -> Result<Self> {
+        if report.len() != layout.len {
+            return Err(OctoError::InvalidReport).with_context(|| {
+                format!(
+                    "Expected {} byte status report, got {}",
+                    layout.len,
+                    report.len()
+                )
+            });
+        }
+        let id = report.first().copied().unwrap_or_default();
+        if id != layout.report_id {
+            return Err(OctoError::InvalidReport)
+                .with_context(|| format!("Expected report ID {}, got {id}", layout.report_id));
+        }
+        if !layout.checksum.verify(report) {
+            return Err(OctoError::ChecksumMismatch)
+                .with_context(|| format!("{} checksum mismatch", layout.checksum.name()));
+        }
+        Ok(Self {
+            power_cycles: codec::get_u32(report, layout.power_cycles)?,
+            sensors: (0..layout.sensor_count)
+                .map(|index| codec::get_temperature(report, layout.sensors + SENSOR_SIZE * index))
+                .collect::<Result<_>>()?,
+            virtual_sensors: (0..layout.virtual_sensor_count)
+                .map(|index| {
+                    let offset = layout.virtual_sensors + SENSOR_SIZE * index;
+                    codec::get_temperature(report, offset)
+                })
+                .collect::<Result<_>>()?,
+            flow: layout
+                .flow
+                .map(|offset| codec::get_u16(report, offset))
+                .transpose()?,
+            fans: layout
+                .fans
+                .iter()
+                .map(|&offset| FanStatus::parse(report, offset, &layout.fan_fields))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod test {
+    use super::Status;
+    use crate::{emulator::Emulator, layout::OCTO, OctoError};
+
+    /// Flow and fan speeds come from the emulated device
+    #[test]
+    fn parse_emulated() {
+        let emulator = Emulator::new();
+        emulator.set_flow(1500);
+        emulator.set_fan_rpm(7, 2100);
+        emulator.set_fan_current(7, 250);
+        let status = Status::parse(&OCTO.status, &emulator.status_report()).unwrap();
+        assert_eq!(status.power_cycles, 1);
+        assert_eq!(status.sensors, [Some(2500), None, None, None]);
+        assert_eq!(status.virtual_sensors, [None; 16]);
+        assert_eq!(status.flow, Some(1500));
+        let speeds: Vec<u16> = status.fans.iter().map(|fan| fan.rpm).collect();
+        assert_eq!(speeds, [0, 0, 0, 0, 0, 0, 0, 2100]);
+        let fan = status.fans[7];
+        assert_eq!((fan.voltage, fan.current, fan.power), (1200, 250, 300));
+    }
+
+    /// Truncated or corrupt reports are errors
+    #[test]
+    fn reject_bad_reports() {
+        let mut report = Emulator::new().status_report();
+        let error = Status::parse(&OCTO.status, &report[..100]).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::InvalidReport));
+        report[0x7B] ^= 1;
+        let error = Status::parse(&OCTO.status, &report).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::ChecksumMismatch));
+    }
+
+    /// A layout pointing past the end of its report is an error too
+    #[test]
+    fn layout_out_of_bounds() {
+        let report = Emulator::new().status_report();
+        let mut layout = OCTO.status;
+        layout.virtual_sensor_count = 200;
+        let error = Status::parse(&layout, &report).unwrap_err();
+        assert_eq!(error.kind(), Some(OctoError::InvalidLayout));
+    }
+
+    /// Readings and device info survive a trip through JSON
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        use crate::{units::Temperature, DeviceInfo};
+        let status = Status::parse(&OCTO.status, &Emulator::new().status_report()).unwrap();
+        let text = serde_json::to_string(&status).unwrap();
+        assert!(
+            text.contains(r#""sensors":[2500,null,null,null]"#),
+            "{text}"
+        );
+        assert_eq!(serde_json::from_str::<Status>(&text).unwrap(), status);
+        let info = DeviceInfo {
+            name: "Octo",
+            firmware: 1019,
+            serial: "12345-06789".to_owned(),
+            power_cycles: 3,
+        };
+        let text = serde_json::to_string(&info).unwrap();
+        assert_eq!(serde_json::from_str::<DeviceInfo>(&text).unwrap(), info);
+        let unknown = text.replace("Octo", "Kryos");
+        assert!(serde_json::from_str::<DeviceInfo>(&unknown).is_err());
+        let temperature = Temperature::celsius(41.5).unwrap();
+        assert_eq!(serde_json::to_string(&temperature).unwrap(), "41.5");
+        assert!(serde_json::from_str::<Temperature>("900").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Temperatures streamed in as lines of text
+//!
+//! [`watch`] reads lines such as `3=41.7` from stdin, a pipe or a socket
+//! and publishes each one as it arrives, so a script in any language can
+//! drive the virtual sensors by printing:
+//!
+//! ```sh
+//! mkfifo /run/octo-vs.fifo
+//! octo-vs watch /run/octo-vs.fifo &
+//! echo "1=41.5 2=38" > /run/octo-vs.fifo
+//! ```
+//!
+//! A line holds `SLOT=TEMP` pairs separated by whitespace, slots numbered
+//! from 1 as in `octo-vs set`. `SLOT=none` or `SLOT=` disconnects a slot,
+//! and slots not mentioned keep their values. Blank lines and lines
+//! starting with `#` are skipped. A line that doesn't parse is reported
+//! and skipped, so one bad write doesn't stop the stream.
+//!
+//! Values are re-sent while the input is quiet, so they don't time out on
+//! the device between lines.
+use crate::error::{Context, Result};
+use crate::{units::Unit, Octo};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+/// How often values are re-sent while the input is quiet, if the device's
+/// timeout isn't known
+static KEEP_ALIVE: Duration = Duration::from_secs(1);
+
+/// `(slot, centidegrees)` pairs from one line, slots from 0
+///
+/// Empty for blank lines and comments.
+pub fn parse_line(line: &str, unit: Unit) -> Result<Vec<(usize, Option<i16>)>> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return Ok(Vec::new());
+    }
+    line.split_whitespace()
+        .map(|assignment| {
+            let (slot, temperature) = assignment
+                .split_once('=')
+                .with_context(|| format!("Expected SLOT=TEMP, got {assignment:?}"))?;
+            let slot: usize = slot.parse().with_context(|| format!("Bad slot {slot:?}"))?;
+            let slot = slot.checked_sub(1).context("Slots are numbered from 1")?;
+            let value = match temperature {
+                "" | "none" => None,
+                temperature => {
+                    let degrees: f64 = temperature
+                        .parse()
+                        .with_context(|| format!("Bad temperature {temperature:?}"))?;
+                    Some(unit.centidegrees(degrees)?)
+                }
+            };
+            Ok((slot, value))
+        })
+        .collect()
+}
+
+/// Publish every line of `input` as it arrives, until it ends
+///
+/// Temperatures are in `unit`. Fails when the device does; bad lines are
+/// reported and skipped.
+pub fn watch(octo: &mut Octo, input: impl BufRead + Send + 'static, unit: Unit) -> Result<()> {
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in input.lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    publish(octo, &lines, unit)
+}
+
+/// Like [`watch`] for the file at `path`
+///
+/// A named pipe is opened again each time its writer closes it, so writers
+/// can come and go and this runs until the process is stopped. Anything
+/// else is read to the end once.
+pub fn watch_path(octo: &mut Octo, path: &Path, unit: Unit) -> Result<()> {
+    let file = open(path)?;
+    if !is_fifo(&file) {
+        return watch(octo, BufReader::new(file), unit);
+    }
+    let (sender, lines) = mpsc::channel();
+    let path = path.to_owned();
+    thread::spawn(move || {
+        let mut file = Ok(file);
+        while let Ok(open) = file {
+            for line in BufReader::new(open).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+            file = reopen(&path);
+        }
+    });
+    publish(octo, &lines, unit)
+}
+
+/// Open `path` for reading, waiting for a writer if it's a pipe
+fn open(path: &Path) -> Result<File> {
+    File::open(path).with_context(|| format!("Opening {}", path.display()))
+}
+
+/// Open the pipe at `path` again for the next writer
+fn reopen(path: &Path) -> Result<File> {
+    open(path).inspect_err(|error| warn!("{error:#}"))
+}
+
+/// Whether `file` is a named pipe
+#[cfg(unix)]
+fn is_fifo(file: &File) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    file.metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+/// Whether `file` is a named pipe
+#[cfg(not(unix))]
+fn is_fifo(_file: &File) -> bool {
+    false
+}
+
+/// Apply `lines` until the sender hangs up, keeping the values alive
+fn publish(octo: &mut Octo, lines: &Receiver<String>, unit: Unit) -> Result<()> {
+    let interval = octo.keep_alive_interval().unwrap_or(KEEP_ALIVE);
+    loop {
+        match lines.recv_timeout(interval) {
+            Ok(line) => apply(octo, &line, unit)?,
+            Err(RecvTimeoutError::Timeout) => {
+                if octo.last_report().values().iter().any(Option::is_some) {
+                    octo.republish()?;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Publish one line, skipping it if it doesn't parse
+fn apply(octo: &mut Octo, line: &str, unit: Unit) -> Result<()> {
+    let slots = octo.report_layout().sensor_count;
+    let values = parse_line(line, unit).and_then(|values| {
+        match values.iter().find(|(slot, _)| *slot >= slots) {
+            Some((slot, _)) => {
+                bail!("Slot {} doesn't exist, the device has {slots}", slot + 1)
+            }
+            None => Ok(values),
+        }
+    });
+    match values {
+        Ok(values) if values.is_empty() => {}
+        Ok(values) => {
+            octo.update_slots(&values)?;
+        }
+        Err(error) => warn!("Skipping {line:?}: {error:#}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_line, watch};
+    use crate::{emulator::Emulator, units::Unit, Octo};
+    use std::io::Cursor;
+
+    /// Assignments parse in the unit given, with slots from 0
+    #[test]
+    fn lines() {
+        assert_eq!(
+            parse_line(" 3=41.7 1=none 2= ", Unit::Celsius).unwrap(),
+            [(2, Some(4170)), (0, None), (1, None)]
+        );
+        assert_eq!(
+            parse_line("1=212", Unit::Fahrenheit).unwrap(),
+            [(0, Some(10000))]
+        );
+        assert!(parse_line("# comment", Unit::Celsius).unwrap().is_empty());
+        assert!(parse_line("", Unit::Celsius).unwrap().is_empty());
+        for bad in ["3", "0=40", "x=40", "1=warm", "1=900"] {
+            assert!(parse_line(bad, Unit::Celsius).is_err(), "{bad}");
+        }
+    }
+
+    /// Each line updates its slots, and bad lines are skipped
+    #[test]
+    fn stream() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let input = Cursor::new("1=40 2=35\nrubbish\n17=40\n2=none 3=30.5\n# done\n");
+        watch(&mut octo, input, Unit::Celsius).unwrap();
+        assert_eq!(
+            emulator.virtual_sensors()[..3],
+            [Some(4000), None, Some(3050)]
+        );
+        assert_eq!(emulator.accepted_reports(), 2);
+    }
+}
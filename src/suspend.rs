@@ -0,0 +1,89 @@
+//! Noticing system suspend
+//!
+//! After sleep the device has timed out every virtual sensor, and it stays
+//! that way until the next update. Polling a [`SuspendDetector`] from the
+//! update loop lets the caller republish as soon as the system resumes
+//! rather than on its next, possibly slow, poll.
+//! [`SyncEngine`](crate::daemon::SyncEngine) does this on every tick.
+//!
+//! Detection compares `CLOCK_BOOTTIME`, which counts time spent suspended,
+//! with `CLOCK_MONOTONIC`, which doesn't. It needs no D-Bus connection or
+//! logind inhibitor, and works the same with or without systemd.
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{suspend::SuspendDetector, Octo};
+//! let mut octo = Octo::new().unwrap();
+//! let mut suspend = SuspendDetector::new().unwrap();
+//! loop {
+//!     if suspend.check().unwrap().is_some() {
+//!         // The device may have been re-enumerated while asleep
+//!         if octo.republish().is_err() {
+//!             octo = Octo::new().unwrap();
+//!         }
+//!     }
+//!     std::thread::sleep(std::time::Duration::from_secs(1));
+//! }
+//! ```
+use crate::error::Result;
+use std::{io, time::Duration};
+
+/// Suspended time shorter than this is treated as clock noise
+static THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Watches for time the system spent suspended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspendDetector {
+    suspended: Duration,
+}
+
+impl SuspendDetector {
+    /// Start watching from now
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            suspended: suspended_total()?,
+        })
+    }
+
+    /// Time spent suspended since the last check, if the system slept
+    pub fn check(&mut self) -> Result<Option<Duration>> {
+        let total = suspended_total()?;
+        let slept = total.saturating_sub(self.suspended);
+        if slept < THRESHOLD {
+            return Ok(None);
+        }
+        self.suspended = total;
+        Ok(Some(slept))
+    }
+}
+
+/// Total time spent suspended since boot
+fn suspended_total() -> Result<Duration> {
+    let boottime = clock(libc::CLOCK_BOOTTIME)?;
+    let monotonic = clock(libc::CLOCK_MONOTONIC)?;
+    Ok(boottime.saturating_sub(monotonic))
+}
+
+/// Read a clock
+fn clock(id: libc::clockid_t) -> Result<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec for clock_gettime to fill in
+    if unsafe { libc::clock_gettime(id, &mut time) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::SuspendDetector;
+
+    /// Nothing is reported without a suspend in between
+    #[test]
+    fn no_suspend() {
+        let mut detector = SuspendDetector::new().unwrap();
+        assert_eq!(detector.check().unwrap(), None);
+    }
+}
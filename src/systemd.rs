@@ -0,0 +1,172 @@
+//! Running under systemd
+//!
+//! A [`Notifier`] speaks the `sd_notify` protocol over `$NOTIFY_SOCKET`,
+//! so a `Type=notify` unit knows when the service is up and, with
+//! `WatchdogSec=`, restarts it once it stops answering.
+//! [`SyncEngine::with_notifier`](crate::daemon::SyncEngine::with_notifier)
+//! only pets the watchdog after successful writes to the device, so a hung
+//! USB stack gets the service restarted instead of silently leaving the
+//! fans on stale values.
+//!
+//! [`handle_termination`] turns `SIGTERM` and `SIGINT` into a flag the
+//! update loop can stop on, leaving the device in a known state:
+//!
+//! ```no_run
+//! use octo_virtual_sensors::{daemon::SyncEngine, systemd, Failsafe, Octo};
+//! systemd::handle_termination().unwrap();
+//! let mut engine = SyncEngine::new(Octo::new().unwrap());
+//! if let Some(notifier) = systemd::Notifier::from_env().unwrap() {
+//!     engine = engine.with_notifier(notifier);
+//! }
+//! engine.run_until(systemd::terminated);
+//! engine.shutdown(Failsafe::Disconnect).unwrap();
+//! ```
+use crate::error::{Context, Result};
+use std::{
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Set by the signal handler
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+/// Sends state changes to the service manager
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Notifier for the socket systemd passed in `$NOTIFY_SOCKET`
+    ///
+    /// `None` when not started by systemd, or by a unit without
+    /// `Type=notify`. The watchdog timeout is taken from `$WATCHDOG_USEC`
+    /// if `$WATCHDOG_PID`, when set, is this process.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        let pid_matches =
+            std::env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| pid_matches)
+            .and_then(|usec| usec.parse().ok())
+            .map(Duration::from_micros);
+        let socket = socket.to_string_lossy();
+        let address = match socket.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(Path::new(socket.as_ref())),
+        }
+        .with_context(|| format!("Bad NOTIFY_SOCKET {socket:?}"))?;
+        Self::connect(address, watchdog).map(Some)
+    }
+
+    /// Notifier for the socket at `address`, with the given watchdog
+    /// timeout
+    pub fn connect(address: SocketAddr, watchdog: Option<Duration>) -> Result<Self> {
+        let socket = UnixDatagram::unbound().context("Creating the notify socket")?;
+        Ok(Self {
+            socket,
+            address,
+            watchdog,
+        })
+    }
+
+    /// The watchdog timeout, `None` if the unit has no `WatchdogSec=`
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// Send `state`, newline separated `KEY=VALUE` assignments
+    pub fn notify(&self, state: &str) -> Result<()> {
+        self.socket
+            .send_to_addr(state.as_bytes(), &self.address)
+            .context("Notifying systemd")?;
+        Ok(())
+    }
+
+    /// Tell systemd startup has finished
+    pub fn ready(&self) -> Result<()> {
+        self.notify("READY=1")
+    }
+
+    /// Tell systemd the service is shutting down
+    pub fn stopping(&self) -> Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Show `status` in `systemctl status`
+    pub fn status(&self, status: &str) -> Result<()> {
+        self.notify(&format!("STATUS={status}"))
+    }
+
+    /// Pet the watchdog, if the unit has one
+    pub fn ping(&self) -> Result<()> {
+        if self.watchdog.is_none() {
+            return Ok(());
+        }
+        self.notify("WATCHDOG=1")
+    }
+}
+
+/// Record that termination was asked for
+extern "C" fn on_signal(_: libc::c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
+}
+
+/// Catch `SIGTERM` and `SIGINT` from now on, see [`terminated`]
+pub fn handle_termination() -> Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // SAFETY: the handler only stores to an atomic, which is
+        // async-signal-safe
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let previous = unsafe { libc::signal(signal, handler) };
+        if previous == libc::SIG_ERR {
+            return Err(std::io::Error::last_os_error()).context("Installing a signal handler");
+        }
+    }
+    Ok(())
+}
+
+/// Whether `SIGTERM` or `SIGINT` arrived since [`handle_termination`]
+pub fn terminated() -> bool {
+    TERMINATED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Notifier;
+    use std::{os::unix::net::UnixDatagram, time::Duration};
+
+    /// Notifications arrive as datagrams, pings only with a watchdog
+    #[test]
+    fn notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let systemd = UnixDatagram::bind(dir.path().join("notify")).unwrap();
+        systemd.set_nonblocking(true).unwrap();
+        let address = systemd.local_addr().unwrap();
+        let receive = || {
+            let mut buf = [0; 64];
+            let len = systemd.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+        let notifier = Notifier::connect(address.clone(), None).unwrap();
+        notifier.ping().unwrap();
+        notifier.ready().unwrap();
+        assert_eq!(receive(), "READY=1");
+        let notifier = Notifier::connect(address, Some(Duration::from_secs(10))).unwrap();
+        notifier.ping().unwrap();
+        assert_eq!(receive(), "WATCHDOG=1");
+        notifier.status("Publishing 3 sensors").unwrap();
+        assert_eq!(receive(), "STATUS=Publishing 3 sensors");
+        assert!(systemd.recv(&mut [0; 64]).is_err());
+    }
+}
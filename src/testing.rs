@@ -0,0 +1,38 @@
+//! Fixtures shared by the unit tests
+//!
+//! Everything lives in a temporary directory that is removed when the
+//! fixture is dropped, whether the test passed or not.
+use std::{fs, os::unix::fs::symlink, path::PathBuf};
+use tempfile::TempDir;
+
+/// Fake `/sys` with hwmon chips, some bound to the Aquacomputer driver
+pub struct HwmonTree {
+    root: TempDir,
+}
+
+impl HwmonTree {
+    /// Tree with no chips yet
+    pub fn new() -> Self {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("drivers/aquacomputer_d5next")).unwrap();
+        Self { root }
+    }
+
+    /// Stands in for `/sys/class/hwmon`
+    pub fn class(&self) -> PathBuf {
+        self.root.path().join("class")
+    }
+
+    /// Add chip `name` as `class/<hwmon>`, bound to `aquacomputer_d5next`
+    /// if `aquacomputer`, returning its directory
+    pub fn chip(&self, hwmon: &str, name: &str, aquacomputer: bool) -> PathBuf {
+        let dir = self.class().join(hwmon);
+        fs::create_dir_all(dir.join("device")).unwrap();
+        fs::write(dir.join("name"), format!("{name}\n")).unwrap();
+        if aquacomputer {
+            let driver = self.root.path().join("drivers/aquacomputer_d5next");
+            symlink(driver, dir.join("device/driver")).unwrap();
+        }
+        dir
+    }
+}
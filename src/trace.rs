@@ -0,0 +1,189 @@
+//! Debug output for daemon operators
+//!
+//! With the `trace` feature the crate records device discovery, the values
+//! put into each report and every transfer with its outcome and duration
+//! as [`tracing`] events at `DEBUG`, and the raw report bytes at `TRACE`,
+//! which is what timeouts and checksum problems usually come down to.
+//! Opening a device, each report written and each sync tick run inside the
+//! `open`, `write` and `sync` spans.
+//!
+//! The crate never prints them itself. An application installs a
+//! `tracing` subscriber, or without one the events go to the [`log`]
+//! facade under the `octo_virtual_sensors` target. The command line tools
+//! log them on stderr when [`TRACE_ENV`] is set, see [`env_level`]:
+//!
+//! ```text
+//! OCTO_VS_TRACE=trace octo-vs sync 1=k10temp/temp1
+//! ```
+//!
+//! Without the feature none of this is compiled in.
+use crate::error::Result;
+use crate::Transport;
+use log::LevelFilter;
+use std::{fmt::Write as _, time::Instant};
+
+/// Environment variable the command line tools read with [`env_level`]
+pub const TRACE_ENV: &str = "OCTO_VS_TRACE";
+
+/// Level asked for with [`TRACE_ENV`], `None` when it is unset or off
+///
+/// `trace` includes the raw bytes of every report, anything else gives the
+/// events only.
+pub fn env_level() -> Option<LevelFilter> {
+    match std::env::var(TRACE_ENV).ok()?.as_str() {
+        "" | "0" | "off" => None,
+        "trace" => Some(LevelFilter::Trace),
+        _ => Some(LevelFilter::Debug),
+    }
+}
+
+/// Whether the raw bytes of a report would be recorded anywhere, through a
+/// subscriber or the `log` fallback
+fn bytes_enabled() -> bool {
+    tracing::enabled!(target: "octo_virtual_sensors", tracing::Level::TRACE)
+        || log::log_enabled!(target: "octo_virtual_sensors", log::Level::Trace)
+}
+
+/// Bytes as space separated hex
+pub fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 3);
+    for (index, byte) in bytes.iter().enumerate() {
+        if index > 0 {
+            out.push(' ');
+        }
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+/// Transport recording every call to the one it wraps
+pub(crate) struct Traced {
+    inner: Box<dyn Transport + Send>,
+}
+
+impl Traced {
+    /// Record `inner`'s transfers
+    pub(crate) fn new(inner: Box<dyn Transport + Send>) -> Self {
+        Self { inner }
+    }
+}
+
+/// Record one transfer of `bytes` and its outcome
+fn transfer<T: std::fmt::Debug>(
+    what: &str,
+    start: Instant,
+    bytes: impl FnOnce(&T) -> Option<Vec<u8>>,
+    result: &Result<T>,
+) {
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    match result {
+        Ok(value) => {
+            trace!(Debug, "{what}: {value:?} after {elapsed:.2} ms");
+            if bytes_enabled() {
+                if let Some(bytes) = bytes(value) {
+                    trace!(Trace, "{what}: [{}]", hex(&bytes));
+                }
+            }
+        }
+        Err(error) => trace!(Debug, "{what}: failed after {elapsed:.2} ms: {error:#}"),
+    }
+}
+
+impl Transport for Traced {
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        let start = Instant::now();
+        let result = self.inner.write_report(report);
+        transfer("write report", start, |_| Some(report.to_vec()), &result);
+        result
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let start = Instant::now();
+        let result = self.inner.read_report(buf);
+        transfer(
+            "read report",
+            start,
+            |&len| buf.get(..len).map(<[u8]>::to_vec),
+            &result,
+        );
+        result
+    }
+
+    fn report_descriptor(&mut self) -> Result<Vec<u8>> {
+        let start = Instant::now();
+        let result = self.inner.report_descriptor();
+        transfer(
+            "report descriptor",
+            start,
+            |descriptor: &Vec<u8>| Some(descriptor.clone()),
+            &result,
+        );
+        result
+    }
+
+    fn read_feature_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let id = buf.first().copied().unwrap_or_default();
+        let start = Instant::now();
+        let result = self.inner.read_feature_report(buf);
+        transfer(
+            &format!("read feature report {id}"),
+            start,
+            |&len| buf.get(..len).map(<[u8]>::to_vec),
+            &result,
+        );
+        result
+    }
+
+    fn write_feature_report(&mut self, report: &[u8]) -> Result<usize> {
+        let id = report.first().copied().unwrap_or_default();
+        let start = Instant::now();
+        let result = self.inner.write_feature_report(report);
+        transfer(
+            &format!("write feature report {id}"),
+            start,
+            |_| Some(report.to_vec()),
+            &result,
+        );
+        result
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.reset();
+        transfer("reset", start, |_| None, &result);
+        result
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.reopen();
+        transfer("reopen", start, |_| None, &result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hex, Traced};
+    use crate::{mock::MockTransport, Transport};
+
+    /// Reports print as space separated hex
+    #[test]
+    fn hex_bytes() {
+        assert_eq!(hex(&[0x04, 0x00, 0xff]), "04 00 ff");
+        assert_eq!(hex(&[]), "");
+    }
+
+    /// Wrapping a transport changes nothing it does
+    #[test]
+    fn passes_through() {
+        let mock = MockTransport::new();
+        mock.queue_read([1, 2, 3]);
+        let mut traced = Traced::new(Box::new(mock.clone()));
+        assert_eq!(traced.write_report(&[4, 5]).unwrap(), 2);
+        let mut buf = [0; 8];
+        assert_eq!(traced.read_report(&mut buf).unwrap(), 3);
+        assert!(traced.read_report(&mut buf).is_err());
+        assert_eq!(mock.written(), [vec![4, 5]]);
+    }
+}
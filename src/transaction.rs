@@ -0,0 +1,186 @@
+//! Changes made as one unit
+//!
+//! A [`Transaction`] sends its steps in order and checks each one took.
+//! If a step fails, the virtual sensors go back to what they were before
+//! the transaction, so the device isn't left half way between two setups.
+use crate::error::{Context, Result};
+use crate::Octo;
+
+/// Status reports read while waiting for written values to show up
+static READBACK_ATTEMPTS: usize = 3;
+
+/// Custom step run against the device
+type CustomStep<'a> = Box<dyn FnOnce(&mut Octo) -> Result<()> + 'a>;
+
+/// One step of a transaction
+enum Step<'a> {
+    /// Publish centidegree values and check they read back
+    Sensors(Vec<Option<i16>>),
+    /// Anything else, verifying itself
+    Custom(CustomStep<'a>),
+}
+
+/// Steps applied in order, rolling back the sensors on failure
+///
+/// ```no_run
+/// use octo_virtual_sensors::Octo;
+/// let mut octo = Octo::new().unwrap();
+/// octo.transaction()
+///     .set_sensors(&[Some(4200), Some(3850)])
+///     .then(|octo| {
+///         // Change a setting that depends on the new values
+///         Ok(())
+///     })
+///     .commit()
+///     .unwrap();
+/// ```
+#[must_use = "a transaction does nothing until committed"]
+pub struct Transaction<'a> {
+    octo: &'a mut Octo,
+    steps: Vec<Step<'a>>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Empty transaction on `octo`
+    pub(crate) fn new(octo: &'a mut Octo) -> Self {
+        Self {
+            octo,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Publish every virtual sensor in centidegrees
+    ///
+    /// The step only succeeds once the device reports the new values back.
+    pub fn set_sensors(mut self, values: &[Option<i16>]) -> Self {
+        self.steps.push(Step::Sensors(values.to_vec()));
+        self
+    }
+
+    /// Run a custom step, which should fail if its change didn't take
+    pub fn then(mut self, step: impl FnOnce(&mut Octo) -> Result<()> + 'a) -> Self {
+        self.steps.push(Step::Custom(Box::new(step)));
+        self
+    }
+
+    /// Run every step, stopping and rolling back at the first failure
+    ///
+    /// Rolling back restores the virtual sensor values from before the
+    /// transaction. Changes custom steps made are theirs to undo.
+    pub fn commit(self) -> Result<()> {
+        let original = self.octo.last_report().values();
+        for (index, step) in self.steps.into_iter().enumerate() {
+            let result = match step {
+                Step::Sensors(values) => set_and_verify(self.octo, &values),
+                Step::Custom(step) => step(self.octo),
+            };
+            if let Err(error) = result {
+                let error = error.context(format!("Transaction step {} failed", index + 1));
+                return match self.octo.restore_values(&original) {
+                    Ok(_) => Err(error.context("Virtual sensors rolled back")),
+                    Err(rollback) => Err(error.context(format!(
+                        "Rolling back virtual sensors failed too: {rollback:#}"
+                    ))),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Publish `values` and wait for the device to report them back
+///
+/// The report is sent again after each readback that doesn't match, in
+/// case it was lost or corrupted on the way.
+fn set_and_verify(octo: &mut Octo, values: &[Option<i16>]) -> Result<()> {
+    octo.update_centidegrees(values)?;
+    let expected = octo.last_report().values();
+    let mut read = Vec::new();
+    for attempt in 0..READBACK_ATTEMPTS {
+        if attempt > 0 {
+            octo.resend()?;
+        }
+        read = octo
+            .read_status()
+            .context("Reading back virtual sensors")?
+            .virtual_sensors;
+        if read.starts_with(&expected) {
+            return Ok(());
+        }
+    }
+    bail!("Device reports virtual sensors {read:?}, expected {expected:?}")
+}
+
+impl Octo {
+    /// Start a transaction on the device
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{emulator::Emulator, Octo};
+    use std::time::Duration;
+
+    /// Steps run in order and the values stick
+    #[test]
+    fn commit() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        let mut ran = false;
+        octo.transaction()
+            .set_sensors(&[Some(4200)])
+            .then(|octo| {
+                assert_eq!(octo.last_report().values()[0], Some(4200));
+                ran = true;
+                Ok(())
+            })
+            .commit()
+            .unwrap();
+        assert!(ran);
+        assert_eq!(emulator.virtual_sensors()[0], Some(4200));
+    }
+
+    /// A failing later step restores the earlier values
+    #[test]
+    fn rollback() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        octo.update_centidegrees(&[Some(3000)]).unwrap();
+        let error = octo
+            .transaction()
+            .set_sensors(&[Some(5000), Some(5100)])
+            .then(|_| bail!("setting refused"))
+            .commit()
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("Transaction step 2 failed: setting refused"));
+        assert_eq!(emulator.virtual_sensors()[..2], [Some(3000), None]);
+        assert_eq!(octo.last_report().values()[0], Some(3000));
+    }
+
+    /// Values the device doesn't report back fail the step
+    #[test]
+    fn unverified_sensors() {
+        let emulator = Emulator::new().with_virtual_sensor_timeout(Duration::ZERO);
+        let mut octo = Octo::with_transport(emulator).unwrap();
+        let result = octo.transaction().set_sensors(&[Some(4000)]).commit();
+        assert!(result.is_err());
+        assert_eq!(octo.link_stats().resends, 2);
+    }
+
+    /// A report corrupted on the way is sent again
+    #[test]
+    fn resend_corrupted() {
+        let emulator = Emulator::new();
+        let mut octo = Octo::with_transport(emulator.clone()).unwrap();
+        emulator.inject_corruption(1);
+        octo.transaction()
+            .set_sensors(&[Some(4000)])
+            .commit()
+            .unwrap();
+        assert_eq!(emulator.rejected_reports(), 1);
+        assert_eq!(octo.link_stats().resends, 1);
+        assert_eq!(emulator.virtual_sensors()[0], Some(4000));
+    }
+}
@@ -0,0 +1,571 @@
+//! Transforms computed from several sensors before publishing
+//!
+//! Like [profiles](crate::profile), transforms work on slot-indexed
+//! centidegree values, `None` for a disconnected sensor.
+//!
+//! [`Filters`] smooth noisy sources one slot at a time, before anything
+//! else looks at them.
+use crate::codec::DISCONNECTED;
+use crate::error::{Context, Result};
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// A source minus a smoothed, clamped ambient reference
+///
+/// Water-minus-ambient tracks how hard the loop is working regardless of
+/// room temperature, which is what most fan curves really want. The
+/// ambient reading is clamped to a plausible range and smoothed, so a
+/// glitching or drafty ambient probe doesn't make the fans hunt.
+///
+/// ```
+/// use octo_virtual_sensors::transform::AmbientCompensation;
+/// let mut delta = AmbientCompensation::new(0, 1).with_output(4);
+/// let values = delta.apply(&[Some(3500), Some(2200)]);
+/// assert_eq!(values[4], Some(1300));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmbientCompensation {
+    source: usize,
+    ambient: usize,
+    output: usize,
+    min: i16,
+    max: i16,
+    smoothing: f32,
+    reference: Option<f32>,
+}
+
+impl AmbientCompensation {
+    /// Subtract slot `ambient` from slot `source`, publishing on `source`
+    ///
+    /// The ambient reference is clamped to 0–50 °C and not smoothed until
+    /// configured otherwise.
+    pub fn new(source: usize, ambient: usize) -> Self {
+        Self {
+            source,
+            ambient,
+            output: source,
+            min: 0,
+            max: 5000,
+            smoothing: 1.0,
+            reference: None,
+        }
+    }
+
+    /// Publish the difference on `slot` instead of the source's slot
+    pub fn with_output(mut self, slot: usize) -> Self {
+        self.output = slot;
+        self
+    }
+
+    /// Clamp the ambient reading to `min..=max` centidegrees
+    pub fn with_clamp(mut self, min: i16, max: i16) -> Self {
+        self.min = min.min(max);
+        self.max = max.max(min);
+        self
+    }
+
+    /// Smooth the ambient reference with an exponential moving average
+    ///
+    /// Each reading moves the reference by `factor` of the difference:
+    /// 1 follows readings exactly, smaller values react more slowly.
+    /// Values outside `(0, 1]` are clamped.
+    pub fn with_smoothing(mut self, factor: f32) -> Self {
+        self.smoothing = if factor.is_nan() {
+            1.0
+        } else {
+            factor.clamp(f32::EPSILON, 1.0)
+        };
+        self
+    }
+
+    /// Current ambient reference in centidegrees
+    pub fn reference(&self) -> Option<i16> {
+        self.reference.map(|reference| reference.round() as i16)
+    }
+
+    /// Update the reference and publish the difference
+    ///
+    /// A missing ambient reading keeps the last reference. The output is
+    /// disconnected if the source is, or no ambient reading was ever seen.
+    /// Other slots pass through unchanged.
+    pub fn apply(&mut self, values: &[Option<i16>]) -> Vec<Option<i16>> {
+        if let Some(ambient) = values.get(self.ambient).copied().flatten() {
+            let ambient = f32::from(ambient.clamp(self.min, self.max));
+            self.reference = Some(match self.reference {
+                Some(reference) => reference + self.smoothing * (ambient - reference),
+                None => ambient,
+            });
+        }
+        let source = values.get(self.source).copied().flatten();
+        let difference = source
+            .zip(self.reference())
+            .map(|(source, reference)| source.saturating_sub(reference).min(DISCONNECTED - 1));
+        let mut output = values.to_vec();
+        output.resize(output.len().max(self.output + 1), None);
+        output[self.output] = difference;
+        output
+    }
+}
+
+/// Ramp from the value last published toward a new target
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Ramp {
+    from: f32,
+    to: i16,
+    start: Instant,
+}
+
+impl Ramp {
+    /// Value `now`, reaching the target after `duration`
+    fn value(&self, now: Instant, duration: Duration) -> f32 {
+        let elapsed = now.saturating_duration_since(self.start).as_secs_f32();
+        let progress = (elapsed / duration.as_secs_f32()).min(1.0);
+        self.from + progress * (f32::from(self.to) - self.from)
+    }
+}
+
+/// Steps toward new values instead of jumping to them
+///
+/// A source read every 30 seconds makes the device's fan curve move in a
+/// staircase. Applied on every tick of a faster update loop, this
+/// publishes evenly spaced steps from the old value to the new one over
+/// the ramp duration, so the fans ramp smoothly. Connecting and
+/// disconnecting take effect straight away.
+///
+/// ```
+/// use octo_virtual_sensors::transform::Interpolation;
+/// use std::time::Duration;
+/// let mut smooth = Interpolation::new(Duration::from_secs(30));
+/// assert_eq!(smooth.apply(&[Some(4000)]), [Some(4000)]);
+/// // Right after a new reading, the published value has barely moved
+/// assert!(smooth.apply(&[Some(5000)])[0] < Some(4100));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpolation {
+    duration: Duration,
+    ramps: Vec<Option<Ramp>>,
+}
+
+impl Interpolation {
+    /// Reach each new value `duration` after it first appears
+    ///
+    /// Usually the source's update interval. Zero disables interpolation.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            ramps: Vec::new(),
+        }
+    }
+
+    /// Publish the next step toward `values`
+    pub fn apply(&mut self, values: &[Option<i16>]) -> Vec<Option<i16>> {
+        self.apply_at(values, Instant::now())
+    }
+
+    /// Publish the step toward `values` due at `now`
+    pub fn apply_at(&mut self, values: &[Option<i16>], now: Instant) -> Vec<Option<i16>> {
+        self.ramps.resize(self.ramps.len().max(values.len()), None);
+        let duration = self.duration;
+        values
+            .iter()
+            .zip(&mut self.ramps)
+            .map(|(&target, ramp)| {
+                let Some(target) = target else {
+                    *ramp = None;
+                    return None;
+                };
+                match ramp {
+                    Some(current) if current.to == target => {}
+                    Some(current) if !duration.is_zero() => {
+                        let from = current.value(now, duration);
+                        *current = Ramp {
+                            from,
+                            to: target,
+                            start: now,
+                        };
+                    }
+                    _ => {
+                        *ramp = Some(Ramp {
+                            from: f32::from(target),
+                            to: target,
+                            start: now,
+                        })
+                    }
+                }
+                ramp.map(|ramp| ramp.value(now, duration).round() as i16)
+            })
+            .collect()
+    }
+}
+
+/// How a [`Filter`] smooths one sensor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterConfig {
+    /// Mean of the last `n` readings
+    MovingAverage(usize),
+    /// Exponential moving average, moving by this factor of each change
+    ///
+    /// 1 follows readings exactly, smaller values react more slowly.
+    Exponential(f32),
+    /// Median of the last `n` readings, which ignores lone spikes
+    Median(usize),
+}
+
+impl FilterConfig {
+    /// Fail for empty windows and factors outside `(0, 1]`
+    pub fn check(self) -> Result<Self> {
+        match self {
+            Self::MovingAverage(0) | Self::Median(0) => {
+                bail!("A filter window needs at least one reading")
+            }
+            Self::Exponential(factor) if !(factor > 0.0 && factor <= 1.0) => {
+                bail!("Smoothing factor {factor} is not more than 0 and at most 1")
+            }
+            _ => Ok(self),
+        }
+    }
+}
+
+impl fmt::Display for FilterConfig {
+    /// The form [`FilterConfig::from_str`] parses
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MovingAverage(window) => write!(f, "average:{window}"),
+            Self::Exponential(factor) => write!(f, "exponential:{factor}"),
+            Self::Median(window) => write!(f, "median:{window}"),
+        }
+    }
+}
+
+impl FromStr for FilterConfig {
+    type Err = crate::Error;
+
+    /// Parse `average:N`, `median:N` or `exponential:FACTOR`
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, parameter) = s
+            .split_once(':')
+            .with_context(|| format!("Expected KIND:PARAMETER, got {s:?}"))?;
+        let window = || {
+            parameter
+                .parse()
+                .with_context(|| format!("Bad window {parameter:?}"))
+        };
+        let filter = match kind {
+            "average" => Self::MovingAverage(window()?),
+            "median" => Self::Median(window()?),
+            "exponential" => Self::Exponential(
+                parameter
+                    .parse()
+                    .with_context(|| format!("Bad smoothing factor {parameter:?}"))?,
+            ),
+            _ => bail!("Unknown filter {kind:?}, expected average, median or exponential"),
+        };
+        filter.check()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for FilterConfig {
+    /// The form [`FilterConfig::from_str`] parses, such as `"median:5"`
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for FilterConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+        text.parse()
+            .map_err(|error| serde::de::Error::custom(format!("{error:#}")))
+    }
+}
+
+/// Smooths one sensor's readings
+///
+/// A disconnected reading passes through and starts the filter afresh, so
+/// a sensor coming back isn't averaged with readings from before it went.
+///
+/// ```
+/// use octo_virtual_sensors::transform::{Filter, FilterConfig};
+/// let mut median = Filter::new(FilterConfig::Median(3)).unwrap();
+/// median.apply(Some(4000));
+/// median.apply(Some(9000));
+/// assert_eq!(median.apply(Some(4100)), Some(4100));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    config: FilterConfig,
+    history: VecDeque<i16>,
+    average: Option<f32>,
+}
+
+impl Filter {
+    /// Filter with no readings yet
+    ///
+    /// Fails for configurations [`FilterConfig::check`] refuses.
+    pub fn new(config: FilterConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.check()?,
+            history: VecDeque::new(),
+            average: None,
+        })
+    }
+
+    /// Add a reading and return the filtered value
+    pub fn apply(&mut self, value: Option<i16>) -> Option<i16> {
+        let Some(value) = value else {
+            self.history.clear();
+            self.average = None;
+            return None;
+        };
+        let filtered = match self.config {
+            FilterConfig::Exponential(factor) => {
+                let value = f32::from(value);
+                let average = match self.average {
+                    Some(average) => average + factor * (value - average),
+                    None => value,
+                };
+                self.average = Some(average);
+                average
+            }
+            FilterConfig::MovingAverage(window) => {
+                self.push(value, window);
+                let sum: i32 = self.history.iter().map(|&v| i32::from(v)).sum();
+                sum as f32 / self.history.len() as f32
+            }
+            FilterConfig::Median(window) => {
+                self.push(value, window);
+                let mut sorted: Vec<_> = self.history.iter().copied().collect();
+                sorted.sort_unstable();
+                let middle = sorted.len() / 2;
+                match sorted.len() % 2 {
+                    0 => (f32::from(sorted[middle - 1]) + f32::from(sorted[middle])) / 2.0,
+                    _ => f32::from(sorted[middle]),
+                }
+            }
+        };
+        Some(filtered.round() as i16)
+    }
+
+    /// Remember `value`, keeping at most `window` readings
+    fn push(&mut self, value: i16, window: usize) {
+        if self.history.len() >= window {
+            self.history.pop_front();
+        }
+        self.history.push_back(value);
+    }
+}
+
+/// Per-slot [`Filter`]s applied to a whole set of values
+///
+/// Slots without a filter pass through unchanged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filters {
+    filters: Vec<(usize, Filter)>,
+}
+
+impl Filters {
+    /// No filters yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter `slot` with `config`, replacing any filter it had
+    pub fn with_filter(mut self, slot: usize, config: FilterConfig) -> Result<Self> {
+        let filter = Filter::new(config)?;
+        self.filters.retain(|(other, _)| *other != slot);
+        self.filters.push((slot, filter));
+        Ok(self)
+    }
+
+    /// Whether no slot is filtered
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Filter every slot that has a filter
+    pub fn apply(&mut self, values: &[Option<i16>]) -> Vec<Option<i16>> {
+        let mut output = values.to_vec();
+        for (slot, filter) in &mut self.filters {
+            if let Some(value) = output.get_mut(*slot) {
+                *value = filter.apply(*value);
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod test {
+    use super::{AmbientCompensation, Filter, FilterConfig, Filters, Interpolation};
+    use std::time::{Duration, Instant};
+
+    /// The difference replaces the source unless another output is set
+    #[test]
+    fn subtract_ambient() {
+        let mut delta = AmbientCompensation::new(0, 1);
+        assert_eq!(
+            delta.apply(&[Some(3500), Some(2200), Some(7)]),
+            [Some(1300), Some(2200), Some(7)]
+        );
+        let mut delta = AmbientCompensation::new(0, 1).with_output(3);
+        assert_eq!(
+            delta.apply(&[Some(3500), Some(2200)]),
+            [Some(3500), Some(2200), None, Some(1300)]
+        );
+    }
+
+    /// Implausible ambient readings are clamped
+    #[test]
+    fn clamp_ambient() {
+        let mut delta = AmbientCompensation::new(0, 1).with_clamp(1500, 4000);
+        assert_eq!(delta.apply(&[Some(3000), Some(-4000)])[0], Some(1500));
+        assert_eq!(delta.apply(&[Some(3000), Some(9000)])[0], Some(-1000));
+        assert_eq!(delta.reference(), Some(4000));
+    }
+
+    /// The reference follows readings gradually
+    #[test]
+    fn smooth_ambient() {
+        let mut delta = AmbientCompensation::new(0, 1).with_smoothing(0.25);
+        delta.apply(&[None, Some(2000)]);
+        delta.apply(&[None, Some(3000)]);
+        assert_eq!(delta.reference(), Some(2250));
+        delta.apply(&[None, Some(3000)]);
+        assert_eq!(delta.reference(), Some(2438));
+    }
+
+    /// Missing readings keep the reference, missing sources disconnect
+    #[test]
+    fn missing_readings() {
+        let mut delta = AmbientCompensation::new(0, 1);
+        assert_eq!(delta.apply(&[Some(3000), None])[0], None);
+        delta.apply(&[Some(3000), Some(2000)]);
+        assert_eq!(delta.apply(&[Some(3100), None])[0], Some(1100));
+        assert_eq!(delta.apply(&[None, Some(2000)])[0], None);
+    }
+
+    /// New values are reached in even steps over the ramp
+    #[test]
+    fn interpolate() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut smooth = Interpolation::new(Duration::from_secs(30));
+        assert_eq!(
+            smooth.apply_at(&[Some(3000), None], at(0)),
+            [Some(3000), None]
+        );
+        assert_eq!(smooth.apply_at(&[Some(6000)], at(0)), [Some(3000)]);
+        assert_eq!(smooth.apply_at(&[Some(6000)], at(10)), [Some(4000)]);
+        assert_eq!(smooth.apply_at(&[Some(6000)], at(30)), [Some(6000)]);
+        assert_eq!(smooth.apply_at(&[Some(6000)], at(90)), [Some(6000)]);
+    }
+
+    /// A new target mid-ramp continues from where the ramp had got to
+    #[test]
+    fn retarget() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut smooth = Interpolation::new(Duration::from_secs(10));
+        smooth.apply_at(&[Some(2000)], at(0));
+        assert_eq!(smooth.apply_at(&[Some(4000)], at(0)), [Some(2000)]);
+        assert_eq!(smooth.apply_at(&[Some(4000)], at(5)), [Some(3000)]);
+        assert_eq!(smooth.apply_at(&[Some(1000)], at(5)), [Some(3000)]);
+        assert_eq!(smooth.apply_at(&[Some(1000)], at(10)), [Some(2000)]);
+    }
+
+    /// Connecting and disconnecting don't ramp, nor does a zero duration
+    #[test]
+    fn interpolate_jumps() {
+        let now = Instant::now();
+        let mut smooth = Interpolation::new(Duration::from_secs(10));
+        smooth.apply_at(&[Some(2000)], now);
+        assert_eq!(smooth.apply_at(&[None], now), [None]);
+        assert_eq!(smooth.apply_at(&[Some(5000)], now), [Some(5000)]);
+        let mut instant = Interpolation::new(Duration::ZERO);
+        instant.apply_at(&[Some(2000)], now);
+        assert_eq!(instant.apply_at(&[Some(5000)], now), [Some(5000)]);
+    }
+
+    /// Each filter smooths the way it's named after
+    #[test]
+    fn filters() {
+        let run = |config, values: &[i16]| {
+            let mut filter = Filter::new(config).unwrap();
+            values
+                .iter()
+                .map(|&value| filter.apply(Some(value)).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            run(FilterConfig::MovingAverage(2), &[4000, 5000, 6000]),
+            [4000, 4500, 5500]
+        );
+        assert_eq!(
+            run(FilterConfig::Exponential(0.5), &[4000, 5000, 5000]),
+            [4000, 4500, 4750]
+        );
+        assert_eq!(
+            run(FilterConfig::Median(3), &[4000, 9000, 4100, 4200]),
+            [4000, 6500, 4100, 4200]
+        );
+        assert!(Filter::new(FilterConfig::Median(0)).is_err());
+        assert!(Filter::new(FilterConfig::Exponential(1.5)).is_err());
+        assert!(Filter::new(FilterConfig::Exponential(f32::NAN)).is_err());
+    }
+
+    /// Disconnecting starts the filter afresh
+    #[test]
+    fn filter_disconnect() {
+        let mut filter = Filter::new(FilterConfig::MovingAverage(4)).unwrap();
+        filter.apply(Some(2000));
+        assert_eq!(filter.apply(None), None);
+        assert_eq!(filter.apply(Some(6000)), Some(6000));
+    }
+
+    /// Only slots with a filter are filtered
+    #[test]
+    fn filter_slots() {
+        let mut filters = Filters::new()
+            .with_filter(1, FilterConfig::Exponential(0.5))
+            .unwrap();
+        filters.apply(&[Some(1000), Some(4000)]);
+        assert_eq!(
+            filters.apply(&[Some(3000), Some(5000), Some(7)]),
+            [Some(3000), Some(4500), Some(7)]
+        );
+    }
+
+    /// Filters parse from and print as KIND:PARAMETER
+    #[test]
+    fn parse_filter() {
+        for text in ["average:5", "median:3", "exponential:0.25"] {
+            let filter: FilterConfig = text.parse().unwrap();
+            assert_eq!(filter.to_string(), text);
+        }
+        assert_eq!(
+            "exponential:0.25".parse::<FilterConfig>().unwrap(),
+            FilterConfig::Exponential(0.25)
+        );
+        for bad in [
+            "median",
+            "median:0",
+            "median:x",
+            "kalman:1",
+            "exponential:0",
+        ] {
+            assert!(bad.parse::<FilterConfig>().is_err(), "{bad}");
+        }
+    }
+}
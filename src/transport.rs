@@ -0,0 +1,757 @@
+//! Moving raw reports to and from a device
+//!
+//! [`Octo`](crate::Octo) only builds and parses reports; a [`Transport`]
+//! gets them onto the wire. [`UsbTransport`] talks to real hardware through
+//! libusb, keeping the device open between transfers. USB failures come
+//! back as [`OctoError`]s.
+use crate::error::{Context, Result};
+use crate::{kernel, OctoError};
+use rusb::{Device, DeviceHandle, Direction, GlobalContext, Recipient, RequestType, TransferType};
+use std::time::{Duration, Instant};
+
+/// Sends and receives raw HID reports
+///
+/// Reports always start with their report ID.
+pub trait Transport {
+    /// Write an output report to the device
+    fn write_report(&mut self, report: &[u8]) -> Result<usize>;
+
+    /// Read the next input report from the device into `buf`
+    ///
+    /// Returns the number of bytes read.
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Fetch the raw HID report descriptor
+    ///
+    /// Transports that can't provide one keep this default, and callers
+    /// fall back to the built-in layout tables.
+    fn report_descriptor(&mut self) -> Result<Vec<u8>> {
+        Err(OctoError::Unsupported).context("Transport does not provide a report descriptor")
+    }
+
+    /// Read a feature report into `buf`
+    ///
+    /// The report ID to read goes in the first byte of `buf`, and the
+    /// report comes back starting with it. Returns the number of bytes
+    /// read. Transports without feature reports keep this default.
+    fn read_feature_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let _ = buf;
+        Err(OctoError::Unsupported).context("Transport cannot read feature reports")
+    }
+
+    /// Write a feature report, starting with its report ID
+    ///
+    /// Transports without feature reports keep this default.
+    fn write_feature_report(&mut self, report: &[u8]) -> Result<usize> {
+        let _ = report;
+        Err(OctoError::Unsupported).context("Transport cannot write feature reports")
+    }
+
+    /// Reset the device's USB port, as if it had been replugged
+    ///
+    /// The device may forget its virtual sensors. Transports that can't
+    /// reset keep this default.
+    fn reset(&mut self) -> Result<()> {
+        Err(OctoError::Unsupported).context("Transport cannot reset the device")
+    }
+
+    /// Close the device and open it again
+    ///
+    /// For recovering after the device was replugged. Transports without a
+    /// connection to reopen keep this default.
+    fn reopen(&mut self) -> Result<()> {
+        Err(OctoError::Unsupported).context("Transport cannot reopen the device")
+    }
+}
+
+/// What [`UsbTransport`] does when an endpoint stalls
+///
+/// Hubs and VM passthrough stacks misbehave differently, so which recovery
+/// works best depends on the setup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StallPolicy {
+    /// Clear the endpoint halt and retry the transfer once
+    #[default]
+    ClearHaltAndRetry,
+    /// Reset the device and retry the transfer once
+    ResetDevice,
+    /// Return the error straight away
+    FailFast,
+}
+
+/// How [`UsbTransport`] writes output reports
+///
+/// Some hosts and hubs reject writes to the interrupt OUT endpoint but
+/// take the same report as a HID SET_REPORT control transfer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteStrategy {
+    /// Write to the OUT endpoint, switching to SET_REPORT for good if the
+    /// endpoint stalls or isn't supported
+    #[default]
+    Auto,
+    /// Always write to the OUT endpoint
+    Endpoint,
+    /// Always send a SET_REPORT control transfer
+    SetReport,
+}
+
+impl WriteStrategy {
+    /// Whether an endpoint write that failed with `error` is sent again
+    /// with SET_REPORT
+    fn falls_back(self, error: &crate::Error) -> bool {
+        self == Self::Auto
+            && matches!(
+                error.kind(),
+                Some(OctoError::Usb(
+                    rusb::Error::Pipe | rusb::Error::NotSupported
+                ))
+            )
+    }
+}
+
+/// Recovery step to take before retrying a failed transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    ClearHalt,
+    Reset,
+}
+
+impl StallPolicy {
+    /// Recovery for a failed transfer, `None` to give up
+    ///
+    /// Only stalls, which show up as pipe errors, are recovered. A timeout
+    /// is an ordinary quiet endpoint, not a halt to clear.
+    fn recovery(self, error: rusb::Error) -> Option<Recovery> {
+        if error != rusb::Error::Pipe {
+            return None;
+        }
+        match self {
+            Self::ClearHaltAndRetry => Some(Recovery::ClearHalt),
+            Self::ResetDevice => Some(Recovery::Reset),
+            Self::FailFast => None,
+        }
+    }
+}
+
+/// Transport over libusb
+///
+/// The device is opened and its HID interface claimed on the first
+/// transfer, then kept until the transport is dropped or the device goes
+/// away. A kernel driver bound to the interface, usually the
+/// `aquacomputer_d5next` hwmon driver on Linux, is detached while the
+/// interface is claimed and reattached when it's released, unless turned
+/// off with [`UsbTransport::with_kernel_driver_detach`].
+pub struct UsbTransport {
+    device: Device<GlobalContext>,
+    handle: Option<Handle>,
+    stall_policy: StallPolicy,
+    timeout: Duration,
+    detach_kernel_driver: bool,
+    write_strategy: WriteStrategy,
+    /// Set once [`WriteStrategy::Auto`] fell back to SET_REPORT
+    set_report: bool,
+}
+
+/// An open device with its HID interface claimed
+struct Handle {
+    open: DeviceHandle<GlobalContext>,
+    endpoints: Endpoints,
+    /// Whether a kernel driver was detached to claim the interface
+    reattach: bool,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // Fails if the device is already gone, which releases it anyway
+        let _ = self.open.release_interface(self.endpoints.interface);
+        if self.reattach {
+            if let Err(error) = self.open.attach_kernel_driver(self.endpoints.interface) {
+                if !matches!(error, rusb::Error::NoDevice | rusb::Error::NotFound) {
+                    warn!("Reattaching the kernel driver: {error}");
+                }
+            }
+        }
+    }
+}
+
+/// The HID interface reports go through and its endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Endpoints {
+    interface: u8,
+    /// Address and transfer type of the endpoint reports are written to
+    out: (u8, TransferType),
+    /// Address of the interrupt endpoint status reports arrive on
+    input: u8,
+}
+
+impl Endpoints {
+    /// Endpoint address for transfers in `direction`
+    fn address(&self, direction: Direction) -> u8 {
+        match direction {
+            Direction::Out => self.out.0,
+            Direction::In => self.input,
+        }
+    }
+}
+
+/// An interface from the configuration descriptor, as far as endpoint
+/// discovery cares
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InterfaceSummary {
+    number: u8,
+    class: u8,
+    /// Address and transfer type of each endpoint
+    endpoints: Vec<(u8, TransferType)>,
+}
+
+/// Pick the first HID interface with an interrupt IN endpoint and an
+/// interrupt or bulk OUT endpoint
+fn choose_endpoints(interfaces: &[InterfaceSummary]) -> Result<Endpoints> {
+    let hid: Vec<&InterfaceSummary> = interfaces
+        .iter()
+        .filter(|interface| interface.class == HID_CLASS)
+        .collect();
+    if hid.is_empty() {
+        return Err(OctoError::Unsupported).context("Device has no HID interface");
+    }
+    let is_in = |address: u8| address & 0x80 != 0;
+    for interface in &hid {
+        let input = interface
+            .endpoints
+            .iter()
+            .find(|(address, kind)| is_in(*address) && *kind == TransferType::Interrupt);
+        let out = interface.endpoints.iter().find(|(address, kind)| {
+            !is_in(*address) && matches!(kind, TransferType::Interrupt | TransferType::Bulk)
+        });
+        if let (Some(&(input, _)), Some(&out)) = (input, out) {
+            return Ok(Endpoints {
+                interface: interface.number,
+                out,
+                input,
+            });
+        }
+    }
+    let found: Vec<String> = hid
+        .iter()
+        .map(|interface| {
+            let endpoints: Vec<String> = interface
+                .endpoints
+                .iter()
+                .map(|(address, kind)| format!("{address:#04x} {kind:?}"))
+                .collect();
+            if endpoints.is_empty() {
+                format!("interface {} without endpoints", interface.number)
+            } else {
+                format!(
+                    "interface {} with {}",
+                    interface.number,
+                    endpoints.join(", ")
+                )
+            }
+        })
+        .collect();
+    Err(OctoError::Unsupported).with_context(|| {
+        format!(
+            "No HID interface has both an interrupt IN and an OUT endpoint, found {}",
+            found.join("; ")
+        )
+    })
+}
+
+/// How long a transfer waits unless set with [`UsbTransport::with_timeout`]
+static TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a reset device gets to enumerate again
+static REENUMERATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// USB interface class code for HID
+static HID_CLASS: u8 = 3;
+
+/// Standard GET_DESCRIPTOR request
+static GET_DESCRIPTOR: u8 = 0x06;
+
+/// HID report descriptor type
+static REPORT_DESCRIPTOR: u16 = 0x22;
+
+/// HID class GET_REPORT request
+static GET_REPORT: u8 = 0x01;
+
+/// HID class SET_REPORT request
+static SET_REPORT: u8 = 0x09;
+
+/// HID output report type
+static OUTPUT_REPORT: u16 = 0x02;
+
+/// HID feature report type
+static FEATURE_REPORT: u16 = 0x03;
+
+impl UsbTransport {
+    /// Wrap a USB device
+    pub fn new(device: Device<GlobalContext>) -> Self {
+        Self {
+            device,
+            handle: None,
+            stall_policy: StallPolicy::default(),
+            timeout: TIMEOUT,
+            detach_kernel_driver: true,
+            write_strategy: WriteStrategy::default(),
+            set_report: false,
+        }
+    }
+
+    /// Set how output reports are written
+    pub fn with_write_strategy(mut self, write_strategy: WriteStrategy) -> Self {
+        self.write_strategy = write_strategy;
+        self
+    }
+
+    /// Set whether a kernel driver bound to the HID interface is detached
+    /// to claim it, on by default
+    ///
+    /// Without detaching, claiming fails with [`OctoError::Busy`] while a
+    /// driver is bound. Turn it off to keep the driver's hwmon readings
+    /// while the device is open, with the driver unbound some other way.
+    pub fn with_kernel_driver_detach(mut self, detach: bool) -> Self {
+        self.detach_kernel_driver = detach;
+        self
+    }
+
+    /// Give up on a transfer after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how stalled or unresponsive endpoints are handled
+    pub fn with_stall_policy(mut self, stall_policy: StallPolicy) -> Self {
+        self.stall_policy = stall_policy;
+        self
+    }
+
+    /// The open device, opening it and claiming its HID interface if needed
+    fn take_handle(&mut self) -> Result<Handle> {
+        if let Some(handle) = self.handle.take() {
+            return Ok(handle);
+        }
+        let endpoints = self.endpoints()?;
+        let interface = endpoints.interface;
+        let mut open = self
+            .device
+            .open()
+            .map_err(|error| self.explain(error))
+            .context("Opening USB device")?;
+        // Platforms without kernel drivers to detach report NotSupported
+        let reattach =
+            self.detach_kernel_driver && open.kernel_driver_active(interface).unwrap_or(false);
+        if reattach {
+            open.detach_kernel_driver(interface)
+                .map_err(|error| self.explain(error))
+                .context("Detaching the kernel driver")?;
+        }
+        let mut handle = Handle {
+            open,
+            endpoints,
+            reattach,
+        };
+        handle
+            .open
+            .claim_interface(interface)
+            .map_err(|error| self.explain(error))
+            .context("Claiming the HID interface")?;
+        Ok(handle)
+    }
+
+    /// Run `call` on the open device
+    ///
+    /// The handle is kept for the next call unless the device went away,
+    /// in which case the next call opens it afresh.
+    fn with_handle<T>(&mut self, call: impl FnOnce(&Self, &mut Handle) -> Result<T>) -> Result<T> {
+        let mut handle = self.take_handle()?;
+        let result = call(self, &mut handle);
+        let gone = result.as_ref().err().and_then(crate::Error::kind);
+        if gone != Some(OctoError::Disconnected) {
+            self.handle = Some(handle);
+        }
+        result
+    }
+
+    /// Run a transfer on the report endpoint for `direction`, recovering
+    /// once according to the stall policy
+    ///
+    /// `transfer` gets the endpoint's address and transfer type.
+    fn transfer<T>(
+        &mut self,
+        direction: Direction,
+        mut transfer: impl FnMut(&DeviceHandle<GlobalContext>, u8, TransferType) -> rusb::Result<T>,
+    ) -> Result<T> {
+        self.with_handle(|this, handle| {
+            let endpoint = handle.endpoints.address(direction);
+            let kind = match direction {
+                Direction::Out => handle.endpoints.out.1,
+                Direction::In => TransferType::Interrupt,
+            };
+            let error = match transfer(&handle.open, endpoint, kind) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let recovered = match this.stall_policy.recovery(error) {
+                Some(Recovery::ClearHalt) => handle
+                    .open
+                    .clear_halt(endpoint)
+                    .map_err(|recovery| format!("clearing the endpoint halt failed: {recovery}")),
+                Some(Recovery::Reset) => handle
+                    .open
+                    .reset()
+                    .map_err(|recovery| format!("resetting the device failed: {recovery}")),
+                None => return Err(this.explain(error)),
+            };
+            // The stall is what the caller needs to see, with why it stuck
+            if let Err(recovery) = recovered {
+                return Err(this.explain(error).context(recovery));
+            }
+            transfer(&handle.open, endpoint, kind).map_err(|error| this.explain(error))
+        })
+    }
+
+    /// Send a report of `report_type` with a HID SET_REPORT control
+    /// transfer
+    fn set_report(&mut self, report_type: u16, report: &[u8]) -> Result<usize> {
+        let id = *report.first().context("Report is empty")?;
+        let request_type = rusb::request_type(
+            rusb::Direction::Out,
+            RequestType::Class,
+            Recipient::Interface,
+        );
+        self.with_handle(|this, handle| {
+            handle
+                .open
+                .write_control(
+                    request_type,
+                    SET_REPORT,
+                    report_type << 8 | u16::from(id),
+                    u16::from(handle.endpoints.interface),
+                    report,
+                    this.timeout,
+                )
+                .map_err(|error| this.explain(error))
+        })
+    }
+
+    /// [`OctoError`] for a USB error, with the likely cause if the device
+    /// is claimed elsewhere
+    fn explain(&self, error: rusb::Error) -> crate::Error {
+        let octo_error = OctoError::from(error);
+        if error == rusb::Error::Access {
+            return crate::Error::from(octo_error).context(format!(
+                "No permission to open /dev/bus/usb/{:03}/{:03}. Install a udev rule \
+                 granting access, e.g. with `octo-vs install-udev-rule`, then replug \
+                 the device",
+                self.device.bus_number(),
+                self.device.address()
+            ));
+        }
+        if error != rusb::Error::Busy {
+            return octo_error.into();
+        }
+        let Ok(descriptor) = self.device.device_descriptor() else {
+            return octo_error.into();
+        };
+        match kernel::hwmon_driver_bound(descriptor.vendor_id(), descriptor.product_id()) {
+            Ok(true) => crate::Error::from(octo_error).context(format!(
+                "The {driver} kernel driver is bound to the device. Unbind it through \
+                 /sys/bus/hid/drivers/{driver}/unbind or blacklist the module to write \
+                 over USB",
+                driver = kernel::HWMON_DRIVER
+            )),
+            _ => octo_error.into(),
+        }
+    }
+
+    /// Find the device again after it re-enumerated on the same port
+    fn rediscover(&self) -> Result<Device<GlobalContext>> {
+        let deadline = Instant::now() + REENUMERATE_TIMEOUT;
+        loop {
+            if let Some(device) = self.find_on_port()? {
+                return Ok(device);
+            }
+            if Instant::now() >= deadline {
+                return Err(OctoError::Disconnected)
+                    .context("Device did not come back after reset");
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// The device with the same IDs on the same port, if it's there now
+    fn find_on_port(&self) -> Result<Option<Device<GlobalContext>>> {
+        let descriptor = self.device.device_descriptor()?;
+        let id = (descriptor.vendor_id(), descriptor.product_id());
+        let bus = self.device.bus_number();
+        let ports = self.device.port_numbers()?;
+        for device in rusb::devices()?.iter() {
+            let Ok(descriptor) = device.device_descriptor() else {
+                continue;
+            };
+            let same_port =
+                device.bus_number() == bus && device.port_numbers().is_ok_and(|p| p == ports);
+            if (descriptor.vendor_id(), descriptor.product_id()) == id && same_port {
+                return Ok(Some(device));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The HID interface and endpoints of the active configuration
+    fn endpoints(&self) -> Result<Endpoints> {
+        let config = self
+            .device
+            .active_config_descriptor()
+            .map_err(OctoError::from)
+            .context("Getting configuration descriptor")?;
+        let interfaces: Vec<InterfaceSummary> = config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .map(|descriptor| InterfaceSummary {
+                number: descriptor.interface_number(),
+                class: descriptor.class_code(),
+                endpoints: descriptor
+                    .endpoint_descriptors()
+                    .map(|endpoint| (endpoint.address(), endpoint.transfer_type()))
+                    .collect(),
+            })
+            .collect();
+        let endpoints = choose_endpoints(&interfaces)?;
+        trace!(
+            Debug,
+            "Using interface {}, OUT endpoint {:#04x}, IN endpoint {:#04x}",
+            endpoints.interface,
+            endpoints.out.0,
+            endpoints.input
+        );
+        Ok(endpoints)
+    }
+}
+
+impl Transport for UsbTransport {
+    /// Send the report to the HID interface's OUT endpoint or with
+    /// SET_REPORT, per the write strategy
+    fn write_report(&mut self, report: &[u8]) -> Result<usize> {
+        if self.set_report || self.write_strategy == WriteStrategy::SetReport {
+            return self
+                .set_report(OUTPUT_REPORT, report)
+                .context("Sending SET_REPORT to Octo");
+        }
+        let timeout = self.timeout;
+        let result = self.transfer(Direction::Out, |open, endpoint, kind| match kind {
+            TransferType::Bulk => open.write_bulk(endpoint, report, timeout),
+            _ => open.write_interrupt(endpoint, report, timeout),
+        });
+        match result {
+            Err(error) if self.write_strategy.falls_back(&error) => {
+                trace!(Debug, "OUT endpoint failed ({error:#}), trying SET_REPORT");
+                let written = self
+                    .set_report(OUTPUT_REPORT, report)
+                    .context("Sending SET_REPORT to Octo after the OUT endpoint failed")?;
+                self.set_report = true;
+                Ok(written)
+            }
+            result => result.context("Sending report to Octo"),
+        }
+    }
+
+    fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let timeout = self.timeout;
+        self.transfer(Direction::In, |open, endpoint, _| {
+            open.read_interrupt(endpoint, buf, timeout)
+        })
+        .context("Reading interrupt transfer from Octo")
+    }
+
+    fn report_descriptor(&mut self) -> Result<Vec<u8>> {
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            RequestType::Standard,
+            Recipient::Interface,
+        );
+        let mut buf = vec![0; 4096];
+        let len = self.with_handle(|this, handle| {
+            handle
+                .open
+                .read_control(
+                    request_type,
+                    GET_DESCRIPTOR,
+                    REPORT_DESCRIPTOR << 8,
+                    u16::from(handle.endpoints.interface),
+                    &mut buf,
+                    this.timeout,
+                )
+                .map_err(OctoError::from)
+                .context("Reading HID report descriptor")
+        })?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+
+    /// Read the report with a HID GET_REPORT control transfer
+    fn read_feature_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let id = *buf.first().context("No room for the report ID")?;
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            RequestType::Class,
+            Recipient::Interface,
+        );
+        self.with_handle(|this, handle| {
+            handle
+                .open
+                .read_control(
+                    request_type,
+                    GET_REPORT,
+                    FEATURE_REPORT << 8 | u16::from(id),
+                    u16::from(handle.endpoints.interface),
+                    buf,
+                    this.timeout,
+                )
+                .map_err(|error| this.explain(error))
+        })
+        .with_context(|| format!("Reading feature report {id}"))
+    }
+
+    /// Write the report with a HID SET_REPORT control transfer
+    fn write_feature_report(&mut self, report: &[u8]) -> Result<usize> {
+        let id = *report.first().context("Feature report is empty")?;
+        self.set_report(FEATURE_REPORT, report)
+            .with_context(|| format!("Writing feature report {id}"))
+    }
+
+    /// Reset the port, following the device if it re-enumerates
+    fn reset(&mut self) -> Result<()> {
+        let mut handle = self.take_handle()?;
+        match handle.open.reset() {
+            Ok(()) => {
+                self.handle = Some(handle);
+                Ok(())
+            }
+            // libusb reports a device that re-enumerated as gone
+            Err(rusb::Error::NotFound | rusb::Error::NoDevice) => {
+                drop(handle);
+                self.device = self.rediscover()?;
+                Ok(())
+            }
+            Err(error) => {
+                let error = self.explain(error);
+                self.handle = Some(handle);
+                Err(error).context("Resetting USB device")
+            }
+        }
+    }
+
+    /// Release the device and claim it again, following it if it was
+    /// replugged into the same port
+    ///
+    /// Doesn't wait for a device that isn't back yet.
+    fn reopen(&mut self) -> Result<()> {
+        self.handle = None;
+        let handle = match self.take_handle() {
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    Some(OctoError::Disconnected | OctoError::Usb(rusb::Error::NotFound))
+                ) =>
+            {
+                self.device = self
+                    .find_on_port()?
+                    .ok_or(OctoError::Disconnected)
+                    .context("The device is not back yet")?;
+                self.take_handle()?
+            }
+            handle => handle?,
+        };
+        self.handle = Some(handle);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        choose_endpoints, Endpoints, InterfaceSummary, Recovery, StallPolicy, WriteStrategy,
+        HID_CLASS,
+    };
+    use crate::OctoError;
+    use rusb::TransferType;
+
+    /// Stalls are recovered per policy, other errors never are
+    #[test]
+    fn stall_recovery() {
+        let stall = rusb::Error::Pipe;
+        let clear = StallPolicy::ClearHaltAndRetry.recovery(stall);
+        assert_eq!(clear, Some(Recovery::ClearHalt));
+        let reset = StallPolicy::ResetDevice.recovery(stall);
+        assert_eq!(reset, Some(Recovery::Reset));
+        assert_eq!(StallPolicy::FailFast.recovery(stall), None);
+        for error in [rusb::Error::Timeout, rusb::Error::NoDevice] {
+            assert_eq!(StallPolicy::ClearHaltAndRetry.recovery(error), None);
+            assert_eq!(StallPolicy::ResetDevice.recovery(error), None);
+        }
+    }
+
+    /// Only stalled or unsupported endpoint writes fall back, and only
+    /// for the automatic strategy
+    #[test]
+    fn write_fallback() {
+        let error = |error| crate::Error::from(OctoError::from(error)).context("Sending");
+        assert!(WriteStrategy::Auto.falls_back(&error(rusb::Error::Pipe)));
+        assert!(WriteStrategy::Auto.falls_back(&error(rusb::Error::NotSupported)));
+        assert!(!WriteStrategy::Auto.falls_back(&error(rusb::Error::Timeout)));
+        assert!(!WriteStrategy::Auto.falls_back(&error(rusb::Error::NoDevice)));
+        assert!(!WriteStrategy::Endpoint.falls_back(&error(rusb::Error::Pipe)));
+    }
+
+    /// The HID interface with report endpoints is found wherever it is
+    #[test]
+    fn endpoint_discovery() {
+        let interface = |number, class, endpoints: &[(u8, TransferType)]| InterfaceSummary {
+            number,
+            class,
+            endpoints: endpoints.to_vec(),
+        };
+        let octo = [interface(
+            0,
+            HID_CLASS,
+            &[
+                (0x81, TransferType::Interrupt),
+                (0x02, TransferType::Interrupt),
+            ],
+        )];
+        assert_eq!(
+            choose_endpoints(&octo).unwrap(),
+            Endpoints {
+                interface: 0,
+                out: (0x02, TransferType::Interrupt),
+                input: 0x81,
+            }
+        );
+        let moved = [
+            interface(0, 0xff, &[(0x01, TransferType::Bulk)]),
+            interface(1, HID_CLASS, &[(0x83, TransferType::Interrupt)]),
+            interface(
+                2,
+                HID_CLASS,
+                &[(0x04, TransferType::Bulk), (0x85, TransferType::Interrupt)],
+            ),
+        ];
+        let endpoints = choose_endpoints(&moved).unwrap();
+        assert_eq!(
+            (endpoints.interface, endpoints.out.0, endpoints.input),
+            (2, 0x04, 0x85)
+        );
+        let error = choose_endpoints(&moved[..2]).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "No HID interface has both an interrupt IN and an OUT endpoint, found interface 1 \
+             with 0x83 Interrupt"
+        );
+        let error = choose_endpoints(&moved[..1]).unwrap_err();
+        assert_eq!(error.to_string(), "Device has no HID interface");
+    }
+}
@@ -0,0 +1,257 @@
+//! Temperature units for user-facing values
+//!
+//! The wire format is always centidegrees Celsius. A [`Unit`] converts what
+//! users type and read, so someone whose other sources report °F or K never
+//! has to convert by hand, and a [`Temperature`] carries a converted value
+//! into [`Octo::update_temperatures`](crate::Octo::update_temperatures):
+//!
+//! ```
+//! use octo_virtual_sensors::units::{Temperature, Unit};
+//! let temperature = Temperature::fahrenheit(98.6).unwrap();
+//! assert_eq!(temperature.centidegrees(), 3700);
+//! assert_eq!(temperature.to_string(), "37.00 °C");
+//! assert_eq!(Temperature::new(310.15, Unit::Kelvin).unwrap(), temperature);
+//! ```
+use crate::error::{Context, Result};
+use crate::{codec, OctoError};
+use std::{fmt, str::FromStr};
+
+/// Unit temperatures are entered and shown in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Unit {
+    /// Degrees Celsius, the device's own unit
+    #[default]
+    Celsius,
+    /// Degrees Fahrenheit
+    Fahrenheit,
+    /// Kelvin
+    Kelvin,
+}
+
+/// Offset of the Kelvin scale from Celsius
+const KELVIN_OFFSET: f64 = 273.15;
+
+impl Unit {
+    /// Symbol shown after values, such as `°C`
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Celsius => "°C",
+            Self::Fahrenheit => "°F",
+            Self::Kelvin => "K",
+        }
+    }
+
+    /// Centidegrees Celsius for a temperature in this unit
+    ///
+    /// Fails for values that aren't finite or don't fit the wire format.
+    pub fn centidegrees(self, degrees: f64) -> Result<i16> {
+        let celsius = match self {
+            Self::Celsius => degrees,
+            Self::Fahrenheit => (degrees - 32.0) * 5.0 / 9.0,
+            Self::Kelvin => degrees - KELVIN_OFFSET,
+        };
+        to_centidegrees(celsius, degrees, self)
+    }
+
+    /// Centidegrees Celsius for a temperature difference in this unit
+    ///
+    /// For thresholds and offsets, which don't shift with the scale's zero.
+    pub fn delta_centidegrees(self, degrees: f64) -> Result<i16> {
+        let celsius = match self {
+            Self::Celsius | Self::Kelvin => degrees,
+            Self::Fahrenheit => degrees * 5.0 / 9.0,
+        };
+        to_centidegrees(celsius, degrees, self)
+    }
+
+    /// Temperature in this unit for centidegrees Celsius
+    pub fn degrees(self, centidegrees: i16) -> f64 {
+        let celsius = f64::from(centidegrees) / 100.0;
+        match self {
+            Self::Celsius => celsius,
+            Self::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+            Self::Kelvin => celsius + KELVIN_OFFSET,
+        }
+    }
+
+    /// Temperature difference in this unit for centidegrees Celsius
+    pub fn delta_degrees(self, centidegrees: i16) -> f64 {
+        let celsius = f64::from(centidegrees) / 100.0;
+        match self {
+            Self::Celsius | Self::Kelvin => celsius,
+            Self::Fahrenheit => celsius * 9.0 / 5.0,
+        }
+    }
+
+    /// Temperature with two decimals and the unit's symbol
+    pub fn format(self, centidegrees: i16) -> String {
+        format!("{:.2} {}", self.degrees(centidegrees), self.symbol())
+    }
+}
+
+/// Round degrees Celsius to centidegrees, refusing what doesn't fit
+fn to_centidegrees(celsius: f64, degrees: f64, unit: Unit) -> Result<i16> {
+    let centidegrees = (celsius * 100.0).round();
+    let range = f64::from(codec::MIN_CENTIDEGREES)..=f64::from(codec::MAX_CENTIDEGREES);
+    if !range.contains(&centidegrees) {
+        return Err(OctoError::OutOfRange)
+            .with_context(|| format!("{degrees} {} is out of range", unit.symbol()));
+    }
+    Ok(centidegrees as i16)
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Celsius => "celsius",
+            Self::Fahrenheit => "fahrenheit",
+            Self::Kelvin => "kelvin",
+        })
+    }
+}
+
+impl FromStr for Unit {
+    type Err = crate::Error;
+
+    fn from_str(unit: &str) -> Result<Self> {
+        match unit.to_ascii_lowercase().as_str() {
+            "celsius" | "c" | "°c" => Ok(Self::Celsius),
+            "fahrenheit" | "f" | "°f" => Ok(Self::Fahrenheit),
+            "kelvin" | "k" => Ok(Self::Kelvin),
+            _ => bail!("Unknown unit {unit:?}, expected celsius, fahrenheit or kelvin"),
+        }
+    }
+}
+
+/// A temperature already converted to the device's centidegrees Celsius
+///
+/// Constructing one is where the unit is named, so values in °F or K can't
+/// reach the device unconverted. Displays in °C.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Temperature {
+    centidegrees: i16,
+}
+
+impl Temperature {
+    /// Temperature of `degrees` in `unit`
+    ///
+    /// Fails for values that aren't finite or don't fit the wire format.
+    pub fn new(degrees: f64, unit: Unit) -> Result<Self> {
+        Ok(Self::from_centidegrees(unit.centidegrees(degrees)?))
+    }
+
+    /// Temperature in degrees Celsius
+    pub fn celsius(degrees: f64) -> Result<Self> {
+        Self::new(degrees, Unit::Celsius)
+    }
+
+    /// Temperature in degrees Fahrenheit
+    pub fn fahrenheit(degrees: f64) -> Result<Self> {
+        Self::new(degrees, Unit::Fahrenheit)
+    }
+
+    /// Temperature in kelvin
+    pub fn kelvin(degrees: f64) -> Result<Self> {
+        Self::new(degrees, Unit::Kelvin)
+    }
+
+    /// Temperature already in centidegrees Celsius
+    pub const fn from_centidegrees(centidegrees: i16) -> Self {
+        Self { centidegrees }
+    }
+
+    /// Centidegrees Celsius, as sent to the device
+    pub const fn centidegrees(self) -> i16 {
+        self.centidegrees
+    }
+
+    /// The temperature in `unit`
+    pub fn degrees(self, unit: Unit) -> f64 {
+        unit.degrees(self.centidegrees)
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Unit::Celsius.format(self.centidegrees))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Temperature {
+    /// Degrees Celsius
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.degrees(Unit::Celsius))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Temperature {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let degrees = <f64 as serde::Deserialize>::deserialize(deserializer)?;
+        Self::celsius(degrees).map_err(|error| serde::de::Error::custom(format!("{error:#}")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Temperature, Unit};
+
+    /// Fahrenheit converts both ways around the wire format
+    #[test]
+    fn fahrenheit() {
+        let unit = Unit::Fahrenheit;
+        assert_eq!(unit.centidegrees(212.0).unwrap(), 10000);
+        assert_eq!(unit.centidegrees(-40.0).unwrap(), -4000);
+        assert_eq!(unit.delta_centidegrees(9.0).unwrap(), 500);
+        assert_eq!(unit.format(3700), "98.60 °F");
+        assert_eq!(unit.format(0), "32.00 °F");
+        assert!(unit.centidegrees(700.0).is_err());
+        assert!(Unit::Celsius.centidegrees(400.0).is_err());
+        assert!(Unit::Celsius.centidegrees(f64::NAN).is_err());
+    }
+
+    /// Units parse by name or symbol and print by name
+    #[test]
+    fn parse() {
+        assert_eq!("Fahrenheit".parse::<Unit>().unwrap(), Unit::Fahrenheit);
+        assert_eq!("°C".parse::<Unit>().unwrap(), Unit::Celsius);
+        assert_eq!(Unit::Fahrenheit.to_string(), "fahrenheit");
+        assert_eq!("K".parse::<Unit>().unwrap(), Unit::Kelvin);
+        assert_eq!(Unit::Kelvin.to_string(), "kelvin");
+        assert!("rankine".parse::<Unit>().is_err());
+    }
+
+    /// Kelvin shifts the zero but not the size of a degree
+    #[test]
+    fn kelvin() {
+        let unit = Unit::Kelvin;
+        assert_eq!(unit.centidegrees(273.15).unwrap(), 0);
+        assert_eq!(unit.centidegrees(313.65).unwrap(), 4050);
+        assert_eq!(unit.delta_centidegrees(5.0).unwrap(), 500);
+        assert_eq!(unit.format(-27315), "0.00 K");
+        assert!(unit.centidegrees(700.0).is_err());
+    }
+
+    /// Temperatures convert once, when they're made
+    #[test]
+    fn temperature() {
+        let body = Temperature::celsius(37.0).unwrap();
+        assert_eq!(Temperature::fahrenheit(98.6).unwrap(), body);
+        assert_eq!(Temperature::kelvin(310.15).unwrap(), body);
+        assert_eq!(body.centidegrees(), 3700);
+        assert!((body.degrees(Unit::Fahrenheit) - 98.6).abs() < 1e-9);
+        assert_eq!(Temperature::from_centidegrees(-250).to_string(), "-2.50 °C");
+        assert!(Temperature::fahrenheit(f64::INFINITY).is_err());
+    }
+}
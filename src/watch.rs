@@ -0,0 +1,218 @@
+//! Host-side safety watchers fed by device readback
+//!
+//! Watchers check [`Status`] readings from [`Octo::read_status`] and
+//! override published values when something is wrong.
+//!
+//! [`Octo::read_status`]: crate::Octo::read_status
+use crate::status::Status;
+
+/// Why a [`PumpWatch`] tripped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PumpFailure {
+    /// Flow dropped below the minimum, in dL/h
+    LowFlow {
+        /// Flow that was read
+        flow: u16,
+    },
+    /// The pump's fan channel fell below the minimum speed
+    PumpStalled {
+        /// Fan channel the pump is connected to
+        channel: usize,
+        /// Speed that was read
+        rpm: u16,
+    },
+}
+
+/// Watches flow and pump speed for a failing loop
+///
+/// A failure has to show in several consecutive readings before the watch
+/// trips, so one odd reading doesn't raise the alarm. Once tripped it
+/// stays tripped until [`PumpWatch::reset`]: a loop that stopped once
+/// deserves a look even if it restarts.
+///
+/// While tripped, [`PumpWatch::apply`] publishes the panic value, so fan
+/// curves on that slot go to full speed.
+///
+/// ```
+/// use octo_virtual_sensors::{status::Status, watch::PumpWatch};
+/// let mut watch = PumpWatch::new()
+///     .with_min_flow(300)
+///     .with_panic_value(15, 10_000)
+///     .with_grace(1);
+/// let status = Status {
+///     power_cycles: 1,
+///     sensors: vec![None; 4],
+///     virtual_sensors: vec![None; 16],
+///     flow: Some(0),
+///     fans: vec![Default::default(); 8],
+/// };
+/// assert!(watch.check(&status).is_some());
+/// assert_eq!(watch.apply(&[])[15], Some(10_000));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PumpWatch {
+    min_flow: Option<u16>,
+    pump: Option<(usize, u16)>,
+    panic: Option<(usize, i16)>,
+    grace: u32,
+    failing: u32,
+    failure: Option<PumpFailure>,
+}
+
+impl Default for PumpWatch {
+    fn default() -> Self {
+        Self {
+            min_flow: None,
+            pump: None,
+            panic: None,
+            grace: 3,
+            failing: 0,
+            failure: None,
+        }
+    }
+}
+
+impl PumpWatch {
+    /// Watch that checks nothing until configured
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trip when flow falls below `dl_per_hour`
+    pub fn with_min_flow(mut self, dl_per_hour: u16) -> Self {
+        self.min_flow = Some(dl_per_hour);
+        self
+    }
+
+    /// Trip when fan `channel`, the pump, runs slower than `rpm`
+    pub fn with_pump_channel(mut self, channel: usize, rpm: u16) -> Self {
+        self.pump = Some((channel, rpm));
+        self
+    }
+
+    /// Publish `centidegrees` on `slot` while tripped
+    pub fn with_panic_value(mut self, slot: usize, centidegrees: i16) -> Self {
+        self.panic = Some((slot, centidegrees));
+        self
+    }
+
+    /// Number of consecutive failing readings needed to trip, at least one
+    pub fn with_grace(mut self, readings: u32) -> Self {
+        self.grace = readings.max(1);
+        self
+    }
+
+    /// Check a status reading, returning the failure while tripped
+    ///
+    /// Readings without the watched value, such as a missing fan channel,
+    /// count as healthy.
+    pub fn check(&mut self, status: &Status) -> Option<PumpFailure> {
+        if self.failure.is_none() {
+            match self.failure_in(status) {
+                Some(failure) => {
+                    self.failing += 1;
+                    if self.failing >= self.grace {
+                        self.failure = Some(failure);
+                    }
+                }
+                None => self.failing = 0,
+            }
+        }
+        self.failure
+    }
+
+    /// Failure the watch tripped on, if any
+    pub fn failure(&self) -> Option<PumpFailure> {
+        self.failure
+    }
+
+    /// Clear a tripped watch
+    pub fn reset(&mut self) {
+        self.failing = 0;
+        self.failure = None;
+    }
+
+    /// Publish the panic value over slot-indexed centidegree values
+    ///
+    /// Values pass through unchanged unless the watch has tripped.
+    pub fn apply(&self, values: &[Option<i16>]) -> Vec<Option<i16>> {
+        let mut output = values.to_vec();
+        if let (Some(_), Some((slot, value))) = (self.failure, self.panic) {
+            output.resize(output.len().max(slot + 1), None);
+            output[slot] = Some(value);
+        }
+        output
+    }
+
+    /// Failure shown by a single reading
+    fn failure_in(&self, status: &Status) -> Option<PumpFailure> {
+        if let (Some(min), Some(flow)) = (self.min_flow, status.flow) {
+            if flow < min {
+                return Some(PumpFailure::LowFlow { flow });
+            }
+        }
+        let (channel, min) = self.pump?;
+        let rpm = status.fans.get(channel)?.rpm;
+        (rpm < min).then_some(PumpFailure::PumpStalled { channel, rpm })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PumpFailure, PumpWatch};
+    use crate::status::{FanStatus, Status};
+
+    /// Status with the given flow and pump speed on channel 0
+    fn status(flow: u16, pump_rpm: u16) -> Status {
+        Status {
+            power_cycles: 1,
+            sensors: vec![None; 4],
+            virtual_sensors: vec![None; 16],
+            flow: Some(flow),
+            fans: [pump_rpm, 1000]
+                .map(|rpm| FanStatus {
+                    rpm,
+                    ..FanStatus::default()
+                })
+                .to_vec(),
+        }
+    }
+
+    /// Consecutive low flow readings trip the watch, isolated ones don't
+    #[test]
+    fn low_flow_grace() {
+        let mut watch = PumpWatch::new().with_min_flow(300).with_grace(2);
+        assert_eq!(watch.check(&status(100, 3000)), None);
+        assert_eq!(watch.check(&status(900, 3000)), None);
+        assert_eq!(watch.check(&status(100, 3000)), None);
+        let failure = PumpFailure::LowFlow { flow: 50 };
+        assert_eq!(watch.check(&status(50, 3000)), Some(failure));
+    }
+
+    /// A stalled pump trips the watch until reset
+    #[test]
+    fn stalled_pump_latches() {
+        let mut watch = PumpWatch::new().with_pump_channel(0, 1000).with_grace(1);
+        let failure = PumpFailure::PumpStalled { channel: 0, rpm: 0 };
+        assert_eq!(watch.check(&status(900, 0)), Some(failure));
+        assert_eq!(watch.check(&status(900, 3000)), Some(failure));
+        watch.reset();
+        assert_eq!(watch.check(&status(900, 3000)), None);
+        let missing = PumpWatch::new()
+            .with_pump_channel(5, 1000)
+            .check(&status(0, 0));
+        assert_eq!(missing, None);
+    }
+
+    /// The panic value is only published while tripped
+    #[test]
+    fn panic_value() {
+        let mut watch = PumpWatch::new()
+            .with_min_flow(300)
+            .with_panic_value(2, 9000)
+            .with_grace(1);
+        assert_eq!(watch.apply(&[Some(3000)]), [Some(3000)]);
+        watch.check(&status(0, 3000));
+        assert_eq!(watch.apply(&[Some(3000)]), [Some(3000), None, Some(9000)]);
+    }
+}
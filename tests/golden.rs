@@ -0,0 +1,48 @@
+//! Compare encoded reports against fixtures
+//!
+//! Captures from hardware are known-good; snapshots are the encoder's own
+//! earlier output and only catch changes, see `tests/fixtures/README.md`.
+
+use octo_virtual_sensors::VirtualSensorReport;
+use std::{fs, path::Path};
+
+/// Load a hex fixture, `name` relative to `tests/fixtures`
+fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(format!("{name}.hex"));
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    text.split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).unwrap())
+        .collect()
+}
+
+/// Assert the encoder output for `values` matches the named fixture
+fn assert_golden(name: &str, values: &[i16]) {
+    let expected = fixture(name);
+    let mut report = VirtualSensorReport::default();
+    report.update(values);
+    assert_eq!(report.as_bytes(), expected, "{name}");
+    VirtualSensorReport::from_bytes(&expected).unwrap();
+}
+
+/// Nothing set, every slot carries the disconnected sentinel; snapshot
+#[test]
+fn all_disconnected() {
+    assert_golden("snapshots/all_disconnected", &[]);
+}
+
+/// Every slot set; capture checked against hardware
+#[test]
+fn sensors_1_to_16() {
+    assert_golden(
+        "sensors_1_to_16",
+        &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+    );
+}
+
+/// Partial update including a negative value; snapshot
+#[test]
+fn first_three() {
+    assert_golden("snapshots/first_three", &[20, 35, -5]);
+}
@@ -0,0 +1,60 @@
+//! Hardware-in-the-loop tests
+//!
+//! These need a connected Octo with the aquacomputer_d5next driver loaded.
+//! The driver is detached while an `Octo` holds the device over USB, so
+//! readings through its hwmon node are taken after the `Octo` is dropped
+//! and the driver is back. Run with `cargo test --features hardware-tests`.
+#![cfg(feature = "hardware-tests")]
+
+use octo_virtual_sensors::{layout::OCTO, Backend, Error, Octo, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Find the hwmon directory the aquacomputer_d5next driver created for the Octo
+fn octo_hwmon() -> Result<PathBuf> {
+    for entry in fs::read_dir("/sys/class/hwmon")? {
+        let path = entry?.path();
+        if fs::read_to_string(path.join("name"))?.trim() == OCTO.hwmon_name {
+            return Ok(path);
+        }
+    }
+    Err(Error::msg("Could not find octo hwmon device"))
+}
+
+/// Read the temperature in millidegrees of the channel with the given label
+fn read_labelled_temp(hwmon: &Path, label: &str) -> Result<i32> {
+    for entry in fs::read_dir(hwmon)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if let Some(channel) = name.strip_suffix("_label") {
+            if fs::read_to_string(&path)?.trim() == label {
+                let input = hwmon.join(format!("{channel}_input"));
+                return Ok(fs::read_to_string(input)?.trim().parse()?);
+            }
+        }
+    }
+    Err(Error::msg(format!("Could not find {label}")))
+}
+
+/// Test sensors sent over USB actually update
+#[test]
+fn update_virtual_sensors() -> Result<()> {
+    let mut octo = Octo::builder().backend(Backend::Usb).open()?;
+    octo.update_virtual_sensors(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])?;
+    // Hand the device back to the driver, which then needs a moment to
+    // bind and read the sensors; the values outlast that on the device
+    drop(octo);
+    std::thread::sleep(Duration::from_secs(1));
+    let hwmon = octo_hwmon()?;
+    for sensor in 1..=16 {
+        let temp = read_labelled_temp(&hwmon, &format!("Virtual sensor {sensor}"))?;
+        assert_eq!(temp, sensor * 1000);
+    }
+    Ok(())
+}